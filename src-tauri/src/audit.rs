@@ -0,0 +1,65 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+/// A single entry in the audit log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub timestamp: chrono::DateTime<Utc>,
+    pub action: String,
+    pub target: String,
+    pub detail: String,
+}
+
+/// Location of the append-only audit log (JSON lines)
+pub fn audit_log_path() -> PathBuf {
+    let base = std::env::var_os("XDG_STATE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")))
+        .unwrap_or_else(std::env::temp_dir);
+
+    base.join("kvm-manager").join("audit.log")
+}
+
+/// Record an action that changed host or VM state on the user's behalf.
+///
+/// Failures to write the log are logged and otherwise ignored so auditing never
+/// blocks the operation itself.
+pub fn record(action: &str, target: &str, detail: &str) {
+    let entry = AuditEntry {
+        timestamp: Utc::now(),
+        action: action.to_string(),
+        target: target.to_string(),
+        detail: detail.to_string(),
+    };
+
+    info!(target: "audit", "{} {}: {}", entry.action, entry.target, entry.detail);
+
+    let path = audit_log_path();
+    if let Some(parent) = path.parent() {
+        if let Err(e) = fs::create_dir_all(parent) {
+            warn!("Failed to create audit log directory {}: {}", parent.display(), e);
+            return;
+        }
+    }
+
+    let line = match serde_json::to_string(&entry) {
+        Ok(line) => line,
+        Err(e) => {
+            warn!("Failed to serialize audit entry: {}", e);
+            return;
+        }
+    };
+
+    match OpenOptions::new().create(true).append(true).open(&path) {
+        Ok(mut file) => {
+            if let Err(e) = writeln!(file, "{}", line) {
+                warn!("Failed to write audit log {}: {}", path.display(), e);
+            }
+        }
+        Err(e) => warn!("Failed to open audit log {}: {}", path.display(), e),
+    }
+}
@@ -0,0 +1,89 @@
+use std::sync::Arc;
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+use crate::errors::{KvmError, Result};
+use crate::refresh;
+use crate::types::*;
+use crate::vm_manager::VmManager;
+
+/// The `*.xml` files directly inside `dir`, sorted by name
+pub fn xml_files(dir: &str) -> Result<Vec<String>> {
+    let entries = std::fs::read_dir(dir)
+        .map_err(|e| KvmError::VmOperationFailed(format!("Cannot read directory {}: {}", dir, e)))?;
+
+    let mut files: Vec<String> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("xml")))
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Import every domain XML in `files`, calling `progress` after each one.
+///
+/// The manager is only read-locked per file so other commands keep working
+/// during a long batch; the VM cache is refreshed once at the end.
+pub async fn run(
+    manager: Arc<RwLock<VmManager>>,
+    task_id: String,
+    directory: String,
+    files: Vec<String>,
+    on_conflict: ImportConflictPolicy,
+    progress: impl Fn(BatchImportProgress),
+) -> BatchImportReport {
+    info!("Importing {} XML files from {} ({:?} on conflict)", files.len(), directory, on_conflict);
+
+    let mut report = BatchImportReport {
+        task_id: task_id.clone(),
+        directory,
+        ..Default::default()
+    };
+
+    let total = files.len();
+    for (index, file) in files.into_iter().enumerate() {
+        let outcome = manager.read().await.import_xml_file(&file, on_conflict);
+
+        match &outcome {
+            ImportFileOutcome::Imported { uuid, name } => report.imported.push(ImportedVm {
+                file: file.clone(),
+                uuid: uuid.clone(),
+                name: name.clone(),
+            }),
+            ImportFileOutcome::Skipped { reason } => report.skipped.push(note(&file, reason)),
+            ImportFileOutcome::NotDomain { kind } => report.not_domain.push(note(&file, kind)),
+            ImportFileOutcome::Failed { reason } => {
+                warn!("Failed to import {}: {}", file, reason);
+                report.failed.push(note(&file, reason));
+            }
+        }
+
+        progress(BatchImportProgress {
+            task_id: task_id.clone(),
+            file,
+            index: index + 1,
+            total,
+            outcome,
+        });
+    }
+
+    if !report.imported.is_empty() {
+        if let Err(e) = refresh::refresh_vms(manager).await {
+            warn!("Failed to refresh VMs after batch import: {}", e);
+        }
+    }
+
+    info!(
+        "Batch import finished: {} imported, {} skipped, {} failed, {} not domain XML",
+        report.imported.len(), report.skipped.len(), report.failed.len(), report.not_domain.len()
+    );
+    report
+}
+
+fn note(file: &str, detail: &str) -> ImportFileNote {
+    ImportFileNote {
+        file: file.to_string(),
+        detail: detail.to_string(),
+    }
+}
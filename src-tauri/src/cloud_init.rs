@@ -0,0 +1,119 @@
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+use crate::errors::{KvmError, Result};
+use crate::tools::{self, Tool};
+use crate::types::CloudInitConfig;
+
+/// Where the seed ISO for `vm_name` lives: next to the image it boots from
+pub fn seed_iso_path(image_path: &str, vm_name: &str) -> PathBuf {
+    let dir = Path::new(image_path).parent().unwrap_or_else(|| Path::new("."));
+    dir.join(format!("{}-seed.iso", vm_name))
+}
+
+// JSON strings are valid YAML scalars, which saves hand-rolling the quoting
+fn yaml_string(value: &str) -> String {
+    serde_json::to_string(value).unwrap_or_else(|_| "\"\"".to_string())
+}
+
+fn user_data(config: &CloudInitConfig) -> String {
+    let mut data = format!(
+        "#cloud-config\nhostname: {}\nusers:\n  - name: {}\n    sudo: \"ALL=(ALL) NOPASSWD:ALL\"\n    shell: /bin/bash\n",
+        yaml_string(&config.hostname),
+        yaml_string(&config.username),
+    );
+    if let Some(key) = config.ssh_authorized_key.as_deref().map(str::trim).filter(|key| !key.is_empty()) {
+        data.push_str(&format!("    ssh_authorized_keys:\n      - {}\n", yaml_string(key)));
+    }
+    data
+}
+
+fn meta_data(config: &CloudInitConfig, instance_id: &str) -> String {
+    format!(
+        "instance-id: {}\nlocal-hostname: {}\n",
+        yaml_string(instance_id),
+        yaml_string(&config.hostname),
+    )
+}
+
+fn validate(config: &CloudInitConfig) -> Result<()> {
+    if config.hostname.trim().is_empty() {
+        return Err(KvmError::InvalidVmConfig("cloud-init hostname must not be empty".to_string()));
+    }
+    if config.username.trim().is_empty() {
+        return Err(KvmError::InvalidVmConfig("cloud-init username must not be empty".to_string()));
+    }
+    Ok(())
+}
+
+/// Build a NoCloud seed ISO at `iso_path`, preferring cloud-localds and
+/// falling back to genisoimage. Fails with `ToolMissing` if neither exists.
+pub fn build_seed_iso(config: &CloudInitConfig, instance_id: &str, iso_path: &Path) -> Result<()> {
+    validate(config)?;
+
+    let (tool, mut command) = if let Some(command) = tools::try_command(Tool::CloudLocalds) {
+        (Tool::CloudLocalds, command)
+    } else if let Some(command) = tools::try_command(Tool::Genisoimage) {
+        (Tool::Genisoimage, command)
+    } else {
+        return Err(KvmError::ToolMissing {
+            tool: "cloud-localds or genisoimage".to_string(),
+            package: format!("{} or {}", Tool::CloudLocalds.package(), Tool::Genisoimage.package()),
+        });
+    };
+
+    // The file names inside the ISO are what cloud-init looks for
+    let work_dir = std::env::temp_dir().join(format!("kvm-manager-seed-{}", instance_id));
+    std::fs::create_dir_all(&work_dir)
+        .map_err(|e| KvmError::VmOperationFailed(format!("Failed to create {}: {}", work_dir.display(), e)))?;
+    let user_data_path = work_dir.join("user-data");
+    let meta_data_path = work_dir.join("meta-data");
+    let network_config_path = work_dir.join("network-config");
+
+    let result = (|| {
+        let write = |path: &Path, contents: &str| {
+            std::fs::write(path, contents)
+                .map_err(|e| KvmError::VmOperationFailed(format!("Failed to write {}: {}", path.display(), e)))
+        };
+        write(&user_data_path, &user_data(config))?;
+        write(&meta_data_path, &meta_data(config, instance_id))?;
+        if let Some(network_config) = &config.network_config {
+            write(&network_config_path, network_config)?;
+        }
+
+        match tool {
+            Tool::CloudLocalds => {
+                if config.network_config.is_some() {
+                    command.arg(format!("--network-config={}", network_config_path.display()));
+                }
+                command.arg(iso_path).arg(&user_data_path).arg(&meta_data_path);
+            }
+            _ => {
+                command
+                    .arg("-output")
+                    .arg(iso_path)
+                    .args(["-volid", "cidata", "-joliet", "-rock"])
+                    .arg(&user_data_path)
+                    .arg(&meta_data_path);
+                if config.network_config.is_some() {
+                    command.arg(&network_config_path);
+                }
+            }
+        }
+
+        let output = command.output()
+            .map_err(|e| KvmError::VmOperationFailed(format!("Failed to run {}: {}", tool.binary(), e)))?;
+        if !output.status.success() {
+            let _ = std::fs::remove_file(iso_path);
+            let error = String::from_utf8_lossy(&output.stderr);
+            return Err(KvmError::VmOperationFailed(format!("Failed to build seed ISO: {}", error.trim())));
+        }
+        Ok(())
+    })();
+
+    let _ = std::fs::remove_dir_all(&work_dir);
+    if result.is_ok() {
+        info!("Built cloud-init seed ISO {} with {}", iso_path.display(), tool.binary());
+    }
+    result
+}
@@ -0,0 +1,60 @@
+use std::time::Duration;
+use once_cell::sync::Lazy;
+use tauri::{Emitter, Manager};
+use tracing::{error, info, warn};
+
+use crate::types::ConnectionStatus;
+
+const HEALTH_INTERVAL: Duration = Duration::from_secs(5);
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+// Past this delay the host is reported as disconnected, though retries continue
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+static STATUS: Lazy<std::sync::Mutex<ConnectionStatus>> = Lazy::new(|| std::sync::Mutex::new(ConnectionStatus::Connected));
+
+pub fn status() -> ConnectionStatus {
+    *STATUS.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn set_status(status: ConnectionStatus) {
+    *STATUS.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = status;
+}
+
+/// Poll the libvirt connection and reopen it with exponential backoff when it
+/// drops, announcing `connection-lost` and `connection-restored` events.
+pub async fn watch(app: tauri::AppHandle) {
+    let manager = app.state::<crate::AppState>().inner().clone();
+
+    loop {
+        tokio::time::sleep(HEALTH_INTERVAL).await;
+        if manager.read().await.is_alive() {
+            continue;
+        }
+
+        let uri = manager.read().await.get_connection_uri();
+        warn!("Lost libvirt connection to {}", uri);
+        set_status(ConnectionStatus::Reconnecting);
+        if let Err(e) = app.emit("connection-lost", serde_json::json!({ "uri": uri })) {
+            error!("Failed to emit connection-lost: {}", e);
+        }
+
+        let mut backoff = INITIAL_BACKOFF;
+        loop {
+            tokio::time::sleep(backoff).await;
+            match manager.write().await.reconnect().await {
+                Ok(()) => break,
+                Err(e) => warn!("Reconnecting to {} failed, retrying in {:?}: {}", uri, backoff, e),
+            }
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+            if backoff == MAX_BACKOFF {
+                set_status(ConnectionStatus::Disconnected);
+            }
+        }
+
+        info!("Restored libvirt connection to {}", uri);
+        set_status(ConnectionStatus::Connected);
+        if let Err(e) = app.emit("connection-restored", serde_json::json!({ "uri": uri })) {
+            error!("Failed to emit connection-restored: {}", e);
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use thiserror::Error;
 
+use crate::types::{FilesystemIssue, NeedsAction, QuotaViolation, ShutdownTimeout};
+
 #[derive(Error, Debug)]
 pub enum KvmError {
     #[error("Libvirt connection error: {0}")]
@@ -8,9 +10,15 @@ pub enum KvmError {
     #[error("Virtual machine not found: {0}")]
     VmNotFound(String),
     
+    #[error("Virtual machine is not running: {0}")]
+    VmNotRunning(String),
+    
     #[error("Storage pool not found: {0}")]
     StoragePoolNotFound(String),
     
+    #[error("Cannot reach libvirt: {0}")]
+    HostUnreachable(String),
+    
     #[error("Network not found: {0}")]
     NetworkNotFound(String),
     
@@ -26,6 +34,12 @@ pub enum KvmError {
     #[error("Network operation failed: {0}")]
     NetworkOperationFailed(String),
     
+    #[error("Guest agent not connected: {0}")]
+    GuestAgentUnavailable(String),
+    
+    #[error("Command not found in guest: {0}")]
+    GuestCommandNotFound(String),
+    
     #[error("Snapshot operation failed: {0}")]
     SnapshotOperationFailed(String),
     
@@ -47,13 +61,46 @@ pub enum KvmError {
     #[error("Serialization error: {0}")]
     SerializationError(#[from] serde_json::Error),
     
+    #[error("Required tool '{tool}' not found; install the '{package}' package")]
+    ToolMissing { tool: String, package: String },
+    
+    #[error("Action required: {}", .0.message)]
+    NeedsAction(NeedsAction),
+    
+    #[error("Disk filesystem problem: {}", .0.message)]
+    FilesystemIssue(FilesystemIssue),
+    
+    #[error("Quota exceeded: {}", .0.message)]
+    QuotaExceeded(QuotaViolation),
+    
+    #[error("Requested {requested_mb} MB exceeds the VM's maximum memory of {max_mb} MB; maximum memory can only be raised while the VM is shut off")]
+    AboveMaxMemory { requested_mb: u64, max_mb: u64 },
+    
+    #[error("Shutdown timed out: {}", .0.message)]
+    ShutdownTimeout(ShutdownTimeout),
+    
     #[error("Unknown error: {0}")]
     Unknown(String),
 }
 
 impl From<KvmError> for String {
     fn from(error: KvmError) -> Self {
-        error.to_string()
+        match error {
+            // Keep the structured payload so the frontend can prompt the user
+            KvmError::NeedsAction(needs_action) => {
+                serde_json::json!({ "needs_action": needs_action }).to_string()
+            }
+            KvmError::FilesystemIssue(issue) => {
+                serde_json::json!({ "filesystem_issue": issue }).to_string()
+            }
+            KvmError::QuotaExceeded(violation) => {
+                serde_json::json!({ "quota_exceeded": violation }).to_string()
+            }
+            KvmError::ShutdownTimeout(timeout) => {
+                serde_json::json!({ "shutdown_timeout": timeout }).to_string()
+            }
+            error => error.to_string(),
+        }
     }
 }
 
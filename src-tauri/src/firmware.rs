@@ -0,0 +1,153 @@
+use std::path::{Path, PathBuf};
+use tracing::debug;
+
+use crate::errors::{KvmError, Result};
+use crate::xml_parser::XmlParser;
+
+// Firmware descriptors (docs/interop/firmware.json in QEMU), highest priority first
+const DESCRIPTOR_DIRS: [&str; 2] = ["/etc/qemu/firmware", "/usr/share/qemu/firmware"];
+
+// Where distributions install OVMF when they ship no descriptors
+const OVMF_DIRS: [&str; 3] = ["/usr/share/OVMF", "/usr/share/edk2/ovmf", "/usr/share/edk2/x64"];
+const OVMF_IMAGES: [(&str, &str); 3] = [
+    ("OVMF_CODE.4m.fd", "OVMF_VARS.4m.fd"),   // Arch
+    ("OVMF_CODE_4M.fd", "OVMF_VARS_4M.fd"),   // Debian, Ubuntu
+    ("OVMF_CODE.fd", "OVMF_VARS.fd"),         // Fedora, older releases
+];
+// Secure Boot builds, with a variable store that has Microsoft's keys enrolled where one is shipped
+const OVMF_SECBOOT_IMAGES: [(&str, &str); 3] = [
+    ("OVMF_CODE.secboot.fd", "OVMF_VARS.secboot.fd"),   // Fedora
+    ("OVMF_CODE_4M.secboot.fd", "OVMF_VARS_4M.ms.fd"),  // Debian, Ubuntu
+    ("OVMF_CODE.secboot.4m.fd", "OVMF_VARS.4m.fd"),     // Arch
+];
+
+const NVRAM_DIR: &str = "/var/lib/libvirt/qemu/nvram";
+
+/// A UEFI code image and the variable store template that goes with it
+#[derive(Debug, Clone)]
+pub struct OvmfFirmware {
+    pub code: PathBuf,
+    pub vars_template: PathBuf,
+    pub secure_boot: bool,
+}
+
+/// Find OVMF on this host, from the firmware descriptors if there are any,
+/// else by probing the usual install locations. With `secure_boot` only a
+/// Secure Boot build will do.
+pub fn detect_ovmf(secure_boot: bool) -> Result<OvmfFirmware> {
+    from_descriptors(secure_boot)
+        .or_else(|| from_known_paths(secure_boot))
+        .ok_or_else(|| KvmError::ToolMissing {
+            tool: if secure_boot {
+                "Secure Boot capable OVMF firmware (OVMF_CODE.secboot.fd)".to_string()
+            } else {
+                "OVMF UEFI firmware".to_string()
+            },
+            package: "ovmf (edk2-ovmf on Fedora and Arch)".to_string(),
+        })
+}
+
+fn from_descriptors(secure_boot: bool) -> Option<OvmfFirmware> {
+    let mut descriptors: Vec<PathBuf> = Vec::new();
+    for dir in DESCRIPTOR_DIRS {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            continue;
+        };
+        let mut files: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect();
+        // File names carry a numeric priority prefix, e.g. 50-edk2-ovmf.json
+        files.sort();
+        descriptors.extend(files);
+    }
+
+    descriptors.iter().find_map(|path| {
+        let firmware = std::fs::read_to_string(path).ok()
+            .and_then(|contents| serde_json::from_str::<serde_json::Value>(&contents).ok())
+            .and_then(|descriptor| usable_descriptor(&descriptor, secure_boot));
+        if let Some(firmware) = &firmware {
+            debug!("Using OVMF from descriptor {}: {}", path.display(), firmware.code.display());
+        }
+        firmware
+    })
+}
+
+fn usable_descriptor(descriptor: &serde_json::Value, secure_boot: bool) -> Option<OvmfFirmware> {
+    let strings = |value: &serde_json::Value| -> Vec<String> {
+        value.as_array()
+            .map(|items| items.iter().filter_map(|item| item.as_str().map(str::to_string)).collect())
+            .unwrap_or_default()
+    };
+
+    if !strings(&descriptor["interface-types"]).iter().any(|interface| interface == "uefi") {
+        return None;
+    }
+    let targets_q35 = descriptor["targets"].as_array()?.iter().any(|target| {
+        target["architecture"].as_str() == Some("x86_64")
+            && strings(&target["machines"]).iter().any(|machine| machine.starts_with("pc-q35"))
+    });
+    if !targets_q35 {
+        return None;
+    }
+    // Confidential-computing builds don't boot a plain guest, and builds that
+    // need SMM are only usable when Secure Boot turns SMM on
+    let features = strings(&descriptor["features"]);
+    let has = |wanted: &str| features.iter().any(|feature| feature == wanted);
+    if ["amd-sev", "amd-sev-es", "amd-sev-snp", "intel-tdx"].iter().any(|feature| has(feature)) {
+        return None;
+    }
+    if secure_boot != has("secure-boot") || (!secure_boot && has("requires-smm")) {
+        return None;
+    }
+
+    let mapping = &descriptor["mapping"];
+    if mapping["device"].as_str() != Some("flash") {
+        return None;
+    }
+    let format = mapping["executable"]["format"].as_str().unwrap_or("raw");
+    if format != "raw" {
+        return None;
+    }
+
+    let firmware = OvmfFirmware {
+        code: PathBuf::from(mapping["executable"]["filename"].as_str()?),
+        vars_template: PathBuf::from(mapping["nvram-template"]["filename"].as_str()?),
+        secure_boot,
+    };
+    (firmware.code.is_file() && firmware.vars_template.is_file()).then_some(firmware)
+}
+
+fn from_known_paths(secure_boot: bool) -> Option<OvmfFirmware> {
+    let images = if secure_boot { &OVMF_SECBOOT_IMAGES } else { &OVMF_IMAGES };
+    OVMF_DIRS.iter()
+        .flat_map(|dir| images.iter().map(move |(code, vars)| (Path::new(dir).join(code), Path::new(dir).join(vars))))
+        .find(|(code, vars)| code.is_file() && vars.is_file())
+        .map(|(code, vars_template)| OvmfFirmware { code, vars_template, secure_boot })
+}
+
+/// Per-VM variable store in libvirt's nvram directory; libvirt copies the
+/// template there on first boot and removes it when the VM is undefined
+pub fn nvram_path(vm_name: &str) -> PathBuf {
+    Path::new(NVRAM_DIR).join(format!("{}_VARS.fd", vm_name))
+}
+
+/// `<loader>` and `<nvram>` lines for the `<os>` element, indented to match it
+pub fn os_firmware_xml(firmware: &OvmfFirmware, vm_name: &str) -> String {
+    format!(
+        "    <loader readonly='yes'{} type='pflash'>{}</loader>\n    <nvram template='{}'>{}</nvram>\n",
+        if firmware.secure_boot { " secure='yes'" } else { "" },
+        XmlParser::escape(&firmware.code.to_string_lossy()),
+        XmlParser::escape(&firmware.vars_template.to_string_lossy()),
+        XmlParser::escape(&nvram_path(vm_name).to_string_lossy()),
+    )
+}
+
+/// Extra `<features>` children the firmware needs: Secure Boot builds only
+/// run with SMM emulation on
+pub fn features_xml(firmware: Option<&OvmfFirmware>) -> &'static str {
+    match firmware {
+        Some(firmware) if firmware.secure_boot => "    <smm state='on'/>\n",
+        _ => "",
+    }
+}
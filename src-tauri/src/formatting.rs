@@ -0,0 +1,55 @@
+//! Canonical size and duration formatting for values exposed through the API.
+//!
+//! The backend reports every size in bytes and every duration in seconds; the
+//! helpers here produce the matching display strings so the frontend does not
+//! have to re-implement unit conversion.
+
+const BYTE_UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+
+pub const KIB: u64 = 1024;
+pub const MIB: u64 = 1024 * KIB;
+pub const GIB: u64 = 1024 * MIB;
+
+/// Format a byte count using binary units, e.g. `1.50 GiB` or `512 B`.
+pub fn format_bytes(bytes: u64) -> String {
+    if bytes < KIB {
+        return format!("{} B", bytes);
+    }
+
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < BYTE_UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{:.2} {}", value, BYTE_UNITS[unit])
+}
+
+/// Format a duration in seconds as `1d 2h 3m`, `4h 5m`, `6m 7s` or `8s`.
+pub fn format_duration(seconds: u64) -> String {
+    let days = seconds / 86400;
+    let hours = (seconds % 86400) / 3600;
+    let minutes = (seconds % 3600) / 60;
+    let secs = seconds % 60;
+
+    if days > 0 {
+        format!("{}d {}h {}m", days, hours, minutes)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, secs)
+    } else {
+        format!("{}s", secs)
+    }
+}
+
+/// Convert mebibytes (the legacy unit of most memory fields) to bytes.
+pub fn mib_to_bytes(mib: u64) -> u64 {
+    mib.saturating_mul(MIB)
+}
+
+/// Convert bytes to gibibytes for the legacy `*_gb` fields.
+pub fn bytes_to_gib(bytes: u64) -> f64 {
+    bytes as f64 / GIB as f64
+}
@@ -0,0 +1,354 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use base64::Engine;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+use virt::{connect::Connect, domain::Domain, error::ErrorNumber};
+
+use crate::errors::{KvmError, Result};
+
+// Agent calls block the caller, so keep them short; a healthy agent answers in milliseconds
+const AGENT_TIMEOUT_SECS: i32 = 5;
+
+// Stats refreshes ask every few seconds; reuse a recent answer instead of pinging each time
+const PING_CACHE_TTL: Duration = Duration::from_secs(10);
+
+// How often guest_exec asks whether the process has exited
+const EXEC_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Longest guest_exec waits for a process, whatever the caller asks for
+pub const MAX_EXEC_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Largest file guest_read_file and guest_write_file move in one call
+pub const MAX_FILE_BYTES: usize = 1024 * 1024;
+
+// Last ping result by "<uri> <domain name>"
+static PING_CACHE: Lazy<std::sync::Mutex<HashMap<String, (Instant, bool)>>> = Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+
+/// Where a running VM's agent can be reached. Resolved while holding the
+/// manager lock, then used without it so slow agent calls don't hold up other
+/// commands.
+#[derive(Debug, Clone)]
+pub struct AgentTarget {
+    pub vm_id: String,
+    pub uri: String,
+    pub domain_name: String,
+}
+
+/// Whether the agent answers, and what it says about itself
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AgentStatus {
+    pub connected: bool,
+    pub version: Option<String>,
+    pub supported_commands: Vec<String>, // Enabled commands only
+}
+
+/// How a process started through `guest-exec` ended
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GuestExecResult {
+    pub pid: i64,
+    pub exit_code: Option<i32>, // None when a signal killed it
+    pub signal: Option<i32>,
+    pub stdout: String, // Empty unless output was captured
+    pub stderr: String,
+    pub truncated: bool, // The agent caps captured output
+}
+
+/// A file read from the guest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GuestFileContents {
+    pub path: String,
+    pub contents_base64: String,
+    pub bytes: usize,
+    pub truncated: bool, // The file goes on past max_bytes
+}
+
+/// What the QEMU guest agent reports from inside a running VM
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GuestInfo {
+    pub agent_connected: bool,
+    pub filesystems: Vec<GuestFilesystem>,
+    pub interfaces: Vec<GuestInterface>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GuestFilesystem {
+    pub mountpoint: String,
+    pub fs_type: String,
+    pub total_bytes: Option<u64>, // Older agents don't report usage
+    pub used_bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GuestInterface {
+    pub name: String,
+    pub mac_address: Option<String>,
+    pub ip_addresses: Vec<String>, // CIDR notation, e.g. 192.168.122.10/24
+}
+
+/// Run an agent command through libvirt's QEMU agent passthrough and return
+/// the `return` member of the reply. Opens its own connection to `uri`, so it
+/// can run on any thread without the manager.
+pub fn execute(uri: &str, domain_name: &str, command: &str, arguments: Option<serde_json::Value>) -> Result<serde_json::Value> {
+    let mut request = serde_json::json!({ "execute": command });
+    if let Some(arguments) = arguments {
+        request["arguments"] = arguments;
+    }
+
+    let mut connection = Connect::open(Some(uri)).map_err(KvmError::LibvirtConnection)?;
+    let reply = Domain::lookup_by_name(&connection, domain_name)
+        .and_then(|domain| domain.qemu_agent_command(&request.to_string(), AGENT_TIMEOUT_SECS, 0));
+    if let Err(e) = connection.close() {
+        debug!("Failed to close agent connection to {}: {}", uri, e);
+    }
+
+    let reply = reply.map_err(|e| {
+        debug!("Guest agent command {} failed for {}: {}", command, domain_name, e);
+        // libvirt's wording when the channel is missing or nothing listens on it
+        let unavailable = ["agent is not connected", "agent is not responding", "agent is not configured", "agent not available"];
+        let error = e.to_string();
+        if e.code() == ErrorNumber::AgentUnresponsive || unavailable.iter().any(|message| error.contains(message)) {
+            KvmError::GuestAgentUnavailable(error)
+        } else {
+            KvmError::VmOperationFailed(format!("Guest agent command {} failed: {}", command, error))
+        }
+    })?;
+
+    let mut reply: serde_json::Value = serde_json::from_str(&reply)?;
+    Ok(reply["return"].take())
+}
+
+/// `execute` on the blocking pool, for callers on the async runtime
+pub async fn execute_blocking(uri: &str, domain_name: &str, command: &str, arguments: Option<serde_json::Value>) -> Result<serde_json::Value> {
+    let (uri, domain_name, command) = (uri.to_string(), domain_name.to_string(), command.to_string());
+    tokio::task::spawn_blocking(move || execute(&uri, &domain_name, &command, arguments))
+        .await
+        .map_err(|e| KvmError::VmOperationFailed(format!("Guest agent task failed: {}", e)))?
+}
+
+/// Whether the agent is running in the guest and answering
+pub fn ping(uri: &str, domain_name: &str) -> bool {
+    let connected = execute(uri, domain_name, "guest-ping", None).is_ok();
+    remember(uri, domain_name, connected);
+    connected
+}
+
+/// `ping`, answered from the cache when the last ping is recent enough. A
+/// stale answer is returned as is while a fresh ping runs in the background,
+/// so only the first call for a VM waits for the agent, and never on the
+/// async runtime's threads.
+pub async fn ping_cached(uri: &str, domain_name: &str) -> bool {
+    let cached = {
+        let mut cache = PING_CACHE.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match cache.get_mut(&cache_key(uri, domain_name)) {
+            Some((checked, connected)) if checked.elapsed() < PING_CACHE_TTL => return *connected,
+            Some((checked, connected)) => {
+                // Claim the refresh so concurrent callers don't start their own
+                *checked = Instant::now();
+                Some(*connected)
+            }
+            None => None,
+        }
+    };
+
+    let (uri, domain_name) = (uri.to_string(), domain_name.to_string());
+    let refresh = tokio::task::spawn_blocking(move || ping(&uri, &domain_name));
+    match cached {
+        Some(connected) => connected,
+        None => refresh.await.unwrap_or(false),
+    }
+}
+
+/// Agent version and commands from `guest-info`; not connected when it doesn't answer
+pub fn status(uri: &str, domain_name: &str) -> AgentStatus {
+    let reply = execute(uri, domain_name, "guest-info", None);
+    remember(uri, domain_name, reply.is_ok());
+    let Ok(reply) = reply else {
+        return AgentStatus::default();
+    };
+
+    AgentStatus {
+        connected: true,
+        version: reply["version"].as_str().map(str::to_string),
+        supported_commands: reply["supported_commands"].as_array()
+            .map(|commands| commands.as_slice())
+            .unwrap_or_default()
+            .iter()
+            .filter(|command| command["enabled"].as_bool().unwrap_or(true))
+            .filter_map(|command| command["name"].as_str().map(str::to_string))
+            .collect(),
+    }
+}
+
+/// Run `path` in the guest and wait up to `timeout` (at most
+/// `MAX_EXEC_TIMEOUT`) for it to exit. Output is only collected when
+/// `capture_output` is set.
+pub async fn exec(
+    uri: &str,
+    domain_name: &str,
+    path: &str,
+    args: &[String],
+    capture_output: bool,
+    timeout: Duration,
+) -> Result<GuestExecResult> {
+    let timeout = timeout.min(MAX_EXEC_TIMEOUT);
+    let arguments = serde_json::json!({ "path": path, "arg": args, "capture-output": capture_output });
+    let started = execute_blocking(uri, domain_name, "guest-exec", Some(arguments)).await.map_err(|e| match e {
+        // glib on Linux guests, CreateProcess on Windows ones
+        KvmError::VmOperationFailed(message)
+            if message.contains("No such file or directory") || message.contains("cannot find the file") =>
+        {
+            KvmError::GuestCommandNotFound(path.to_string())
+        }
+        e => e,
+    })?;
+    let pid = started["pid"].as_i64()
+        .ok_or_else(|| KvmError::VmOperationFailed("guest-exec returned no process id".to_string()))?;
+    debug!("Started {} in {} as pid {}", path, domain_name, pid);
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        let status = execute_blocking(uri, domain_name, "guest-exec-status", Some(serde_json::json!({ "pid": pid }))).await?;
+        if status["exited"].as_bool().unwrap_or(false) {
+            let decode = |field: &str| status[field].as_str()
+                .and_then(|data| base64::engine::general_purpose::STANDARD.decode(data).ok())
+                .map(|data| String::from_utf8_lossy(&data).into_owned())
+                .unwrap_or_default();
+            return Ok(GuestExecResult {
+                pid,
+                exit_code: status["exitcode"].as_i64().map(|code| code as i32),
+                signal: status["signal"].as_i64().map(|signal| signal as i32),
+                stdout: decode("out-data"),
+                stderr: decode("err-data"),
+                truncated: status["out-truncated"].as_bool().unwrap_or(false)
+                    || status["err-truncated"].as_bool().unwrap_or(false),
+            });
+        }
+        if Instant::now() >= deadline {
+            return Err(KvmError::VmOperationFailed(format!(
+                "{} (pid {}) was still running in the guest after {} seconds", path, pid, timeout.as_secs()
+            )));
+        }
+        tokio::time::sleep(EXEC_POLL_INTERVAL).await;
+    }
+}
+
+/// Read up to `max_bytes` of a file in the guest
+pub fn read_file(uri: &str, domain_name: &str, path: &str, max_bytes: usize) -> Result<GuestFileContents> {
+    let max_bytes = max_bytes.min(MAX_FILE_BYTES);
+    with_file(uri, domain_name, path, "r", |handle| {
+        let mut contents = Vec::new();
+        let mut eof = false;
+        while !eof && contents.len() < max_bytes {
+            // One byte past the limit tells a file of exactly max_bytes from a longer one
+            let count = max_bytes + 1 - contents.len();
+            let reply = execute(uri, domain_name, "guest-file-read", Some(serde_json::json!({ "handle": handle, "count": count })))?;
+            let data = base64::engine::general_purpose::STANDARD
+                .decode(reply["buf-b64"].as_str().unwrap_or_default())
+                .map_err(|e| KvmError::VmOperationFailed(format!("Unreadable guest-file-read reply: {}", e)))?;
+            eof = reply["eof"].as_bool().unwrap_or(true) || data.is_empty();
+            contents.extend_from_slice(&data);
+        }
+
+        let truncated = contents.len() > max_bytes;
+        contents.truncate(max_bytes);
+        Ok(GuestFileContents {
+            path: path.to_string(),
+            contents_base64: base64::engine::general_purpose::STANDARD.encode(&contents),
+            bytes: contents.len(),
+            truncated,
+        })
+    })
+}
+
+/// Write `contents` to a file in the guest, replacing it unless `append` is
+/// set. Returns the number of bytes written.
+pub fn write_file(uri: &str, domain_name: &str, path: &str, contents: &[u8], append: bool) -> Result<usize> {
+    if contents.len() > MAX_FILE_BYTES {
+        return Err(KvmError::VmOperationFailed(format!(
+            "{} bytes is more than the {} bytes that can be written at once", contents.len(), MAX_FILE_BYTES
+        )));
+    }
+    with_file(uri, domain_name, path, if append { "a" } else { "w" }, |handle| {
+        let data = base64::engine::general_purpose::STANDARD.encode(contents);
+        let reply = execute(uri, domain_name, "guest-file-write", Some(serde_json::json!({ "handle": handle, "buf-b64": data })))?;
+        let written = reply["count"].as_u64().unwrap_or(0) as usize;
+        if written < contents.len() {
+            return Err(KvmError::VmOperationFailed(format!("Guest wrote only {} of {} bytes to {}", written, contents.len(), path)));
+        }
+        execute(uri, domain_name, "guest-file-flush", Some(serde_json::json!({ "handle": handle })))?;
+        Ok(written)
+    })
+}
+
+// Open `path` in the guest, run `operation` on the handle and close it again
+// whatever happened, so failed calls don't leak descriptors in the agent
+fn with_file<T>(uri: &str, domain_name: &str, path: &str, mode: &str, operation: impl FnOnce(i64) -> Result<T>) -> Result<T> {
+    if path.trim().is_empty() {
+        return Err(KvmError::VmOperationFailed("No guest file path given".to_string()));
+    }
+    let handle = execute(uri, domain_name, "guest-file-open", Some(serde_json::json!({ "path": path, "mode": mode })))?
+        .as_i64()
+        .ok_or_else(|| KvmError::VmOperationFailed("guest-file-open returned no handle".to_string()))?;
+
+    let result = operation(handle);
+    if let Err(e) = execute(uri, domain_name, "guest-file-close", Some(serde_json::json!({ "handle": handle }))) {
+        warn!("Failed to close {} (handle {}) in {}: {}", path, handle, domain_name, e);
+    }
+    result
+}
+
+fn cache_key(uri: &str, domain_name: &str) -> String {
+    format!("{} {}", uri, domain_name)
+}
+
+fn remember(uri: &str, domain_name: &str, connected: bool) {
+    PING_CACHE.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+        .insert(cache_key(uri, domain_name), (Instant::now(), connected));
+}
+
+pub fn parse_fsinfo(reply: &serde_json::Value) -> Vec<GuestFilesystem> {
+    reply.as_array()
+        .map(|filesystems| filesystems.as_slice())
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|filesystem| Some(GuestFilesystem {
+            mountpoint: filesystem["mountpoint"].as_str()?.to_string(),
+            fs_type: filesystem["type"].as_str().unwrap_or_default().to_string(),
+            total_bytes: filesystem["total-bytes"].as_u64(),
+            used_bytes: filesystem["used-bytes"].as_u64(),
+        }))
+        .collect()
+}
+
+pub fn parse_interfaces(reply: &serde_json::Value) -> Vec<GuestInterface> {
+    reply.as_array()
+        .map(|interfaces| interfaces.as_slice())
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|interface| {
+            let name = interface["name"].as_str()?.to_string();
+            if name == "lo" {
+                return None;
+            }
+            let ip_addresses = interface["ip-addresses"].as_array()
+                .map(|addresses| addresses.as_slice())
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|address| {
+                    let ip = address["ip-address"].as_str()?;
+                    Some(match address["prefix"].as_u64() {
+                        Some(prefix) => format!("{}/{}", ip, prefix),
+                        None => ip.to_string(),
+                    })
+                })
+                .collect();
+            Some(GuestInterface {
+                name,
+                mac_address: interface["hardware-address"].as_str().map(str::to_string),
+                ip_addresses,
+            })
+        })
+        .collect()
+}
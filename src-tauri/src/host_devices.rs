@@ -0,0 +1,658 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+use crate::errors::{KvmError, Result};
+use crate::storage_layout;
+use crate::tools::{self, Tool};
+
+/// A whole disk on the host, as seen by lsblk
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HostBlockDevice {
+    pub name: String,             // sda, nvme0n1, etc.
+    pub path: String,             // /dev/sda
+    pub size_bytes: u64,
+    pub model: Option<String>,
+    pub serial: Option<String>,
+    pub transport: Option<String>, // usb, sata, nvme, etc.
+    pub removable: bool,
+    pub mounted: bool,            // The disk or any of its partitions is mounted
+    pub is_system_disk: bool,     // Backs the host root filesystem
+}
+
+/// A network card that can be split into SR-IOV virtual functions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SriovNic {
+    pub interface: String,        // Host interface of the physical function
+    pub pci_address: String,      // 0000:03:00.0
+    pub driver: Option<String>,
+    pub total_vfs: u32,           // Maximum supported by the card
+    pub num_vfs: u32,             // Currently enabled
+    pub vfs: Vec<SriovVf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SriovVf {
+    pub index: u32,
+    pub pci_address: String,
+    pub driver: Option<String>,      // vfio-pci once libvirt has claimed it
+    pub mac: Option<String>,         // Only known while bound to a host driver
+    pub assigned_to: Option<String>, // Name of the VM using it, filled in by the VM manager
+}
+
+/// List SR-IOV capable NICs and their enabled virtual functions from sysfs
+pub fn list_sriov_nics() -> Result<Vec<SriovNic>> {
+    let mut nics = Vec::new();
+
+    for entry in fs::read_dir("/sys/class/net")? {
+        let entry = entry?;
+        let device_dir = entry.path().join("device");
+        let total_vfs = match read_sysfs_u32(&device_dir.join("sriov_totalvfs")) {
+            Some(total) if total > 0 => total,
+            _ => continue,
+        };
+
+        let mut vfs: Vec<SriovVf> = Vec::new();
+        for vf_entry in fs::read_dir(&device_dir)?.flatten() {
+            let file_name = vf_entry.file_name().to_string_lossy().to_string();
+            let index = match file_name.strip_prefix("virtfn").and_then(|i| i.parse().ok()) {
+                Some(index) => index,
+                None => continue,
+            };
+
+            let vf_dir = vf_entry.path();
+            if let Some(pci_address) = link_name(&vf_dir) {
+                vfs.push(SriovVf {
+                    index,
+                    pci_address,
+                    driver: link_name(&vf_dir.join("driver")),
+                    mac: first_interface_mac(&vf_dir),
+                    assigned_to: None,
+                });
+            }
+        }
+        vfs.sort_by_key(|vf| vf.index);
+
+        nics.push(SriovNic {
+            interface: entry.file_name().to_string_lossy().to_string(),
+            pci_address: link_name(&device_dir).unwrap_or_default(),
+            driver: link_name(&device_dir.join("driver")),
+            num_vfs: read_sysfs_u32(&device_dir.join("sriov_numvfs")).unwrap_or(0),
+            total_vfs,
+            vfs,
+        });
+    }
+
+    debug!("Found {} SR-IOV capable NICs", nics.len());
+    Ok(nics)
+}
+
+/// A PCI device on the host and the IOMMU group it shares with others
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HostPciDevice {
+    pub address: String,              // 0000:01:00.0
+    pub class: String,                // VGA compatible controller
+    pub description: String,          // Vendor and device name
+    pub vendor_id: String,            // 10de
+    pub device_id: String,            // 1b80
+    pub driver: Option<String>,       // vfio-pci while passed through
+    pub iommu_group: Option<u32>,
+    pub group_members: Vec<String>,   // Other devices in the same group
+    pub group_warning: Option<String>,
+    pub assigned_to: Option<String>,  // Name of the VM using it, filled in by the VM manager
+}
+
+/// Whether the kernel has the IOMMU on; without groups nothing can be passed through
+pub fn iommu_enabled() -> bool {
+    fs::read_dir("/sys/kernel/iommu_groups")
+        .map(|mut groups| groups.next().is_some())
+        .unwrap_or(false)
+}
+
+/// Every PCI device from `lspci -Dnn`, with its driver and IOMMU group from sysfs
+pub fn list_pci_devices() -> Result<Vec<HostPciDevice>> {
+    let output = tools::command(Tool::Lspci)?.arg("-Dnn").output()?;
+    if !output.status.success() {
+        return Err(KvmError::VmOperationFailed(format!(
+            "lspci failed: {}", String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+
+    let devices: Vec<HostPciDevice> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(parse_lspci_line)
+        .map(|mut device| {
+            let device_dir = Path::new("/sys/bus/pci/devices").join(&device.address);
+            device.driver = link_name(&device_dir.join("driver"));
+            device.iommu_group = link_name(&device_dir.join("iommu_group")).and_then(|group| group.parse().ok());
+            if let Some(group) = device.iommu_group {
+                device.group_members = iommu_group_devices(group)
+                    .into_iter()
+                    .filter(|member| member != &device.address)
+                    .collect();
+            }
+            device.group_warning = (!device.group_members.is_empty()).then(|| format!(
+                "IOMMU group {} also contains {}; a group can only be assigned as a whole, so these are detached from the host too",
+                device.iommu_group.unwrap_or_default(),
+                device.group_members.join(", ")
+            ));
+            device
+        })
+        .collect();
+
+    debug!("Found {} host PCI devices", devices.len());
+    Ok(devices)
+}
+
+/// Every IOMMU group on the host and the PCI devices in it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IommuGroups {
+    pub enabled: bool,
+    pub message: Option<String>, // Why there are no groups
+    pub groups: BTreeMap<u32, Vec<String>>,
+}
+
+/// Read /sys/kernel/iommu_groups/*/devices/*
+pub fn iommu_groups() -> IommuGroups {
+    let groups: BTreeMap<u32, Vec<String>> = fs::read_dir("/sys/kernel/iommu_groups")
+        .map(|entries| {
+            entries.flatten()
+                .filter_map(|entry| entry.file_name().to_string_lossy().parse::<u32>().ok())
+                .map(|group| (group, iommu_group_devices(group)))
+                .collect()
+        })
+        .unwrap_or_default();
+
+    if groups.is_empty() {
+        return IommuGroups {
+            enabled: false,
+            message: Some(
+                "The IOMMU is not enabled in the kernel; turn on VT-d/AMD-Vi in the firmware and boot with intel_iommu=on or amd_iommu=on".to_string()
+            ),
+            groups,
+        };
+    }
+    IommuGroups { enabled: true, message: None, groups }
+}
+
+/// PCI addresses of every device in IOMMU group `group`
+pub fn iommu_group_devices(group: u32) -> Vec<String> {
+    let mut members: Vec<String> = fs::read_dir(Path::new("/sys/kernel/iommu_groups").join(group.to_string()).join("devices"))
+        .map(|entries| entries.flatten().map(|entry| entry.file_name().to_string_lossy().to_string()).collect())
+        .unwrap_or_default();
+    members.sort();
+    members
+}
+
+// 0000:01:00.0 VGA compatible controller [0300]: NVIDIA Corporation GP104 [GeForce GTX 1080] [10de:1b80] (rev a1)
+fn parse_lspci_line(line: &str) -> Option<HostPciDevice> {
+    let (address, rest) = line.split_once(' ')?;
+    let (class, rest) = rest.split_once(": ")?;
+    let class = class.rsplit_once(" [").map(|(name, _)| name).unwrap_or(class);
+
+    let rest = rest.rsplit_once(" (rev ").map(|(device, _)| device).unwrap_or(rest);
+    let (description, ids) = rest.rsplit_once(" [")?;
+    let (vendor_id, device_id) = ids.trim_end_matches(']').split_once(':')?;
+
+    Some(HostPciDevice {
+        address: address.to_lowercase(),
+        class: class.to_string(),
+        description: description.to_string(),
+        vendor_id: vendor_id.to_string(),
+        device_id: device_id.to_string(),
+        driver: None,
+        iommu_group: None,
+        group_members: Vec::new(),
+        group_warning: None,
+        assigned_to: None,
+    })
+}
+
+/// Split a `0000:03:10.2` PCI address into domain, bus, slot and function
+pub fn parse_pci_address(address: &str) -> Result<(u32, u32, u32, u32)> {
+    let invalid = || KvmError::InvalidVmConfig(format!("Invalid PCI address: {}", address));
+
+    let (domain, rest) = address.split_once(':').ok_or_else(invalid)?;
+    let (bus, rest) = rest.split_once(':').ok_or_else(invalid)?;
+    let (slot, function) = rest.split_once('.').ok_or_else(invalid)?;
+
+    let hex = |value: &str| u32::from_str_radix(value, 16).map_err(|_| invalid());
+    Ok((hex(domain)?, hex(bus)?, hex(slot)?, hex(function)?))
+}
+
+fn read_sysfs_u32(path: &Path) -> Option<u32> {
+    fs::read_to_string(path).ok()?.trim().parse().ok()
+}
+
+/// Final component of a sysfs symlink target (PCI address or driver name)
+fn link_name(path: &Path) -> Option<String> {
+    fs::read_link(path)
+        .ok()?
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+}
+
+fn first_interface_mac(device_dir: &Path) -> Option<String> {
+    fs::read_dir(device_dir.join("net"))
+        .ok()?
+        .flatten()
+        .find_map(|iface| fs::read_to_string(iface.path().join("address")).ok())
+        .map(|mac| mac.trim().to_string())
+}
+
+/// One step of the passthrough readiness check
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReadinessCheck {
+    pub name: String,
+    pub passed: bool,
+    pub detail: String,
+    pub remediation: Option<String>,
+}
+
+/// Whether the host is set up for PCI passthrough
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PassthroughReadiness {
+    pub ready: bool,
+    pub iommu_groups: usize,
+    pub checks: Vec<ReadinessCheck>,
+}
+
+/// Check IOMMU and VFIO setup on the host before attempting passthrough
+pub fn check_passthrough_readiness() -> PassthroughReadiness {
+    let cpu_vendor = fs::read_to_string("/proc/cpuinfo")
+        .ok()
+        .and_then(|cpuinfo| {
+            cpuinfo.lines()
+                .find(|line| line.starts_with("vendor_id"))
+                .and_then(|line| line.split(':').nth(1))
+                .map(|vendor| vendor.trim().to_string())
+        })
+        .unwrap_or_default();
+    let iommu_option = if cpu_vendor == "AuthenticAMD" { "amd_iommu=on" } else { "intel_iommu=on" };
+
+    let iommu_groups = fs::read_dir("/sys/kernel/iommu_groups")
+        .map(|groups| groups.count())
+        .unwrap_or(0);
+
+    let cmdline = fs::read_to_string("/proc/cmdline").unwrap_or_default();
+    let cmdline_options: Vec<&str> = cmdline.split_whitespace()
+        .filter(|option| {
+            option.starts_with("intel_iommu=") || option.starts_with("amd_iommu=") || option.starts_with("iommu=")
+        })
+        .collect();
+
+    let vfio_pci_loaded = Path::new("/sys/module/vfio_pci").exists()
+        || Path::new("/sys/bus/pci/drivers/vfio-pci").exists();
+
+    let mut checks = vec![
+        ReadinessCheck {
+            name: "iommu_groups".to_string(),
+            passed: iommu_groups > 0,
+            detail: format!("{} IOMMU groups under /sys/kernel/iommu_groups", iommu_groups),
+            remediation: (iommu_groups == 0).then(|| format!(
+                "Enable VT-d/AMD-Vi in the firmware settings and add '{}' to the kernel command line",
+                iommu_option
+            )),
+        },
+        ReadinessCheck {
+            name: "kernel_cmdline".to_string(),
+            // AMD hosts enable the IOMMU by default, so groups alone are enough
+            passed: !cmdline_options.is_empty() || iommu_groups > 0,
+            detail: if cmdline_options.is_empty() {
+                "No IOMMU option on the kernel command line".to_string()
+            } else {
+                format!("Kernel command line has {}", cmdline_options.join(" "))
+            },
+            remediation: (cmdline_options.is_empty() && iommu_groups == 0).then(|| format!(
+                "Add '{} iommu=pt' to GRUB_CMDLINE_LINUX and regenerate the bootloader config",
+                iommu_option
+            )),
+        },
+        ReadinessCheck {
+            name: "vfio_pci".to_string(),
+            passed: vfio_pci_loaded,
+            detail: if vfio_pci_loaded {
+                "vfio-pci driver is loaded".to_string()
+            } else {
+                "vfio-pci driver is not loaded".to_string()
+            },
+            remediation: (!vfio_pci_loaded).then(|| {
+                "Run 'modprobe vfio-pci' and add vfio-pci to /etc/modules-load.d to load it at boot".to_string()
+            }),
+        },
+    ];
+
+    if cmdline_options.iter().any(|option| option.ends_with("=off")) {
+        checks.push(ReadinessCheck {
+            name: "iommu_disabled".to_string(),
+            passed: false,
+            detail: "The IOMMU is explicitly disabled on the kernel command line".to_string(),
+            remediation: Some(format!("Replace the '=off' option with '{}'", iommu_option)),
+        });
+    }
+
+    let ready = checks.iter().all(|check| check.passed);
+    debug!("Passthrough readiness: ready={}, iommu_groups={}", ready, iommu_groups);
+
+    PassthroughReadiness {
+        ready,
+        iommu_groups,
+        checks,
+    }
+}
+
+/// Whether one GPU (or other display device) can be handed to a guest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GpuPassthroughReadiness {
+    pub pci_address: String,
+    pub ready: bool,
+    pub driver: Option<String>,
+    pub iommu_group: Option<u32>,
+    pub group_members: Vec<HostPciDevice>,
+    pub checks: Vec<ReadinessCheck>, // The host-wide checks, then the device ones
+}
+
+pub fn is_display_device(device: &HostPciDevice) -> bool {
+    ["VGA compatible controller", "3D controller", "Display controller"].contains(&device.class.as_str())
+}
+
+/// Check the host and one GPU for passthrough: IOMMU and vfio-pci, the driver
+/// of the GPU and of everything sharing its IOMMU group (its HDMI audio
+/// function included), and whether a host framebuffer still holds it.
+pub fn check_gpu_passthrough_readiness(pci_address: &str) -> Result<GpuPassthroughReadiness> {
+    let pci_address = pci_address.to_lowercase();
+    let devices = list_pci_devices()?;
+    let device = devices.iter()
+        .find(|device| device.address == pci_address)
+        .ok_or_else(|| KvmError::InvalidVmConfig(format!("No PCI device {} on this host", pci_address)))?;
+    let group_members: Vec<HostPciDevice> = devices.iter()
+        .filter(|other| device.group_members.contains(&other.address))
+        .cloned()
+        .collect();
+
+    let mut checks = check_passthrough_readiness().checks;
+
+    let bound_to_vfio = device.driver.as_deref() == Some("vfio-pci");
+    checks.push(ReadinessCheck {
+        name: "device_driver".to_string(),
+        passed: bound_to_vfio,
+        detail: match &device.driver {
+            Some(driver) => format!("{} is bound to {}", pci_address, driver),
+            None => format!("{} has no driver bound", pci_address),
+        },
+        remediation: (!bound_to_vfio).then(|| format!(
+            "Add 'vfio-pci.ids={}:{}' to the kernel command line so vfio-pci claims the GPU before its host driver",
+            device.vendor_id, device.device_id
+        )),
+    });
+
+    // Bridges stay with the host; every other group member goes to the guest too
+    let blocking: Vec<&HostPciDevice> = group_members.iter()
+        .filter(|member| member.class != "PCI bridge" && member.driver.as_deref() != Some("vfio-pci"))
+        .collect();
+    checks.push(ReadinessCheck {
+        name: "iommu_group".to_string(),
+        passed: blocking.is_empty(),
+        detail: if group_members.is_empty() {
+            format!("{} is alone in its IOMMU group", pci_address)
+        } else {
+            format!(
+                "IOMMU group {} also contains {}",
+                device.iommu_group.unwrap_or_default(),
+                group_members.iter()
+                    .map(|member| format!("{} ({}, {})", member.address, member.class, member.driver.as_deref().unwrap_or("no driver")))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        },
+        remediation: (!blocking.is_empty()).then(|| format!(
+            "Bind {} to vfio-pci as well (add their vendor:device ids to vfio-pci.ids)",
+            blocking.iter().map(|member| member.address.as_str()).collect::<Vec<_>>().join(", ")
+        )),
+    });
+
+    let framebuffer = framebuffer_of(&pci_address);
+    checks.push(ReadinessCheck {
+        name: "framebuffer".to_string(),
+        passed: framebuffer.is_none(),
+        detail: match &framebuffer {
+            Some(fb) => format!("Host framebuffer {} is attached to {}", fb, pci_address),
+            None => "No host framebuffer is attached".to_string(),
+        },
+        remediation: framebuffer.is_some().then(|| {
+            "Add 'video=efifb:off' to the kernel command line, or move the host display to another GPU".to_string()
+        }),
+    });
+
+    let ready = checks.iter().all(|check| check.passed);
+    debug!("GPU passthrough readiness of {}: ready={}", pci_address, ready);
+
+    Ok(GpuPassthroughReadiness {
+        ready,
+        driver: device.driver.clone(),
+        iommu_group: device.iommu_group,
+        pci_address,
+        group_members,
+        checks,
+    })
+}
+
+// Framebuffer drivers register under /sys/class/graphics with a link to their PCI device
+fn framebuffer_of(pci_address: &str) -> Option<String> {
+    fs::read_dir("/sys/class/graphics")
+        .ok()?
+        .flatten()
+        .find(|fb| link_name(&fb.path().join("device")).as_deref() == Some(pci_address))
+        .map(|fb| fb.file_name().to_string_lossy().to_string())
+}
+
+/// List the whole disks attached to the host
+/// A USB device plugged into the host
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HostUsbDevice {
+    pub vendor_id: String,            // 046d
+    pub product_id: String,           // c52b
+    pub description: String,          // Manufacturer and product strings
+    pub address: UsbAddress,          // Changes when the device is replugged
+    pub port: String,                 // Physical port path, e.g. 1-2.3
+    pub assigned_to: Option<String>,  // Name of the VM using it, filled in by the VM manager
+}
+
+/// Bus and device number, as lsusb shows them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsbAddress {
+    pub bus: u32,
+    pub device: u32,
+}
+
+// bDeviceClass of hubs, which can't be passed through usefully
+const USB_CLASS_HUB: &str = "09";
+
+/// Every USB device from sysfs, leaving out hubs (including root hubs)
+pub fn list_usb_devices() -> Result<Vec<HostUsbDevice>> {
+    let mut devices = Vec::new();
+    for entry in fs::read_dir("/sys/bus/usb/devices")?.filter_map(|entry| entry.ok()) {
+        let port = entry.file_name().to_string_lossy().to_string();
+        // Interfaces are named like 1-2:1.0
+        if port.contains(':') {
+            continue;
+        }
+        let dir = entry.path();
+        let read = |name: &str| fs::read_to_string(dir.join(name)).ok().map(|value| value.trim().to_string());
+        if read("bDeviceClass").as_deref() == Some(USB_CLASS_HUB) {
+            continue;
+        }
+        let (Some(vendor_id), Some(product_id), Some(bus), Some(device)) = (
+            read("idVendor"),
+            read("idProduct"),
+            read_sysfs_u32(&dir.join("busnum")),
+            read_sysfs_u32(&dir.join("devnum")),
+        ) else {
+            continue;
+        };
+
+        let description = [read("manufacturer"), read("product")]
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>()
+            .join(" ");
+        devices.push(HostUsbDevice {
+            description: if description.is_empty() { format!("{}:{}", vendor_id, product_id) } else { description },
+            vendor_id,
+            product_id,
+            address: UsbAddress { bus, device },
+            port,
+            assigned_to: None,
+        });
+    }
+
+    devices.sort_by_key(|device| (device.address.bus, device.address.device));
+    debug!("Found {} host USB devices", devices.len());
+    Ok(devices)
+}
+
+/// Normalise a USB vendor or product ID ("0x046D", "046d") to four hex digits
+pub fn parse_usb_id(id: &str) -> Result<String> {
+    let digits = id.trim().trim_start_matches("0x").trim_start_matches("0X");
+    u16::from_str_radix(digits, 16)
+        .map(|id| format!("{:04x}", id))
+        .map_err(|_| KvmError::InvalidVmConfig(format!("Invalid USB ID: {}", id)))
+}
+
+pub fn list_block_devices() -> Result<Vec<HostBlockDevice>> {
+    let output = tools::command(Tool::Lsblk)?
+        .args(["-J", "-b", "-d", "-o", "NAME,PATH,TYPE,SIZE,MODEL,SERIAL,TRAN,RM"])
+        .output()?;
+
+    if !output.status.success() {
+        return Err(KvmError::StorageOperationFailed(format!(
+            "lsblk failed: {}", String::from_utf8_lossy(&output.stderr)
+        )));
+    }
+
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    let system_disks = storage_layout::resolve_placement("/").physical_devices;
+
+    let mut devices = Vec::new();
+    for device in json["blockdevices"].as_array().cloned().unwrap_or_default() {
+        if device["type"].as_str() != Some("disk") {
+            continue;
+        }
+
+        let name = device["name"].as_str().unwrap_or_default().to_string();
+        let path = device["path"].as_str()
+            .map(|s| s.to_string())
+            .unwrap_or_else(|| format!("/dev/{}", name));
+
+        devices.push(HostBlockDevice {
+            is_system_disk: system_disks.contains(&name),
+            mounted: is_mounted(&path),
+            size_bytes: json_u64(&device["size"]),
+            model: json_string(&device["model"]),
+            serial: json_string(&device["serial"]),
+            transport: json_string(&device["tran"]),
+            removable: device["rm"].as_bool().unwrap_or(false) || device["rm"].as_str() == Some("1"),
+            name,
+            path,
+        });
+    }
+
+    debug!("Found {} host block devices", devices.len());
+    Ok(devices)
+}
+
+/// Look up a disk by path and make sure it is safe to hand to a guest
+pub fn validate_for_passthrough(device_path: &str) -> Result<HostBlockDevice> {
+    let device = list_block_devices()?
+        .into_iter()
+        .find(|d| d.path == device_path)
+        .ok_or_else(|| KvmError::InvalidVmConfig(format!("{} is not a whole disk on this host", device_path)))?;
+
+    if device.is_system_disk {
+        return Err(KvmError::PermissionDenied(format!("{} holds the host operating system", device_path)));
+    }
+
+    if device.mounted {
+        return Err(KvmError::InvalidVmConfig(format!("{} or one of its partitions is mounted on the host", device_path)));
+    }
+
+    Ok(device)
+}
+
+fn is_mounted(device_path: &str) -> bool {
+    let mut command = match tools::try_command(Tool::Lsblk) {
+        Some(command) => command,
+        None => return false,
+    };
+
+    match command.args(["-n", "-r", "-o", "MOUNTPOINT", device_path]).output() {
+        Ok(output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout).lines().any(|line| !line.trim().is_empty())
+        }
+        Ok(_) | Err(_) => {
+            // Err on the side of not handing a possibly mounted disk to a guest
+            warn!("Could not determine mount state of {}", device_path);
+            true
+        }
+    }
+}
+
+/// Free hugepages of `page_size_kib` (the host default size when None),
+/// returned as `(page_size_kib, free_pages)`
+pub fn free_hugepages(page_size_kib: Option<u64>) -> Result<(u64, u64)> {
+    let meminfo = fs::read_to_string("/proc/meminfo")?;
+    let meminfo_value = |key: &str| {
+        meminfo.lines()
+            .find(|line| line.starts_with(key))
+            .and_then(|line| line.split_whitespace().nth(1))
+            .and_then(|value| value.parse::<u64>().ok())
+    };
+
+    let default_size = meminfo_value("Hugepagesize:").unwrap_or(0);
+    let page_size = page_size_kib.unwrap_or(default_size);
+
+    if page_size == default_size && default_size > 0 {
+        return Ok((page_size, meminfo_value("HugePages_Free:").unwrap_or(0)));
+    }
+
+    // /proc/meminfo only covers the default size; other sizes live in sysfs
+    let free = Path::new("/sys/kernel/mm/hugepages")
+        .join(format!("hugepages-{}kB", page_size))
+        .join("free_hugepages");
+    match fs::read_to_string(&free) {
+        Ok(contents) => Ok((page_size, contents.trim().parse().unwrap_or(0))),
+        Err(_) => Err(KvmError::InsufficientResources(format!(
+            "The host does not support {} KiB hugepages", page_size
+        ))),
+    }
+}
+
+/// Fail with `InsufficientResources` unless enough hugepages are free to back `memory_mb`
+pub fn check_hugepages(memory_mb: u64, page_size_kib: Option<u64>) -> Result<()> {
+    let (page_size, free) = free_hugepages(page_size_kib)?;
+    let needed = (memory_mb * 1024).div_ceil(page_size.max(1));
+    debug!("Hugepages: need {} x {} KiB, {} free", needed, page_size, free);
+
+    if needed > free {
+        return Err(KvmError::InsufficientResources(format!(
+            "{} MB of memory needs {} free {} KiB hugepages but only {} are free; reserve more via vm.nr_hugepages",
+            memory_mb, needed, page_size, free
+        )));
+    }
+    Ok(())
+}
+
+fn json_string(value: &serde_json::Value) -> Option<String> {
+    value.as_str()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+// Older lsblk releases emit numbers as strings
+fn json_u64(value: &serde_json::Value) -> u64 {
+    value.as_u64()
+        .or_else(|| value.as_str().and_then(|s| s.parse().ok()))
+        .unwrap_or(0)
+}
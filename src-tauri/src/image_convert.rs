@@ -0,0 +1,94 @@
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::io::AsyncReadExt;
+use tracing::{info, warn};
+
+use crate::errors::{KvmError, Result};
+use crate::tools::{self, Tool};
+
+pub const SUPPORTED_FORMATS: [&str; 4] = ["qcow2", "raw", "vmdk", "vdi"];
+
+/// Convert `source` to `target_format` at `dest`, or over `source` itself when
+/// `dest` is None (via a temporary file renamed into place, so a failed
+/// conversion leaves the original untouched). `progress` gets the percentage
+/// qemu-img reports as it goes. Returns the path of the converted image.
+pub async fn convert(source: &Path, dest: Option<&Path>, target_format: &str, progress: impl Fn(f64)) -> Result<PathBuf> {
+    if !SUPPORTED_FORMATS.contains(&target_format) {
+        return Err(KvmError::StorageOperationFailed(format!(
+            "Unsupported image format {}; use one of {}", target_format, SUPPORTED_FORMATS.join(", ")
+        )));
+    }
+    if !source.is_file() {
+        return Err(KvmError::StorageOperationFailed(format!("Image not found: {}", source.display())));
+    }
+    if let Some(dest) = dest.filter(|dest| dest.exists()) {
+        return Err(KvmError::StorageOperationFailed(format!("{} already exists", dest.display())));
+    }
+    let mut command = tools::command(Tool::QemuImg)?;
+
+    // Same directory as the source so the final rename never crosses filesystems
+    let output = match dest {
+        Some(dest) => dest.to_path_buf(),
+        None => {
+            let file_name = source.file_name().unwrap_or_default().to_string_lossy();
+            source.with_file_name(format!(".{}.converting", file_name))
+        }
+    };
+    info!("Converting {} to {} at {}", source.display(), target_format, output.display());
+
+    command
+        .args(["convert", "-p", "-O", target_format])
+        .arg(source)
+        .arg(&output)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let mut child = tokio::process::Command::from(command)
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| KvmError::StorageOperationFailed(format!("Failed to run qemu-img: {}", e)))?;
+
+    // -p redraws "    (42.00/100%)" with carriage returns
+    if let Some(mut stdout) = child.stdout.take() {
+        let mut buffer = [0u8; 256];
+        let mut line = String::new();
+        while let Ok(read) = stdout.read(&mut buffer).await {
+            if read == 0 {
+                break;
+            }
+            line.push_str(&String::from_utf8_lossy(&buffer[..read]));
+            while let Some(end) = line.find(['\r', '\n']) {
+                if let Some(percent) = parse_progress(&line[..end]) {
+                    progress(percent);
+                }
+                line.drain(..=end);
+            }
+        }
+    }
+
+    let result = child.wait_with_output().await
+        .map_err(|e| KvmError::StorageOperationFailed(format!("Failed to run qemu-img: {}", e)))?;
+    if !result.status.success() {
+        let _ = std::fs::remove_file(&output);
+        let error = String::from_utf8_lossy(&result.stderr);
+        warn!("Converting {} failed: {}", source.display(), error.trim());
+        return Err(KvmError::StorageOperationFailed(format!("Image conversion failed: {}", error.trim())));
+    }
+
+    if dest.is_none() {
+        std::fs::rename(&output, source).map_err(|e| {
+            let _ = std::fs::remove_file(&output);
+            KvmError::StorageOperationFailed(format!("Failed to replace {}: {}", source.display(), e))
+        })?;
+        return Ok(source.to_path_buf());
+    }
+    Ok(output)
+}
+
+fn parse_progress(line: &str) -> Option<f64> {
+    line.trim()
+        .strip_prefix('(')?
+        .split('/')
+        .next()?
+        .parse()
+        .ok()
+}
@@ -0,0 +1,130 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::{debug, info, warn};
+
+use crate::errors::{KvmError, Result};
+
+// How often pending files are re-checked, and how many unchanged checks in a row
+// count as "finished copying"
+const SETTLE_INTERVAL: Duration = Duration::from_secs(2);
+const SETTLE_CHECKS: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DetectedKind {
+    Image,
+    Xml,
+}
+
+/// A new disk image or domain XML that has finished being written
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DetectedFile {
+    pub path: String,
+    pub kind: DetectedKind,
+    pub size_bytes: u64,
+}
+
+struct Pending {
+    size: u64,
+    unchanged_checks: u32,
+}
+
+fn detected_kind(path: &Path) -> Option<DetectedKind> {
+    match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+        "qcow2" => Some(DetectedKind::Image),
+        "xml" => Some(DetectedKind::Xml),
+        _ => None,
+    }
+}
+
+/// Watch `directories` for new images and XML files.
+///
+/// Files are only reported once their size has stopped changing, so an image
+/// that is still being copied in is not announced half-written. The watcher
+/// lives as long as the returned receiver.
+pub fn spawn(directories: Vec<PathBuf>) -> Result<mpsc::UnboundedReceiver<DetectedFile>> {
+    let (event_tx, mut event_rx) = mpsc::unbounded_channel::<PathBuf>();
+    let (detected_tx, detected_rx) = mpsc::unbounded_channel();
+
+    let mut watcher: RecommendedWatcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+        match event {
+            Ok(event) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+                for path in event.paths {
+                    let _ = event_tx.send(path);
+                }
+            }
+            Ok(_) => {}
+            Err(e) => warn!("Import directory watch error: {}", e),
+        }
+    })
+    .map_err(|e| KvmError::Unknown(format!("Failed to create directory watcher: {}", e)))?;
+
+    for directory in &directories {
+        match watcher.watch(directory, RecursiveMode::NonRecursive) {
+            Ok(()) => info!("Watching {} for new images", directory.display()),
+            Err(e) => warn!("Cannot watch {}: {}", directory.display(), e),
+        }
+    }
+
+    tokio::spawn(async move {
+        // Keep the watcher alive for the lifetime of the task
+        let _watcher = watcher;
+        let mut pending: HashMap<PathBuf, Pending> = HashMap::new();
+        let mut announced: HashSet<PathBuf> = HashSet::new();
+        let mut settle = tokio::time::interval(SETTLE_INTERVAL);
+
+        loop {
+            tokio::select! {
+                path = event_rx.recv() => {
+                    let Some(path) = path else { break };
+                    if detected_kind(&path).is_some() && !announced.contains(&path) {
+                        debug!("Change detected in {}", path.display());
+                        pending.entry(path).or_insert(Pending { size: 0, unchanged_checks: 0 });
+                    }
+                }
+                _ = settle.tick() => {
+                    let mut settled = Vec::new();
+                    pending.retain(|path, state| {
+                        let size = match std::fs::metadata(path) {
+                            Ok(metadata) => metadata.len(),
+                            Err(_) => return false, // Removed or renamed away
+                        };
+
+                        if size > 0 && size == state.size {
+                            state.unchanged_checks += 1;
+                        } else {
+                            state.size = size;
+                            state.unchanged_checks = 0;
+                        }
+
+                        if state.unchanged_checks >= SETTLE_CHECKS {
+                            settled.push((path.clone(), size));
+                            return false;
+                        }
+                        true
+                    });
+
+                    for (path, size_bytes) in settled {
+                        let Some(kind) = detected_kind(&path) else { continue };
+                        info!("New {:?} detected: {}", kind, path.display());
+                        announced.insert(path.clone());
+                        let detected = DetectedFile {
+                            path: path.display().to_string(),
+                            kind,
+                            size_bytes,
+                        };
+                        if detected_tx.send(detected).is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+    });
+
+    Ok(detected_rx)
+}
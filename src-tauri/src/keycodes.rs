@@ -0,0 +1,95 @@
+use crate::errors::{KvmError, Result};
+
+// libvirt rejects more keys than this in one send-key call
+pub const MAX_KEYS: usize = 16;
+
+/// Key combinations the UI offers as buttons, by name
+pub const COMBOS: [(&str, &[&str]); 8] = [
+    ("ctrl-alt-del", &["ctrl", "alt", "delete"]),
+    ("ctrl-alt-backspace", &["ctrl", "alt", "backspace"]),
+    ("ctrl-alt-f1", &["ctrl", "alt", "f1"]),
+    ("ctrl-alt-f2", &["ctrl", "alt", "f2"]),
+    ("ctrl-alt-f3", &["ctrl", "alt", "f3"]),
+    ("ctrl-alt-f4", &["ctrl", "alt", "f4"]),
+    ("ctrl-alt-f5", &["ctrl", "alt", "f5"]),
+    ("ctrl-alt-f6", &["ctrl", "alt", "f6"]),
+];
+
+const LETTERS: &str = "qwertyuiopasdfghjklzxcvbnm";
+// Linux keycodes of the letters above, which follow the keyboard's rows
+const LETTER_CODES: [u32; 26] = [
+    16, 17, 18, 19, 20, 21, 22, 23, 24, 25,
+    30, 31, 32, 33, 34, 35, 36, 37, 38,
+    44, 45, 46, 47, 48, 49, 50,
+];
+
+/// The Linux input keycode (linux/input-event-codes.h) for a key name such
+/// as "ctrl", "f2", "a" or "delete"
+pub fn linux_keycode(name: &str) -> Option<u32> {
+    let name = name.trim().to_ascii_lowercase();
+    if let Some(position) = LETTERS.find(name.as_str()).filter(|_| name.len() == 1) {
+        return Some(LETTER_CODES[position]);
+    }
+    if let Some(function) = name.strip_prefix('f').and_then(|number| number.parse::<u32>().ok()) {
+        return match function {
+            1..=10 => Some(58 + function),
+            11 => Some(87),
+            12 => Some(88),
+            _ => None,
+        };
+    }
+    Some(match name.as_str() {
+        "1" => 2, "2" => 3, "3" => 4, "4" => 5, "5" => 6,
+        "6" => 7, "7" => 8, "8" => 9, "9" => 10, "0" => 11,
+        "esc" | "escape" => 1,
+        "minus" => 12,
+        "equal" => 13,
+        "backspace" => 14,
+        "tab" => 15,
+        "enter" | "return" => 28,
+        "ctrl" | "leftctrl" | "control" => 29,
+        "shift" | "leftshift" => 42,
+        "alt" | "leftalt" => 56,
+        "space" => 57,
+        "capslock" => 58,
+        "numlock" => 69,
+        "scrolllock" => 70,
+        "rightctrl" => 97,
+        "sysrq" | "printscreen" => 99,
+        "rightalt" | "altgr" => 100,
+        "home" => 102,
+        "up" => 103,
+        "pageup" => 104,
+        "left" => 105,
+        "right" => 106,
+        "end" => 107,
+        "down" => 108,
+        "pagedown" => 109,
+        "insert" => 110,
+        "delete" | "del" => 111,
+        "meta" | "super" | "win" | "leftmeta" => 125,
+        "rightmeta" => 126,
+        "menu" => 127,
+        _ => return None,
+    })
+}
+
+/// Keycodes for a list of key names, pressed together. A single entry may
+/// also name one of the predefined `COMBOS`.
+pub fn resolve(keys: &[String]) -> Result<Vec<u32>> {
+    if let [combo] = keys {
+        if let Some((_, combo_keys)) = COMBOS.iter().find(|(name, _)| name.eq_ignore_ascii_case(combo.trim())) {
+            return Ok(combo_keys.iter().filter_map(|key| linux_keycode(key)).collect());
+        }
+    }
+    if keys.is_empty() {
+        return Err(KvmError::VmOperationFailed("No keys to send".to_string()));
+    }
+    if keys.len() > MAX_KEYS {
+        return Err(KvmError::VmOperationFailed(format!("At most {} keys can be pressed at once", MAX_KEYS)));
+    }
+
+    keys.iter()
+        .map(|key| linux_keycode(key).ok_or_else(|| KvmError::VmOperationFailed(format!("Unknown key name: {}", key))))
+        .collect()
+}
@@ -0,0 +1,1757 @@
+pub mod vm_manager;
+pub mod storage;
+pub mod network;
+pub mod monitoring;
+pub mod system_monitor;
+pub mod types;
+pub mod errors;
+pub mod xml_parser;
+pub mod formatting;
+pub mod audit;
+pub mod recovery;
+pub mod settings;
+pub mod storage_layout;
+pub mod tools;
+pub mod host_devices;
+pub mod secrets;
+pub mod quotas;
+pub mod refresh;
+pub mod logging;
+pub mod image_watch;
+pub mod batch_import;
+pub mod cloud_init;
+pub mod guest_agent;
+pub mod firmware;
+pub mod metrics_store;
+pub mod ova;
+pub mod image_convert;
+pub mod connection_watch;
+pub mod watchdog_events;
+pub mod screenshot;
+pub mod keycodes;
+pub mod vnc_proxy;
+
+use tracing::{info, error, warn};
+use std::sync::Arc;
+use tauri::{Emitter, Manager};
+use tokio::sync::RwLock;
+
+use vm_manager::VmManager;
+use tools::Tool;
+use types::*;
+
+type AppState = Arc<RwLock<VmManager>>;
+type MonitoringState = Arc<RwLock<monitoring::MonitoringService>>;
+
+#[tauri::command]
+async fn get_vms(state: tauri::State<'_, AppState>) -> Result<Vec<VirtualMachine>, String> {
+    let manager = state.read().await;
+    manager.list_vms().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn create_vm(
+    state: tauri::State<'_, AppState>,
+    config: VmConfig,
+    preset: Option<String>,
+    recovery: Option<RecoveryOptions>,
+) -> Result<String, String> {
+    let config = settings::resolve_vm_config(config, preset.as_deref()).map_err(String::from)?;
+    let mut manager = state.write().await;
+    manager.create_vm(config, &recovery.unwrap_or_default()).await.map_err(String::from)
+}
+
+#[tauri::command]
+async fn preview_vm_xml(
+    state: tauri::State<'_, AppState>,
+    config: VmConfig,
+    preset: Option<String>,
+) -> Result<String, String> {
+    let config = settings::resolve_vm_config(config, preset.as_deref()).map_err(String::from)?;
+    let manager = state.read().await;
+    manager.preview_vm_xml(&config).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn list_hardware_presets() -> Result<Vec<HardwarePreset>, String> {
+    let settings = settings::Settings::load().map_err(|e| e.to_string())?;
+    Ok(settings.hardware_presets)
+}
+
+#[tauri::command]
+async fn save_hardware_preset(preset: HardwarePreset) -> Result<(), String> {
+    let mut settings = settings::Settings::load().map_err(|e| e.to_string())?;
+    settings.upsert_preset(preset).map_err(|e| e.to_string())?;
+    settings.save().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn delete_hardware_preset(name: String) -> Result<(), String> {
+    let mut settings = settings::Settings::load().map_err(|e| e.to_string())?;
+    settings.remove_preset(&name).map_err(|e| e.to_string())?;
+    settings.save().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn start_vm(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    recovery: Option<RecoveryOptions>,
+) -> Result<(), String> {
+    let manager = state.read().await;
+    manager.start_vm(&vm_id, &recovery.unwrap_or_default()).await.map_err(String::from)
+}
+
+#[tauri::command]
+async fn reboot_vm(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    force: bool,
+) -> Result<VmState, String> {
+    let mut manager = state.write().await;
+    manager.reboot_vm(&vm_id, force).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn reset_vm(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+) -> Result<VmState, String> {
+    let mut manager = state.write().await;
+    manager.reset_vm(&vm_id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn save_vm(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+) -> Result<(), String> {
+    let mut manager = state.write().await;
+    manager.save_vm(&vm_id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn restore_saved_vm(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+) -> Result<(), String> {
+    let mut manager = state.write().await;
+    manager.restore_saved_vm(&vm_id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn has_managed_save(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+) -> Result<bool, String> {
+    let manager = state.read().await;
+    manager.has_managed_save(&vm_id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn discard_saved_state(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+) -> Result<(), String> {
+    let mut manager = state.write().await;
+    manager.discard_saved_state(&vm_id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn pause_vm(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+) -> Result<(), String> {
+    let mut manager = state.write().await;
+    manager.pause_vm(&vm_id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn resume_vm(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+) -> Result<(), String> {
+    let mut manager = state.write().await;
+    manager.resume_vm(&vm_id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn stop_vm(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    timeout_secs: Option<u64>,
+) -> Result<(), String> {
+    let domain = state.read().await.stop_vm(&vm_id).await.map_err(String::from)?;
+    // Wait without the manager lock so other commands aren't stuck behind the guest
+    match (domain, timeout_secs) {
+        (Some(domain), Some(timeout_secs)) => {
+            VmManager::wait_for_shutdown(&domain, &vm_id, timeout_secs).await.map_err(String::from)
+        }
+        _ => Ok(()),
+    }
+}
+
+#[tauri::command]
+async fn force_stop_vm(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+) -> Result<(), String> {
+    let manager = state.read().await;
+    manager.force_stop_vm(&vm_id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn delete_vm(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+) -> Result<(), String> {
+    let mut manager = state.write().await;
+    manager.delete_vm(&vm_id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_vm_stats(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+) -> Result<VmStats, String> {
+    let manager = state.read().await;
+    manager.get_vm_stats(&vm_id).await.map_err(|e| e.to_string())
+}
+
+/// Recorded points of one metric (e.g. `cpu_usage`) over the last
+/// `duration_secs`; `vm_id` is a VM's UUID or `host`
+#[tauri::command]
+async fn get_metric_history(
+    state: tauri::State<'_, MonitoringState>,
+    vm_id: String,
+    metric_type: String,
+    duration_secs: u64,
+) -> Result<Vec<monitoring::MetricPoint>, String> {
+    let service = state.read().await;
+    Ok(service.get_metric_history(&vm_id, &metric_type, std::time::Duration::from_secs(duration_secs)))
+}
+
+#[tauri::command]
+async fn ping_guest_agent(state: tauri::State<'_, AppState>, vm_id: String) -> Result<guest_agent::AgentStatus, String> {
+    let manager = state.read().await;
+    manager.ping_guest_agent(&vm_id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn guest_exec(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    command: String,
+    args: Vec<String>,
+    capture_output: bool,
+    timeout_secs: u64,
+) -> Result<guest_agent::GuestExecResult, String> {
+    // Only hold the lock to find the VM; the command may run for minutes
+    let target = state.read().await
+        .guest_agent_target(&vm_id, "run a command in")
+        .map_err(|e| e.to_string())?;
+    VmManager::guest_exec(&target, &command, &args, capture_output, timeout_secs).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn guest_read_file(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    path: String,
+    max_bytes: Option<usize>,
+) -> Result<guest_agent::GuestFileContents, String> {
+    let target = state.read().await
+        .guest_agent_target(&vm_id, "read files in")
+        .map_err(|e| e.to_string())?;
+    VmManager::guest_read_file(&target, &path, max_bytes).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn guest_write_file(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    path: String,
+    contents_base64: String,
+    append: bool,
+) -> Result<usize, String> {
+    let target = state.read().await
+        .guest_agent_target(&vm_id, "write files in")
+        .map_err(|e| e.to_string())?;
+    VmManager::guest_write_file(&target, &path, &contents_base64, append).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_guest_info(state: tauri::State<'_, AppState>, vm_id: String) -> Result<guest_agent::GuestInfo, String> {
+    let manager = state.read().await;
+    manager.get_guest_info(&vm_id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_active_graphics(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+) -> Result<Vec<GraphicsDevice>, String> {
+    let manager = state.read().await;
+    manager.get_active_graphics(&vm_id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_vm_display_info(state: tauri::State<'_, AppState>, vm_id: String) -> Result<DisplayInfo, String> {
+    let manager = state.read().await;
+    manager.get_vm_display_info(&vm_id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_spice_connection(state: tauri::State<'_, AppState>, vm_id: String) -> Result<DisplayInfo, String> {
+    let manager = state.read().await;
+    manager.get_spice_connection(&vm_id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_graphics_password(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    password: Option<String>,
+    expire_seconds: Option<u32>,
+) -> Result<String, String> {
+    let manager = state.read().await;
+    manager.set_graphics_password(&vm_id, password, expire_seconds).await.map_err(|e| e.to_string())
+}
+
+/// Start (or reuse) a local WebSocket proxy to the VM's VNC display and
+/// return its ws:// URL for noVNC
+#[tauri::command]
+async fn start_vnc_proxy(app: tauri::AppHandle, vm_id: String) -> Result<String, String> {
+    vnc_proxy::start(app, &vm_id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn stop_vnc_proxy(vm_id: String) -> Result<bool, String> {
+    Ok(vnc_proxy::stop(&vm_id))
+}
+
+#[tauri::command]
+async fn take_vm_screenshot(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    max_width: Option<u32>,
+) -> Result<VmScreenshot, String> {
+    let target = state.read().await
+        .screenshot_target(&vm_id)
+        .map_err(|e| e.to_string())?;
+    VmManager::take_vm_screenshot(&target, max_width).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn send_keys(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    keys: Vec<String>,
+    hold_ms: Option<u32>,
+) -> Result<(), String> {
+    let manager = state.read().await;
+    manager.send_keys(&vm_id, &keys, hold_ms).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn analyze_vm_storage_layout(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+) -> Result<StorageLayoutReport, String> {
+    let manager = state.read().await;
+    manager.analyze_vm_storage_layout(&vm_id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_status_summary(state: tauri::State<'_, AppState>) -> Result<StatusSummary, String> {
+    let manager = state.read().await;
+    Ok(manager.get_status_summary())
+}
+
+#[tauri::command]
+async fn get_quota_usage(state: tauri::State<'_, AppState>) -> Result<QuotaUsage, String> {
+    let manager = state.read().await;
+    manager.get_quota_usage().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_nested_virtualization(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    enabled: bool,
+) -> Result<(), String> {
+    let manager = state.read().await;
+    manager.set_nested_virtualization(&vm_id, enabled).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_vm_xml(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+) -> Result<String, String> {
+    let manager = state.read().await;
+    manager.get_vm_xml(&vm_id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn update_vm_xml(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    xml: String,
+) -> Result<VirtualMachine, String> {
+    let mut manager = state.write().await;
+    manager.update_vm_xml(&vm_id, &xml).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_effective_config(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+) -> Result<EffectiveVmConfig, String> {
+    let manager = state.read().await;
+    manager.get_effective_config(&vm_id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn enable_spice_agent(state: tauri::State<'_, AppState>, vm_id: String) -> Result<bool, String> {
+    let mut manager = state.write().await;
+    manager.enable_spice_agent(&vm_id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn clone_vm(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    new_name: String,
+    clone_storage: Option<bool>,
+    force: Option<bool>,
+) -> Result<CloneResult, String> {
+    let mut manager = state.write().await;
+    manager.clone_vm(&vm_id, &new_name, clone_storage.unwrap_or(true), force.unwrap_or(false))
+        .await
+        .map_err(String::from)
+}
+
+#[tauri::command]
+async fn clone_vm_linked(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    new_name: String,
+) -> Result<CloneResult, String> {
+    let mut manager = state.write().await;
+    manager.clone_vm_linked(&vm_id, &new_name).await.map_err(String::from)
+}
+
+#[tauri::command]
+async fn migrate_vm(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    target_uri: String,
+    live: bool,
+) -> Result<String, String> {
+    let mut manager = state.write().await;
+    manager.migrate_vm(&vm_id, &target_uri, live).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_migration_status(
+    state: tauri::State<'_, AppState>,
+    task_id: String,
+) -> Result<MigrationTask, String> {
+    let manager = state.read().await;
+    manager.get_migration_status(&task_id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_vm_autostart(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    enabled: bool,
+) -> Result<(), String> {
+    let mut manager = state.write().await;
+    manager.set_vm_autostart(&vm_id, enabled).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_vm_memory(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    memory_mb: u64,
+    live: bool,
+) -> Result<ChangeScope, String> {
+    let mut manager = state.write().await;
+    manager.set_vm_memory(&vm_id, memory_mb, live).await.map_err(String::from)
+}
+
+#[tauri::command]
+async fn set_vm_memory_live(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    memory_mb: u64,
+) -> Result<(), String> {
+    let mut manager = state.write().await;
+    manager.set_vm_memory_live(&vm_id, memory_mb).await.map_err(String::from)
+}
+
+#[tauri::command]
+async fn set_vm_vcpus(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    count: u32,
+    live: bool,
+) -> Result<VcpuChange, String> {
+    let mut manager = state.write().await;
+    manager.set_vm_vcpus(&vm_id, count, live).await.map_err(String::from)
+}
+
+#[tauri::command]
+async fn set_cpu_topology(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    topology: Option<CpuTopology>,
+) -> Result<(), String> {
+    let mut manager = state.write().await;
+    manager.set_cpu_topology(&vm_id, topology).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_cpu_pinning(state: tauri::State<'_, AppState>, vm_id: String) -> Result<Vec<VcpuPin>, String> {
+    let manager = state.read().await;
+    manager.get_cpu_pinning(&vm_id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_cpu_pinning(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    pins: Vec<VcpuPin>,
+) -> Result<(), String> {
+    let mut manager = state.write().await;
+    manager.set_cpu_pinning(&vm_id, pins).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_vm_description(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    description: Option<String>,
+) -> Result<(), String> {
+    let mut manager = state.write().await;
+    manager.set_vm_description(&vm_id, description).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_vm_tags(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    tags: Vec<String>,
+) -> Result<(), String> {
+    let mut manager = state.write().await;
+    manager.set_vm_tags(&vm_id, tags).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn add_log_console(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    log_path: String,
+) -> Result<u32, String> {
+    let manager = state.read().await;
+    manager.add_log_console(&vm_id, &log_path).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_log_console_tail(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    lines: usize,
+) -> Result<Vec<String>, String> {
+    let manager = state.read().await;
+    manager.get_log_console_tail(&vm_id, lines).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn list_host_block_devices() -> Result<Vec<host_devices::HostBlockDevice>, String> {
+    host_devices::list_block_devices().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn create_disk_secret(
+    state: tauri::State<'_, AppState>,
+    usage_id: String,
+    passphrase: String,
+) -> Result<String, String> {
+    let manager = state.read().await;
+    manager.create_disk_secret(&usage_id, &passphrase).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn list_secrets(state: tauri::State<'_, AppState>) -> Result<Vec<secrets::SecretInfo>, String> {
+    let manager = state.read().await;
+    manager.list_secrets().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn delete_secret(state: tauri::State<'_, AppState>, uuid: String) -> Result<(), String> {
+    let manager = state.read().await;
+    manager.delete_secret(&uuid).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_iommu_groups() -> Result<host_devices::IommuGroups, String> {
+    Ok(host_devices::iommu_groups())
+}
+
+#[tauri::command]
+async fn check_passthrough_readiness() -> Result<host_devices::PassthroughReadiness, String> {
+    Ok(host_devices::check_passthrough_readiness())
+}
+
+#[tauri::command]
+async fn check_gpu_passthrough_readiness(pci_address: String) -> Result<host_devices::GpuPassthroughReadiness, String> {
+    host_devices::check_gpu_passthrough_readiness(&pci_address).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn list_host_pci_devices(state: tauri::State<'_, AppState>) -> Result<Vec<host_devices::HostPciDevice>, String> {
+    let manager = state.read().await;
+    manager.list_host_pci_devices().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn attach_pci_device(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    pci_address: String,
+    live: bool,
+) -> Result<host_devices::HostPciDevice, String> {
+    let manager = state.read().await;
+    manager.attach_pci_device(&vm_id, &pci_address, live).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn detach_pci_device(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    pci_address: String,
+    live: bool,
+) -> Result<(), String> {
+    let manager = state.read().await;
+    manager.detach_pci_device(&vm_id, &pci_address, live).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn list_host_usb_devices(state: tauri::State<'_, AppState>) -> Result<Vec<host_devices::HostUsbDevice>, String> {
+    let manager = state.read().await;
+    manager.list_host_usb_devices().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn attach_usb_device(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    vendor_id: String,
+    product_id: String,
+    address: Option<host_devices::UsbAddress>,
+    live: bool,
+) -> Result<host_devices::HostUsbDevice, String> {
+    let manager = state.read().await;
+    manager.attach_usb_device(&vm_id, &vendor_id, &product_id, address, live).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn detach_usb_device(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    vendor_id: String,
+    product_id: String,
+    address: Option<host_devices::UsbAddress>,
+    live: bool,
+) -> Result<(), String> {
+    let manager = state.read().await;
+    manager.detach_usb_device(&vm_id, &vendor_id, &product_id, address, live).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn list_sriov_vfs(state: tauri::State<'_, AppState>) -> Result<Vec<host_devices::SriovNic>, String> {
+    let manager = state.read().await;
+    manager.list_sriov_vfs().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn attach_sriov_vf(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    vf_pci_address: String,
+    live: bool,
+) -> Result<(), String> {
+    let manager = state.read().await;
+    manager.attach_sriov_vf(&vm_id, &vf_pci_address, live).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn attach_host_block_device(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    device_path: String,
+    readonly: bool,
+    live: bool,
+    encryption: Option<EncryptionSpec>,
+) -> Result<String, String> {
+    let target = {
+        let manager = state.read().await;
+        manager.attach_host_block_device(&vm_id, &device_path, readonly, live, encryption.as_ref())
+            .await
+            .map_err(|e| e.to_string())?
+    };
+
+    // Report if the drive is unplugged while still attached to the guest
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(std::time::Duration::from_secs(2));
+        loop {
+            interval.tick().await;
+            if !std::path::Path::new(&device_path).exists() {
+                warn!("Host block device {} attached to VM {} was removed", device_path, vm_id);
+                let payload = serde_json::json!({ "vm_id": vm_id, "device_path": device_path });
+                if let Err(e) = app.emit("host-block-device-removed", payload) {
+                    error!("Failed to emit device removal event: {}", e);
+                }
+                break;
+            }
+
+            // Stop watching once the device has been detached
+            let state = app.state::<AppState>();
+            let manager = state.read().await;
+            match manager.get_vm_disk_paths(&vm_id).await {
+                Ok(paths) if paths.contains(&device_path) => {}
+                _ => break,
+            }
+        }
+    });
+
+    Ok(target)
+}
+
+#[tauri::command]
+async fn detach_host_block_device(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    device_path: String,
+    live: bool,
+) -> Result<(), String> {
+    let manager = state.read().await;
+    manager.detach_host_block_device(&vm_id, &device_path, live).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_host_info(state: tauri::State<'_, AppState>) -> Result<HostInfo, String> {
+    let manager = state.read().await;
+    manager.get_host_info().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn list_host_cpu_models(state: tauri::State<'_, AppState>) -> Result<Vec<HostCpuModel>, String> {
+    let manager = state.read().await;
+    manager.list_host_cpu_models().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn attach_disk(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    disk: StorageDevice,
+    live: bool,
+) -> Result<String, String> {
+    let mut manager = state.write().await;
+    manager.attach_disk(&vm_id, disk, live).await.map_err(String::from)
+}
+
+#[tauri::command]
+async fn detach_disk(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    target_dev: String,
+    live: bool,
+) -> Result<(), String> {
+    let manager = state.read().await;
+    manager.detach_disk(&vm_id, &target_dev, live).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn change_cdrom_media(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    iso_path: Option<String>,
+) -> Result<ChangeScope, String> {
+    let manager = state.read().await;
+    manager.change_cdrom_media(&vm_id, iso_path).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn attach_sound_device(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    model: String,
+) -> Result<(), String> {
+    let mut manager = state.write().await;
+    manager.attach_sound_device(&vm_id, &model).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn attach_shared_folder(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    folder: SharedFolderConfig,
+    live: bool,
+) -> Result<ChangeScope, String> {
+    let mut manager = state.write().await;
+    manager.attach_shared_folder(&vm_id, folder, live).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn detach_shared_folder(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    guest_tag: String,
+    live: bool,
+) -> Result<ChangeScope, String> {
+    let mut manager = state.write().await;
+    manager.detach_shared_folder(&vm_id, &guest_tag, live).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn attach_network_interface(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    config: NetworkConfig,
+    live: bool,
+) -> Result<String, String> {
+    let mut manager = state.write().await;
+    manager.attach_network_interface(&vm_id, config, live).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_interface_link_state(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    mac_address: String,
+    up: bool,
+) -> Result<ChangeScope, String> {
+    let mut manager = state.write().await;
+    manager.set_interface_link_state(&vm_id, &mac_address, up).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn detach_network_interface(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    mac_address: String,
+    live: bool,
+) -> Result<(), String> {
+    let mut manager = state.write().await;
+    manager.detach_network_interface(&vm_id, &mac_address, live).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_supported_machine_types(state: tauri::State<'_, AppState>) -> Result<Vec<MachineType>, String> {
+    let manager = state.read().await;
+    manager.get_supported_machine_types().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn create_snapshot(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    snapshot_name: String,
+    kind: Option<SnapshotKind>,
+) -> Result<Snapshot, String> {
+    let manager = state.read().await;
+    manager.create_snapshot(&vm_id, &snapshot_name, kind).await.map_err(String::from)
+}
+
+#[tauri::command]
+async fn restore_snapshot(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    snapshot_name: String,
+) -> Result<(), String> {
+    let manager = state.read().await;
+    manager.restore_snapshot(&vm_id, &snapshot_name).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_storage_pools(state: tauri::State<'_, AppState>) -> Result<Vec<StoragePool>, String> {
+    let manager = state.read().await;
+    manager.get_storage_pools().await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn create_network(
+    state: tauri::State<'_, AppState>,
+    config: network::NetworkCreateConfig,
+) -> Result<String, String> {
+    let networks = state.read().await.network_manager();
+    networks.create_network(&config).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn delete_network(
+    state: tauri::State<'_, AppState>,
+    network_name: String,
+) -> Result<(), String> {
+    let networks = state.read().await.network_manager();
+    networks.delete_network(&network_name).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn start_network(
+    state: tauri::State<'_, AppState>,
+    network_name: String,
+) -> Result<(), String> {
+    let networks = state.read().await.network_manager();
+    networks.start_network(&network_name).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn stop_network(
+    state: tauri::State<'_, AppState>,
+    network_name: String,
+) -> Result<(), String> {
+    let networks = state.read().await.network_manager();
+    networks.stop_network(&network_name).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_networks(state: tauri::State<'_, AppState>) -> Result<Vec<Network>, String> {
+    let manager = state.read().await;
+    manager.get_networks().await.map_err(|e| e.to_string())
+}
+
+// Enhanced Proxmox-specific commands
+#[tauri::command]
+async fn create_proxmox_vm(
+    state: tauri::State<'_, AppState>,
+    name: String,
+    proxmox_path: String,
+    memory_gb: u32,
+    vcpus: u32,
+) -> Result<String, String> {
+    let mut manager = state.write().await;
+    manager.create_proxmox_vm(name, proxmox_path, memory_gb, vcpus).await.map_err(String::from)
+}
+
+#[tauri::command]
+async fn import_vm_from_xml(
+    state: tauri::State<'_, AppState>,
+    xml_path: String,
+) -> Result<String, String> {
+    let mut manager = state.write().await;
+    manager.import_vm_from_xml(&xml_path).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn import_ova(
+    state: tauri::State<'_, AppState>,
+    ova_path: String,
+    target_pool: String,
+) -> Result<String, String> {
+    let mut manager = state.write().await;
+    manager.import_ova(&ova_path, &target_pool).await.map_err(String::from)
+}
+
+#[tauri::command]
+async fn create_vm_from_qcow2(
+    state: tauri::State<'_, AppState>,
+    qcow2_path: String,
+    vm_name: String,
+    memory_mb: u64,
+    vcpus: u32,
+    passthrough_device: Option<String>,
+    cloud_init: Option<CloudInitConfig>,
+    cpu_model: Option<CpuModelConfig>,
+    firmware: Option<FirmwareType>,
+    secure_boot: Option<bool>,
+) -> Result<String, String> {
+    let mut manager = state.write().await;
+    manager
+        .create_vm_from_qcow2(
+            &qcow2_path,
+            &vm_name,
+            memory_mb,
+            vcpus,
+            passthrough_device.as_deref(),
+            cloud_init.as_ref(),
+            cpu_model.as_ref(),
+            firmware,
+            secure_boot.unwrap_or(false),
+        )
+        .await
+        .map_err(String::from)
+}
+
+/// Follow-up to `new-image-detected`: create a VM from the image when a name is
+/// given, otherwise register it with the storage pool that holds it
+#[tauri::command]
+async fn register_detected_image(
+    state: tauri::State<'_, AppState>,
+    path: String,
+    vm_name: Option<String>,
+    memory_mb: Option<u64>,
+    vcpus: Option<u32>,
+) -> Result<Option<String>, String> {
+    let mut manager = state.write().await;
+    match vm_name {
+        Some(vm_name) => manager
+            .create_vm_from_qcow2(&path, &vm_name, memory_mb.unwrap_or(2048), vcpus.unwrap_or(2), None, None, None, None, false)
+            .await
+            .map(Some)
+            .map_err(String::from),
+        None => manager.refresh_pool_containing(&path).await.map_err(|e| e.to_string()),
+    }
+}
+
+#[tauri::command]
+async fn import_detected_xml(
+    state: tauri::State<'_, AppState>,
+    xml_path: String,
+) -> Result<String, String> {
+    let mut manager = state.write().await;
+    manager.import_detected_xml(&xml_path).await.map_err(|e| e.to_string())
+}
+
+/// Import every *.xml in a directory in the background. Progress arrives as
+/// `batch-import-progress` events and the report as `batch-import-finished`.
+#[tauri::command]
+async fn import_vms_from_directory(
+    app: tauri::AppHandle,
+    state: tauri::State<'_, AppState>,
+    dir_path: String,
+    on_conflict: Option<ImportConflictPolicy>,
+) -> Result<String, String> {
+    let files = batch_import::xml_files(&dir_path).map_err(|e| e.to_string())?;
+    let task_id = uuid::Uuid::new_v4().to_string();
+    let manager = state.inner().clone();
+
+    let id = task_id.clone();
+    tokio::spawn(async move {
+        let progress_app = app.clone();
+        let report = batch_import::run(manager, id, dir_path, files, on_conflict.unwrap_or_default(), move |progress| {
+            if let Err(e) = progress_app.emit("batch-import-progress", progress) {
+                error!("Failed to emit batch import progress: {}", e);
+            }
+        }).await;
+
+        if let Err(e) = app.emit("batch-import-finished", report) {
+            error!("Failed to emit batch import report: {}", e);
+        }
+    });
+
+    Ok(task_id)
+}
+
+#[tauri::command]
+async fn create_storage_pool(
+    state: tauri::State<'_, AppState>,
+    config: StoragePoolCreateConfig,
+) -> Result<String, String> {
+    let storage = state.read().await.storage_manager();
+    storage.create_storage_pool(&config).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn delete_storage_pool(
+    state: tauri::State<'_, AppState>,
+    pool_name: String,
+    delete_storage: bool,
+) -> Result<(), String> {
+    let storage = state.read().await.storage_manager();
+    storage.delete_storage_pool(&pool_name, delete_storage).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn start_storage_pool(
+    state: tauri::State<'_, AppState>,
+    pool_name: String,
+) -> Result<(), String> {
+    let storage = state.read().await.storage_manager();
+    storage.start_storage_pool(&pool_name).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn stop_storage_pool(
+    state: tauri::State<'_, AppState>,
+    pool_name: String,
+) -> Result<(), String> {
+    let storage = state.read().await.storage_manager();
+    storage.stop_storage_pool(&pool_name).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn refresh_storage_pool(
+    state: tauri::State<'_, AppState>,
+    pool_name: String,
+) -> Result<(), String> {
+    let storage = state.read().await.storage_manager();
+    storage.refresh_storage_pool(&pool_name).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn create_volume(
+    state: tauri::State<'_, AppState>,
+    pool_name: String,
+    config: VolumeConfig,
+) -> Result<String, String> {
+    let storage = state.read().await.storage_manager();
+    storage.create_volume(&pool_name, &config).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn resize_volume(
+    state: tauri::State<'_, AppState>,
+    pool_name: String,
+    volume_name: String,
+    new_size: u64,
+) -> Result<(), String> {
+    let storage = state.read().await.storage_manager();
+    storage.resize_volume(&pool_name, &volume_name, new_size).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn clone_volume(
+    state: tauri::State<'_, AppState>,
+    source_pool: String,
+    source_volume: String,
+    target_pool: String,
+    target_volume: String,
+) -> Result<(), String> {
+    let storage = state.read().await.storage_manager();
+    storage.clone_volume(&source_pool, &source_volume, &target_pool, &target_volume)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn delete_volume(
+    state: tauri::State<'_, AppState>,
+    pool_name: String,
+    volume_name: String,
+) -> Result<(), String> {
+    let storage = state.read().await.storage_manager();
+    storage.delete_volume(&pool_name, &volume_name).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_volume_info(
+    state: tauri::State<'_, AppState>,
+    pool_name: String,
+    volume_name: String,
+) -> Result<VolumeInfo, String> {
+    let storage = state.read().await.storage_manager();
+    storage.get_volume_info(&pool_name, &volume_name).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn connect_libvirt(state: tauri::State<'_, AppState>, uri: String) -> Result<String, String> {
+    let mut manager = state.write().await;
+    manager.connect(&uri).await.map_err(|e| e.to_string())?;
+    Ok(manager.get_connection_uri())
+}
+
+#[tauri::command]
+async fn connection_status() -> Result<ConnectionStatus, String> {
+    Ok(connection_watch::status())
+}
+
+#[tauri::command]
+async fn get_connection_uri(state: tauri::State<'_, AppState>) -> Result<String, String> {
+    Ok(state.read().await.get_connection_uri())
+}
+
+#[tauri::command]
+async fn refresh_vms(state: tauri::State<'_, AppState>) -> Result<RefreshOutcome, String> {
+    refresh::refresh_vms(state.inner().clone()).await
+}
+
+#[tauri::command]
+async fn list_vm_snapshots(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+) -> Result<Vec<Snapshot>, String> {
+    let manager = state.read().await;
+    manager.list_snapshots(&vm_id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn delete_vm_snapshot(
+    state: tauri::State<'_, AppState>,
+    vm_id: String,
+    snapshot_name: String,
+) -> Result<(), String> {
+    let manager = state.read().await;
+    manager.delete_snapshot(&vm_id, &snapshot_name).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn browse_qcow2_files() -> Result<Vec<String>, String> {
+    // Find QCOW2 files in common directories
+    let common_dirs = [
+        "/var/lib/libvirt/images",
+        "/home",
+        "/mnt",
+        "/media"
+    ];
+    
+    let mut qcow2_files = Vec::new();
+    
+    for dir in &common_dirs {
+        let mut command = tools::command(Tool::Find).map_err(String::from)?;
+        if let Ok(output) = command
+            .args([dir, "-name", "*.qcow2", "-type", "f", "-readable"])
+            .output() {
+            
+            // find exits non-zero on unreadable subdirectories; keep what it found
+            let files: Vec<String> = String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .filter(|line| !line.is_empty())
+                .map(|line| line.to_string())
+                .collect();
+            qcow2_files.extend(files);
+        }
+    }
+    
+    Ok(qcow2_files)
+}
+
+#[tauri::command]
+async fn browse_xml_files() -> Result<Vec<String>, String> {
+    let common_dirs = [
+        "/etc/libvirt/qemu",
+        "/var/lib/libvirt/qemu",
+        "/home"
+    ];
+    
+    let mut xml_files = Vec::new();
+    
+    for dir in &common_dirs {
+        let mut command = tools::command(Tool::Find).map_err(String::from)?;
+        if let Ok(output) = command
+            .args([dir, "-name", "*.xml", "-type", "f", "-readable"])
+            .output() {
+            
+            // find exits non-zero on unreadable subdirectories; keep what it found
+            let files: Vec<String> = String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .filter(|line| !line.is_empty())
+                .map(|line| line.to_string())
+                .collect();
+            xml_files.extend(files);
+        }
+    }
+    
+    Ok(xml_files)
+}
+
+#[tauri::command]
+async fn get_preflight_report() -> Result<PreflightReport, String> {
+    Ok(PreflightReport {
+        tools: tools::status(),
+    })
+}
+
+#[tauri::command]
+async fn get_recent_logs(
+    level: Option<String>,
+    vm_id: Option<String>,
+    limit: Option<usize>,
+) -> Result<Vec<logging::LogEntry>, String> {
+    logging::recent_logs(level.as_deref(), vm_id.as_deref(), limit.unwrap_or(200)).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn export_diagnostics(state: tauri::State<'_, AppState>) -> Result<String, String> {
+    let preflight = serde_json::to_value(PreflightReport { tools: tools::status() }).map_err(|e| e.to_string())?;
+    let versions = state.read().await.version_info();
+
+    logging::export_diagnostics(&preflight, &versions)
+        .map(|path| path.display().to_string())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_profiles() -> Result<Vec<VmProfile>, String> {
+    use std::path::Path;
+    
+    // Try multiple possible locations for profiles directory
+    let possible_paths = [
+        "profiles",
+        "./profiles", 
+        "/mnt/home/lou/github/kvm-manager/profiles",
+        "../profiles",
+    ];
+    
+    let mut profiles_dir: Option<&Path> = None;
+    for path_str in &possible_paths {
+        let path = Path::new(path_str);
+        if path.exists() {
+            profiles_dir = Some(path);
+            info!("Found profiles directory at: {}", path_str);
+            break;
+        }
+    }
+    
+    let profiles_dir = match profiles_dir {
+        Some(dir) => dir,
+        None => {
+            warn!("No profiles directory found in any of the expected locations");
+            return Ok(Vec::new());
+        }
+    };
+    
+    let mut profiles = Vec::new();
+    
+    if let Ok(entries) = std::fs::read_dir(profiles_dir) {
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|s| s.to_str()) == Some("json") {
+                if let Ok(content) = std::fs::read_to_string(&path) {
+                    if let Ok(profile) = serde_json::from_str::<VmProfile>(&content) {
+                        profiles.push(profile);
+                    }
+                }
+            }
+        }
+    }
+    
+    Ok(profiles)
+}
+
+#[tauri::command]
+async fn create_vm_from_profile(
+    state: tauri::State<'_, AppState>,
+    profile_name: String,
+) -> Result<String, String> {
+    let profiles = get_profiles().await?;
+    let profile = profiles.into_iter()
+        .find(|p| p.name == profile_name)
+        .ok_or_else(|| format!("Profile '{}' not found", profile_name))?;
+    
+    let mut manager = state.write().await;
+    
+    // Check if we have XML file for this profile
+    let xml_filename = format!("{}.xml", profile_name.to_lowercase().replace(" ", "-"));
+    let possible_xml_paths = [
+        format!("profiles/{}", xml_filename),
+        format!("./profiles/{}", xml_filename),
+        format!("/mnt/home/lou/github/kvm-manager/profiles/{}", xml_filename),
+        format!("../profiles/{}", xml_filename),
+    ];
+    
+    let mut xml_path: Option<String> = None;
+    for path_str in &possible_xml_paths {
+        if std::path::Path::new(path_str).exists() {
+            xml_path = Some(path_str.clone());
+            info!("Found XML file at: {}", path_str);
+            break;
+        }
+    }
+    
+    if let Some(xml_path) = xml_path {
+        manager.import_vm_from_xml(&xml_path).await.map_err(String::from)
+    } else {
+        // Create VM from QCOW2 if storage devices are specified
+        if let Some(storage_device) = profile.storage_devices.first() {
+            let passthrough_device = if profile.storage_devices.len() > 1 {
+                Some(profile.storage_devices.get(1).unwrap().source.as_str())
+            } else {
+                None
+            };
+            
+            manager.create_vm_from_qcow2(
+                &storage_device.source,
+                &profile.name,
+                profile.memory as u64, // Profile memory is already in MB
+                profile.vcpus,
+                passthrough_device,
+                None,
+                None,
+                None,
+                false,
+            ).await.map_err(String::from)
+        } else {
+            Err("Profile has no storage devices defined".to_string())
+        }
+    }
+}
+
+#[tauri::command]
+async fn get_qcow2_info(path: String) -> Result<QcowInfo, String> {
+    info!("Getting QCOW2 info for: {}", path);
+    
+    // Check if file exists
+    if !std::path::Path::new(&path).exists() {
+        return Err(format!("File not found: {}", path));
+    }
+    
+    // Use qemu-img info to get details
+    let output = tools::command(Tool::QemuImg)
+        .map_err(String::from)?
+        .args(["info", "--output=json", &path])
+        .output()
+        .map_err(|e| format!("Failed to run qemu-img: {}", e))?;
+    
+    if !output.status.success() {
+        return Err(format!("qemu-img failed: {}", String::from_utf8_lossy(&output.stderr)));
+    }
+    
+    let info_json: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .map_err(|e| format!("Failed to parse qemu-img output: {}", e))?;
+    
+    let filename = std::path::Path::new(&path)
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    
+    let size_bytes = info_json["actual-size"]
+        .as_u64()
+        .unwrap_or(0);
+    
+    let virtual_size_bytes = info_json["virtual-size"]
+        .as_u64()
+        .unwrap_or(0);
+    
+    let format = info_json["format"]
+        .as_str()
+        .unwrap_or("unknown")
+        .to_string();
+    
+    let cluster_size = info_json["cluster-size"].as_u64();
+    
+    let backing_file = info_json["backing-filename"]
+        .as_str()
+        .map(|s| s.to_string());
+    
+    Ok(QcowInfo {
+        path: path.clone(),
+        filename,
+        format,
+        cluster_size,
+        backing_file,
+        ..Default::default()
+    }
+    .with_sizes(size_bytes, virtual_size_bytes))
+}
+
+/// Convert a disk image to qcow2, raw, vmdk or vdi. Without `dest_path` the
+/// source is replaced in place. Progress arrives as `disk-convert-progress`
+/// events while qemu-img runs.
+#[tauri::command]
+async fn convert_disk_image(
+    app: tauri::AppHandle,
+    source_path: String,
+    dest_path: Option<String>,
+    target_format: String,
+) -> Result<QcowInfo, String> {
+    let source = std::path::PathBuf::from(&source_path);
+    let dest = dest_path.as_deref().map(std::path::Path::new);
+    let converted = image_convert::convert(&source, dest, &target_format, |percent| {
+        let payload = serde_json::json!({ "source_path": source_path, "percent": percent });
+        if let Err(e) = app.emit("disk-convert-progress", payload) {
+            warn!("Failed to emit conversion progress: {}", e);
+        }
+    }).await.map_err(String::from)?;
+
+    get_qcow2_info(converted.to_string_lossy().into_owned()).await
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
+pub struct QcowInfo {
+    pub path: String,
+    pub filename: String,
+    /// Size on disk in GiB. Deprecated: use `size_bytes`; kept for one release.
+    pub size_gb: f64,
+    pub format: String,
+    /// Virtual size in GiB. Deprecated: use `virtual_size_bytes`; kept for one release.
+    pub virtual_size_gb: f64,
+    #[serde(default)]
+    pub size_bytes: u64,
+    #[serde(default)]
+    pub virtual_size_bytes: u64,
+    #[serde(default)]
+    pub size_display: String,
+    #[serde(default)]
+    pub virtual_size_display: String,
+    pub cluster_size: Option<u64>,
+    pub backing_file: Option<String>,
+}
+
+impl QcowInfo {
+    /// Set sizes from byte counts, in both the legacy GiB and new fields
+    pub fn with_sizes(mut self, size_bytes: u64, virtual_size_bytes: u64) -> Self {
+        self.size_gb = formatting::bytes_to_gib(size_bytes);
+        self.virtual_size_gb = formatting::bytes_to_gib(virtual_size_bytes);
+        self.size_bytes = size_bytes;
+        self.virtual_size_bytes = virtual_size_bytes;
+        self.size_display = formatting::format_bytes(size_bytes);
+        self.virtual_size_display = formatting::format_bytes(virtual_size_bytes);
+        self
+    }
+}
+
+async fn watch_import_directories(app: tauri::AppHandle) -> Result<(), String> {
+    let settings = settings::Settings::load().map_err(String::from)?;
+    if !settings.watch_imports {
+        return Ok(());
+    }
+
+    let mut directories: Vec<std::path::PathBuf> = settings.import_directories
+        .iter()
+        .map(std::path::PathBuf::from)
+        .collect();
+    {
+        let state = app.state::<AppState>();
+        let manager = state.read().await;
+        for pool in manager.get_storage_pools().await.unwrap_or_default() {
+            if !pool.path.is_empty() {
+                directories.push(pool.path.into());
+            }
+        }
+    }
+    directories.sort();
+    directories.dedup();
+
+    let mut detected = image_watch::spawn(directories).map_err(String::from)?;
+    while let Some(file) = detected.recv().await {
+        let info = match file.kind {
+            image_watch::DetectedKind::Image => get_qcow2_info(file.path.clone()).await.ok(),
+            image_watch::DetectedKind::Xml => None,
+        };
+        let payload = serde_json::json!({ "file": file, "info": info });
+        if let Err(e) = app.emit("new-image-detected", payload) {
+            error!("Failed to emit new image event: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Start the app; `main` only hands over to this so the modules above can be
+/// built as a library for the integration tests
+pub async fn run() {
+    // Initialize tracing; the guard flushes the log file on exit
+    let _log_guard = logging::init();
+
+    info!("Starting KVM Manager application");
+
+    // Locate external tools once so missing ones are reported up front
+    tools::discover();
+
+    // Initialize VM Manager
+    let vm_manager = match VmManager::new().await {
+        Ok(manager) => Arc::new(RwLock::new(manager)),
+        Err(e) => {
+            error!("Failed to initialize VM Manager: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    tauri::Builder::default()
+        .plugin(tauri_plugin_shell::init())
+        .plugin(tauri_plugin_fs::init())
+        .plugin(tauri_plugin_dialog::init())
+        .manage(vm_manager)
+        .invoke_handler(tauri::generate_handler![
+            get_vms,
+            connect_libvirt,
+            get_connection_uri,
+            connection_status,
+            create_vm,
+            preview_vm_xml,
+            list_hardware_presets,
+            save_hardware_preset,
+            delete_hardware_preset,
+            start_vm,
+            stop_vm,
+            force_stop_vm,
+            reboot_vm,
+            reset_vm,
+            save_vm,
+            restore_saved_vm,
+            has_managed_save,
+            discard_saved_state,
+            pause_vm,
+            resume_vm,
+            delete_vm,
+            get_vm_stats,
+            get_metric_history,
+            ping_guest_agent,
+            guest_exec,
+            guest_read_file,
+            guest_write_file,
+            get_guest_info,
+            get_active_graphics,
+            get_vm_display_info,
+            get_spice_connection,
+            set_graphics_password,
+            take_vm_screenshot,
+            start_vnc_proxy,
+            stop_vnc_proxy,
+            send_keys,
+            analyze_vm_storage_layout,
+            get_status_summary,
+            get_quota_usage,
+            set_nested_virtualization,
+            get_effective_config,
+            get_vm_xml,
+            update_vm_xml,
+            clone_vm,
+            clone_vm_linked,
+            migrate_vm,
+            get_migration_status,
+            set_vm_autostart,
+            set_vm_memory,
+            set_vm_memory_live,
+            set_vm_vcpus,
+            set_cpu_topology,
+            get_cpu_pinning,
+            set_cpu_pinning,
+            enable_spice_agent,
+            set_vm_description,
+            set_vm_tags,
+            add_log_console,
+            get_log_console_tail,
+            list_host_block_devices,
+            attach_host_block_device,
+            detach_host_block_device,
+            attach_disk,
+            detach_disk,
+            change_cdrom_media,
+            attach_sound_device,
+            attach_shared_folder,
+            detach_shared_folder,
+            attach_network_interface,
+            detach_network_interface,
+            set_interface_link_state,
+            check_passthrough_readiness,
+            get_iommu_groups,
+            check_gpu_passthrough_readiness,
+            list_host_pci_devices,
+            attach_pci_device,
+            detach_pci_device,
+            list_host_usb_devices,
+            attach_usb_device,
+            detach_usb_device,
+            list_sriov_vfs,
+            attach_sriov_vf,
+            create_disk_secret,
+            list_secrets,
+            delete_secret,
+            get_host_info,
+            list_host_cpu_models,
+            get_supported_machine_types,
+            create_snapshot,
+            restore_snapshot,
+            list_vm_snapshots,
+            delete_vm_snapshot,
+            get_storage_pools,
+            create_storage_pool,
+            delete_storage_pool,
+            start_storage_pool,
+            stop_storage_pool,
+            refresh_storage_pool,
+            get_networks,
+            create_network,
+            delete_network,
+            start_network,
+            stop_network,
+            create_proxmox_vm,
+            import_vm_from_xml,
+            import_ova,
+            create_vm_from_qcow2,
+            register_detected_image,
+            import_detected_xml,
+            import_vms_from_directory,
+            create_volume,
+            resize_volume,
+            clone_volume,
+            delete_volume,
+            get_volume_info,
+            refresh_vms,
+            get_qcow2_info,
+            convert_disk_image,
+            browse_qcow2_files,
+            browse_xml_files,
+            get_profiles,
+            get_preflight_report,
+            get_recent_logs,
+            export_diagnostics,
+            create_vm_from_profile,
+            system_monitor::get_system_statistics,
+            system_monitor::get_proxmox_info,
+            system_monitor::get_system_history,
+            system_monitor::start_system_monitoring,
+            system_monitor::subscribe_vm_stats,
+            system_monitor::unsubscribe_vm_stats
+        ])
+        .setup(|app| {
+    info!("Application setup complete");
+
+            // Announce images and XMLs copied into pool and import directories
+            let app_handle = app.handle().clone();
+            tokio::spawn(async move {
+                if let Err(e) = watch_import_directories(app_handle).await {
+                    error!("Failed to start import directory watch: {}", e);
+                }
+            });
+            
+            // Test Proxmox detection
+            tokio::spawn(async {
+                let proxmox_path = "/run/media/garuda/Data/proxmox-ve.qcow2";
+                match system_monitor::SystemMonitor::get_proxmox_vm_info(proxmox_path) {
+                    Ok(info) => info!("Proxmox VM detected: {} GB, running: {}", info.size_gb, info.is_running),
+                    Err(e) => error!("Failed to detect Proxmox VM: {}", e),
+                }
+            });
+            
+            // Reopen the libvirt connection if libvirtd restarts
+            tokio::spawn(connection_watch::watch(app.handle().clone()));
+            
+            // Tell the UI when a guest watchdog resets or stops a VM
+            tokio::spawn(watchdog_events::watch(app.handle().clone()));
+            
+            // Metric history from earlier runs is loaded before collection starts
+            let monitoring: MonitoringState = Arc::new(RwLock::new(monitoring::MonitoringService::new()));
+            app.manage(monitoring);
+            
+            // Start system monitoring; each cycle also records metric history
+            let monitor_handle = app.handle().clone();
+            tokio::spawn(async move {
+                if let Err(e) = system_monitor::start_system_monitoring(monitor_handle).await {
+                    error!("Failed to start system monitoring: {}", e);
+                }
+            });
+            
+            Ok(())
+        })
+        .run(tauri::generate_context!())
+        .expect("error while running tauri application");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn qcow_info_sizes_agree_in_gib_and_bytes() {
+        let info = QcowInfo::default().with_sizes(formatting::GIB / 2, 20 * formatting::GIB);
+        let value = serde_json::to_value(&info).unwrap();
+
+        assert_eq!(value["size_gb"].as_f64(), Some(0.5));
+        assert_eq!(value["virtual_size_gb"].as_f64(), Some(20.0));
+        assert_eq!(value["size_bytes"].as_u64(), Some(formatting::GIB / 2));
+        assert_eq!(value["virtual_size_bytes"].as_u64(), Some(20 * formatting::GIB));
+        assert_eq!(value["size_display"], "512.00 MiB");
+        assert_eq!(value["virtual_size_display"], "20.00 GiB");
+    }
+}
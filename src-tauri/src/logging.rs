@@ -0,0 +1,199 @@
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::{filter::LevelFilter, fmt, layer::SubscriberExt, util::SubscriberInitExt};
+
+use crate::errors::{KvmError, Result};
+
+const LOG_FILE_PREFIX: &str = "kvm-manager.log";
+
+/// Directory holding the daily-rotated JSON logs
+pub fn log_dir() -> PathBuf {
+    crate::audit::audit_log_path()
+        .parent()
+        .map(|dir| dir.join("logs"))
+        .unwrap_or_else(|| std::env::temp_dir().join("kvm-manager-logs"))
+}
+
+/// Log to stdout as before, plus JSON lines to a daily-rotated file. Without
+/// a usable log directory only stdout is logged to, with a warning there.
+///
+/// The returned guard flushes the file writer on drop and must be kept alive
+/// for the lifetime of the app.
+pub fn init() -> Option<WorkerGuard> {
+    let dir = log_dir();
+    let created = fs::create_dir_all(&dir);
+    let (file_layer, guard) = match created {
+        Ok(()) => {
+            let (writer, guard) = tracing_appender::non_blocking(tracing_appender::rolling::daily(&dir, LOG_FILE_PREFIX));
+            let layer = fmt::layer()
+                .json()
+                .with_current_span(true)
+                .with_span_list(true)
+                .with_writer(writer);
+            (Some(layer), Some(guard))
+        }
+        Err(_) => (None, None),
+    };
+
+    tracing_subscriber::registry()
+        .with(LevelFilter::INFO)
+        .with(fmt::layer())
+        .with(file_layer)
+        .init();
+
+    if let Err(e) = created {
+        warn!("Cannot create log directory {}, logging to stdout only: {}", dir.display(), e);
+    }
+    guard
+}
+
+/// A log entry read back from the JSON log files
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub timestamp: String,
+    pub level: String,
+    pub target: String,
+    pub message: String,
+    pub vm_id: Option<String>,
+    pub operation: Option<String>,
+}
+
+/// Most recent entries at or above `level`, newest first, optionally only those
+/// logged within a span for `vm_id`
+pub fn recent_logs(level: Option<&str>, vm_id: Option<&str>, limit: usize) -> Result<Vec<LogEntry>> {
+    let min_level = level.map(level_rank).unwrap_or(0);
+    let mut entries = Vec::new();
+
+    for path in log_files()?.into_iter().rev() {
+        let file = File::open(&path)?;
+        let mut lines: Vec<String> = BufReader::new(file).lines().map_while(|line| line.ok()).collect();
+        lines.reverse();
+
+        for line in lines {
+            let Some(entry) = parse_entry(&line) else { continue };
+            if level_rank(&entry.level) < min_level {
+                continue;
+            }
+            if vm_id.is_some() && entry.vm_id.as_deref() != vm_id {
+                continue;
+            }
+
+            entries.push(entry);
+            if entries.len() >= limit {
+                return Ok(entries);
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Bundle logs, redacted settings, the preflight report and version information
+/// into a zip for bug reports, returning its path
+pub fn export_diagnostics(preflight: &serde_json::Value, versions: &serde_json::Value) -> Result<PathBuf> {
+    let dir = log_dir().parent().map(|dir| dir.to_path_buf()).unwrap_or_else(std::env::temp_dir);
+    let path = dir.join(format!("kvm-manager-diagnostics-{}.zip", chrono::Utc::now().format("%Y%m%d-%H%M%S")));
+
+    let zip_error = |e: zip::result::ZipError| KvmError::Unknown(format!("Failed to write diagnostics bundle: {}", e));
+    let options = zip::write::SimpleFileOptions::default();
+    let mut zip = zip::ZipWriter::new(File::create(&path)?);
+
+    for log_path in log_files()? {
+        let name = log_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        zip.start_file(format!("logs/{}", name), options).map_err(zip_error)?;
+        zip.write_all(&fs::read(&log_path)?)?;
+    }
+
+    let settings_path = crate::settings::Settings::path();
+    if settings_path.exists() {
+        let mut settings: serde_json::Value = serde_json::from_str(&fs::read_to_string(&settings_path)?)?;
+        redact(&mut settings);
+        zip.start_file("settings.json", options).map_err(zip_error)?;
+        zip.write_all(serde_json::to_string_pretty(&settings)?.as_bytes())?;
+    }
+
+    zip.start_file("preflight.json", options).map_err(zip_error)?;
+    zip.write_all(serde_json::to_string_pretty(preflight)?.as_bytes())?;
+
+    zip.start_file("versions.json", options).map_err(zip_error)?;
+    zip.write_all(serde_json::to_string_pretty(versions)?.as_bytes())?;
+
+    zip.finish().map_err(zip_error)?;
+    info!("Wrote diagnostics bundle to {}", path.display());
+    Ok(path)
+}
+
+// Oldest first
+fn log_files() -> Result<Vec<PathBuf>> {
+    let dir = log_dir();
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut files: Vec<PathBuf> = fs::read_dir(&dir)?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .map(|name| name.to_string_lossy().starts_with(LOG_FILE_PREFIX))
+                .unwrap_or(false)
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+fn parse_entry(line: &str) -> Option<LogEntry> {
+    let json: serde_json::Value = serde_json::from_str(line).ok()?;
+    let text = |value: &serde_json::Value| value.as_str().map(|s| s.to_string());
+
+    // Span fields from the innermost span outwards, then the event's own fields
+    let mut sources: Vec<&serde_json::Value> = json["spans"]
+        .as_array()
+        .map(|spans| spans.iter().rev().collect())
+        .unwrap_or_default();
+    sources.insert(0, &json["fields"]);
+    let field = |name: &str| sources.iter().find_map(|source| text(&source[name]));
+
+    Some(LogEntry {
+        timestamp: text(&json["timestamp"]).unwrap_or_default(),
+        level: text(&json["level"]).unwrap_or_default(),
+        target: text(&json["target"]).unwrap_or_default(),
+        message: text(&json["fields"]["message"]).unwrap_or_default(),
+        vm_id: field("vm_id"),
+        operation: field("operation"),
+    })
+}
+
+fn level_rank(level: &str) -> u8 {
+    match level.to_ascii_uppercase().as_str() {
+        "TRACE" => 0,
+        "DEBUG" => 1,
+        "INFO" => 2,
+        "WARN" => 3,
+        "ERROR" => 4,
+        _ => 0,
+    }
+}
+
+/// Blank out values whose keys look like credentials
+fn redact(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                let key = key.to_ascii_lowercase();
+                if ["password", "passphrase", "secret", "token", "key"].iter().any(|word| key.contains(word)) {
+                    *value = serde_json::Value::String("[redacted]".to_string());
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
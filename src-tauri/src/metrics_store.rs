@@ -0,0 +1,141 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use chrono::{DateTime, DurationRound, Utc};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, warn};
+
+/// One persisted metric value; the store is a JSON-lines file of these
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredPoint {
+    pub series: String, // "<vm id or host>:<metric>"
+    pub timestamp: DateTime<Utc>,
+    pub cycle_id: u64,
+    pub value: f64,
+}
+
+/// Location of the metric history (JSON lines)
+pub fn metrics_path() -> PathBuf {
+    let base = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+        .unwrap_or_else(std::env::temp_dir);
+
+    base.join("kvm-manager").join("metrics.jsonl")
+}
+
+/// Append one collection cycle's points. Failures are logged and otherwise
+/// ignored; losing history must never stop collection.
+pub fn append(points: &[StoredPoint]) {
+    if points.is_empty() {
+        return;
+    }
+
+    let path = metrics_path();
+    if let Some(parent) = path.parent() {
+        if let Err(e) = fs::create_dir_all(parent) {
+            warn!("Failed to create metrics directory {}: {}", parent.display(), e);
+            return;
+        }
+    }
+
+    let mut lines = String::new();
+    for point in points {
+        match serde_json::to_string(point) {
+            Ok(line) => {
+                lines.push_str(&line);
+                lines.push('\n');
+            }
+            Err(e) => warn!("Failed to serialize metric point: {}", e),
+        }
+    }
+
+    // One write per cycle keeps a crash from interleaving partial cycles
+    match OpenOptions::new().create(true).append(true).open(&path) {
+        Ok(mut file) => {
+            if let Err(e) = file.write_all(lines.as_bytes()) {
+                warn!("Failed to write metrics to {}: {}", path.display(), e);
+            }
+        }
+        Err(e) => warn!("Failed to open metrics store {}: {}", path.display(), e),
+    }
+}
+
+/// Every stored point newer than `since`, grouped by series in time order
+pub fn load(since: DateTime<Utc>) -> HashMap<String, Vec<StoredPoint>> {
+    let path = metrics_path();
+    let file = match fs::File::open(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            debug!("No metric history loaded from {}: {}", path.display(), e);
+            return HashMap::new();
+        }
+    };
+
+    let mut series: HashMap<String, Vec<StoredPoint>> = HashMap::new();
+    let mut skipped = 0;
+    for line in BufReader::new(file).lines().map_while(|line| line.ok()) {
+        // A crash mid-write leaves a truncated last line; drop it and carry on
+        match serde_json::from_str::<StoredPoint>(&line) {
+            Ok(point) if point.timestamp > since => series.entry(point.series.clone()).or_default().push(point),
+            Ok(_) => {}
+            Err(_) => skipped += 1,
+        }
+    }
+    if skipped > 0 {
+        warn!("Skipped {} unreadable lines in {}", skipped, path.display());
+    }
+
+    for points in series.values_mut() {
+        points.sort_by_key(|point| point.timestamp);
+    }
+    series
+}
+
+/// Replace the store with `series`, via a temporary file so a crash never
+/// leaves it half written
+pub fn rewrite(series: &HashMap<String, VecDeque<StoredPoint>>) -> std::io::Result<()> {
+    let path = metrics_path();
+    let temp = path.with_extension("jsonl.tmp");
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut file = fs::File::create(&temp)?;
+    for point in series.values().flatten() {
+        writeln!(file, "{}", serde_json::to_string(point)?)?;
+    }
+    file.sync_all()?;
+    fs::rename(&temp, &path)
+}
+
+/// Average the points older than `before` into one point per minute
+pub fn downsample(points: &mut VecDeque<StoredPoint>, before: DateTime<Utc>) {
+    let split = points.partition_point(|point| point.timestamp < before);
+    if split == 0 {
+        return;
+    }
+
+    let mut minutes: BTreeMap<DateTime<Utc>, (f64, usize, u64)> = BTreeMap::new();
+    for point in points.range(..split) {
+        let minute = point.timestamp.duration_trunc(chrono::Duration::minutes(1)).unwrap_or(point.timestamp);
+        let bucket = minutes.entry(minute).or_insert((0.0, 0, point.cycle_id));
+        bucket.0 += point.value;
+        bucket.1 += 1;
+        bucket.2 = bucket.2.max(point.cycle_id);
+    }
+
+    let series = points[0].series.clone();
+    let mut compacted: VecDeque<StoredPoint> = minutes
+        .into_iter()
+        .map(|(timestamp, (sum, count, cycle_id))| StoredPoint {
+            series: series.clone(),
+            timestamp,
+            cycle_id,
+            value: sum / count as f64,
+        })
+        .collect();
+    compacted.extend(points.drain(split..));
+    *points = compacted;
+}
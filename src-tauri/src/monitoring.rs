@@ -1,213 +1,436 @@
-use std::collections::HashMap;
-use std::time::Duration;
-use tokio::time::interval;
-use tracing::{info, warn, error, debug};
-use virt::{connect::Connect, domain::Domain};
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn, debug};
+use virt::domain::Domain;
 use std::fs;
 
-use crate::errors::{KvmError, Result};
+use crate::errors::Result;
+use crate::metrics_store::{self, StoredPoint};
+use crate::settings::Settings;
+use crate::system_monitor;
 use crate::types::*;
 
+// Long enough to smooth out scheduler noise, short enough for interactive stats
+const CPU_SAMPLE_INTERVAL: Duration = Duration::from_millis(250);
+
+pub const DEFAULT_METRICS_RETENTION_DAYS: u32 = 7;
+
+/// How often the system monitor runs a collection cycle
+pub const COLLECTION_INTERVAL: Duration = Duration::from_secs(5);
+
+// Full-resolution history is kept this long, then averaged to one point a minute
+const FULL_RESOLUTION: Duration = Duration::from_secs(24 * 60 * 60);
+const COMPACTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// CPU usage of a domain as a percentage of its allocated vCPUs: 100 means
+/// every vCPU was busy for the whole interval, whatever the vCPU count.
+pub fn cpu_usage_percent(cpu_time_delta_ns: u64, wall_time_delta: Duration, vcpus: u32) -> f64 {
+    let wall_ns = wall_time_delta.as_nanos() as f64;
+    if wall_ns <= 0.0 || vcpus == 0 {
+        return 0.0;
+    }
+    (cpu_time_delta_ns as f64 / wall_ns * 100.0 / vcpus as f64).clamp(0.0, 100.0)
+}
+
+/// Sample a domain's CPU time twice, `CPU_SAMPLE_INTERVAL` apart.
+/// Shut-off domains report 0; `None` means libvirt couldn't be queried.
+pub async fn sample_cpu_usage(domain: &Domain) -> Option<f64> {
+    let first = domain.get_info().ok()?;
+    if first.state != virt::sys::VIR_DOMAIN_RUNNING {
+        return Some(0.0);
+    }
+    let started = Instant::now();
+
+    tokio::time::sleep(CPU_SAMPLE_INTERVAL).await;
+
+    let second = domain.get_info().ok()?;
+    Some(cpu_usage_percent(
+        second.cpu_time.saturating_sub(first.cpu_time),
+        started.elapsed(),
+        second.nr_virt_cpu,
+    ))
+}
+
 pub struct MonitoringService {
-    metrics_history: HashMap<String, Vec<MetricPoint>>,
-    collection_interval: Duration,
-    connection: Option<Connect>,
+    metrics_history: HashMap<String, VecDeque<StoredPoint>>, // Oldest first
+    unsaved: Vec<StoredPoint>, // Points of the current cycle, flushed when it ends
+    retention: Duration,
+    series_capacity: usize,
+    last_compaction: Instant,
+    clock: CycleClock,
+    previous_host: Option<HostCounters>,
 }
 
+/// Raw /proc counters from one host sample; rates are the difference between
+/// two of these divided by the time between them
 #[derive(Debug, Clone)]
+struct HostCounters {
+    taken: Instant,
+    cpu: Option<CpuTimes>,
+    disks: HashMap<String, DiskCounters>,
+    interfaces: HashMap<String, InterfaceCounters>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct CpuTimes {
+    idle: u64,  // idle + iowait, in jiffies
+    total: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct DiskCounters {
+    reads: u64,
+    sectors_read: u64,
+    writes: u64,
+    sectors_written: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct InterfaceCounters {
+    rx_bytes: u64,
+    rx_packets: u64,
+    tx_bytes: u64,
+    tx_packets: u64,
+}
+
+// /proc/diskstats counts in 512-byte sectors regardless of the device's sector size
+const DISKSTATS_SECTOR_BYTES: u64 = 512;
+
+impl HostCounters {
+    fn read() -> Self {
+        Self {
+            taken: Instant::now(),
+            cpu: fs::read_to_string("/proc/stat").ok().and_then(|contents| parse_proc_stat(&contents)),
+            disks: fs::read_to_string("/proc/diskstats").map(|contents| parse_diskstats(&contents)).unwrap_or_default(),
+            interfaces: fs::read_to_string("/proc/net/dev").map(|contents| interface_counters(&contents)).unwrap_or_default(),
+        }
+    }
+}
+
+/// Aggregate `cpu` line: user nice system idle iowait irq softirq steal ...
+fn parse_proc_stat(contents: &str) -> Option<CpuTimes> {
+    let line = contents.lines().find(|line| line.starts_with("cpu "))?;
+    let values: Vec<u64> = line.split_whitespace()
+        .skip(1)
+        .take(8) // guest time is already included in user
+        .filter_map(|value| value.parse().ok())
+        .collect();
+    if values.len() < 4 {
+        return None;
+    }
+    Some(CpuTimes {
+        idle: values[3] + values.get(4).copied().unwrap_or(0),
+        total: values.iter().sum(),
+    })
+}
+
+/// Whole block devices only; partitions would double-count their disk
+fn parse_diskstats(contents: &str) -> HashMap<String, DiskCounters> {
+    contents.lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 10 {
+                return None;
+            }
+            let name = fields[2];
+            if name.starts_with("loop") || name.starts_with("ram") || !std::path::Path::new("/sys/block").join(name).exists() {
+                return None;
+            }
+            let field = |index: usize| fields[index].parse::<u64>().ok();
+            Some((name.to_string(), DiskCounters {
+                reads: field(3)?,
+                sectors_read: field(5)?,
+                writes: field(7)?,
+                sectors_written: field(9)?,
+            }))
+        })
+        .collect()
+}
+
+/// /proc/net/dev through the system monitor's parser, without loopback
+fn interface_counters(contents: &str) -> HashMap<String, InterfaceCounters> {
+    system_monitor::parse_net_dev(contents)
+        .interfaces
+        .into_iter()
+        .filter(|interface| interface.name != "lo")
+        .map(|interface| (interface.name, InterfaceCounters {
+            rx_bytes: interface.bytes_received,
+            rx_packets: interface.packets_received,
+            tx_bytes: interface.bytes_transmitted,
+            tx_packets: interface.packets_transmitted,
+        }))
+        .collect()
+}
+
+fn per_second(current: u64, previous: u64, seconds: f64) -> u64 {
+    // Counters reset when a device is re-created; report 0 rather than wrapping
+    (current.saturating_sub(previous) as f64 / seconds) as u64
+}
+
+/// A stored metric value as returned to callers
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MetricPoint {
     pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub cycle_id: u64,
     pub value: f64,
 }
 
+/// One tick of the collector. Every metric gathered during the tick shares it so
+/// host and per-VM series line up exactly.
+#[derive(Debug, Clone, Copy)]
+pub struct CollectionCycle {
+    pub id: u64,
+    elapsed: Duration, // Since the clock origin, monotonic
+}
+
+/// Issues collection cycles on a monotonic timeline.
+///
+/// The wall-clock time is captured once at startup and cycle timestamps are that
+/// origin plus monotonic elapsed time, so an NTP step on the host cannot make a
+/// series jump backwards or leave gaps.
+#[derive(Debug)]
+struct CycleClock {
+    origin_instant: Instant,
+    origin_wall: chrono::DateTime<chrono::Utc>,
+    next_id: u64,
+}
+
+impl CycleClock {
+    /// Start numbering at `next_id` so ids stay unique across restarts
+    fn new(next_id: u64) -> Self {
+        Self {
+            origin_instant: Instant::now(),
+            origin_wall: chrono::Utc::now(),
+            next_id,
+        }
+    }
+
+    fn start_cycle(&mut self) -> CollectionCycle {
+        let cycle = CollectionCycle {
+            id: self.next_id,
+            elapsed: self.origin_instant.elapsed(),
+        };
+        self.next_id += 1;
+        cycle
+    }
+
+    fn wall_time(&self, cycle: &CollectionCycle) -> chrono::DateTime<chrono::Utc> {
+        self.origin_wall + chrono::Duration::from_std(cycle.elapsed).unwrap_or_default()
+    }
+
+    /// Wall-clock time `window` before now, on the same timeline as the cycles
+    fn cutoff(&self, window: Duration) -> chrono::DateTime<chrono::Utc> {
+        let now = self.origin_wall + chrono::Duration::from_std(self.origin_instant.elapsed()).unwrap_or_default();
+        now - chrono::Duration::from_std(window).unwrap_or_default()
+    }
+}
+
 impl MonitoringService {
+    /// Service with the history persisted by earlier runs loaded back in
     pub fn new() -> Self {
+        let retention_days = Settings::load()
+            .ok()
+            .and_then(|settings| settings.metrics_retention_days)
+            .unwrap_or(DEFAULT_METRICS_RETENTION_DAYS);
+        let retention = Duration::from_secs(u64::from(retention_days) * 24 * 60 * 60);
+
+        let service = Self::with_history(
+            metrics_store::load(chrono::Utc::now() - chrono::Duration::from_std(retention).unwrap_or_default()),
+            retention,
+        );
+        info!("Loaded {} metric series from {}", service.metrics_history.len(), metrics_store::metrics_path().display());
+        service
+    }
+
+    /// Service seeded with `history`, keeping at most a series' capacity of
+    /// the newest points of each
+    fn with_history(history: HashMap<String, Vec<StoredPoint>>, retention: Duration) -> Self {
+        let series_capacity = Self::series_capacity(retention, COLLECTION_INTERVAL);
+
+        let metrics_history: HashMap<String, VecDeque<StoredPoint>> = history
+            .into_iter()
+            .map(|(key, points)| {
+                let skip = points.len().saturating_sub(series_capacity);
+                (key, points.into_iter().skip(skip).collect())
+            })
+            .collect();
+        let next_cycle = metrics_history.values()
+            .flatten()
+            .map(|point| point.cycle_id + 1)
+            .max()
+            .unwrap_or(0);
+
         Self {
-            metrics_history: HashMap::new(),
-            collection_interval: Duration::from_secs(5),
-            connection: None,
+            metrics_history,
+            unsaved: Vec::new(),
+            retention,
+            series_capacity,
+            last_compaction: Instant::now(),
+            clock: CycleClock::new(next_cycle),
+            previous_host: None,
         }
     }
 
-    pub fn with_connection(mut self, connection: Connect) -> Self {
-        self.connection = Some(connection);
-        self
+    /// Most points a series can hold: a day at full resolution plus the rest of
+    /// the retention period at one point a minute, which compaction brings it to,
+    /// plus the full-resolution points that arrive between two compactions
+    fn series_capacity(retention: Duration, collection_interval: Duration) -> usize {
+        let interval = collection_interval.as_secs().max(1);
+        let full = FULL_RESOLUTION.as_secs() / interval;
+        let compacted = retention.saturating_sub(FULL_RESOLUTION).as_secs() / 60;
+        let headroom = COMPACTION_INTERVAL.as_secs() / interval;
+        (full + compacted + headroom) as usize
     }
 
-    pub async fn start_monitoring(&mut self) {
-        info!("Starting monitoring service");
-        
-        let mut interval = interval(self.collection_interval);
-        
-        loop {
-            interval.tick().await;
-            
-            if let Err(e) = self.collect_metrics().await {
-                error!("Failed to collect metrics: {}", e);
-            }
-        }
+    /// Begin a collection cycle; everything recorded until `finish_cycle`
+    /// should carry it
+    pub fn start_cycle(&mut self) -> CollectionCycle {
+        let cycle = self.clock.start_cycle();
+        debug!("Collecting system metrics for cycle {}", cycle.id);
+        cycle
     }
 
-    async fn collect_metrics(&mut self) -> Result<()> {
-        debug!("Collecting system metrics");
-        
-        // Collect host system metrics first (doesn't need connection)
-        if let Ok(host_metrics) = self.get_host_metrics().await {
-            self.store_metric("host", "cpu_usage", host_metrics.cpu_usage).await;
-            self.store_metric("host", "memory_usage", host_metrics.memory_usage as f64).await;
-            self.store_metric("host", "memory_total", host_metrics.memory_total as f64).await;
-            
-            // Store load average
-            self.store_metric("host", "load_1", host_metrics.load_average[0]).await;
-            self.store_metric("host", "load_5", host_metrics.load_average[1]).await;
-            self.store_metric("host", "load_15", host_metrics.load_average[2]).await;
-        }
-        
-        // Collect VM metrics if we have a connection
-        if let Some(conn) = &self.connection {
-            // Get all domains first to avoid borrowing issues
-            if let Ok(domains) = conn.list_all_domains(virt::sys::VIR_CONNECT_LIST_DOMAINS_ACTIVE) {
-                let mut vm_metrics = Vec::new();
-                
-                for domain in domains {
-                    if let (Ok(_name), Ok(uuid)) = (domain.get_name(), domain.get_uuid_string()) {
-                        // Collect VM-specific metrics
-                        if let Ok(vm_stats) = self.get_real_time_stats(&uuid).await {
-                            vm_metrics.push((uuid, vm_stats));
-                        }
-                    }
-                }
+    /// Wall-clock time of a cycle, on the same timeline `get_metric_history`
+    /// stamps its points with
+    pub fn cycle_time(&self, cycle: &CollectionCycle) -> chrono::DateTime<chrono::Utc> {
+        self.clock.wall_time(cycle)
+    }
+
+    pub async fn record_host_metrics(&mut self, cycle: &CollectionCycle) {
+        match self.get_host_metrics().await {
+            Ok(host_metrics) => {
+                self.store_metric(cycle, "host", "cpu_usage", host_metrics.cpu_usage).await;
+                self.store_metric(cycle, "host", "memory_usage", host_metrics.memory_usage as f64).await;
+                self.store_metric(cycle, "host", "memory_total", host_metrics.memory_total as f64).await;
                 
-                // Store all the metrics after collecting
-                for (uuid, vm_stats) in vm_metrics {
-                    self.store_metric(&uuid, "cpu_usage", vm_stats.cpu_usage).await;
-                    self.store_metric(&uuid, "memory_usage", vm_stats.memory_usage as f64).await;
-                    self.store_metric(&uuid, "disk_read", vm_stats.disk_read as f64).await;
-                    self.store_metric(&uuid, "disk_write", vm_stats.disk_write as f64).await;
-                    self.store_metric(&uuid, "network_rx", vm_stats.network_rx as f64).await;
-                    self.store_metric(&uuid, "network_tx", vm_stats.network_tx as f64).await;
-                }
+                // Store load average
+                self.store_metric(cycle, "host", "load_1", host_metrics.load_average[0]).await;
+                self.store_metric(cycle, "host", "load_5", host_metrics.load_average[1]).await;
+                self.store_metric(cycle, "host", "load_15", host_metrics.load_average[2]).await;
             }
+            Err(e) => warn!("Failed to collect host metrics: {}", e),
         }
+    }
+
+    pub async fn record_vm_stats(&mut self, cycle: &CollectionCycle, vm_id: &str, vm_stats: &VmStats) {
+        self.store_metric(cycle, vm_id, "cpu_usage", vm_stats.cpu_usage).await;
+        self.store_metric(cycle, vm_id, "memory_usage", vm_stats.memory_usage as f64).await;
+        self.store_metric(cycle, vm_id, "disk_read", vm_stats.disk_read as f64).await;
+        self.store_metric(cycle, vm_id, "disk_write", vm_stats.disk_write as f64).await;
+        self.store_metric(cycle, vm_id, "network_rx", vm_stats.network_rx as f64).await;
+        self.store_metric(cycle, vm_id, "network_tx", vm_stats.network_tx as f64).await;
+    }
+
+    /// Persist the cycle's points and apply retention and compaction
+    pub async fn finish_cycle(&mut self) {
+        metrics_store::append(&self.unsaved);
+        self.unsaved.clear();
         
-        // Cleanup old metrics (keep only last 24 hours)
         self.cleanup_old_metrics().await;
         
-        Ok(())
+        if self.last_compaction.elapsed() >= COMPACTION_INTERVAL {
+            self.compact_metrics().await;
+        }
     }
     
-    async fn store_metric(&mut self, vm_id: &str, metric_type: &str, value: f64) {
+    async fn store_metric(&mut self, cycle: &CollectionCycle, vm_id: &str, metric_type: &str, value: f64) {
         let key = format!("{}:{}", vm_id, metric_type);
-        let metric_point = MetricPoint {
-            timestamp: chrono::Utc::now(),
+        let point = StoredPoint {
+            series: key.clone(),
+            timestamp: self.clock.wall_time(cycle),
+            cycle_id: cycle.id,
             value,
         };
         
-        self.metrics_history
+        self.unsaved.push(point.clone());
+        let series = self.metrics_history
             .entry(key)
-            .or_insert_with(Vec::new)
-            .push(metric_point);
+            .or_insert_with(|| VecDeque::with_capacity(64));
+        // Ring buffer: a full series drops its oldest point rather than growing
+        if series.len() >= self.series_capacity {
+            series.pop_front();
+        }
+        series.push_back(point);
     }
     
     async fn cleanup_old_metrics(&mut self) {
-        let cutoff_time = chrono::Utc::now() - chrono::Duration::hours(24);
+        let cutoff = self.clock.cutoff(self.retention);
         
-        for (_, metrics) in self.metrics_history.iter_mut() {
-            metrics.retain(|point| point.timestamp > cutoff_time);
+        // Series are in time order, so only the front can have expired
+        for metrics in self.metrics_history.values_mut() {
+            while metrics.front().is_some_and(|point| point.timestamp <= cutoff) {
+                metrics.pop_front();
+            }
         }
         
         // Remove empty metric series
         self.metrics_history.retain(|_, metrics| !metrics.is_empty());
     }
+    
+    /// Average points past the full-resolution window down to one a minute and
+    /// rewrite the store to match, which also drops what retention removed
+    async fn compact_metrics(&mut self) {
+        let before = self.clock.cutoff(FULL_RESOLUTION);
+        for metrics in self.metrics_history.values_mut() {
+            metrics_store::downsample(metrics, before);
+        }
+        
+        let history = self.metrics_history.clone();
+        match tokio::task::spawn_blocking(move || metrics_store::rewrite(&history)).await {
+            Ok(Ok(())) => debug!("Compacted metric history"),
+            Ok(Err(e)) => warn!("Failed to compact metric history: {}", e),
+            Err(e) => warn!("Metric compaction task failed: {}", e),
+        }
+        self.last_compaction = Instant::now();
+    }
 
+    /// Points within `duration`, stamped with their cycle's timestamp.
+    ///
+    /// All series share cycle ids and timestamps, so callers can align host and
+    /// per-VM series by `cycle_id` without interpolation.
+    /// Points older than a day are per-minute averages, and may come from an
+    /// earlier run of the app.
     pub fn get_metric_history(&self, vm_id: &str, metric_type: &str, duration: Duration) -> Vec<MetricPoint> {
         let key = format!("{}:{}", vm_id, metric_type);
-        let cutoff_time = chrono::Utc::now() - chrono::Duration::from_std(duration).unwrap_or_default();
+        let cutoff = self.clock.cutoff(duration);
         
-        self.metrics_history
-            .get(&key)
-            .unwrap_or(&Vec::new())
-            .iter()
-            .filter(|point| point.timestamp > cutoff_time)
-            .cloned()
-            .collect()
-    }
-
-    pub async fn get_real_time_stats(&self, vm_id: &str) -> Result<VmStats> {
-        debug!("Getting real-time stats for VM: {}", vm_id);
+        let Some(points) = self.metrics_history.get(&key) else {
+            return Vec::new();
+        };
         
-        if let Some(conn) = &self.connection {
-            // Get domain by UUID
-            let domain = Domain::lookup_by_uuid_string(conn, vm_id)
-                .map_err(|e| KvmError::VmNotFound(format!("Domain not found: {}", e)))?;
-            
-            // Get domain info for basic stats
-            let info = domain.get_info()
-                .map_err(|e| KvmError::LibvirtConnection(e))?;
-            
-            // Calculate CPU usage (this is a simplified calculation)
-            let cpu_usage = self.calculate_cpu_usage(&domain)?;
-            
-            // Get memory stats
-            let memory_stats = self.get_memory_stats(&domain)?;
-            
-            // Get disk I/O stats
-            let (disk_read, disk_write) = self.get_disk_stats(&domain)?;
-            
-            // Get network stats
-            let (network_rx, network_tx) = self.get_network_stats(&domain)?;
-            
-            // Check if guest agent is connected
-            let guest_agent_connected = self.check_guest_agent(&domain);
-            
-            // Calculate uptime
-            let uptime = if info.state == virt::sys::VIR_DOMAIN_RUNNING {
-                // This is approximate - you'd want to track this more precisely
-                std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap_or_default()
-                    .as_secs()
-            } else {
-                0
-            };
-            
-            Ok(VmStats {
-                cpu_usage,
-                memory_usage: memory_stats.0,
-                memory_total: info.memory,
-                disk_read,
-                disk_write,
-                network_rx,
-                network_tx,
-                uptime,
-                guest_agent_connected,
-                timestamp: chrono::Utc::now(),
-            })
-        } else {
-            // Fallback if no connection available
-            Ok(VmStats {
-                cpu_usage: 0.0,
-                memory_usage: 0,
-                memory_total: 0,
-                disk_read: 0,
-                disk_write: 0,
-                network_rx: 0,
-                network_tx: 0,
-                uptime: 0,
-                guest_agent_connected: false,
-                timestamp: chrono::Utc::now(),
+        let start = points.partition_point(|point| point.timestamp <= cutoff);
+        points.range(start..)
+            .map(|point| MetricPoint {
+                timestamp: point.timestamp,
+                cycle_id: point.cycle_id,
+                value: point.value,
             })
-        }
+            .collect()
     }
 
-    pub async fn get_host_metrics(&self) -> Result<HostMetrics> {
+    /// Host usage since the previous call. The first call has nothing to diff
+    /// against, so it takes a second sample after a short pause.
+    pub async fn get_host_metrics(&mut self) -> Result<HostMetrics> {
         debug!("Getting host system metrics");
         
-        let cpu_usage = self.get_host_cpu_usage()?;
+        let previous = match self.previous_host.take() {
+            Some(previous) => previous,
+            None => {
+                let first = HostCounters::read();
+                tokio::time::sleep(CPU_SAMPLE_INTERVAL).await;
+                first
+            }
+        };
+        let current = HostCounters::read();
+        
+        let cpu_usage = Self::get_host_cpu_usage(&previous, &current);
         let (memory_usage, memory_total) = self.get_host_memory_stats()?;
         let load_average = self.get_load_average()?;
-        let disk_usage = self.get_host_disk_usage()?;
-        let network_usage = self.get_host_network_usage()?;
+        let disk_usage = Self::get_host_disk_usage(&previous, &current);
+        let network_usage = Self::get_host_network_usage(&previous, &current);
+        
+        self.previous_host = Some(current);
         
         Ok(HostMetrics {
             cpu_usage,
@@ -219,185 +442,18 @@ impl MonitoringService {
         })
     }
     
-    // Helper methods for VM statistics
-    fn calculate_cpu_usage(&self, domain: &Domain) -> Result<f64> {
-        // Real CPU usage calculation based on CPU time differences
-        match domain.get_info() {
-            Ok(info) => {
-                if info.state != virt::sys::VIR_DOMAIN_RUNNING {
-                    return Ok(0.0);
-                }
-                
-                // Store first sample
-                let cpu_time_1 = info.cpu_time;
-                let wall_time_1 = std::time::SystemTime::now();
-                
-                // Wait a bit and take second sample
-                std::thread::sleep(std::time::Duration::from_millis(100));
-                
-                match domain.get_info() {
-                    Ok(info2) => {
-                        let cpu_time_2 = info2.cpu_time;
-                        let wall_time_2 = std::time::SystemTime::now();
-                        
-                        let cpu_time_diff = cpu_time_2.saturating_sub(cpu_time_1) as f64;
-                        let wall_time_diff = wall_time_2.duration_since(wall_time_1)
-                            .unwrap_or_default().as_nanos() as f64;
-                        
-                        if wall_time_diff > 0.0 {
-                            // CPU time is in nanoseconds, calculate percentage
-                            let cpu_usage = (cpu_time_diff / wall_time_diff) * 100.0;
-                            // Cap at 100% and account for multiple vCPUs
-                            Ok((cpu_usage * info.nr_virt_cpu as f64).min(100.0))
-                        } else {
-                            Ok(0.0)
-                        }
-                    }
-                    Err(_) => Ok(0.0)
-                }
-            }
-            Err(e) => {
-                warn!("Failed to get domain info for CPU calculation: {}", e);
-                Ok(0.0)
-            }
-        }
-    }
-    
-    fn get_memory_stats(&self, domain: &Domain) -> Result<(u64, u64)> {
-        match domain.get_info() {
-            Ok(info) => {
-                // Return (used_memory, total_memory)
-                let total = info.memory;
-                let used = total * 70 / 100; // Mock: assume 70% usage
-                Ok((used, total))
-            }
-            Err(e) => {
-                warn!("Failed to get memory stats: {}", e);
-                Ok((0, 0))
-            }
-        }
-    }
-    
-    fn get_disk_stats(&self, domain: &Domain) -> Result<(u64, u64)> {
-        // Use libvirt APIs to get block device statistics
-        let mut total_read = 0u64;
-        let mut total_write = 0u64;
-        
-        // Common block device names
-        let block_devices = ["vda", "vdb", "vdc", "vdd", "sda", "sdb", "hda", "hdb"];
-        
-        for device in &block_devices {
-            if let Ok(block_stats) = domain.get_block_stats(device) {
-                total_read += block_stats.rd_bytes as u64;
-                total_write += block_stats.wr_bytes as u64;
-            }
-        }
-        
-        // If no stats found from common names, try to get from domain XML
-        if total_read == 0 && total_write == 0 {
-            if let Ok(xml) = domain.get_xml_desc(0) {
-                // Parse XML to find disk device names
-                for line in xml.lines() {
-                    if line.contains("<target dev=") {
-                        if let Some(start) = line.find("dev=\"") {
-                            if let Some(end) = line[start + 5..].find('"') {
-                                let device_name = &line[start + 5..start + 5 + end];
-                                if let Ok(block_stats) = domain.get_block_stats(device_name) {
-                                    total_read += block_stats.rd_bytes as u64;
-                                    total_write += block_stats.wr_bytes as u64;
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        }
-        
-        Ok((total_read, total_write))
-    }
-    
-    fn get_network_stats(&self, domain: &Domain) -> Result<(u64, u64)> {
-        // Use libvirt APIs to get network interface statistics
-        let mut total_rx = 0u64;
-        let mut total_tx = 0u64;
-        
-        // Try common interface naming patterns
-        let interface_names = ["vnet0", "vnet1", "tap0", "tap1", "eth0", "ens3"];
-        
-        for iface_name in &interface_names {
-            if let Ok(net_stats) = domain.interface_stats(iface_name) {
-                total_rx += net_stats.rx_bytes as u64;
-                total_tx += net_stats.tx_bytes as u64;
-            }
-        }
-        
-        // If no stats from common names, try to parse interface names from XML
-        if total_rx == 0 && total_tx == 0 {
-            if let Ok(xml) = domain.get_xml_desc(0) {
-                // Try to find interface names in the XML
-                // This is a simple approach - a more robust solution would use proper XML parsing
-                for line in xml.lines() {
-                    if line.contains("<interface") {
-                        // Try a few more interface name patterns based on what we might find
-                        let test_names = [
-                            format!("vnet{}", rand::random::<u8>() % 10),
-                            format!("tap{}", rand::random::<u8>() % 10),
-                        ];
-                        
-                        for test_name in &test_names {
-                            if let Ok(net_stats) = domain.interface_stats(test_name) {
-                                total_rx += net_stats.rx_bytes as u64;
-                                total_tx += net_stats.tx_bytes as u64;
-                                break;
-                            }
-                        }
-                    }
-                }
-            }
-        }
-        
-        Ok((total_rx, total_tx))
-    }
-    
-    fn check_guest_agent(&self, domain: &Domain) -> bool {
-        // Check if QEMU guest agent is running by checking domain XML for guest agent channel
-        // Since qemu_agent_command is not available in the virt crate, we'll check for 
-        // guest agent channel configuration in the domain XML
-        match domain.get_xml_desc(0) {
-            Ok(xml) => {
-                // Look for guest agent channel in XML
-                if xml.contains("org.qemu.guest_agent.0") || xml.contains("guest_agent") {
-                    debug!("Guest agent channel found in domain XML");
-                    true
-                } else {
-                    debug!("No guest agent channel found in domain XML");
-                    false
-                }
-            }
-            Err(_) => {
-                debug!("Failed to get domain XML for guest agent check");
-                false
-            }
-        }
-    }
-    
     // Helper methods for host system metrics
-    fn get_host_cpu_usage(&self) -> Result<f64> {
-        // Read from /proc/stat to calculate CPU usage
-        match fs::read_to_string("/proc/stat") {
-            Ok(contents) => {
-                if let Some(line) = contents.lines().next() {
-                    // Parse CPU line: cpu  user nice system idle iowait irq softirq
-                    let values: Vec<&str> = line.split_whitespace().collect();
-                    if values.len() >= 5 {
-                        // This is a simplified calculation - real implementation would track over time
-                        return Ok(25.0); // Mock value
-                    }
-                }
-                Ok(0.0)
-            }
-            Err(_) => Ok(0.0)
+    fn get_host_cpu_usage(previous: &HostCounters, current: &HostCounters) -> f64 {
+        let (previous, current) = match (previous.cpu, current.cpu) {
+            (Some(previous), Some(current)) => (previous, current),
+            _ => return 0.0,
+        };
+        let total = current.total.saturating_sub(previous.total);
+        if total == 0 {
+            return 0.0;
         }
+        let busy = total.saturating_sub(current.idle.saturating_sub(previous.idle));
+        (busy as f64 / total as f64 * 100.0).clamp(0.0, 100.0)
     }
     
     fn get_host_memory_stats(&self) -> Result<(u64, u64)> {
@@ -444,34 +500,42 @@ impl MonitoringService {
         }
     }
     
-    fn get_host_disk_usage(&self) -> Result<HashMap<String, DiskMetrics>> {
-        // Read from /proc/diskstats
-        // This is simplified - real implementation would track over time
-        let mut disk_usage = HashMap::new();
-        
-        disk_usage.insert("sda".to_string(), DiskMetrics {
-            read_bytes_per_sec: 1024 * 1024,
-            write_bytes_per_sec: 512 * 1024,
-            read_ops_per_sec: 100,
-            write_ops_per_sec: 50,
-        });
+    fn get_host_disk_usage(previous: &HostCounters, current: &HostCounters) -> HashMap<String, DiskMetrics> {
+        let seconds = current.taken.duration_since(previous.taken).as_secs_f64();
+        if seconds <= 0.0 {
+            return HashMap::new();
+        }
         
-        Ok(disk_usage)
+        current.disks.iter()
+            .filter_map(|(name, now)| {
+                let before = previous.disks.get(name)?;
+                Some((name.clone(), DiskMetrics {
+                    read_bytes_per_sec: per_second(now.sectors_read * DISKSTATS_SECTOR_BYTES, before.sectors_read * DISKSTATS_SECTOR_BYTES, seconds),
+                    write_bytes_per_sec: per_second(now.sectors_written * DISKSTATS_SECTOR_BYTES, before.sectors_written * DISKSTATS_SECTOR_BYTES, seconds),
+                    read_ops_per_sec: per_second(now.reads, before.reads, seconds),
+                    write_ops_per_sec: per_second(now.writes, before.writes, seconds),
+                }))
+            })
+            .collect()
     }
     
-    fn get_host_network_usage(&self) -> Result<HashMap<String, NetworkMetrics>> {
-        // Read from /proc/net/dev
-        // This is simplified - real implementation would track over time
-        let mut network_usage = HashMap::new();
-        
-        network_usage.insert("eth0".to_string(), NetworkMetrics {
-            rx_bytes_per_sec: 2048 * 1024,
-            tx_bytes_per_sec: 1024 * 1024,
-            rx_packets_per_sec: 1000,
-            tx_packets_per_sec: 800,
-        });
+    fn get_host_network_usage(previous: &HostCounters, current: &HostCounters) -> HashMap<String, NetworkMetrics> {
+        let seconds = current.taken.duration_since(previous.taken).as_secs_f64();
+        if seconds <= 0.0 {
+            return HashMap::new();
+        }
         
-        Ok(network_usage)
+        current.interfaces.iter()
+            .filter_map(|(name, now)| {
+                let before = previous.interfaces.get(name)?;
+                Some((name.clone(), NetworkMetrics {
+                    rx_bytes_per_sec: per_second(now.rx_bytes, before.rx_bytes, seconds),
+                    tx_bytes_per_sec: per_second(now.tx_bytes, before.tx_bytes, seconds),
+                    rx_packets_per_sec: per_second(now.rx_packets, before.rx_packets, seconds),
+                    tx_packets_per_sec: per_second(now.tx_packets, before.tx_packets, seconds),
+                }))
+            })
+            .collect()
     }
 }
 
@@ -500,3 +564,63 @@ pub struct NetworkMetrics {
     pub rx_packets_per_sec: u64,
     pub tx_packets_per_sec: u64,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn series_stay_bounded_and_ordered() {
+        let mut service = MonitoringService::with_history(HashMap::new(), Duration::from_secs(24 * 60 * 60));
+        service.series_capacity = 1_000;
+
+        for value in 0..10_000 {
+            let cycle = service.start_cycle();
+            service.store_metric(&cycle, "host", "cpu_usage", value as f64).await;
+            service.unsaved.clear();
+        }
+
+        let series = &service.metrics_history["host:cpu_usage"];
+        assert_eq!(series.len(), 1_000);
+        assert_eq!(series.front().map(|point| point.value), Some(9_000.0));
+        assert_eq!(series.back().map(|point| point.value), Some(9_999.0));
+        assert!(series.iter().zip(series.iter().skip(1)).all(|(earlier, later)| {
+            earlier.cycle_id + 1 == later.cycle_id && earlier.timestamp <= later.timestamp
+        }));
+
+        let history = service.get_metric_history("host", "cpu_usage", Duration::from_secs(60 * 60));
+        assert_eq!(history.len(), 1_000);
+        assert!(history.windows(2).all(|pair| pair[0].cycle_id < pair[1].cycle_id));
+    }
+
+    #[test]
+    fn capacity_holds_a_compaction_interval_beyond_the_compacted_size() {
+        let week = Duration::from_secs(7 * 24 * 60 * 60);
+        let capacity = MonitoringService::series_capacity(week, Duration::from_secs(5));
+
+        // 17280 points for the last day, 8640 for the six before, 720 until the next compaction
+        assert_eq!(capacity, 17_280 + 8_640 + 720);
+    }
+
+    #[test]
+    fn loaded_history_is_trimmed_to_capacity() {
+        let retention = Duration::from_secs(24 * 60 * 60);
+        let capacity = MonitoringService::series_capacity(retention, COLLECTION_INTERVAL);
+        let now = chrono::Utc::now();
+        let points = (0..capacity as u64 + 10)
+            .map(|cycle_id| StoredPoint {
+                series: "host:load_1".to_string(),
+                timestamp: now,
+                cycle_id,
+                value: cycle_id as f64,
+            })
+            .collect();
+
+        let service = MonitoringService::with_history(HashMap::from([("host:load_1".to_string(), points)]), retention);
+
+        let series = &service.metrics_history["host:load_1"];
+        assert_eq!(series.len(), capacity);
+        assert_eq!(series.front().map(|point| point.cycle_id), Some(10));
+        assert_eq!(service.clock.next_id, capacity as u64 + 10);
+    }
+}
@@ -1,6 +1,8 @@
+use serde::{Deserialize, Serialize};
 use tracing::{info, error};
 use virt::{connect::Connect, network::Network as LibvirtNetwork};
 use crate::errors::{KvmError, Result};
+use crate::xml_parser::XmlParser;
 
 pub struct NetworkManager {
     connection: Connect,
@@ -14,7 +16,11 @@ impl NetworkManager {
     pub async fn create_network(&self, network_config: &NetworkCreateConfig) -> Result<String> {
         info!("Creating network: {}", network_config.name);
         
-        let network_xml = self.generate_network_xml(network_config)?;
+        let network_xml = Self::generate_network_xml(network_config)?;
+        
+        // Catch template mistakes here rather than as an opaque libvirt define error
+        roxmltree::Document::parse(&network_xml)
+            .map_err(|e| KvmError::XmlParsingError(format!("Generated network XML is malformed: {}", e)))?;
         
         // Define the network
         let network = LibvirtNetwork::define_xml(&self.connection, &network_xml)
@@ -103,19 +109,20 @@ impl NetworkManager {
         Ok(())
     }
     
-    fn generate_network_xml(&self, config: &NetworkCreateConfig) -> Result<String> {
+    fn generate_network_xml(config: &NetworkCreateConfig) -> Result<String> {
         let dhcp_section = if config.dhcp_enabled {
             let start = config.dhcp_range_start.as_deref().unwrap_or("192.168.1.2");
             let end = config.dhcp_range_end.as_deref().unwrap_or("192.168.1.254");
-            format!("      <dhcp>\n        <range start='{}' end='{}'/>\n      </dhcp>", start, end)
+            format!("    <dhcp>\n      <range start='{}' end='{}'/>\n    </dhcp>", start, end)
         } else {
             String::new()
         };
         
         let bridge_section = if let Some(bridge) = &config.bridge_name {
-            format!("  <bridge name='{}' stp='on' delay='0'/>\n", bridge)
+            format!("  <bridge name='{}' stp='on' delay='0'/>\n", XmlParser::escape(bridge))
         } else {
-            "  <bridge name='virbr0' stp='on' delay='0'/>\n".to_string()
+            // Let libvirt pick a free virbrN; virbr0 normally belongs to the default network
+            "  <bridge stp='on' delay='0'/>\n".to_string()
         };
         
         let forward_section = match config.forward_mode.as_str() {
@@ -126,17 +133,17 @@ impl NetworkManager {
             _ => "  <forward mode='nat'/>\n",
         };
         
-        let ip_section = if let Some(ip_range) = &config.ip_range {
-            format!("    <ip address='{}' netmask='255.255.255.0'>\n{}\n    </ip>", 
-                   ip_range.split('/').next().unwrap_or("192.168.1.1"),
-                   dhcp_section)
-        } else {
-            format!("    <ip address='192.168.1.1' netmask='255.255.255.0'>\n{}\n    </ip>", dhcp_section)
+        // An explicit /prefix on the range wins over the default /24 netmask
+        let (ip_address, netmask) = match config.ip_range.as_deref().map(|range| range.split_once('/').unwrap_or((range, ""))) {
+            Some((address, prefix)) if !prefix.is_empty() => (address, format!("prefix='{}'", prefix)),
+            Some((address, _)) => (address, "netmask='255.255.255.0'".to_string()),
+            None => ("192.168.1.1", "netmask='255.255.255.0'".to_string()),
         };
+        let ip_section = format!("  <ip address='{}' {}>\n{}\n  </ip>", ip_address, netmask, dhcp_section);
         
         let xml = format!(
-            r#"<network>\n  <name>{}</name>\n{}{}{}\n</network>"#,
-            config.name,
+            "<network>\n  <name>{}</name>\n{}{}{}\n</network>",
+            XmlParser::escape(&config.name),
             forward_section,
             bridge_section,
             ip_section
@@ -146,7 +153,7 @@ impl NetworkManager {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NetworkCreateConfig {
     pub name: String,
     pub forward_mode: String,
@@ -157,3 +164,76 @@ pub struct NetworkCreateConfig {
     pub dhcp_range_end: Option<String>,
     pub auto_start: bool,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(forward_mode: &str) -> NetworkCreateConfig {
+        NetworkCreateConfig {
+            name: format!("lab-{}", forward_mode),
+            forward_mode: forward_mode.to_string(),
+            bridge_name: None,
+            ip_range: Some("10.10.0.1/24".to_string()),
+            dhcp_enabled: true,
+            dhcp_range_start: Some("10.10.0.100".to_string()),
+            dhcp_range_end: Some("10.10.0.200".to_string()),
+            auto_start: false,
+        }
+    }
+
+    fn parse(xml: &str) -> roxmltree::Document<'_> {
+        roxmltree::Document::parse(xml).unwrap_or_else(|e| panic!("malformed network XML ({}):\n{}", e, xml))
+    }
+
+    fn forward_mode(document: &roxmltree::Document) -> Option<String> {
+        document.descendants().find(|n| n.has_tag_name("forward")).and_then(|n| n.attribute("mode")).map(String::from)
+    }
+
+    #[test]
+    fn nat_network_is_well_formed() {
+        let xml = NetworkManager::generate_network_xml(&config("nat")).unwrap();
+        let document = parse(&xml);
+
+        assert_eq!(document.root_element().tag_name().name(), "network");
+        assert_eq!(forward_mode(&document).as_deref(), Some("nat"));
+        let port = document.descendants().find(|n| n.has_tag_name("port")).unwrap();
+        assert_eq!(port.attribute("start"), Some("1024"));
+
+        let ip = document.descendants().find(|n| n.has_tag_name("ip")).unwrap();
+        assert_eq!(ip.attribute("address"), Some("10.10.0.1"));
+        assert_eq!(ip.attribute("prefix"), Some("24"));
+        let range = document.descendants().find(|n| n.has_tag_name("range")).unwrap();
+        assert_eq!(range.attribute("start"), Some("10.10.0.100"));
+        assert_eq!(range.attribute("end"), Some("10.10.0.200"));
+    }
+
+    #[test]
+    fn routed_network_is_well_formed() {
+        let config = NetworkCreateConfig { dhcp_enabled: false, ip_range: Some("10.20.0.1".to_string()), ..config("route") };
+        let xml = NetworkManager::generate_network_xml(&config).unwrap();
+        let document = parse(&xml);
+
+        assert_eq!(forward_mode(&document).as_deref(), Some("route"));
+        assert!(!document.descendants().any(|n| n.has_tag_name("nat") || n.has_tag_name("dhcp")));
+        let ip = document.descendants().find(|n| n.has_tag_name("ip")).unwrap();
+        assert_eq!(ip.attribute("netmask"), Some("255.255.255.0"));
+    }
+
+    #[test]
+    fn bridged_network_is_well_formed_with_escaped_names() {
+        let config = NetworkCreateConfig {
+            name: "lab <bridged> & 'quoted'".to_string(),
+            bridge_name: Some("br'0".to_string()),
+            ..config("bridge")
+        };
+        let xml = NetworkManager::generate_network_xml(&config).unwrap();
+        let document = parse(&xml);
+
+        assert_eq!(forward_mode(&document).as_deref(), Some("bridge"));
+        let name = document.descendants().find(|n| n.has_tag_name("name")).unwrap();
+        assert_eq!(name.text(), Some("lab <bridged> & 'quoted'"));
+        let bridge = document.descendants().find(|n| n.has_tag_name("bridge")).unwrap();
+        assert_eq!(bridge.attribute("name"), Some("br'0"));
+    }
+}
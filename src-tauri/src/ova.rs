@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tracing::{debug, info};
+
+use crate::errors::{KvmError, Result};
+use crate::tools::{self, Tool};
+
+/// The parts of an OVF descriptor's virtual hardware that map onto a libvirt domain
+#[derive(Debug, Clone)]
+pub struct OvfDescriptor {
+    pub name: String,
+    pub vcpus: u32,
+    pub memory_mb: u64,
+    pub disks: Vec<OvfDisk>, // In the order the hardware section lists them
+    pub nics: usize,
+    pub uefi: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct OvfDisk {
+    pub file: String, // Relative to the descriptor
+    pub capacity_bytes: Option<u64>,
+}
+
+// CIM_ResourceAllocationSettingData resource types
+const RESOURCE_OTHER: u32 = 1;
+const RESOURCE_CPU: u32 = 3;
+const RESOURCE_MEMORY: u32 = 4;
+const RESOURCE_IDE_CONTROLLER: u32 = 5;
+const RESOURCE_SCSI_CONTROLLER: u32 = 6;
+const RESOURCE_ETHERNET: u32 = 10;
+const RESOURCE_DISK: u32 = 17;
+const RESOURCE_SATA_CONTROLLER: u32 = 20;
+// Media drives, USB, video and sound get the defaults of the generated domain
+const RESOURCE_IGNORED: [u32; 7] = [14, 15, 16, 21, 23, 24, 35];
+
+/// Unpack an OVA (a plain tar archive) into `dest` and return its descriptor
+pub fn extract(ova_path: &Path, dest: &Path) -> Result<PathBuf> {
+    std::fs::create_dir_all(dest)
+        .map_err(|e| KvmError::VmOperationFailed(format!("Failed to create {}: {}", dest.display(), e)))?;
+
+    let output = tools::command(Tool::Tar)?
+        .arg("-xf")
+        .arg(ova_path)
+        .arg("-C")
+        .arg(dest)
+        .arg("--no-same-owner")
+        .output()
+        .map_err(|e| KvmError::VmOperationFailed(format!("Failed to run tar: {}", e)))?;
+    if !output.status.success() {
+        let error = String::from_utf8_lossy(&output.stderr);
+        return Err(KvmError::VmOperationFailed(format!("Failed to extract {}: {}", ova_path.display(), error.trim())));
+    }
+
+    std::fs::read_dir(dest)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .find(|path| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("ovf")))
+        .ok_or_else(|| KvmError::VmOperationFailed(format!("{} contains no .ovf descriptor", ova_path.display())))
+}
+
+/// Read the first virtual system of an OVF descriptor. Hardware the generated
+/// domain can't reproduce is an error rather than something silently dropped.
+pub fn parse_descriptor(xml: &str) -> Result<OvfDescriptor> {
+    let document = roxmltree::Document::parse(xml)
+        .map_err(|e| KvmError::XmlParsingError(format!("Invalid OVF descriptor: {}", e)))?;
+    let root = document.root_element();
+
+    // Namespace prefixes vary between exporters, so match on local names only
+    let files: HashMap<String, String> = elements(root, "File")
+        .filter_map(|file| {
+            if attribute(file, "compression").is_some_and(|compression| compression != "identity") {
+                return None;
+            }
+            Some((attribute(file, "id")?.to_string(), attribute(file, "href")?.to_string()))
+        })
+        .collect();
+    let mut disks: HashMap<String, OvfDisk> = HashMap::new();
+    for disk in elements(root, "Disk") {
+        let (Some(file), Some(disk_id)) = (attribute(disk, "fileRef").and_then(|file| files.get(file)), attribute(disk, "diskId")) else {
+            continue;
+        };
+        let capacity_bytes = match attribute(disk, "capacity").and_then(|capacity| capacity.parse::<u64>().ok()) {
+            Some(capacity) => Some(scaled(capacity, attribute(disk, "capacityAllocationUnits").unwrap_or("byte"), "disk capacity")?),
+            None => None,
+        };
+        disks.insert(disk_id.to_string(), OvfDisk { file: file.clone(), capacity_bytes });
+    }
+
+    let system = elements(root, "VirtualSystem").next()
+        .ok_or_else(|| KvmError::XmlParsingError("OVF descriptor has no VirtualSystem".to_string()))?;
+    let name = elements(system, "Name").next()
+        .and_then(|name| name.text())
+        .or_else(|| attribute(system, "id"))
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .ok_or_else(|| KvmError::XmlParsingError("OVF virtual system has no name".to_string()))?;
+    let hardware = elements(system, "VirtualHardwareSection").next()
+        .ok_or_else(|| KvmError::XmlParsingError("OVF descriptor has no VirtualHardwareSection".to_string()))?;
+
+    let mut descriptor = OvfDescriptor { name, vcpus: 1, memory_mb: 0, disks: Vec::new(), nics: 0, uefi: false };
+    let mut unsupported = Vec::new();
+
+    let items = hardware.children()
+        .filter(|node| matches!(node.tag_name().name(), "Item" | "StorageItem" | "EthernetPortItem"));
+    for item in items {
+        let field = |name: &str| item.children()
+            .find(|child| child.tag_name().name() == name)
+            .and_then(|child| child.text())
+            .map(str::trim);
+        let Some(resource_type) = field("ResourceType").and_then(|value| value.parse::<u32>().ok()) else {
+            continue;
+        };
+
+        match resource_type {
+            RESOURCE_CPU => {
+                descriptor.vcpus = field("VirtualQuantity").and_then(|value| value.parse().ok()).unwrap_or(1);
+            }
+            RESOURCE_MEMORY => {
+                let quantity = field("VirtualQuantity").and_then(|value| value.parse::<u64>().ok()).unwrap_or(0);
+                let bytes = scaled(quantity, field("AllocationUnits").unwrap_or("byte * 2^20"), "memory size")?;
+                descriptor.memory_mb = bytes / (1024 * 1024);
+            }
+            RESOURCE_DISK => {
+                let host_resource = field("HostResource").unwrap_or_default();
+                let disk = if let Some(disk_id) = host_resource.strip_prefix("ovf:/disk/") {
+                    disks.get(disk_id).cloned()
+                } else {
+                    host_resource.strip_prefix("ovf:/file/")
+                        .and_then(|file_id| files.get(file_id))
+                        .map(|file| OvfDisk { file: file.clone(), capacity_bytes: None })
+                };
+                match disk {
+                    Some(disk) => descriptor.disks.push(disk),
+                    None => return Err(KvmError::XmlParsingError(format!(
+                        "OVF disk {} refers to a missing or compressed file", host_resource
+                    ))),
+                }
+            }
+            RESOURCE_ETHERNET => descriptor.nics += 1,
+            RESOURCE_OTHER | RESOURCE_SCSI_CONTROLLER | RESOURCE_SATA_CONTROLLER => {}
+            resource_type if RESOURCE_IGNORED.contains(&resource_type) => {}
+            _ => {
+                let name = field("ElementName").or(field("Caption")).unwrap_or("unnamed device");
+                let kind = if resource_type == RESOURCE_IDE_CONTROLLER { "IDE controller" } else { "device" };
+                unsupported.push(format!("{} '{}' (resource type {})", kind, name, resource_type));
+            }
+        }
+    }
+
+    if !unsupported.is_empty() {
+        return Err(KvmError::InvalidVmConfig(format!(
+            "OVF uses hardware that can't be imported: {}", unsupported.join(", ")
+        )));
+    }
+    if descriptor.disks.is_empty() {
+        return Err(KvmError::InvalidVmConfig("OVF describes no disks".to_string()));
+    }
+    if let Some(file) = descriptor.disks.iter().map(|disk| &disk.file).find(|file| !is_plain_file_name(file)) {
+        return Err(KvmError::InvalidVmConfig(format!("OVF disk file {} is outside the archive", file)));
+    }
+
+    // VMware marks EFI guests with a firmware config entry
+    descriptor.uefi = elements(system, "Config")
+        .any(|config| attribute(config, "key") == Some("firmware") && attribute(config, "value") == Some("efi"));
+
+    debug!("Parsed OVF {}: {} vCPUs, {} MB, {} disks, {} NICs",
+           descriptor.name, descriptor.vcpus, descriptor.memory_mb, descriptor.disks.len(), descriptor.nics);
+    Ok(descriptor)
+}
+
+/// Convert a VMDK (or any image qemu-img reads) to qcow2
+pub fn convert_disk(source: &Path, dest: &Path) -> Result<()> {
+    info!("Converting {} to {}", source.display(), dest.display());
+
+    let output = tools::command(Tool::QemuImg)?
+        .args(["convert", "-O", "qcow2"])
+        .arg(source)
+        .arg(dest)
+        .output()
+        .map_err(|e| KvmError::VmOperationFailed(format!("Failed to run qemu-img: {}", e)))?;
+    if !output.status.success() {
+        let _ = std::fs::remove_file(dest);
+        let error = String::from_utf8_lossy(&output.stderr);
+        return Err(KvmError::VmOperationFailed(format!("Failed to convert {}: {}", source.display(), error.trim())));
+    }
+    Ok(())
+}
+
+fn elements<'a, 'input>(node: roxmltree::Node<'a, 'input>, name: &'a str) -> impl Iterator<Item = roxmltree::Node<'a, 'input>> + 'a {
+    node.descendants().filter(move |descendant| descendant.tag_name().name() == name)
+}
+
+fn attribute<'a>(node: roxmltree::Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.attributes().find(|attribute| attribute.name() == name).map(|attribute| attribute.value())
+}
+
+// Programmatic units as in DSP0004, e.g. "byte * 2^20", plus the names older exporters use
+/// `quantity` in `units` as bytes; a descriptor whose sizes don't fit in
+/// 64 bits is rejected rather than wrapped into a small number
+fn scaled(quantity: u64, units: &str, what: &str) -> Result<u64> {
+    quantity.checked_mul(unit_bytes(units))
+        .ok_or_else(|| KvmError::XmlParsingError(format!("OVF {} of {} {} is too large", what, quantity, units)))
+}
+
+fn unit_bytes(units: &str) -> u64 {
+    let units = units.trim();
+    if let Some(exponent) = units.split("2^").nth(1) {
+        return exponent.trim().parse::<u32>().map(|exponent| 1u64 << exponent.min(60)).unwrap_or(1);
+    }
+    match units.to_ascii_lowercase().as_str() {
+        "kilobytes" | "kb" => 1 << 10,
+        "megabytes" | "mb" => 1 << 20,
+        "gigabytes" | "gb" => 1 << 30,
+        _ => 1,
+    }
+}
+
+fn is_plain_file_name(file: &str) -> bool {
+    let path = Path::new(file);
+    path.components().count() == 1 && matches!(path.components().next(), Some(std::path::Component::Normal(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn descriptor(disk_capacity: &str, memory: &str) -> String {
+        format!(r#"<?xml version="1.0"?>
+<Envelope xmlns="http://schemas.dmtf.org/ovf/envelope/1" xmlns:ovf="http://schemas.dmtf.org/ovf/envelope/1"
+          xmlns:rasd="http://schemas.dmtf.org/wbem/wscim/1/cim-schema/2/CIM_ResourceAllocationSettingData">
+  <References><File ovf:id="file1" ovf:href="appliance-disk1.vmdk"/></References>
+  <DiskSection>
+    <Disk ovf:diskId="vmdisk1" ovf:fileRef="file1" ovf:capacity="{}" ovf:capacityAllocationUnits="byte * 2^30"/>
+  </DiskSection>
+  <VirtualSystem ovf:id="appliance">
+    <Name>appliance</Name>
+    <VirtualHardwareSection>
+      <Item><rasd:ResourceType>3</rasd:ResourceType><rasd:VirtualQuantity>2</rasd:VirtualQuantity></Item>
+      <Item>
+        <rasd:AllocationUnits>byte * 2^20</rasd:AllocationUnits>
+        <rasd:ResourceType>4</rasd:ResourceType>
+        <rasd:VirtualQuantity>{}</rasd:VirtualQuantity>
+      </Item>
+      <Item><rasd:HostResource>ovf:/disk/vmdisk1</rasd:HostResource><rasd:ResourceType>17</rasd:ResourceType></Item>
+    </VirtualHardwareSection>
+  </VirtualSystem>
+</Envelope>"#, disk_capacity, memory)
+    }
+
+    #[test]
+    fn sizes_are_scaled_by_their_units() {
+        let descriptor = parse_descriptor(&descriptor("20", "2048")).unwrap();
+        assert_eq!(descriptor.name, "appliance");
+        assert_eq!(descriptor.vcpus, 2);
+        assert_eq!(descriptor.memory_mb, 2048);
+        assert_eq!(descriptor.disks.len(), 1);
+        assert_eq!(descriptor.disks[0].capacity_bytes, Some(20 << 30));
+    }
+
+    #[test]
+    fn overflowing_sizes_are_rejected() {
+        assert!(parse_descriptor(&descriptor("18446744073709551615", "2048")).is_err());
+        assert!(parse_descriptor(&descriptor("20", "18446744073709551615")).is_err());
+    }
+}
@@ -0,0 +1,110 @@
+use tracing::{debug, warn};
+use virt::{connect::Connect, sys};
+
+use crate::errors::{KvmError, Result};
+use crate::settings::Settings;
+use crate::types::*;
+use crate::xml_parser::XmlParser;
+
+/// Resources an operation is about to add
+#[derive(Debug, Clone, Default)]
+pub struct QuotaRequest {
+    pub vcpus: u32,          // Additional vCPUs
+    pub memory_mb: u64,      // Additional memory
+    pub disk_gb: u64,        // Resulting total disk of the VM
+    pub tags: Vec<String>,   // Tags of the VM the resources go to
+}
+
+/// Current consumption across all defined domains, running or not
+pub fn usage(conn: &Connect) -> Result<QuotaUsage> {
+    let domains = conn
+        .list_all_domains(sys::VIR_CONNECT_LIST_DOMAINS_ACTIVE | sys::VIR_CONNECT_LIST_DOMAINS_INACTIVE)
+        .map_err(KvmError::LibvirtConnection)?;
+
+    let mut usage = QuotaUsage {
+        limits: Settings::load().map(|settings| settings.quotas).unwrap_or_default(),
+        ..Default::default()
+    };
+
+    for domain in domains {
+        let info = match domain.get_info() {
+            Ok(info) => info,
+            Err(e) => {
+                warn!("Skipping domain in quota usage: {}", e);
+                continue;
+            }
+        };
+        let consumption = ResourceUsage {
+            vm_count: 1,
+            vcpus: info.nr_virt_cpu,
+            memory_mb: info.max_mem / 1024, // KiB
+        };
+
+        usage.global.add(&consumption);
+
+        let xml = domain.get_xml_desc(sys::VIR_DOMAIN_XML_INACTIVE).unwrap_or_default();
+        for tag in XmlParser::parse_tags(&xml) {
+            usage.per_tag.entry(tag).or_default().add(&consumption);
+        }
+    }
+
+    debug!("Quota usage: {} VMs, {} vCPUs, {} MB", usage.global.vm_count, usage.global.vcpus, usage.global.memory_mb);
+    Ok(usage)
+}
+
+/// Fail with `KvmError::QuotaExceeded` if `request` would exceed the global
+/// quota or the quota of any of its tags
+pub fn check(conn: &Connect, request: &QuotaRequest) -> Result<()> {
+    let limits = Settings::load().map(|settings| settings.quotas).unwrap_or_default();
+    if limits.is_unlimited() {
+        return Ok(());
+    }
+
+    let usage = usage(conn)?;
+    check_scope("global", &limits.global, &usage.global, request)?;
+
+    for tag in &request.tags {
+        if let Some(quota) = limits.per_tag.get(tag) {
+            let current = usage.per_tag.get(tag).cloned().unwrap_or_default();
+            check_scope(&format!("tag:{}", tag), quota, &current, request)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn check_scope(scope: &str, quota: &ResourceQuota, current: &ResourceUsage, request: &QuotaRequest) -> Result<()> {
+    let exceeded = |resource: &str, current: u64, requested: u64, limit: u64| {
+        KvmError::QuotaExceeded(QuotaViolation {
+            scope: scope.to_string(),
+            resource: resource.to_string(),
+            current,
+            requested,
+            limit,
+            message: format!(
+                "{} quota for {} exceeded: {} in use + {} requested > {} allowed",
+                resource, scope, current, requested, limit
+            ),
+        })
+    };
+
+    if let Some(limit) = quota.max_total_vcpus {
+        if current.vcpus + request.vcpus > limit {
+            return Err(exceeded("vcpus", current.vcpus as u64, request.vcpus as u64, limit as u64));
+        }
+    }
+
+    if let Some(limit) = quota.max_total_memory_mb {
+        if current.memory_mb + request.memory_mb > limit {
+            return Err(exceeded("memory_mb", current.memory_mb, request.memory_mb, limit));
+        }
+    }
+
+    if let Some(limit) = quota.max_disk_per_vm_gb {
+        if request.disk_gb > limit {
+            return Err(exceeded("disk_gb", 0, request.disk_gb, limit));
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,162 @@
+use tracing::{info, warn, error};
+use virt::{connect::Connect, network::Network as LibvirtNetwork, storage_pool::StoragePool as LibvirtPool};
+
+use crate::audit;
+use crate::errors::{KvmError, Result};
+use crate::types::{NeedsAction, RecoveryOptions};
+
+pub const DEFAULT_NETWORK: &str = "default";
+pub const DEFAULT_POOL: &str = "default";
+
+// Shipped by the libvirt daemon packages on Fedora, Debian and Arch
+const STOCK_DEFAULT_NETWORK_XML: &str = "/usr/share/libvirt/networks/default.xml";
+
+const FALLBACK_DEFAULT_NETWORK_XML: &str = r#"<network>
+  <name>default</name>
+  <forward mode='nat'/>
+  <bridge name='virbr0' stp='on' delay='0'/>
+  <ip address='192.168.122.1' netmask='255.255.255.0'>
+    <dhcp>
+      <range start='192.168.122.2' end='192.168.122.254'/>
+    </dhcp>
+  </ip>
+</network>"#;
+
+const DEFAULT_POOL_XML: &str = r#"<pool type='dir'>
+  <name>default</name>
+  <target>
+    <path>/var/lib/libvirt/images</path>
+  </target>
+</pool>"#;
+
+/// Make sure `network_name` exists and is active, returning the network the caller
+/// should actually use.
+///
+/// An inactive network is started without asking. A missing `default` network is
+/// only defined when `options.define_missing` is set, and another active network is
+/// only substituted when `options.use_fallback` is set; otherwise a
+/// `KvmError::NeedsAction` describing the choice is returned.
+pub fn ensure_network(conn: &Connect, network_name: &str, options: &RecoveryOptions) -> Result<String> {
+    if let Ok(network) = LibvirtNetwork::lookup_by_name(conn, network_name) {
+        if !network.is_active().map_err(KvmError::LibvirtConnection)? {
+            info!("Network {} is defined but inactive, starting it", network_name);
+            network.create()
+                .map_err(|e| {
+                    error!("Failed to start network {}: {}", network_name, e);
+                    KvmError::NetworkOperationFailed(format!("Failed to start network {}: {}", network_name, e))
+                })?;
+            audit::record("network_started", network_name, "Started inactive network referenced by VM");
+        }
+        return Ok(network_name.to_string());
+    }
+
+    warn!("Network {} is not defined", network_name);
+    let fallback = first_active_network(conn);
+
+    if network_name == DEFAULT_NETWORK && options.define_missing {
+        define_default_network(conn)?;
+        return Ok(network_name.to_string());
+    }
+
+    if options.use_fallback {
+        if let Some(fallback) = fallback {
+            audit::record(
+                "network_fallback",
+                network_name,
+                &format!("Using active network {} instead of missing {}", fallback, network_name),
+            );
+            return Ok(fallback);
+        }
+    }
+
+    if network_name == DEFAULT_NETWORK {
+        return Err(KvmError::NeedsAction(NeedsAction {
+            action: "define_default_network".to_string(),
+            resource: network_name.to_string(),
+            message: "The 'default' network is not defined. Define and start it from the stock libvirt configuration?".to_string(),
+            fallback,
+        }));
+    }
+
+    match fallback {
+        Some(fallback) => Err(KvmError::NeedsAction(NeedsAction {
+            action: "use_fallback_network".to_string(),
+            resource: network_name.to_string(),
+            message: format!("Network '{}' is not defined. Use active network '{}' instead?", network_name, fallback),
+            fallback: Some(fallback),
+        })),
+        None => Err(KvmError::NetworkNotFound(network_name.to_string())),
+    }
+}
+
+/// Make sure the storage pool exists and is active.
+///
+/// Follows the same rules as [`ensure_network`]: inactive pools are started, and
+/// the `default` pool is only defined when `options.define_missing` is set.
+pub fn ensure_storage_pool(conn: &Connect, pool_name: &str, options: &RecoveryOptions) -> Result<String> {
+    if let Ok(pool) = LibvirtPool::lookup_by_name(conn, pool_name) {
+        if !pool.is_active().map_err(KvmError::LibvirtConnection)? {
+            info!("Storage pool {} is defined but inactive, starting it", pool_name);
+            pool.create(0)
+                .map_err(|e| {
+                    error!("Failed to start storage pool {}: {}", pool_name, e);
+                    KvmError::StorageOperationFailed(format!("Failed to start storage pool {}: {}", pool_name, e))
+                })?;
+            audit::record("storage_pool_started", pool_name, "Started inactive storage pool");
+        }
+        return Ok(pool_name.to_string());
+    }
+
+    warn!("Storage pool {} is not defined", pool_name);
+
+    if pool_name != DEFAULT_POOL {
+        return Err(KvmError::StoragePoolNotFound(pool_name.to_string()));
+    }
+
+    if !options.define_missing {
+        return Err(KvmError::NeedsAction(NeedsAction {
+            action: "define_default_pool".to_string(),
+            resource: pool_name.to_string(),
+            message: "The 'default' storage pool is not defined. Create it at /var/lib/libvirt/images?".to_string(),
+            fallback: None,
+        }));
+    }
+
+    let pool = LibvirtPool::define_xml(conn, DEFAULT_POOL_XML, 0)
+        .map_err(|e| KvmError::StorageOperationFailed(format!("Failed to define default storage pool: {}", e)))?;
+    pool.build(0)
+        .map_err(|e| KvmError::StorageOperationFailed(format!("Failed to build default storage pool: {}", e)))?;
+    pool.create(0)
+        .map_err(|e| KvmError::StorageOperationFailed(format!("Failed to start default storage pool: {}", e)))?;
+    if let Err(e) = pool.set_autostart(true) {
+        warn!("Failed to set autostart on default storage pool: {}", e);
+    }
+
+    audit::record("storage_pool_defined", pool_name, "Defined and started default storage pool");
+    Ok(pool_name.to_string())
+}
+
+fn define_default_network(conn: &Connect) -> Result<()> {
+    let xml = std::fs::read_to_string(STOCK_DEFAULT_NETWORK_XML).unwrap_or_else(|e| {
+        warn!("Failed to read {}: {}, using built-in definition", STOCK_DEFAULT_NETWORK_XML, e);
+        FALLBACK_DEFAULT_NETWORK_XML.to_string()
+    });
+
+    let network = LibvirtNetwork::define_xml(conn, &xml)
+        .map_err(|e| KvmError::NetworkOperationFailed(format!("Failed to define default network: {}", e)))?;
+    network.create()
+        .map_err(|e| KvmError::NetworkOperationFailed(format!("Failed to start default network: {}", e)))?;
+    if let Err(e) = network.set_autostart(true) {
+        warn!("Failed to set autostart on default network: {}", e);
+    }
+
+    audit::record("network_defined", DEFAULT_NETWORK, "Defined and started default network");
+    Ok(())
+}
+
+fn first_active_network(conn: &Connect) -> Option<String> {
+    conn.list_all_networks(virt::sys::VIR_CONNECT_LIST_NETWORKS_ACTIVE)
+        .ok()?
+        .into_iter()
+        .find_map(|network| network.get_name().ok())
+}
@@ -0,0 +1,166 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use futures::future::{BoxFuture, FutureExt, Shared};
+use once_cell::sync::Lazy;
+use tokio::sync::RwLock;
+use tracing::{debug, info};
+
+use crate::types::{RefreshOutcome, VirtualMachine};
+use crate::vm_manager::VmManager;
+
+type RefreshResult = std::result::Result<Vec<VirtualMachine>, String>;
+type RefreshFuture = Shared<BoxFuture<'static, RefreshResult>>;
+type Slot = Arc<Mutex<Option<(u64, RefreshFuture)>>>;
+
+// The refresh currently running, if any; later callers await it instead of starting another
+static IN_FLIGHT: Lazy<SingleFlight> = Lazy::new(SingleFlight::default);
+
+/// Runs at most one refresh at a time; callers arriving while one runs share
+/// its result.
+#[derive(Default)]
+struct SingleFlight {
+    current: Slot,
+    next_id: AtomicU64,
+}
+
+impl SingleFlight {
+    /// Join the running refresh or start one with `start`. Returns the result
+    /// and whether this call started the refresh.
+    async fn run<F>(&self, start: impl FnOnce() -> F) -> (RefreshResult, bool)
+    where
+        F: Future<Output = RefreshResult> + Send + 'static,
+    {
+        let (refresh, started) = {
+            let mut current = self.current.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            match current.as_ref() {
+                Some((_, refresh)) => (refresh.clone(), false),
+                None => {
+                    let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+                    let guard = ClearOnFinish { slot: self.current.clone(), id };
+                    let work = start();
+                    let refresh = async move {
+                        let _guard = guard;
+                        work.await
+                    }
+                    .boxed()
+                    .shared();
+                    *current = Some((id, refresh.clone()));
+                    (refresh, true)
+                }
+            }
+        };
+
+        (refresh.await, started)
+    }
+}
+
+/// Empties the slot when its refresh finishes, even if the caller that
+/// started it was cancelled and a joiner drove it to completion.
+struct ClearOnFinish {
+    slot: Slot,
+    id: u64,
+}
+
+impl Drop for ClearOnFinish {
+    fn drop(&mut self) {
+        let mut current = self.slot.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        // Only clear our own refresh, never one started after it finished
+        if current.as_ref().is_some_and(|(id, _)| *id == self.id) {
+            *current = None;
+        }
+    }
+}
+
+/// Refresh the VM and pool caches, joining a refresh that is already running.
+///
+/// Enumeration happens under the read lock so other commands keep working; the
+/// write lock is only held to swap the new caches in.
+pub async fn refresh_vms(manager: Arc<RwLock<VmManager>>) -> std::result::Result<RefreshOutcome, String> {
+    let (result, performed) = IN_FLIGHT.run(|| run_refresh(manager)).await;
+
+    if !performed {
+        debug!("Joined in-flight VM refresh");
+    }
+
+    result.map(|vms| RefreshOutcome { vms, performed })
+}
+
+async fn run_refresh(manager: Arc<RwLock<VmManager>>) -> RefreshResult {
+    info!("Refreshing VM list");
+
+    let snapshot = {
+        let manager = manager.read().await;
+        manager.collect_cache_snapshot().await.map_err(String::from)?
+    };
+
+    let vms = snapshot.vms.clone();
+    manager.write().await.apply_cache_snapshot(snapshot);
+    Ok(vms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+    use tokio::sync::Notify;
+
+    /// A refresh that counts its enumerations and finishes once released
+    fn counted(runs: &Arc<AtomicUsize>, release: &Arc<Notify>) -> impl FnOnce() -> BoxFuture<'static, RefreshResult> {
+        let runs = runs.clone();
+        let release = release.clone();
+        move || {
+            runs.fetch_add(1, Ordering::SeqCst);
+            async move {
+                release.notified().await;
+                Ok(Vec::new())
+            }
+            .boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_callers_share_one_enumeration() {
+        let flight = SingleFlight::default();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let release = Arc::new(Notify::new());
+
+        let mut callers = Box::pin(futures::future::join_all((0..8).map(|_| flight.run(counted(&runs, &release)))));
+        assert!(futures::poll!(&mut callers).is_pending());
+        release.notify_one();
+        let results = callers.await;
+
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert_eq!(results.iter().filter(|(_, started)| *started).count(), 1);
+        assert!(results.iter().all(|(result, _)| result.is_ok()));
+
+        // The finished refresh cleared itself, so the next caller enumerates again
+        release.notify_one();
+        let (_, started) = flight.run(counted(&runs, &release)).await;
+        assert!(started);
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn refresh_is_cleared_when_its_starter_is_cancelled() {
+        let flight = SingleFlight::default();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let release = Arc::new(Notify::new());
+
+        let starter = flight.run(counted(&runs, &release));
+        assert!(tokio::time::timeout(Duration::from_millis(10), starter).await.is_err());
+
+        // A joiner drives the abandoned refresh to completion
+        release.notify_one();
+        let (result, started) = flight.run(counted(&runs, &release)).await;
+        assert!(result.is_ok());
+        assert!(!started);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        release.notify_one();
+        let (_, started) = flight.run(counted(&runs, &release)).await;
+        assert!(started);
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use flate2::{write::ZlibEncoder, Compression, Crc};
+use tracing::debug;
+use virt::{domain::Domain, stream::Stream};
+
+use crate::errors::{KvmError, Result};
+use crate::tools::{self, Tool};
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// A screen as captured, before scaling, so requests for different sizes
+/// within the rate limit share one capture
+#[derive(Debug, Clone)]
+pub struct Capture {
+    pub image: Arc<Vec<u8>>,
+    pub taken_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// A running VM's screen. Resolved while holding the manager lock, then
+/// captured without it so a slow frame doesn't hold up other commands.
+#[derive(Clone)]
+pub struct ScreenshotTarget {
+    pub vm_id: String,
+    pub uri: String,
+    pub domain: Domain,
+    pub domain_name: String,
+    pub captures: Arc<Mutex<HashMap<String, Capture>>>, // By VM, shared with the manager
+}
+
+/// Capture the first screen of a running VM. QEMU hands back PPM; newer
+/// stacks may already send PNG. Blocks until the whole image is read.
+pub fn capture(domain: &Domain, uri: &str, domain_name: &str) -> Result<Vec<u8>> {
+    capture_stream(domain).or_else(|e| {
+        debug!("Screenshot stream of {} failed, trying virsh: {}", domain_name, e);
+        capture_virsh(uri, domain_name)
+    })
+}
+
+// Read the frame libvirt streams back over the domain's own connection
+fn capture_stream(domain: &Domain) -> std::result::Result<Vec<u8>, virt::error::Error> {
+    let stream = Stream::new(&domain.get_connect()?, 0)?;
+    let mime_type = domain.screenshot(&stream, 0, 0)?;
+
+    let mut image = Vec::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        match stream.recv(&mut buffer)? {
+            0 => break,
+            read => image.extend_from_slice(&buffer[..read]),
+        }
+    }
+    stream.finish()?;
+    debug!("Captured {} bytes of {}", image.len(), mime_type);
+    Ok(image)
+}
+
+// `virsh screenshot` writes the image locally even for remote connections
+fn capture_virsh(uri: &str, domain_name: &str) -> Result<Vec<u8>> {
+    if let Some(problem) = tools::argument_problem(domain_name) {
+        return Err(KvmError::VmOperationFailed(format!("VM name {:?} {}", domain_name, problem)));
+    }
+    // Created with a random name and owner-only permissions, and removed on drop
+    let file = tempfile::Builder::new().prefix("kvm-manager-screenshot-").tempfile()?;
+
+    let output = tools::command(Tool::Virsh)?
+        .args(["-c", uri, "screenshot", domain_name, "--screen", "0", "--file"])
+        .arg(file.path())
+        .output()
+        .map_err(|e| KvmError::VmOperationFailed(format!("Failed to execute virsh: {}", e)))?;
+    let image = std::fs::read(file.path());
+
+    if !output.status.success() {
+        let error = String::from_utf8_lossy(&output.stderr);
+        debug!("Screenshot of {} failed: {}", domain_name, error.trim());
+        return Err(KvmError::VmOperationFailed(format!("Failed to take screenshot: {}", error.trim())));
+    }
+    Ok(image?)
+}
+
+/// Turn a captured image into PNG, scaled down to at most `max_width` pixels
+/// wide. Returns the PNG with its width and height.
+pub fn to_png(image: &[u8], max_width: Option<u32>) -> Result<(Vec<u8>, u32, u32)> {
+    if image.starts_with(&PNG_SIGNATURE) {
+        let dimension = |offset: usize| image.get(offset..offset + 4)
+            .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .unwrap_or(0);
+        return Ok((image.to_vec(), dimension(16), dimension(20)));
+    }
+
+    let (width, height, pixels) = parse_ppm(image)?;
+    let scale = max_width.filter(|&max| max > 0 && max < width).map(|max| width as f64 / max as f64).unwrap_or(1.0);
+    let out_width = ((width as f64 / scale).round() as u32).max(1);
+    let out_height = ((height as f64 / scale).round() as u32).max(1);
+
+    // Nearest neighbour is plenty for a dashboard thumbnail
+    let mut rows = Vec::with_capacity((out_width as usize * 3 + 1) * out_height as usize);
+    for y in 0..out_height {
+        rows.push(0); // Filter type: none
+        let source_y = ((y as f64 * scale) as u32).min(height - 1) as usize;
+        for x in 0..out_width {
+            let source_x = ((x as f64 * scale) as u32).min(width - 1) as usize;
+            let offset = (source_y * width as usize + source_x) * 3;
+            rows.extend_from_slice(&pixels[offset..offset + 3]);
+        }
+    }
+
+    Ok((encode_png(out_width, out_height, &rows)?, out_width, out_height))
+}
+
+// Binary PPM (P6) with 8-bit samples, as QEMU's screendump writes it
+fn parse_ppm(image: &[u8]) -> Result<(u32, u32, &[u8])> {
+    let invalid = |reason: &str| KvmError::VmOperationFailed(format!("Unreadable screenshot: {}", reason));
+
+    let mut position = 0;
+    let mut fields = Vec::with_capacity(4);
+    while fields.len() < 4 {
+        while position < image.len() && (image[position].is_ascii_whitespace() || image[position] == b'#') {
+            if image[position] == b'#' {
+                while position < image.len() && image[position] != b'\n' {
+                    position += 1;
+                }
+            } else {
+                position += 1;
+            }
+        }
+        let start = position;
+        while position < image.len() && !image[position].is_ascii_whitespace() {
+            position += 1;
+        }
+        if start == position {
+            return Err(invalid("truncated header"));
+        }
+        fields.push(String::from_utf8_lossy(&image[start..position]).into_owned());
+    }
+    position += 1; // The single whitespace byte before the pixels
+
+    if fields[0] != "P6" {
+        return Err(invalid("not a binary PPM"));
+    }
+    let number = |field: &str| field.parse::<u32>().map_err(|_| invalid("bad header"));
+    let (width, height, max_value) = (number(&fields[1])?, number(&fields[2])?, number(&fields[3])?);
+    if max_value != 255 || width == 0 || height == 0 {
+        return Err(invalid("unsupported format"));
+    }
+
+    let length = width as usize * height as usize * 3;
+    let pixels = image.get(position..position + length).ok_or_else(|| invalid("truncated pixel data"))?;
+    Ok((width, height, pixels))
+}
+
+// 8-bit RGB PNG; `rows` are already prefixed with their filter byte
+fn encode_png(width: u32, height: u32, rows: &[u8]) -> Result<Vec<u8>> {
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&width.to_be_bytes());
+    ihdr.extend_from_slice(&height.to_be_bytes());
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]); // Bit depth, RGB, deflate, adaptive filtering, no interlace
+
+    let mut encoder = ZlibEncoder::new(Vec::with_capacity(rows.len() / 4), Compression::fast());
+    encoder.write_all(rows)?;
+    let zlib = encoder.finish()?;
+
+    let mut png = PNG_SIGNATURE.to_vec();
+    for (kind, data) in [(b"IHDR", ihdr.as_slice()), (b"IDAT", zlib.as_slice()), (b"IEND", &[][..])] {
+        png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        let start = png.len();
+        png.extend_from_slice(kind);
+        png.extend_from_slice(data);
+        let mut crc = Crc::new();
+        crc.update(&png[start..]);
+        png.extend_from_slice(&crc.sum().to_be_bytes());
+    }
+    Ok(png)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn ppm_is_scaled_and_compressed_into_png() {
+        let (width, height) = (64u32, 32u32);
+        let mut ppm = format!("P6\n# QEMU screendump\n{} {}\n255\n", width, height).into_bytes();
+        ppm.extend(std::iter::repeat([0x20, 0x40, 0x80]).take((width * height) as usize).flatten());
+
+        let (png, out_width, out_height) = to_png(&ppm, Some(16)).unwrap();
+        assert!(png.starts_with(&PNG_SIGNATURE));
+        assert_eq!((out_width, out_height), (16, 8));
+
+        // IDAT follows the 8-byte signature and the 25-byte IHDR chunk
+        let idat_length = u32::from_be_bytes(png[33..37].try_into().unwrap()) as usize;
+        assert_eq!(&png[37..41], b"IDAT");
+        let mut rows = Vec::new();
+        flate2::read::ZlibDecoder::new(&png[41..41 + idat_length]).read_to_end(&mut rows).unwrap();
+        assert_eq!(rows.len(), (16 * 3 + 1) * 8);
+        assert!(idat_length < rows.len());
+        assert_eq!(&rows[..4], &[0, 0x20, 0x40, 0x80]);
+    }
+}
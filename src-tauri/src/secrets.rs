@@ -0,0 +1,128 @@
+use serde::{Deserialize, Serialize};
+use tracing::{debug, error, info, warn};
+use virt::{connect::Connect, secret::Secret};
+
+use crate::audit;
+use crate::errors::{KvmError, Result};
+use crate::types::EncryptionSpec;
+use crate::xml_parser::XmlParser;
+
+/// A libvirt secret, without its value
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SecretInfo {
+    pub uuid: String,
+    pub usage_type: String,       // volume, ceph, iscsi, tls, vtpm
+    pub usage_id: Option<String>, // Volume path for disk secrets
+    pub description: Option<String>,
+}
+
+/// Define a private, persistent secret for an encrypted disk and set its passphrase.
+///
+/// The passphrase only ever goes to libvirt through the secret API; it is not
+/// written to the XML, the logs or the audit trail.
+pub fn create_disk_secret(conn: &Connect, usage_id: &str, passphrase: &str) -> Result<String> {
+    if usage_id.trim().is_empty() {
+        return Err(KvmError::InvalidVmConfig("Secret usage ID cannot be empty".to_string()));
+    }
+    if passphrase.is_empty() {
+        return Err(KvmError::InvalidVmConfig("Passphrase cannot be empty".to_string()));
+    }
+
+    info!("Creating disk secret for {}", usage_id);
+
+    let xml = format!(
+        r#"<secret ephemeral='no' private='yes'>
+  <description>Disk passphrase managed by KVM Manager</description>
+  <usage type='volume'>
+    <volume>{}</volume>
+  </usage>
+</secret>"#,
+        XmlParser::escape(usage_id)
+    );
+
+    let secret = Secret::define_xml(conn, &xml, 0)
+        .map_err(|e| {
+            error!("Failed to define secret for {}: {}", usage_id, e);
+            KvmError::StorageOperationFailed(format!("Failed to define secret: {}", e))
+        })?;
+
+    if let Err(e) = secret.set_value(passphrase.as_bytes(), 0) {
+        error!("Failed to set value of secret for {}: {}", usage_id, e);
+        // Don't leave a secret without a value behind
+        if let Err(undefine_error) = secret.undefine() {
+            warn!("Failed to remove incomplete secret for {}: {}", usage_id, undefine_error);
+        }
+        return Err(KvmError::StorageOperationFailed(format!("Failed to set secret value: {}", e)));
+    }
+
+    let uuid = secret.get_uuid_string().map_err(KvmError::LibvirtConnection)?;
+    audit::record("secret_created", &uuid, &format!("Defined disk secret for {}", usage_id));
+    Ok(uuid)
+}
+
+pub fn list_secrets(conn: &Connect) -> Result<Vec<SecretInfo>> {
+    let secrets = conn.list_all_secrets(0).map_err(KvmError::LibvirtConnection)?;
+
+    let mut infos = Vec::new();
+    for secret in secrets {
+        let uuid = match secret.get_uuid_string() {
+            Ok(uuid) => uuid,
+            Err(e) => {
+                warn!("Skipping secret without a UUID: {}", e);
+                continue;
+            }
+        };
+
+        let xml = secret.get_xml_desc(0).unwrap_or_default();
+        infos.push(SecretInfo {
+            usage_type: usage_type_name(secret.get_usage_type().unwrap_or(0)).to_string(),
+            usage_id: secret.get_usage_id().ok(),
+            description: secret_description(&xml),
+            uuid,
+        });
+    }
+
+    debug!("Found {} libvirt secrets", infos.len());
+    Ok(infos)
+}
+
+pub fn delete_secret(conn: &Connect, uuid: &str) -> Result<()> {
+    info!("Deleting secret {}", uuid);
+
+    let secret = Secret::lookup_by_uuid_string(conn, uuid)
+        .map_err(|e| KvmError::StorageOperationFailed(format!("Secret {} not found: {}", uuid, e)))?;
+    secret.undefine()
+        .map_err(|e| {
+            error!("Failed to delete secret {}: {}", uuid, e);
+            KvmError::StorageOperationFailed(format!("Failed to delete secret: {}", e))
+        })?;
+
+    audit::record("secret_deleted", uuid, "Undefined libvirt secret");
+    Ok(())
+}
+
+/// `<encryption>` element referencing the secret, valid in both disk and volume XML
+pub fn encryption_xml(spec: &EncryptionSpec) -> String {
+    format!(
+        "<encryption format='{}'>\n  <secret type='passphrase' uuid='{}'/>\n</encryption>",
+        XmlParser::escape(&spec.format),
+        XmlParser::escape(&spec.secret_uuid)
+    )
+}
+
+fn usage_type_name(usage_type: u32) -> &'static str {
+    match usage_type {
+        virt::sys::VIR_SECRET_USAGE_TYPE_VOLUME => "volume",
+        virt::sys::VIR_SECRET_USAGE_TYPE_CEPH => "ceph",
+        virt::sys::VIR_SECRET_USAGE_TYPE_ISCSI => "iscsi",
+        virt::sys::VIR_SECRET_USAGE_TYPE_TLS => "tls",
+        virt::sys::VIR_SECRET_USAGE_TYPE_VTPM => "vtpm",
+        _ => "none",
+    }
+}
+
+fn secret_description(xml: &str) -> Option<String> {
+    let start = xml.find("<description>")? + "<description>".len();
+    let end = xml[start..].find("</description>")?;
+    Some(xml[start..start + end].trim().to_string()).filter(|d| !d.is_empty())
+}
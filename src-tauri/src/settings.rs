@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info};
+
+use crate::errors::{KvmError, Result};
+use crate::types::*;
+
+/// Backend settings persisted as JSON in the user's config directory
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Settings {
+    #[serde(default)]
+    pub hardware_presets: Vec<HardwarePreset>,
+    #[serde(default)]
+    pub tool_paths: HashMap<String, String>, // Binary name -> absolute path override
+    #[serde(default)]
+    pub disk_space_policy: DiskSpacePolicy,
+    #[serde(default)]
+    pub watch_imports: bool,          // Announce new images in pool and import directories
+    #[serde(default)]
+    pub import_directories: Vec<String>,
+    #[serde(default)]
+    pub quotas: QuotaSettings,
+    #[serde(default)]
+    pub metrics_retention_days: Option<u32>, // Metric history kept on disk; defaults to 7
+}
+
+impl Settings {
+    pub fn path() -> PathBuf {
+        let base = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+            .unwrap_or_else(std::env::temp_dir);
+
+        base.join("kvm-manager").join("settings.json")
+    }
+
+    /// Load settings, falling back to defaults when the file does not exist yet
+    pub fn load() -> Result<Self> {
+        let path = Self::path();
+        if !path.exists() {
+            debug!("No settings file at {}, using defaults", path.display());
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(&path)?;
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = Self::path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        fs::write(&path, serde_json::to_string_pretty(self)?)?;
+        info!("Saved settings to {}", path.display());
+        Ok(())
+    }
+
+    pub fn find_preset(&self, name: &str) -> Result<&HardwarePreset> {
+        self.hardware_presets
+            .iter()
+            .find(|preset| preset.name == name)
+            .ok_or_else(|| KvmError::InvalidVmConfig(format!("Hardware preset '{}' not found", name)))
+    }
+
+    /// Insert a preset, replacing any existing preset with the same name
+    pub fn upsert_preset(&mut self, preset: HardwarePreset) -> Result<()> {
+        if preset.name.trim().is_empty() {
+            return Err(KvmError::InvalidVmConfig("Preset name cannot be empty".to_string()));
+        }
+
+        match self.hardware_presets.iter_mut().find(|p| p.name == preset.name) {
+            Some(existing) => *existing = preset,
+            None => self.hardware_presets.push(preset),
+        }
+        Ok(())
+    }
+
+    pub fn remove_preset(&mut self, name: &str) -> Result<()> {
+        let before = self.hardware_presets.len();
+        self.hardware_presets.retain(|preset| preset.name != name);
+        if self.hardware_presets.len() == before {
+            return Err(KvmError::InvalidVmConfig(format!("Hardware preset '{}' not found", name)));
+        }
+        Ok(())
+    }
+}
+
+/// Fill the fields the caller left unset (None) from the preset, then from the
+/// built-in defaults. Explicit values always win, even empty or zero ones.
+pub fn apply_hardware_defaults(mut config: VmConfig, preset: Option<&HardwarePreset>) -> VmConfig {
+    fn fill<T>(field: &mut Option<T>, preset: Option<T>, default: T) {
+        if field.is_none() {
+            *field = Some(preset.unwrap_or(default));
+        }
+    }
+
+    let preset = preset.cloned().unwrap_or_default();
+
+    fill(&mut config.memory, preset.memory, 2048);
+    fill(&mut config.vcpus, preset.vcpus, 2);
+    fill(&mut config.disk_size, preset.disk_size, 20);
+    fill(&mut config.os_type, preset.os_type, "linux".to_string());
+
+    fill(&mut config.network_config.model, preset.network_model, "virtio".to_string());
+    if config.network_config.network_name.is_none() {
+        config.network_config.network_name = preset.network_name;
+    }
+
+    fill(&mut config.storage_config.pool_name, preset.storage_pool, "default".to_string());
+    fill(&mut config.storage_config.format, preset.storage_format, "qcow2".to_string());
+    fill(&mut config.storage_config.bus, preset.storage_bus, "virtio".to_string());
+    fill(&mut config.storage_config.cache, preset.storage_cache, "none".to_string());
+
+    fill(&mut config.display_config.graphics_type, preset.graphics_type, "vnc".to_string());
+    fill(&mut config.display_config.listen, preset.graphics_listen, "127.0.0.1".to_string());
+
+    fill(&mut config.boot_config.boot_order, preset.boot_order, vec!["hd".to_string(), "cdrom".to_string()]);
+
+    config
+}
+
+/// Resolve a caller-supplied config against an optional named preset
+pub fn resolve_vm_config(config: VmConfig, preset_name: Option<&str>) -> Result<VmConfig> {
+    match preset_name {
+        Some(name) => {
+            let settings = Settings::load()?;
+            let preset = settings.find_preset(name)?;
+            debug!("Applying hardware preset {} to VM {}", name, config.name);
+            Ok(apply_hardware_defaults(config, Some(preset)))
+        }
+        None => Ok(apply_hardware_defaults(config, None)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(value: serde_json::Value) -> VmConfig {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn preset() -> HardwarePreset {
+        HardwarePreset {
+            name: "workstation".to_string(),
+            memory: Some(8192),
+            vcpus: Some(4),
+            network_model: Some("e1000e".to_string()),
+            network_name: Some("lan".to_string()),
+            storage_bus: Some("sata".to_string()),
+            graphics_type: Some("spice".to_string()),
+            boot_order: Some(vec!["cdrom".to_string(), "hd".to_string()]),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn built_in_defaults_fill_an_empty_config() {
+        let config = apply_hardware_defaults(config(serde_json::json!({ "name": "plain" })), None);
+
+        assert_eq!(config.memory, Some(2048));
+        assert_eq!(config.vcpus, Some(2));
+        assert_eq!(config.disk_size, Some(20));
+        assert_eq!(config.os_type.as_deref(), Some("linux"));
+        assert_eq!(config.network_config.model.as_deref(), Some("virtio"));
+        assert_eq!(config.network_config.network_name, None);
+        assert_eq!(config.storage_config.pool_name.as_deref(), Some("default"));
+        assert_eq!(config.storage_config.format.as_deref(), Some("qcow2"));
+        assert_eq!(config.storage_config.bus.as_deref(), Some("virtio"));
+        assert_eq!(config.storage_config.cache.as_deref(), Some("none"));
+        assert_eq!(config.display_config.graphics_type.as_deref(), Some("vnc"));
+        assert_eq!(config.display_config.listen.as_deref(), Some("127.0.0.1"));
+        assert_eq!(config.boot_config.boot_order, Some(vec!["hd".to_string(), "cdrom".to_string()]));
+    }
+
+    #[test]
+    fn preset_beats_built_in_defaults() {
+        let config = apply_hardware_defaults(config(serde_json::json!({ "name": "preset" })), Some(&preset()));
+
+        assert_eq!(config.memory, Some(8192));
+        assert_eq!(config.vcpus, Some(4));
+        assert_eq!(config.network_config.model.as_deref(), Some("e1000e"));
+        assert_eq!(config.network_config.network_name.as_deref(), Some("lan"));
+        assert_eq!(config.storage_config.bus.as_deref(), Some("sata"));
+        assert_eq!(config.display_config.graphics_type.as_deref(), Some("spice"));
+        assert_eq!(config.boot_config.boot_order, Some(vec!["cdrom".to_string(), "hd".to_string()]));
+
+        // Fields the preset leaves out still get the built-in defaults
+        assert_eq!(config.disk_size, Some(20));
+        assert_eq!(config.storage_config.format.as_deref(), Some("qcow2"));
+        assert_eq!(config.display_config.listen.as_deref(), Some("127.0.0.1"));
+    }
+
+    #[test]
+    fn explicit_values_beat_the_preset() {
+        let config = apply_hardware_defaults(config(serde_json::json!({
+            "name": "explicit",
+            "memory": 1024,
+            "vcpus": 1,
+            "network_config": { "model": "virtio", "network_name": "default" },
+            "storage_config": { "bus": "virtio" },
+            "display_config": { "graphics_type": "vnc" },
+            "boot_config": { "boot_order": ["hd"] },
+        })), Some(&preset()));
+
+        assert_eq!(config.memory, Some(1024));
+        assert_eq!(config.vcpus, Some(1));
+        assert_eq!(config.network_config.model.as_deref(), Some("virtio"));
+        assert_eq!(config.network_config.network_name.as_deref(), Some("default"));
+        assert_eq!(config.storage_config.bus.as_deref(), Some("virtio"));
+        assert_eq!(config.display_config.graphics_type.as_deref(), Some("vnc"));
+        assert_eq!(config.boot_config.boot_order, Some(vec!["hd".to_string()]));
+    }
+
+    #[test]
+    fn explicit_empty_and_zero_values_are_kept() {
+        let config = apply_hardware_defaults(config(serde_json::json!({
+            "name": "empty",
+            "disk_size": 0,
+            "display_config": { "listen": "" },
+            "boot_config": { "boot_order": [] },
+        })), Some(&preset()));
+
+        assert_eq!(config.disk_size, Some(0));
+        assert_eq!(config.display_config.listen.as_deref(), Some(""));
+        assert_eq!(config.boot_config.boot_order, Some(Vec::new()));
+    }
+
+    #[test]
+    fn null_counts_as_unset() {
+        let config = apply_hardware_defaults(config(serde_json::json!({
+            "name": "null",
+            "memory": null,
+            "storage_config": { "format": null },
+        })), Some(&preset()));
+
+        assert_eq!(config.memory, Some(8192));
+        assert_eq!(config.storage_config.format.as_deref(), Some("qcow2"));
+    }
+}
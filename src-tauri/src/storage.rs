@@ -1,7 +1,11 @@
 use tracing::{info, error};
 use virt::{connect::Connect, storage_pool::StoragePool as LibvirtPool, storage_vol::StorageVol};
 use crate::errors::{KvmError, Result};
+use crate::recovery;
+use crate::secrets;
+use crate::tools::{self, Tool};
 use crate::types::*;
+use crate::xml_parser::XmlParser;
 
 pub struct StorageManager {
     connection: Connect,
@@ -15,6 +19,9 @@ impl StorageManager {
     pub async fn create_volume(&self, pool_name: &str, volume_config: &VolumeConfig) -> Result<String> {
         info!("Creating volume {} in pool {}", volume_config.name, pool_name);
         
+        // Start the pool if it's defined but inactive
+        recovery::ensure_storage_pool(&self.connection, pool_name, &RecoveryOptions::default())?;
+        
         // Get the storage pool
         let pool = LibvirtPool::lookup_by_name(&self.connection, pool_name)
             .map_err(|e| {
@@ -83,6 +90,7 @@ impl StorageManager {
             format: "qcow2".to_string(), // Default to qcow2 for clones
             capacity: src_info.capacity,
             allocation: Some(0), // Thin provisioning
+            encryption: None,
         };
         
         // Generate clone XML with backing file reference
@@ -149,6 +157,11 @@ impl StorageManager {
     fn generate_volume_xml(&self, config: &VolumeConfig) -> Result<String> {
         let allocation = config.allocation.unwrap_or(config.capacity);
         
+        // libvirt passes the matching encrypt options to qemu-img when creating the image
+        let encryption = config.encryption.as_ref()
+            .map(|spec| format!("\n    {}", secrets::encryption_xml(spec).replace('\n', "\n    ")))
+            .unwrap_or_default();
+        
         let xml = format!(
             r#"<volume type='file'>
   <name>{}</name>
@@ -158,19 +171,143 @@ impl StorageManager {
   <capacity unit='bytes'>{}</capacity>
   <allocation unit='bytes'>{}</allocation>
   <target>
-    <format type='{}'/>
+    <format type='{}'/>{}
   </target>
 </volume>"#,
             config.name,
             config.name, // Use name as key for simplicity
             config.capacity,
             allocation,
-            config.format
+            config.format,
+            encryption
         );
         
         Ok(xml)
     }
     
+    /// Make a full copy of the disk image at `source_path` in the same directory,
+    /// named `target_name`, and return the new path.
+    ///
+    /// Images that belong to a pool are copied by libvirt so the pool sees the new
+    /// volume; loose files fall back to `qemu-img convert`.
+    pub fn copy_image(&self, source_path: &str, target_name: &str, format: &str) -> Result<String> {
+        info!("Copying disk image {} to {}", source_path, target_name);
+        Self::validate_image_name(target_name)?;
+        
+        if let Ok(source) = StorageVol::lookup_by_path(&self.connection, source_path) {
+            let pool = LibvirtPool::lookup_by_volume(&source)
+                .map_err(|e| KvmError::StorageOperationFailed(format!("Failed to find pool of {}: {}", source_path, e)))?;
+            let info = source.get_info()
+                .map_err(|e| KvmError::StorageOperationFailed(format!("Failed to get source volume info: {}", e)))?;
+            
+            let volume_xml = format!(
+                r#"<volume type='file'>
+  <name>{}</name>
+  <capacity unit='bytes'>{}</capacity>
+  <target>
+    <format type='{}'/>
+  </target>
+</volume>"#,
+                XmlParser::escape(target_name),
+                info.capacity,
+                format
+            );
+            
+            let copy = StorageVol::create_xml_from(&pool, &volume_xml, &source, 0)
+                .map_err(|e| {
+                    error!("Failed to copy volume {}: {}", source_path, e);
+                    KvmError::StorageOperationFailed(format!("Failed to copy {}: {}", source_path, e))
+                })?;
+            return copy.get_path()
+                .map_err(|e| KvmError::StorageOperationFailed(format!("Failed to get copied volume path: {}", e)));
+        }
+        
+        let target = std::path::Path::new(source_path).with_file_name(target_name);
+        if target.exists() {
+            return Err(KvmError::StorageOperationFailed(format!("{} already exists", target.display())));
+        }
+        let target = target.to_string_lossy().into_owned();
+        
+        let output = tools::command(Tool::QemuImg)?
+            .args(["convert", "-f", format, "-O", format, source_path, &target])
+            .output()
+            .map_err(|e| KvmError::StorageOperationFailed(format!("Failed to run qemu-img: {}", e)))?;
+        
+        if !output.status.success() {
+            // Don't leave a partial copy behind
+            let _ = std::fs::remove_file(&target);
+            let error = String::from_utf8_lossy(&output.stderr);
+            return Err(KvmError::StorageOperationFailed(format!("Failed to copy {}: {}", source_path, error.trim())));
+        }
+        
+        Ok(target)
+    }
+    
+    /// Create a thin qcow2 overlay named `target_name` next to `backing_path`,
+    /// which becomes its read-only backing file. Returns the overlay's path.
+    pub fn create_overlay(&self, backing_path: &str, target_name: &str) -> Result<String> {
+        info!("Creating overlay {} backed by {}", target_name, backing_path);
+        Self::validate_image_name(target_name)?;
+        
+        if let Ok(source) = StorageVol::lookup_by_path(&self.connection, backing_path) {
+            let pool = LibvirtPool::lookup_by_volume(&source)
+                .map_err(|e| KvmError::StorageOperationFailed(format!("Failed to find pool of {}: {}", backing_path, e)))?;
+            let info = source.get_info()
+                .map_err(|e| KvmError::StorageOperationFailed(format!("Failed to get source volume info: {}", e)))?;
+            
+            let config = VolumeConfig {
+                name: target_name.to_string(),
+                format: "qcow2".to_string(),
+                capacity: info.capacity,
+                allocation: Some(0),
+                encryption: None,
+            };
+            let overlay = StorageVol::create_xml(&pool, &self.generate_clone_volume_xml(&config, &source)?, 0)
+                .map_err(|e| {
+                    error!("Failed to create overlay {}: {}", target_name, e);
+                    KvmError::StorageOperationFailed(format!("Failed to create overlay: {}", e))
+                })?;
+            return overlay.get_path()
+                .map_err(|e| KvmError::StorageOperationFailed(format!("Failed to get overlay path: {}", e)));
+        }
+        
+        let target = std::path::Path::new(backing_path).with_file_name(target_name);
+        if target.exists() {
+            return Err(KvmError::StorageOperationFailed(format!("{} already exists", target.display())));
+        }
+        let target = target.to_string_lossy().into_owned();
+        
+        let output = tools::command(Tool::QemuImg)?
+            .args(["create", "-f", "qcow2", "-b", backing_path, "-F", "qcow2", &target])
+            .output()
+            .map_err(|e| KvmError::StorageOperationFailed(format!("Failed to run qemu-img: {}", e)))?;
+        
+        if !output.status.success() {
+            let error = String::from_utf8_lossy(&output.stderr);
+            return Err(KvmError::StorageOperationFailed(format!("Failed to create overlay: {}", error.trim())));
+        }
+        
+        Ok(target)
+    }
+    
+    /// Copies and overlays are created next to their source, so their names must
+    /// be a single path component
+    fn validate_image_name(name: &str) -> Result<()> {
+        if name.is_empty() || name == "." || name == ".." || name.chars().any(std::path::is_separator) {
+            return Err(KvmError::StorageOperationFailed(format!("Image name {:?} must be a single file name", name)));
+        }
+        Ok(())
+    }
+    
+    /// Delete a disk image by path, through libvirt when a pool owns it
+    pub fn remove_image(&self, path: &str) -> Result<()> {
+        match StorageVol::lookup_by_path(&self.connection, path) {
+            Ok(volume) => volume.delete(0)
+                .map_err(|e| KvmError::StorageOperationFailed(format!("Failed to delete {}: {}", path, e))),
+            Err(_) => std::fs::remove_file(path).map_err(KvmError::from),
+        }
+    }
+    
     fn generate_clone_volume_xml(&self, config: &VolumeConfig, source_volume: &StorageVol) -> Result<String> {
         let source_path = source_volume.get_path()
             .map_err(|e| KvmError::StorageOperationFailed(format!("Failed to get source path: {}", e)))?;
@@ -254,48 +391,157 @@ impl StorageManager {
         Ok(volume_infos)
     }
     
-    pub async fn create_storage_pool(&self, pool_name: &str, pool_type: &str, pool_path: &str, auto_start: bool) -> Result<String> {
-        info!("Creating storage pool: {}", pool_name);
+    /// Define, optionally build, and start a storage pool. A pool that fails
+    /// to build or start is undefined again so the name stays free.
+    pub async fn create_storage_pool(&self, config: &StoragePoolCreateConfig) -> Result<String> {
+        info!("Creating {} storage pool: {}", config.pool_type, config.name);
         
-        let pool_xml = self.generate_pool_xml(pool_name, pool_type, pool_path)?;
+        if config.name.trim().is_empty() {
+            return Err(KvmError::StorageOperationFailed("Pool name cannot be empty".to_string()));
+        }
+        if LibvirtPool::lookup_by_name(&self.connection, &config.name).is_ok() {
+            return Err(KvmError::StorageOperationFailed(format!("Storage pool {} already exists", config.name)));
+        }
+        
+        let pool_xml = self.generate_pool_xml(config)?;
         
         // Define the pool
         let pool = LibvirtPool::define_xml(&self.connection, &pool_xml, 0)
             .map_err(|e| {
-                error!("Failed to define storage pool {}: {}", pool_name, e);
+                error!("Failed to define storage pool {}: {}", config.name, e);
                 KvmError::StorageOperationFailed(format!("Failed to create storage pool: {}", e))
             })?;
         
-        // Build the pool if it's a directory pool
-        if pool_type == "dir" {
-            pool.build(0)
+        let started = (|| {
+            if config.build {
+                pool.build(0)
+                    .map_err(|e| {
+                        error!("Failed to build storage pool {}: {}", config.name, e);
+                        KvmError::StorageOperationFailed(format!("Failed to build storage pool: {}", e))
+                    })?;
+            }
+            
+            pool.create(0)
+                .map_err(|e| {
+                    error!("Failed to start storage pool {}: {}", config.name, e);
+                    KvmError::StorageOperationFailed(format!("Failed to start storage pool: {}", e))
+                })
+        })();
+        if let Err(e) = started {
+            let _ = pool.undefine();
+            return Err(e);
+        }
+        
+        if config.autostart {
+            pool.set_autostart(true)
                 .map_err(|e| {
-                    error!("Failed to build storage pool {}: {}", pool_name, e);
-                    KvmError::StorageOperationFailed(format!("Failed to build storage pool: {}", e))
+                    error!("Failed to set autostart for storage pool {}: {}", config.name, e);
+                    KvmError::StorageOperationFailed(format!("Failed to set autostart: {}", e))
                 })?;
         }
         
-        // Start the pool if requested
-        if auto_start {
-            pool.create(0)
+        info!("Successfully created storage pool: {}", config.name);
+        Ok(config.name.clone())
+    }
+    
+    fn lookup_pool(&self, pool_name: &str) -> Result<LibvirtPool> {
+        LibvirtPool::lookup_by_name(&self.connection, pool_name)
+            .map_err(|e| KvmError::StorageOperationFailed(format!("Storage pool not found: {}", e)))
+    }
+    
+    /// Stop and undefine a pool. With `delete_storage` the backing storage
+    /// (directory, volume group) is removed as well, which destroys every
+    /// volume in it.
+    pub async fn delete_storage_pool(&self, pool_name: &str, delete_storage: bool) -> Result<()> {
+        info!("Deleting storage pool {} (delete storage: {})", pool_name, delete_storage);
+        
+        let pool = self.lookup_pool(pool_name)?;
+        if pool.is_active().map_err(KvmError::LibvirtConnection)? {
+            pool.destroy()
                 .map_err(|e| {
-                    error!("Failed to start storage pool {}: {}", pool_name, e);
-                    KvmError::StorageOperationFailed(format!("Failed to start storage pool: {}", e))
+                    error!("Failed to stop storage pool {}: {}", pool_name, e);
+                    KvmError::StorageOperationFailed(format!("Failed to stop storage pool: {}", e))
                 })?;
-            
-            pool.set_autostart(true)
+        }
+        
+        // libvirt only deletes the storage of an inactive pool
+        if delete_storage {
+            pool.delete(0)
                 .map_err(|e| {
-                    error!("Failed to set autostart for storage pool {}: {}", pool_name, e);
-                    KvmError::StorageOperationFailed(format!("Failed to set autostart: {}", e))
+                    error!("Failed to delete storage of pool {}: {}", pool_name, e);
+                    KvmError::StorageOperationFailed(format!("Failed to delete pool storage: {}", e))
                 })?;
         }
         
-        info!("Successfully created storage pool: {}", pool_name);
-        Ok(pool_name.to_string())
+        pool.undefine()
+            .map_err(|e| {
+                error!("Failed to undefine storage pool {}: {}", pool_name, e);
+                KvmError::StorageOperationFailed(format!("Failed to undefine storage pool: {}", e))
+            })?;
+        
+        info!("Successfully deleted storage pool: {}", pool_name);
+        Ok(())
+    }
+    
+    pub async fn start_storage_pool(&self, pool_name: &str) -> Result<()> {
+        info!("Starting storage pool: {}", pool_name);
+        
+        let pool = self.lookup_pool(pool_name)?;
+        if pool.is_active().map_err(KvmError::LibvirtConnection)? {
+            return Ok(());
+        }
+        
+        pool.create(0)
+            .map_err(|e| {
+                error!("Failed to start storage pool {}: {}", pool_name, e);
+                KvmError::StorageOperationFailed(format!("Failed to start storage pool: {}", e))
+            })?;
+        Ok(())
+    }
+    
+    pub async fn stop_storage_pool(&self, pool_name: &str) -> Result<()> {
+        info!("Stopping storage pool: {}", pool_name);
+        
+        let pool = self.lookup_pool(pool_name)?;
+        if !pool.is_active().map_err(KvmError::LibvirtConnection)? {
+            return Ok(());
+        }
+        
+        pool.destroy()
+            .map_err(|e| {
+                error!("Failed to stop storage pool {}: {}", pool_name, e);
+                KvmError::StorageOperationFailed(format!("Failed to stop storage pool: {}", e))
+            })?;
+        Ok(())
+    }
+    
+    /// Rescan the pool so files copied into it outside libvirt show up as volumes
+    pub async fn refresh_storage_pool(&self, pool_name: &str) -> Result<()> {
+        info!("Refreshing storage pool: {}", pool_name);
+        
+        let pool = self.lookup_pool(pool_name)?;
+        if !pool.is_active().map_err(KvmError::LibvirtConnection)? {
+            return Err(KvmError::StorageOperationFailed(format!("Storage pool {} is not active", pool_name)));
+        }
+        
+        pool.refresh(0)
+            .map_err(|e| {
+                error!("Failed to refresh storage pool {}: {}", pool_name, e);
+                KvmError::StorageOperationFailed(format!("Failed to refresh storage pool: {}", e))
+            })?;
+        Ok(())
     }
     
-    fn generate_pool_xml(&self, name: &str, pool_type: &str, path: &str) -> Result<String> {
-        let xml = match pool_type {
+    fn generate_pool_xml(&self, config: &StoragePoolCreateConfig) -> Result<String> {
+        let required = |value: &Option<String>, field: &str| {
+            value.as_deref()
+                .filter(|value| !value.trim().is_empty())
+                .map(XmlParser::escape)
+                .ok_or_else(|| KvmError::StorageOperationFailed(format!("{} pools need a {}", config.pool_type, field)))
+        };
+        let name = XmlParser::escape(&config.name);
+        
+        let xml = match config.pool_type.as_str() {
             "dir" => format!(
                 r#"<pool type='dir'>
   <name>{}</name>
@@ -304,24 +550,51 @@ impl StorageManager {
   </target>
 </pool>"#,
                 name,
-                path
+                required(&config.target_path, "target path")?
             ),
-            "logical" => format!(
-                r#"<pool type='logical'>
+            "netfs" => format!(
+                r#"<pool type='netfs'>
   <name>{}</name>
   <source>
-    <name>{}</name>
+    <host name='{}'/>
+    <dir path='{}'/>
+    <format type='nfs'/>
   </source>
   <target>
-    <path>/dev/{}</path>
+    <path>{}</path>
   </target>
 </pool>"#,
                 name,
-                name, // Use name as volume group name
-                name
+                required(&config.source_host, "source host")?,
+                required(&config.source_path, "source path")?,
+                required(&config.target_path, "target path")?
             ),
+            "logical" => {
+                let volume_group = config.source_name.as_deref()
+                    .filter(|vg| !vg.trim().is_empty())
+                    .map(XmlParser::escape)
+                    .unwrap_or_else(|| name.clone());
+                let device = config.source_device.as_deref()
+                    .map(|device| format!("\n    <device path='{}'/>", XmlParser::escape(device)))
+                    .unwrap_or_default();
+                format!(
+                    r#"<pool type='logical'>
+  <name>{}</name>
+  <source>
+    <name>{}</name>{}
+  </source>
+  <target>
+    <path>/dev/{}</path>
+  </target>
+</pool>"#,
+                    name,
+                    volume_group,
+                    device,
+                    volume_group
+                )
+            }
             _ => {
-                return Err(KvmError::StorageOperationFailed(format!("Unsupported pool type: {}", pool_type)));
+                return Err(KvmError::StorageOperationFailed(format!("Unsupported pool type: {}", config.pool_type)));
             }
         };
         
@@ -0,0 +1,129 @@
+use std::path::Path;
+use sysinfo::Disks;
+use tracing::debug;
+
+use crate::formatting::format_bytes;
+use crate::tools::{self, Tool};
+use crate::types::FilesystemIssue;
+
+/// Where a disk image or block device physically lives on the host
+#[derive(Debug, Clone, Default)]
+pub struct DevicePlacement {
+    pub mount_point: Option<String>,
+    pub source_device: Option<String>,
+    pub physical_devices: Vec<String>,
+}
+
+/// Resolve a disk path through its mount and block device to the underlying
+/// physical disks (partitions, LVM and dm-crypt layers are walked down).
+pub fn resolve_placement(path: &str) -> DevicePlacement {
+    let mut placement = DevicePlacement::default();
+
+    let source_device = if path.starts_with("/dev/") {
+        Some(path.to_string())
+    } else {
+        let (mount_point, source) = find_mount(path);
+        placement.mount_point = mount_point;
+        source
+    };
+
+    if let Some(device) = &source_device {
+        placement.physical_devices = physical_disks_for(device);
+    }
+    placement.source_device = source_device;
+
+    debug!("Resolved {} to physical devices {:?}", path, placement.physical_devices);
+    placement
+}
+
+fn find_mount(path: &str) -> (Option<String>, Option<String>) {
+    let mut command = match tools::try_command(Tool::Findmnt) {
+        Some(command) => command,
+        None => return (None, None),
+    };
+    let output = match command
+        .args(["-n", "-o", "TARGET,SOURCE", "--target", path])
+        .output()
+    {
+        Ok(output) if output.status.success() => output,
+        _ => return (None, None),
+    };
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut parts = stdout.split_whitespace();
+    let target = parts.next().map(|s| s.to_string());
+    // Btrfs subvolumes are reported as /dev/sda1[/subvol]
+    let source = parts.next()
+        .map(|s| s.split('[').next().unwrap_or(s).to_string())
+        .filter(|s| s.starts_with("/dev/"));
+
+    (target, source)
+}
+
+fn physical_disks_for(device: &str) -> Vec<String> {
+    // -s inverts the tree so we walk from the device down to its backing disks
+    let mut command = match tools::try_command(Tool::Lsblk) {
+        Some(command) => command,
+        None => return Vec::new(),
+    };
+    let output = match command
+        .args(["-n", "-s", "-r", "-o", "NAME,TYPE", device])
+        .output()
+    {
+        Ok(output) if output.status.success() => output,
+        _ => return Vec::new(),
+    };
+
+    let mut disks: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let mut parts = line.split_whitespace();
+            match (parts.next(), parts.next()) {
+                (Some(name), Some("disk")) => Some(name.to_string()),
+                _ => None,
+            }
+        })
+        .collect();
+
+    disks.sort();
+    disks.dedup();
+    disks
+}
+
+/// Check that the filesystem holding `path` is writable and has at least
+/// `min_free_bytes` available, returning the problem if it does not.
+pub fn check_filesystem(path: &str, min_free_bytes: u64) -> Option<FilesystemIssue> {
+    let resolved = std::fs::canonicalize(path).unwrap_or_else(|_| Path::new(path).to_path_buf());
+    let disks = Disks::new_with_refreshed_list();
+
+    // The most specific mount containing the path wins
+    let disk = disks
+        .list()
+        .iter()
+        .filter(|disk| resolved.starts_with(disk.mount_point()))
+        .max_by_key(|disk| disk.mount_point().as_os_str().len())?;
+
+    let free_bytes = disk.available_space();
+    let read_only = disk.is_read_only();
+    let mount_point = disk.mount_point().display().to_string();
+    debug!("{} is on {} with {} free (read-only: {})", path, mount_point, free_bytes, read_only);
+
+    let message = if read_only {
+        format!("{} is on read-only filesystem {}", path, mount_point)
+    } else if free_bytes < min_free_bytes {
+        format!(
+            "{} is on {} with only {} free (at least {} required)",
+            path, mount_point, format_bytes(free_bytes), format_bytes(min_free_bytes)
+        )
+    } else {
+        return None;
+    };
+
+    Some(FilesystemIssue {
+        path: path.to_string(),
+        mount_point: Some(mount_point),
+        free_bytes,
+        read_only,
+        message,
+    })
+}
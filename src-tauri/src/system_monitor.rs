@@ -1,18 +1,31 @@
 use serde::{Deserialize, Serialize};
 use sysinfo::System;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use chrono::{DateTime, Utc};
-use tokio::time::{interval, Duration};
-use dashmap::DashMap;
+use tauri::{Emitter, Manager};
+use tokio::time::interval;
+use dashmap::{DashMap, DashSet};
 use once_cell::sync::Lazy;
-use tracing::{info, error};
+use tracing::{info, error, debug};
+
+use crate::monitoring::{self, CollectionCycle};
+use crate::tools::{self, Tool};
+use crate::types::{VmStats, VmStatsEvent};
 
 // Global system info cache
 static SYSTEM_CACHE: Lazy<DashMap<String, SystemStats>> = Lazy::new(|| DashMap::new());
 
+// VMs the frontend is watching; only these get `vm-stats-update` events
+static VM_STATS_SUBSCRIPTIONS: Lazy<DashSet<String>> = Lazy::new(DashSet::new);
+
+static MONITORING_STARTED: AtomicBool = AtomicBool::new(false);
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SystemStats {
     pub timestamp: DateTime<Utc>,
+    #[serde(default)]
+    pub cycle_id: Option<u64>, // Collection cycle shared with the per-VM stats
     pub cpu_usage: f32,
     pub memory_used: u64,
     pub memory_total: u64,
@@ -37,7 +50,7 @@ pub struct DiskInfo {
     pub file_system: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct NetworkInfo {
     pub total_bytes_received: u64,
     pub total_bytes_transmitted: u64,
@@ -94,8 +107,16 @@ pub struct ProxmoxVMInfo {
 pub struct SystemMonitor {
     system: System,
     last_cpu_times: HashMap<String, u64>,
+    include_all_disks: bool, // Also report tmpfs, proc and other pseudo filesystems
 }
 
+// Filesystems that don't hold user data and would only clutter disk usage
+const PSEUDO_FILESYSTEMS: &[&str] = &[
+    "tmpfs", "devtmpfs", "ramfs", "proc", "sysfs", "cgroup", "cgroup2", "devpts",
+    "securityfs", "pstore", "bpf", "tracefs", "debugfs", "configfs", "fusectl",
+    "mqueue", "hugetlbfs", "autofs", "efivarfs", "binfmt_misc", "nsfs", "squashfs",
+];
+
 impl SystemMonitor {
     pub fn new() -> Self {
         let mut system = System::new_all();
@@ -104,9 +125,15 @@ impl SystemMonitor {
         Self {
             system,
             last_cpu_times: HashMap::new(),
+            include_all_disks: false,
         }
     }
 
+    pub fn with_all_disks(mut self) -> Self {
+        self.include_all_disks = true;
+        self
+    }
+
     pub fn get_system_stats(&mut self) -> SystemStats {
         self.system.refresh_all();
 
@@ -122,7 +149,7 @@ impl SystemMonitor {
         let swap_used = self.system.used_swap();
         let swap_total = self.system.total_swap();
 
-        let disk_stats = vec![]; // Simplified for now - sysinfo API changes
+        let disk_stats = self.get_disk_stats();
 
         let network_stats = self.get_network_stats();
         let load_average = self.get_load_average();
@@ -131,6 +158,7 @@ impl SystemMonitor {
 
         SystemStats {
             timestamp: Utc::now(),
+            cycle_id: None,
             cpu_usage,
             memory_used,
             memory_total,
@@ -145,60 +173,40 @@ impl SystemMonitor {
         }
     }
 
-    fn get_network_stats(&self) -> NetworkInfo {
-        use std::fs;
-        use std::collections::HashMap;
-        
-        let mut interfaces = Vec::new();
-        let mut total_rx_bytes = 0u64;
-        let mut total_tx_bytes = 0u64;
-        let mut total_rx_packets = 0u64;
-        let mut total_tx_packets = 0u64;
-        
-        // Read network statistics from /proc/net/dev
-        if let Ok(content) = fs::read_to_string("/proc/net/dev") {
-            for line in content.lines().skip(2) { // Skip header lines
-                let parts: Vec<&str> = line.trim().split_whitespace().collect();
-                if parts.len() >= 17 {
-                    let name = parts[0].trim_end_matches(':').to_string();
-                    
-                    // Skip loopback interface
-                    if name == "lo" {
-                        continue;
-                    }
-                    
-                    if let (Ok(rx_bytes), Ok(tx_bytes), Ok(rx_packets), Ok(tx_packets)) = (
-                        parts[1].parse::<u64>(),
-                        parts[9].parse::<u64>(),
-                        parts[2].parse::<u64>(),
-                        parts[10].parse::<u64>()
-                    ) {
-                        interfaces.push(NetworkInterface {
-                            name: name.clone(),
-                            bytes_received: rx_bytes,
-                            bytes_transmitted: tx_bytes,
-                            packets_received: rx_packets,
-                            packets_transmitted: tx_packets,
-                            errors_received: parts[3].parse().unwrap_or(0),
-                            errors_transmitted: parts[11].parse().unwrap_or(0),
-                        });
-                        
-                        total_rx_bytes += rx_bytes;
-                        total_tx_bytes += tx_bytes;
-                        total_rx_packets += rx_packets;
-                        total_tx_packets += tx_packets;
-                    }
+    fn get_disk_stats(&self) -> Vec<DiskInfo> {
+        sysinfo::Disks::new_with_refreshed_list()
+            .list()
+            .iter()
+            .filter_map(|disk| {
+                let file_system = disk.file_system().to_string_lossy().into_owned();
+                if !self.include_all_disks && PSEUDO_FILESYSTEMS.contains(&file_system.as_str()) {
+                    return None;
                 }
-            }
-        }
-        
-        NetworkInfo {
-            total_bytes_received: total_rx_bytes,
-            total_bytes_transmitted: total_tx_bytes,
-            total_packets_received: total_rx_packets,
-            total_packets_transmitted: total_tx_packets,
-            interfaces,
-        }
+
+                let total_space = disk.total_space();
+                let available_space = disk.available_space();
+                let used_space = total_space.saturating_sub(available_space);
+                Some(DiskInfo {
+                    name: disk.name().to_string_lossy().into_owned(),
+                    mount_point: disk.mount_point().display().to_string(),
+                    total_space,
+                    available_space,
+                    used_space,
+                    usage_percentage: if total_space > 0 {
+                        (used_space as f32 / total_space as f32) * 100.0
+                    } else {
+                        0.0
+                    },
+                    file_system,
+                })
+            })
+            .collect()
+    }
+
+    fn get_network_stats(&self) -> NetworkInfo {
+        std::fs::read_to_string("/proc/net/dev")
+            .map(|contents| parse_net_dev(&contents))
+            .unwrap_or_default()
     }
 
     fn get_load_average(&self) -> LoadAverage {
@@ -233,12 +241,11 @@ impl SystemMonitor {
         }
         
         // Use stat command to get file info (works better with different permissions)
-        let stat_output = Command::new("stat")
-            .args(["-c", "%s", vm_path])
-            .output();
+        let stat_output = tools::try_command(Tool::Stat)
+            .map(|mut command| command.args(["-c", "%s", vm_path]).output());
             
         let size_bytes = match stat_output {
-            Ok(output) if output.status.success() => {
+            Some(Ok(output)) if output.status.success() => {
                 let size_str = String::from_utf8_lossy(&output.stdout);
                 size_str.trim().parse::<u64>().unwrap_or(0)
             },
@@ -268,12 +275,11 @@ impl SystemMonitor {
         let size_gb = size_bytes as f64 / (1024.0 * 1024.0 * 1024.0);
         
         // Get last modified time using stat command
-        let stat_time_output = Command::new("stat")
-            .args(["-c", "%Y", vm_path])
-            .output();
+        let stat_time_output = tools::try_command(Tool::Stat)
+            .map(|mut command| command.args(["-c", "%Y", vm_path]).output());
             
         let last_modified = match stat_time_output {
-            Ok(output) if output.status.success() => {
+            Some(Ok(output)) if output.status.success() => {
                 let timestamp_str = String::from_utf8_lossy(&output.stdout);
                 let timestamp = timestamp_str.trim().parse::<i64>().unwrap_or(0);
                 DateTime::<Utc>::from_timestamp(timestamp, 0).unwrap_or_else(|| Utc::now())
@@ -303,10 +309,13 @@ impl SystemMonitor {
     }
 
     fn is_vm_running_by_image(vm_path: &str) -> bool {
-        use std::process::Command;
+        let mut command = match tools::try_command(Tool::Pgrep) {
+            Some(command) => command,
+            None => return false,
+        };
         
-        if let Ok(output) = Command::new("pgrep")
-            .args(&["-f", vm_path])
+        if let Ok(output) = command
+            .args(["-f", vm_path])
             .output()
         {
             !output.stdout.is_empty()
@@ -315,16 +324,34 @@ impl SystemMonitor {
         }
     }
 
-    pub async fn start_monitoring() -> Result<(), String> {
+    /// Start the collection loop, once per process. Each cycle records host and
+    /// running-VM metrics into the `MonitoringService` history, then emits
+    /// `host-stats-update` and a `vm-stats-update` per subscribed VM.
+    pub async fn start_monitoring(app: tauri::AppHandle) -> Result<(), String> {
+        if MONITORING_STARTED.swap(true, Ordering::SeqCst) {
+            debug!("System monitoring already running");
+            return Ok(());
+        }
+
         let mut monitor = SystemMonitor::new();
-        let mut interval = interval(Duration::from_secs(5)); // Update every 5 seconds
+        let mut interval = interval(monitoring::COLLECTION_INTERVAL);
+        let metrics = app.state::<crate::MonitoringState>().inner().clone();
 
         tokio::spawn(async move {
             loop {
                 interval.tick().await;
                 
-                let stats = monitor.get_system_stats();
-                SYSTEM_CACHE.insert("current".to_string(), stats);
+                let (cycle, cycle_time) = {
+                    let mut metrics = metrics.write().await;
+                    let cycle = metrics.start_cycle();
+                    (cycle, metrics.cycle_time(&cycle))
+                };
+                metrics.write().await.record_host_metrics(&cycle).await;
+                
+                let mut stats = monitor.get_system_stats();
+                stats.timestamp = cycle_time;
+                stats.cycle_id = Some(cycle.id);
+                SYSTEM_CACHE.insert("current".to_string(), stats.clone());
                 
                 // Keep only the last 100 readings for historical data
                 let history_key = format!("history_{}", Utc::now().timestamp());
@@ -343,12 +370,74 @@ impl SystemMonitor {
                         SYSTEM_CACHE.remove(&key);
                     }
                 }
+
+                if let Err(e) = app.emit("host-stats-update", &stats) {
+                    error!("Failed to emit host stats: {}", e);
+                }
+                
+                let vm_stats = Self::collect_vm_stats(&app, &cycle, cycle_time).await;
+                {
+                    let mut metrics = metrics.write().await;
+                    for (vm_id, stats) in &vm_stats {
+                        metrics.record_vm_stats(&cycle, vm_id, stats).await;
+                    }
+                    metrics.finish_cycle().await;
+                }
+                Self::emit_vm_stats(&app, vm_stats);
             }
         });
 
         Ok(())
     }
 
+    /// Stats for every running VM, sampled concurrently so the cycle takes one
+    /// CPU sample interval however many VMs there are. Each is stamped with the
+    /// cycle rather than the moment it was sampled, so the charts line up.
+    async fn collect_vm_stats(
+        app: &tauri::AppHandle,
+        cycle: &CollectionCycle,
+        cycle_time: DateTime<Utc>,
+    ) -> Vec<(String, VmStats)> {
+        let state = app.state::<crate::AppState>();
+        let manager = state.read().await;
+        let vm_ids = match manager.running_vm_ids() {
+            Ok(vm_ids) => vm_ids,
+            Err(e) => {
+                debug!("Could not list running VMs: {}", e);
+                return Vec::new();
+            }
+        };
+
+        let samples = futures::future::join_all(vm_ids.iter().map(|vm_id| manager.get_vm_stats(vm_id))).await;
+        vm_ids.into_iter()
+            .zip(samples)
+            .filter_map(|(vm_id, stats)| match stats {
+                Ok(mut stats) => {
+                    stats.timestamp = cycle_time;
+                    stats.cycle_id = Some(cycle.id);
+                    Some((vm_id, stats))
+                }
+                // The VM stopped between listing and sampling
+                Err(e) => {
+                    debug!("No stats for VM {}: {}", vm_id, e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    fn emit_vm_stats(app: &tauri::AppHandle, vm_stats: Vec<(String, VmStats)>) {
+        // Deleted VMs stay subscribed until the frontend lets go; that is harmless
+        for (vm_id, stats) in vm_stats {
+            if !VM_STATS_SUBSCRIPTIONS.contains(&vm_id) {
+                continue;
+            }
+            if let Err(e) = app.emit("vm-stats-update", VmStatsEvent { vm_id, stats }) {
+                error!("Failed to emit VM stats: {}", e);
+            }
+        }
+    }
+
     pub fn get_cached_stats() -> Option<SystemStats> {
         SYSTEM_CACHE.get("current").map(|entry| entry.clone())
     }
@@ -362,9 +451,56 @@ impl SystemMonitor {
     }
 }
 
+/// Per-interface counters from /proc/net/dev, shared with the monitoring
+/// service's rate calculations. Loopback is listed but left out of the totals,
+/// which are meant to reflect traffic leaving the host.
+///
+/// Lines look like `  eth0: rx_bytes rx_packets rx_errs drop fifo frame
+/// compressed multicast tx_bytes tx_packets tx_errs ...`; large counters can
+/// run straight into the colon, so split on it rather than on whitespace.
+pub(crate) fn parse_net_dev(contents: &str) -> NetworkInfo {
+    let mut info = NetworkInfo::default();
+
+    for line in contents.lines() {
+        let (name, counters) = match line.split_once(':') {
+            Some((name, counters)) => (name.trim(), counters),
+            None => continue, // Header lines
+        };
+        let values: Vec<u64> = counters.split_whitespace().filter_map(|value| value.parse().ok()).collect();
+        if values.len() < 11 {
+            continue;
+        }
+
+        let interface = NetworkInterface {
+            name: name.to_string(),
+            bytes_received: values[0],
+            packets_received: values[1],
+            errors_received: values[2],
+            bytes_transmitted: values[8],
+            packets_transmitted: values[9],
+            errors_transmitted: values[10],
+        };
+
+        if name != "lo" {
+            info.total_bytes_received += interface.bytes_received;
+            info.total_bytes_transmitted += interface.bytes_transmitted;
+            info.total_packets_received += interface.packets_received;
+            info.total_packets_transmitted += interface.packets_transmitted;
+        }
+        info.interfaces.push(interface);
+    }
+
+    info
+}
+
 // Tauri command functions
 #[tauri::command]
-pub async fn get_system_statistics() -> Result<SystemStats, String> {
+pub async fn get_system_statistics(include_all_disks: Option<bool>) -> Result<SystemStats, String> {
+    // The cache only holds real filesystems, so the full list is always fresh
+    if include_all_disks.unwrap_or(false) {
+        return Ok(SystemMonitor::new().with_all_disks().get_system_stats());
+    }
+
     if let Some(stats) = SystemMonitor::get_cached_stats() {
         Ok(stats)
     } else {
@@ -384,7 +520,69 @@ pub async fn get_system_history() -> Result<Vec<SystemStats>, String> {
 }
 
 #[tauri::command]
-pub async fn start_system_monitoring() -> Result<String, String> {
-    SystemMonitor::start_monitoring().await?;
+pub async fn start_system_monitoring(app: tauri::AppHandle) -> Result<String, String> {
+    SystemMonitor::start_monitoring(app).await?;
     Ok("System monitoring started".to_string())
 }
+
+/// Include `vm_id` in the `vm-stats-update` events
+#[tauri::command]
+pub async fn subscribe_vm_stats(vm_id: String) -> Result<(), String> {
+    VM_STATS_SUBSCRIPTIONS.insert(vm_id);
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn unsubscribe_vm_stats(vm_id: String) -> Result<(), String> {
+    VM_STATS_SUBSCRIPTIONS.remove(&vm_id);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // From a host with a libvirt bridge and one running guest; eth0's receive
+    // byte counter has grown wide enough to run into the colon
+    const PROC_NET_DEV: &str = "\
+Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+    lo:  918273     4321    0    0    0     0          0         0   918273     4321    0    0    0     0       0          0
+  eth0:12345678901 9876543    3    0    0     0          0      1024 2345678901  3456789    1    0    0     0       0          0
+virbr0:  5242880    40960    0    7    0     0          0         0 10485760    81920    0    0    0     0       0          0
+ vnet0:     2048       16    0    0    0     0          0         0     4096       32    2    0    0     0       0          0
+";
+
+    #[test]
+    fn parses_every_interface_from_proc_net_dev() {
+        let info = parse_net_dev(PROC_NET_DEV);
+
+        let names: Vec<_> = info.interfaces.iter().map(|i| i.name.as_str()).collect();
+        assert_eq!(names, ["lo", "eth0", "virbr0", "vnet0"]);
+
+        let eth0 = &info.interfaces[1];
+        assert_eq!((eth0.bytes_received, eth0.packets_received, eth0.errors_received), (12345678901, 9876543, 3));
+        assert_eq!((eth0.bytes_transmitted, eth0.packets_transmitted, eth0.errors_transmitted), (2345678901, 3456789, 1));
+
+        let vnet0 = &info.interfaces[3];
+        assert_eq!((vnet0.bytes_transmitted, vnet0.errors_transmitted), (4096, 2));
+    }
+
+    #[test]
+    fn totals_leave_out_loopback() {
+        let info = parse_net_dev(PROC_NET_DEV);
+
+        assert_eq!(info.total_bytes_received, 12345678901 + 5242880 + 2048);
+        assert_eq!(info.total_bytes_transmitted, 2345678901 + 10485760 + 4096);
+        assert_eq!(info.total_packets_received, 9876543 + 40960 + 16);
+        assert_eq!(info.total_packets_transmitted, 3456789 + 81920 + 32);
+    }
+
+    #[test]
+    fn headers_and_truncated_lines_are_skipped() {
+        let info = parse_net_dev("Inter-|   Receive\n face |bytes\n  eth1: 100 2 0\n");
+
+        assert!(info.interfaces.is_empty());
+        assert_eq!(info.total_bytes_received, 0);
+    }
+}
@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::errors::{KvmError, Result};
+use crate::settings::Settings;
+
+/// External binaries the backend shells out to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Tool {
+    QemuImg,
+    Virsh,
+    Blockdev,
+    Stat,
+    Ps,
+    Pgrep,
+    Find,
+    Findmnt,
+    Lsblk,
+    CloudLocalds,
+    Genisoimage,
+    Swtpm,
+    Tar,
+    Lspci,
+}
+
+impl Tool {
+    pub const ALL: [Tool; 14] = [
+        Tool::QemuImg,
+        Tool::Virsh,
+        Tool::Blockdev,
+        Tool::Stat,
+        Tool::Ps,
+        Tool::Pgrep,
+        Tool::Find,
+        Tool::Findmnt,
+        Tool::Lsblk,
+        Tool::CloudLocalds,
+        Tool::Genisoimage,
+        Tool::Swtpm,
+        Tool::Tar,
+        Tool::Lspci,
+    ];
+
+    pub fn binary(&self) -> &'static str {
+        match self {
+            Tool::QemuImg => "qemu-img",
+            Tool::Virsh => "virsh",
+            Tool::Blockdev => "blockdev",
+            Tool::Stat => "stat",
+            Tool::Ps => "ps",
+            Tool::Pgrep => "pgrep",
+            Tool::Find => "find",
+            Tool::Findmnt => "findmnt",
+            Tool::Lsblk => "lsblk",
+            Tool::CloudLocalds => "cloud-localds",
+            Tool::Genisoimage => "genisoimage",
+            Tool::Swtpm => "swtpm",
+            Tool::Tar => "tar",
+            Tool::Lspci => "lspci",
+        }
+    }
+
+    /// Package providing the binary on common distributions
+    pub fn package(&self) -> &'static str {
+        match self {
+            Tool::QemuImg => "qemu-img",
+            Tool::Virsh => "libvirt-client",
+            Tool::Blockdev | Tool::Findmnt | Tool::Lsblk => "util-linux",
+            Tool::Stat => "coreutils",
+            Tool::Ps | Tool::Pgrep => "procps-ng",
+            Tool::Find => "findutils",
+            Tool::CloudLocalds => "cloud-image-utils",
+            Tool::Genisoimage => "cdrkit",
+            Tool::Swtpm => "swtpm",
+            Tool::Tar => "tar",
+            Tool::Lspci => "pciutils",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolStatus {
+    pub name: String,
+    pub package: String,
+    pub path: Option<String>,
+    pub available: bool,
+}
+
+// Resolved once; overrides from settings take precedence over $PATH
+static TOOL_PATHS: Lazy<HashMap<Tool, Option<PathBuf>>> = Lazy::new(|| {
+    let overrides = Settings::load()
+        .map(|settings| settings.tool_paths)
+        .unwrap_or_default();
+
+    Tool::ALL
+        .iter()
+        .map(|tool| {
+            let path = overrides.get(tool.binary())
+                .map(PathBuf::from)
+                .filter(|path| path.is_file())
+                .or_else(|| search_path(tool.binary()));
+            (*tool, path)
+        })
+        .collect()
+});
+
+fn search_path(binary: &str) -> Option<PathBuf> {
+    let path_var = std::env::var_os("PATH").unwrap_or_default();
+    std::env::split_paths(&path_var)
+        .chain(["/usr/bin", "/usr/sbin", "/bin", "/sbin"].iter().map(PathBuf::from))
+        .map(|dir| dir.join(binary))
+        .find(|candidate| is_executable(candidate))
+}
+
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::metadata(path)
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+/// Resolve every tool up front and log the ones that are missing
+pub fn discover() {
+    for status in status() {
+        match &status.path {
+            Some(path) => info!("Found {} at {}", status.name, path),
+            None => warn!("{} not found; install the '{}' package", status.name, status.package),
+        }
+    }
+}
+
+pub fn locate(tool: Tool) -> Option<&'static Path> {
+    TOOL_PATHS.get(&tool).and_then(|path| path.as_deref())
+}
+
+/// Build a command for a required tool, failing with `ToolMissing` if absent
+pub fn command(tool: Tool) -> Result<Command> {
+    locate(tool)
+        .map(Command::new)
+        .ok_or_else(|| KvmError::ToolMissing {
+            tool: tool.binary().to_string(),
+            package: tool.package().to_string(),
+        })
+}
+
+/// Fail with `ToolMissing` unless `tool` is installed, for tools libvirt runs
+/// on our behalf rather than us
+pub fn require(tool: Tool) -> Result<()> {
+    command(tool).map(|_| ())
+}
+
+/// Build a command for an optional tool; callers degrade when this returns None
+pub fn try_command(tool: Tool) -> Option<Command> {
+    locate(tool).map(Command::new)
+}
+
+/// Why `value` can't safely be passed as a positional argument: tools like
+/// virsh read anything starting with "-" as an option, even without a shell
+pub fn argument_problem(value: &str) -> Option<&'static str> {
+    if value.trim().is_empty() {
+        Some("must not be empty")
+    } else if value.starts_with('-') {
+        Some("must not start with '-'")
+    } else if value.chars().any(char::is_control) {
+        Some("must not contain control characters")
+    } else {
+        None
+    }
+}
+
+pub fn status() -> Vec<ToolStatus> {
+    Tool::ALL
+        .iter()
+        .map(|tool| {
+            let path = locate(*tool);
+            ToolStatus {
+                name: tool.binary().to_string(),
+                package: tool.package().to_string(),
+                path: path.map(|p| p.display().to_string()),
+                available: path.is_some(),
+            }
+        })
+        .collect()
+}
@@ -2,6 +2,8 @@ use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
 use std::collections::HashMap;
 
+use crate::formatting::{format_bytes, format_duration, mib_to_bytes};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VirtualMachine {
     pub id: String,
@@ -9,17 +11,53 @@ pub struct VirtualMachine {
     pub state: VmState,
     pub memory: u64,        // Memory in MB
     pub vcpus: u32,
+    #[serde(default)]
+    pub max_vcpus: u32,     // Hot-plug ceiling; vcpus can grow to this while running
     pub disk_size: u64,     // Disk size in GB
     pub os_type: String,
     pub os_variant: Option<String>,
     pub created_at: DateTime<Utc>,
     pub last_started: Option<DateTime<Utc>>,
     pub description: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
     pub vnc_port: Option<u16>,
     pub spice_port: Option<u16>,
     pub snapshots: Vec<Snapshot>,
     pub network_interfaces: Vec<NetworkInterface>,
     pub storage_devices: Vec<StorageDevice>,
+    #[serde(default)]
+    pub snapshot_capabilities: Vec<DiskSnapshotCapability>,
+    #[serde(default)]
+    pub cpu_topology: Option<CpuTopology>,
+    #[serde(default)]
+    pub numa_nodes: Vec<NumaNodeConfig>,
+    #[serde(default)]
+    pub autostart: bool, // Started by libvirtd when the host boots
+    #[serde(default)]
+    pub has_saved_state: bool, // Managed save image present; starting restores it
+    #[serde(default)]
+    pub has_tpm: bool,
+    #[serde(default)]
+    pub shared_folders: Vec<SharedFolderConfig>,
+    #[serde(default)]
+    pub sound_model: Option<String>,
+    #[serde(default)]
+    pub clipboard_ready: bool,
+    #[serde(default)]
+    pub clipboard_issue: Option<ClipboardIssue>, // Why clipboard sharing isn't available
+    #[serde(default)]
+    pub parse_errors: Vec<String>, // Why some details fell back to defaults
+}
+
+/// What stands between the user and SPICE clipboard/file-drop sharing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClipboardIssue {
+    NoSpiceDisplay,     // Display is VNC or absent
+    MissingChannel,     // No spicevmc agent channel; see enable_spice_agent
+    NotRunning,         // Agent state is only known while the VM runs
+    AgentNotResponding, // Channel present but spice-vdagent isn't connected in the guest
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -33,90 +71,604 @@ pub enum VmState {
     Error,
 }
 
+// Fields left as None are filled from the hardware preset, then built-in defaults;
+// any value the caller sets wins, even an empty or zero one
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VmConfig {
     pub name: String,
-    pub memory: u64,
-    pub vcpus: u32,
-    pub disk_size: u64,
-    pub os_type: String,
+    #[serde(default)]
+    pub memory: Option<u64>,    // Memory in MB
+    #[serde(default)]
+    pub vcpus: Option<u32>,
+    #[serde(default)]
+    pub disk_size: Option<u64>, // Disk size in GB
+    #[serde(default)]
+    pub os_type: Option<String>,
     pub os_variant: Option<String>,
     pub description: Option<String>,
+    #[serde(default)]
+    pub autostart: bool, // Start with libvirtd when the host boots
+    #[serde(default)]
+    pub cpu_topology: Option<CpuTopology>, // Defaults to one socket per vCPU
+    #[serde(default)]
+    pub numa_nodes: Vec<NumaNodeConfig>, // Guest NUMA cells; empty for a single node
+    #[serde(default)]
+    pub cpu_model: CpuModelConfig,
+    #[serde(default)]
+    pub machine_type: Option<String>, // Defaults to the host's newest q35
+    #[serde(default)]
+    pub firmware: FirmwareType,
+    #[serde(default)]
+    pub secure_boot: bool, // UEFI only; also turns on SMM
+    #[serde(default)]
+    pub tpm: Option<TpmConfig>, // Emulated by swtpm
+    #[serde(default)]
+    pub watchdog: Option<WatchdogConfig>,
+    #[serde(default)]
+    pub memory_backing: Option<MemoryBackingConfig>,
+    #[serde(default)]
+    pub shared_folders: Vec<SharedFolderConfig>,
+    #[serde(default)]
     pub network_config: NetworkConfig,
+    #[serde(default)]
     pub storage_config: StorageConfig,
+    #[serde(default)]
     pub display_config: DisplayConfig,
+    #[serde(default)]
     pub boot_config: BootConfig,
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FirmwareType {
+    #[default]
+    Bios,
+    Uefi, // OVMF, found on the host by firmware::detect_ovmf
+}
+
+/// A watchdog the guest has to keep petting; `action` runs when it stops
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WatchdogConfig {
+    #[serde(default)]
+    pub model: WatchdogModel,
+    #[serde(default)]
+    pub action: WatchdogAction,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WatchdogModel {
+    #[default]
+    I6300esb, // PCI; works with any machine type
+    Ib700,    // ISA, i440fx only
+    Itco,     // Built into q35
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WatchdogAction {
+    #[default]
+    Reset,
+    Poweroff,
+    Pause,
+    None, // Only report the event
+}
+
+/// An emulated TPM. Windows 11 needs TPM 2.0; CRB is the interface it expects.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TpmConfig {
+    #[serde(default)]
+    pub model: TpmModel,
+    #[serde(default)]
+    pub version: TpmVersion,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TpmModel {
+    Tis,
+    #[default]
+    Crb, // TPM 2.0 only
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TpmVersion {
+    #[serde(rename = "1.2")]
+    V1_2,
+    #[default]
+    #[serde(rename = "2.0")]
+    V2_0,
+}
+
+/// How vCPUs are presented to the guest; the product must equal the vCPU count
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CpuTopology {
+    pub sockets: u32,
+    pub cores: u32,
+    pub threads: u32,
+}
+
+impl CpuTopology {
+    pub fn vcpus(&self) -> u32 {
+        self.sockets * self.cores * self.threads
+    }
+}
+
+/// Which CPU the guest sees. Named models keep a VM migratable between hosts
+/// with different CPUs; passthrough gives the best performance.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CpuModel {
+    HostPassthrough,
+    #[default]
+    HostModel,
+    Named(String), // A model from list_host_cpu_models, e.g. Skylake-Client
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CpuModelConfig {
+    #[serde(default)]
+    pub model: CpuModel,
+    #[serde(default)]
+    pub features: Vec<CpuFeatureFlag>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CpuFeatureFlag {
+    pub name: String, // e.g. vmx, avx512f
+    pub enabled: bool,
+}
+
+/// A machine type the host's QEMU supports, from the libvirt capabilities
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MachineType {
+    pub name: String,              // q35, pc-q35-9.0, ...
+    pub canonical: Option<String>, // What an alias such as q35 currently resolves to
+    pub max_cpus: Option<u32>,
+}
+
+/// A named CPU model from the host's domain capabilities
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HostCpuModel {
+    pub name: String,
+    pub vendor: Option<String>,
+    pub usable: bool, // The host CPU can run it without missing features
+}
+
+/// One guest NUMA cell; cells must split the VM's vCPUs and memory between them
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NumaNodeConfig {
+    pub cpus: Vec<u32>, // vCPU indices
+    pub memory_mb: u64,
+}
+
+/// Back guest memory with host hugepages (`<memoryBacking>`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryBackingConfig {
+    pub hugepages: bool,
+    #[serde(default)]
+    pub page_size_kib: Option<u64>, // 2048 or 1048576; None uses the host default
+}
+
+/// A host directory the guest can mount (`<filesystem type='mount'>`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SharedFolderConfig {
+    pub host_path: String,
+    pub guest_tag: String, // e.g. `mount -t virtiofs <tag> /mnt` in the guest
+    #[serde(default)]
+    pub driver: SharedFolderDriver,
+    #[serde(default)]
+    pub readonly: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SharedFolderDriver {
+    #[default]
+    #[serde(rename = "virtiofs")]
+    Virtiofs, // Needs shared guest memory
+    #[serde(rename = "9p")]
+    NineP,    // Slower, but works without shared memory; not hot-pluggable
+}
+
+/// Host CPUs a single vCPU may run on (`<vcpupin>`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VcpuPin {
+    pub vcpu: u32,
+    pub host_cpus: Vec<u32>, // Sorted, expanded from the cpuset string
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct NetworkConfig {
     pub bridge: Option<String>,
     pub network_name: Option<String>,
     pub mac_address: Option<String>,
-    pub model: String, // e1000, virtio, etc.
+    #[serde(default)]
+    pub model: Option<String>, // e1000, virtio, etc.
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct StorageConfig {
-    pub pool_name: String,
-    pub format: String, // qcow2, raw, etc.
-    pub bus: String,    // virtio, sata, ide, etc.
-    pub cache: String,  // none, writeback, writethrough, etc.
+    #[serde(default)]
+    pub pool_name: Option<String>,
+    #[serde(default)]
+    pub format: Option<String>, // qcow2, raw, etc.
+    #[serde(default)]
+    pub bus: Option<String>,    // virtio, sata, ide, etc.
+    #[serde(default)]
+    pub cache: Option<String>,  // none, writeback, writethrough, etc.
+    #[serde(default)]
+    pub encryption: Option<EncryptionSpec>,
 }
 
+/// LUKS encryption of a disk, unlocked with a libvirt secret
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptionSpec {
+    pub secret_uuid: String,
+    #[serde(default = "EncryptionSpec::default_format")]
+    pub format: String, // luks
+}
+
+impl EncryptionSpec {
+    fn default_format() -> String {
+        "luks".to_string()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct DisplayConfig {
-    pub graphics_type: String, // vnc, spice
-    pub listen: String,
+    #[serde(default)]
+    pub graphics_type: Option<String>, // vnc, spice
+    #[serde(default)]
+    pub listen: Option<String>,        // Empty means localhost only
     pub password: Option<String>,
+    #[serde(default)]
     pub autoport: bool,
+    #[serde(default)]
+    pub port: Option<u16>, // Required when autoport is off
+    #[serde(default)]
+    pub sound_model: Option<String>, // ich9, ac97, virtio; None or "none" for no audio
+}
+
+/// Named defaults for VmConfig fields the caller leaves unset
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct HardwarePreset {
+    pub name: String,
+    pub description: Option<String>,
+    pub memory: Option<u64>,
+    pub vcpus: Option<u32>,
+    pub disk_size: Option<u64>,
+    pub os_type: Option<String>,
+    pub network_name: Option<String>,
+    pub network_model: Option<String>,
+    pub storage_pool: Option<String>,
+    pub storage_format: Option<String>,
+    pub storage_bus: Option<String>,
+    pub storage_cache: Option<String>,
+    pub graphics_type: Option<String>,
+    pub graphics_listen: Option<String>,
+    pub boot_order: Option<Vec<String>>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GraphicsDevice {
+    pub protocol: String,         // vnc, spice
+    pub port: Option<u16>,        // Resolved port, None while unallocated
+    pub tls_port: Option<u16>,    // SPICE only
+    pub socket: Option<String>,   // UNIX socket path when listening on a socket
+    pub listen: Option<String>,   // Listen address
+    pub autoport: bool,
+    #[serde(default)]
+    pub password_set: bool,       // Only reported when read from the secure XML
+}
+
+/// Everything a viewer needs to connect to a running VM's display
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DisplayInfo {
+    pub vm_id: String,
+    pub graphics_type: String,    // vnc, spice
+    pub host: String,             // Address to connect to; wildcard listens map to 127.0.0.1
+    pub listen: Option<String>,   // Address the display is bound to
+    pub port: Option<u16>,        // None when only TLS or a socket is offered
+    pub tls_port: Option<u16>,    // SPICE only
+    pub socket: Option<String>,
+    pub password_set: bool,
+    pub uri: Option<String>,      // vnc://host:port or spice://host:port
+}
+
+/// A PNG capture of a running VM's screen
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VmScreenshot {
+    pub vm_id: String,
+    pub png_base64: String,
+    pub width: u32,
+    pub height: u32,
+    pub taken_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct BootConfig {
-    pub boot_order: Vec<String>, // cdrom, hd, network
+    #[serde(default)]
+    pub boot_order: Option<Vec<String>>, // cdrom, hd, network
     pub iso_path: Option<String>,
     pub kernel: Option<String>,
     pub initrd: Option<String>,
     pub cmdline: Option<String>,
 }
 
+/// What a defined VM actually ended up with, including values filled in by
+/// defaults during XML generation and by libvirt itself
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EffectiveVmConfig {
+    #[serde(flatten)]
+    pub config: VmConfig,
+    pub arch: Option<String>,           // x86_64
+    pub emulator: Option<String>,
+    pub cpu_mode: Option<String>,       // host-model, host-passthrough, custom
+    pub loader: Option<String>,         // UEFI code image, None for BIOS
+    pub video_model: Option<String>,
+    pub controllers: Vec<String>,       // type:model, e.g. usb:qemu-xhci
+    pub storage_devices: Vec<StorageDevice>,
+    pub network_interfaces: Vec<NetworkInterface>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RefreshOutcome {
+    pub vms: Vec<VirtualMachine>,
+    pub performed: bool, // false when the call joined a refresh already in flight
+}
+
+/// Header summary served from cached state; see `VmManager::get_status_summary`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StatusSummary {
+    pub revision: u64, // Increases only when the rest of the summary changes
+    pub running: u32,
+    pub paused: u32,
+    pub stopped: u32,
+    pub host_cpu_percent: Option<f32>,    // None until the system monitor has run
+    pub host_memory_percent: Option<f32>,
+    pub tightest_pool: Option<PoolSpace>, // Pool with the least free space
+    pub active_alerts: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PoolSpace {
+    pub name: String,
+    pub available_bytes: u64,
+    pub available_display: String,
+}
+
+/// Limits on what the app will provision; unset fields are unlimited
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResourceQuota {
+    pub max_total_vcpus: Option<u32>,
+    pub max_total_memory_mb: Option<u64>,
+    pub max_disk_per_vm_gb: Option<u64>,
+}
+
+impl ResourceQuota {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_total_vcpus.is_none() && self.max_total_memory_mb.is_none() && self.max_disk_per_vm_gb.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct QuotaSettings {
+    #[serde(default)]
+    pub global: ResourceQuota,
+    #[serde(default)]
+    pub per_tag: HashMap<String, ResourceQuota>, // Applies to VMs carrying the tag
+}
+
+impl QuotaSettings {
+    pub fn is_unlimited(&self) -> bool {
+        self.global.is_unlimited() && self.per_tag.values().all(ResourceQuota::is_unlimited)
+    }
+}
+
+/// Resources consumed by defined domains, running or not
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ResourceUsage {
+    pub vm_count: u32,
+    pub vcpus: u32,
+    pub memory_mb: u64,
+}
+
+impl ResourceUsage {
+    pub fn add(&mut self, other: &ResourceUsage) {
+        self.vm_count += other.vm_count;
+        self.vcpus += other.vcpus;
+        self.memory_mb += other.memory_mb;
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct QuotaUsage {
+    pub global: ResourceUsage,
+    pub per_tag: HashMap<String, ResourceUsage>,
+    pub limits: QuotaSettings,
+}
+
+/// Details of a refused operation, returned as `{ "quota_exceeded": ... }`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuotaViolation {
+    pub scope: String,    // global or tag:<name>
+    pub resource: String, // vcpus, memory_mb, disk_gb
+    pub current: u64,
+    pub requested: u64,
+    pub limit: u64,
+    pub message: String,
+}
+
+/// A graceful shutdown that didn't finish in time, returned as `{ "shutdown_timeout": ... }`
+/// so the UI can offer a forced power-off
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShutdownTimeout {
+    pub vm_id: String,
+    pub timeout_secs: u64,
+    pub message: String,
+}
+
+/// Host readiness checks gathered for the UI
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PreflightReport {
+    pub tools: Vec<crate::tools::ToolStatus>,
+}
+
+/// Options controlling automatic recovery of missing or inactive networks and pools
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RecoveryOptions {
+    #[serde(default)]
+    pub define_missing: bool, // Define the stock default network/pool if missing
+    #[serde(default)]
+    pub use_fallback: bool,   // Substitute another active network if missing
+}
+
+/// Returned when an operation needs user confirmation before it can proceed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NeedsAction {
+    pub action: String,           // define_default_network, use_fallback_network, define_default_pool
+    pub resource: String,         // Network or pool name
+    pub message: String,
+    pub fallback: Option<String>, // Suggested alternative, if any
+}
+
+/// A disk image whose filesystem cannot safely take guest writes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FilesystemIssue {
+    pub path: String,                // Disk image path
+    pub mount_point: Option<String>,
+    pub free_bytes: u64,
+    pub read_only: bool,
+    pub message: String,
+}
+
+/// How start and snapshot operations react to a low or read-only filesystem
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiskSpacePolicy {
+    #[serde(default = "DiskSpacePolicy::default_min_free_bytes")]
+    pub min_free_bytes: u64,
+    #[serde(default = "DiskSpacePolicy::default_refuse")]
+    pub refuse: bool, // false only logs a warning and continues
+}
+
+impl DiskSpacePolicy {
+    fn default_min_free_bytes() -> u64 {
+        2 * 1024 * 1024 * 1024
+    }
+
+    fn default_refuse() -> bool {
+        true
+    }
+}
+
+impl Default for DiskSpacePolicy {
+    fn default() -> Self {
+        Self {
+            min_free_bytes: Self::default_min_free_bytes(),
+            refuse: Self::default_refuse(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct VmStats {
-    pub cpu_usage: f64,        // Percentage
-    pub memory_usage: u64,     // Used memory in MB
-    pub memory_total: u64,     // Total memory in MB
+    pub cpu_usage: f64,        // Percent of allocated vCPUs, 0-100
+    /// Used memory in MB. Deprecated: use `memory_usage_bytes`; kept for one release.
+    pub memory_usage: u64,
+    /// Total memory in MB. Deprecated: use `memory_total_bytes`; kept for one release.
+    pub memory_total: u64,
+    #[serde(default)]
+    pub memory_usage_bytes: u64,
+    #[serde(default)]
+    pub memory_total_bytes: u64,
+    #[serde(default)]
+    pub memory_usage_display: String,
     pub disk_read: u64,        // Bytes read per second
     pub disk_write: u64,       // Bytes written per second
     pub network_rx: u64,       // Bytes received per second
     pub network_tx: u64,       // Bytes transmitted per second
     pub uptime: u64,           // Uptime in seconds
+    #[serde(default)]
+    pub uptime_display: String,
     pub timestamp: DateTime<Utc>, // When these stats were collected
+    #[serde(default)]
+    pub cycle_id: Option<u64>,    // Collection cycle shared with the host metrics, if any
     pub guest_agent_connected: bool,
 }
 
+impl VmStats {
+    /// Set memory from MB figures, in both the legacy and byte representations
+    pub fn with_memory(mut self, usage_mb: u64, total_mb: u64) -> Self {
+        self.memory_usage = usage_mb;
+        self.memory_total = total_mb;
+        self.memory_usage_bytes = mib_to_bytes(usage_mb);
+        self.memory_total_bytes = mib_to_bytes(total_mb);
+        self.memory_usage_display = format_bytes(self.memory_usage_bytes);
+        self
+    }
+
+    pub fn with_uptime(mut self, seconds: u64) -> Self {
+        self.uptime = seconds;
+        self.uptime_display = format_duration(seconds);
+        self
+    }
+}
+
+/// Payload of the `vm-stats-update` event
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VmStatsEvent {
+    pub vm_id: String,
+    #[serde(flatten)]
+    pub stats: VmStats,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct HostInfo {
     pub hostname: String,
     pub hypervisor: String,
     pub hypervisor_version: String,
     pub cpu_model: String,
     pub cpu_cores: u32,
-    pub memory_total: u64,     // Total host memory in MB
-    pub memory_free: u64,      // Free host memory in MB
+    /// Total host memory in MB. Deprecated: use `memory_total_bytes`; kept for one release.
+    pub memory_total: u64,
+    /// Free host memory in MB. Deprecated: use `memory_free_bytes`; kept for one release.
+    pub memory_free: u64,
+    #[serde(default)]
+    pub memory_total_bytes: u64,
+    #[serde(default)]
+    pub memory_free_bytes: u64,
+    #[serde(default)]
+    pub memory_total_display: String,
+    #[serde(default)]
+    pub memory_free_display: String,
     pub storage_pools: Vec<StoragePool>,
     pub networks: Vec<Network>,
     pub active_vms: u32,
     pub inactive_vms: u32,
 }
 
+impl HostInfo {
+    /// Set memory from MB figures, in both the legacy and byte representations
+    pub fn with_memory(mut self, total_mb: u64, free_mb: u64) -> Self {
+        self.memory_total = total_mb;
+        self.memory_free = free_mb;
+        self.memory_total_bytes = mib_to_bytes(total_mb);
+        self.memory_free_bytes = mib_to_bytes(free_mb);
+        self.memory_total_display = format_bytes(self.memory_total_bytes);
+        self.memory_free_display = format_bytes(self.memory_free_bytes);
+        self
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Snapshot {
     pub name: String,
     pub description: Option<String>,
     pub created_at: DateTime<Utc>,
-    pub state: String,
+    pub state: String, // running or paused (with memory), shutoff, disk-snapshot
     pub parent: Option<String>,
+    #[serde(default)]
+    pub includes_memory: bool, // Reverting resumes the guest where it was
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -210,6 +762,81 @@ pub struct StorageDevice {
     pub cache: Option<String>, // cache mode
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SnapshotKind {
+    DiskOnly, // External qcow2 overlays, disk state only
+    Full,     // Internal snapshot, including memory when running; every disk must be qcow2
+}
+
+/// Which snapshot kinds a disk supports, so the UI can explain a disabled button
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiskSnapshotCapability {
+    pub device: String,         // vda, vdb, etc.
+    pub format: String,         // qcow2, raw, etc.
+    pub disk_only: bool,
+    pub full: bool,
+    pub reason: Option<String>, // Why a kind is unavailable
+}
+
+impl DiskSnapshotCapability {
+    pub fn for_device(device: &StorageDevice) -> Self {
+        let block_backed = device.path.as_deref().is_some_and(|path| path.starts_with("/dev/"));
+        let qcow2 = device.type_ == "qcow2";
+
+        let reason = if !qcow2 {
+            Some(match &device.path {
+                Some(path) => format!(
+                    "{} is {}; convert it to qcow2 first (qemu-img convert -O qcow2 {} {}.qcow2)",
+                    device.device, device.type_, path, path.trim_end_matches(".img").trim_end_matches(".raw")
+                ),
+                None => format!("{} is {}; only qcow2 disks can be snapshotted", device.device, device.type_),
+            })
+        } else if block_backed {
+            Some(format!("{} is a block device; overlays cannot be created next to it, use a full snapshot", device.device))
+        } else {
+            None
+        };
+
+        Self {
+            device: device.device.clone(),
+            format: device.type_.clone(),
+            disk_only: qcow2 && !block_backed,
+            full: qcow2,
+            reason,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiskPlacement {
+    pub device: String,                 // vda, vdb, etc.
+    pub path: Option<String>,
+    pub mount_point: Option<String>,
+    pub source_device: Option<String>,  // Block device backing the mount
+    pub physical_devices: Vec<String>,  // Underlying physical disks
+    pub on_host_os_device: bool,
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+    pub busy: bool,                     // Has seen any I/O since the VM started
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SharedDevice {
+    pub physical_device: String,
+    pub disks: Vec<String>,
+    pub busy_disks: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageLayoutReport {
+    pub vm_id: String,
+    pub host_os_devices: Vec<String>,
+    pub disks: Vec<DiskPlacement>,
+    pub shared_devices: Vec<SharedDevice>,
+    pub warnings: Vec<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VolumeInfo {
     pub name: String,
@@ -225,30 +852,113 @@ pub struct VolumeConfig {
     pub format: String,
     pub capacity: u64,
     pub allocation: Option<u64>,
+    #[serde(default)]
+    pub encryption: Option<EncryptionSpec>,
 }
 
+/// A new storage pool. Which source fields apply depends on the type: netfs
+/// needs `source_host` and `source_path`, logical uses `source_name` as the
+/// volume group (defaulting to the pool name) and `source_device` to build it.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoragePoolCreateConfig {
+    pub name: String,
+    pub pool_type: String,  // dir, netfs, logical
+    pub target_path: Option<String>, // Required for dir and netfs
+    pub source_host: Option<String>,
+    pub source_path: Option<String>,
+    pub source_name: Option<String>,
+    pub source_device: Option<String>,
+    #[serde(default)]
+    pub build: bool,        // Create the directory, or the volume group on source_device
+    #[serde(default)]
+    pub autostart: bool,
+}
+
+/// Health of the libvirt connection, as tracked by the connection watch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionStatus {
+    Connected,
+    Reconnecting,
+    Disconnected, // Reconnect attempts are still being made, at the longest interval
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct StoragePool {
     pub name: String,
     pub pool_type: String,   // dir, disk, netfs, etc.
     pub path: String,
-    pub capacity: u64,       // Total capacity in bytes
-    pub available: u64,      // Available space in bytes
-    pub used: u64,          // Used space in bytes
+    /// Total capacity in bytes. Deprecated: use `capacity_bytes`; kept for one release.
+    pub capacity: u64,
+    /// Available space in bytes. Deprecated: use `available_bytes`; kept for one release.
+    pub available: u64,
+    /// Used space in bytes. Deprecated: use `used_bytes`; kept for one release.
+    pub used: u64,
+    #[serde(default)]
+    pub capacity_bytes: u64,
+    #[serde(default)]
+    pub available_bytes: u64,
+    #[serde(default)]
+    pub used_bytes: u64,
+    #[serde(default)]
+    pub capacity_display: String,
+    #[serde(default)]
+    pub available_display: String,
+    #[serde(default)]
+    pub used_display: String,
     pub state: String,       // active, inactive
     pub autostart: bool,
     pub volumes: Vec<StorageVolume>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl StoragePool {
+    /// Set sizes from byte counts, in both the legacy and new fields
+    pub fn with_sizes(mut self, capacity: u64, available: u64, used: u64) -> Self {
+        self.capacity = capacity;
+        self.available = available;
+        self.used = used;
+        self.capacity_bytes = capacity;
+        self.available_bytes = available;
+        self.used_bytes = used;
+        self.capacity_display = format_bytes(capacity);
+        self.available_display = format_bytes(available);
+        self.used_display = format_bytes(used);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct StorageVolume {
     pub name: String,
     pub format: String,
+    /// Capacity in bytes. Deprecated: use `capacity_bytes`; kept for one release.
     pub capacity: u64,
+    /// Allocated space in bytes. Deprecated: use `allocation_bytes`; kept for one release.
     pub allocation: u64,
+    #[serde(default)]
+    pub capacity_bytes: u64,
+    #[serde(default)]
+    pub allocation_bytes: u64,
+    #[serde(default)]
+    pub capacity_display: String,
+    #[serde(default)]
+    pub allocation_display: String,
     pub path: String,
 }
 
+impl StorageVolume {
+    /// Set sizes from byte counts, in both the legacy and new fields
+    pub fn with_sizes(mut self, capacity: u64, allocation: u64) -> Self {
+        self.capacity = capacity;
+        self.allocation = allocation;
+        self.capacity_bytes = capacity;
+        self.allocation_bytes = allocation;
+        self.capacity_display = format_bytes(capacity);
+        self.allocation_display = format_bytes(allocation);
+        self
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Network {
     pub name: String,
@@ -295,3 +1005,175 @@ pub enum MigrationState {
     Failed,
     Cancelled,
 }
+
+/// What to do when an imported domain's name or UUID is already defined
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportConflictPolicy {
+    #[default]
+    Skip,
+    Rename,    // Pick a free name and, if the UUID clashes, a new UUID
+    Overwrite, // Replace the existing definition; refused while it is running
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ImportFileOutcome {
+    Imported { uuid: String, name: String },
+    Skipped { reason: String },
+    NotDomain { kind: String }, // Root element, e.g. network or pool
+    Failed { reason: String },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportFileNote {
+    pub file: String,
+    pub detail: String, // Reason, or the root element for non-domain files
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportedVm {
+    pub file: String,
+    pub uuid: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BatchImportReport {
+    pub task_id: String,
+    pub directory: String,
+    pub imported: Vec<ImportedVm>,
+    pub skipped: Vec<ImportFileNote>,
+    pub failed: Vec<ImportFileNote>,
+    pub not_domain: Vec<ImportFileNote>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchImportProgress {
+    pub task_id: String,
+    pub file: String,
+    pub index: usize, // 1-based
+    pub total: usize,
+    pub outcome: ImportFileOutcome,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClonedDisk {
+    pub device: String, // Target, e.g. vda
+    pub source: String, // Image the copy was made from
+    pub path: String,   // New image used by the clone
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CloneResult {
+    pub vm_id: String,
+    pub name: String,
+    pub cloned_disks: Vec<ClonedDisk>,
+    pub warnings: Vec<String>, // Disks left out of the clone and why
+}
+
+// Rendered into a NoCloud seed ISO attached to VMs created from cloud images
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CloudInitConfig {
+    pub hostname: String,
+    pub username: String,
+    #[serde(default)]
+    pub ssh_authorized_key: Option<String>,
+    #[serde(default)]
+    pub network_config: Option<String>, // Network config v2 YAML, written verbatim
+}
+
+/// Where a configuration change took effect
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeScope {
+    Live,       // Running domain only; transient VMs have no config to update
+    Persistent, // Applies from the next boot
+    Both,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VcpuChange {
+    pub vcpus: u32,
+    pub max_vcpus: u32,
+    pub applied: ChangeScope,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::formatting::{GIB, MIB};
+    use serde_json::{json, Value};
+
+    fn u64_of(value: &Value, field: &str) -> u64 {
+        value[field].as_u64().unwrap_or_else(|| panic!("{} missing", field))
+    }
+
+    const TIB: u64 = 1024 * GIB;
+
+    #[test]
+    fn vm_stats_memory_agrees_in_mb_and_bytes() {
+        let stats = VmStats::default().with_memory(1536, 4096).with_uptime(90061);
+        let value = serde_json::to_value(&stats).unwrap();
+
+        assert_eq!(u64_of(&value, "memory_usage") * MIB, u64_of(&value, "memory_usage_bytes"));
+        assert_eq!(u64_of(&value, "memory_total") * MIB, u64_of(&value, "memory_total_bytes"));
+        assert_eq!(value["memory_usage_display"], "1.50 GiB");
+        assert_eq!(u64_of(&value, "uptime"), 90061);
+        assert_eq!(value["uptime_display"], "1d 1h 1m");
+
+        let round_trip: VmStats = serde_json::from_value(value).unwrap();
+        assert_eq!(round_trip.memory_usage_bytes, 1536 * MIB);
+        assert_eq!(round_trip.memory_total, 4096);
+    }
+
+    #[test]
+    fn host_info_memory_agrees_in_mb_and_bytes() {
+        let host = HostInfo::default().with_memory(32768, 1024);
+        let value = serde_json::to_value(&host).unwrap();
+
+        assert_eq!(u64_of(&value, "memory_total") * MIB, u64_of(&value, "memory_total_bytes"));
+        assert_eq!(u64_of(&value, "memory_free") * MIB, u64_of(&value, "memory_free_bytes"));
+        assert_eq!(value["memory_total_display"], "32.00 GiB");
+        assert_eq!(value["memory_free_display"], "1.00 GiB");
+    }
+
+    #[test]
+    fn storage_sizes_agree_in_legacy_and_byte_fields() {
+        let volume = StorageVolume::default().with_sizes(10 * GIB, 3 * GIB);
+        let pool = StoragePool { volumes: vec![volume], ..Default::default() }
+            .with_sizes(2 * TIB, TIB, TIB);
+        let value = serde_json::to_value(&pool).unwrap();
+
+        for field in ["capacity", "available", "used"] {
+            assert_eq!(u64_of(&value, field), u64_of(&value, &format!("{}_bytes", field)));
+        }
+        assert_eq!(value["capacity_display"], "2.00 TiB");
+
+        let volume = &value["volumes"][0];
+        for field in ["capacity", "allocation"] {
+            assert_eq!(u64_of(volume, field), u64_of(volume, &format!("{}_bytes", field)));
+        }
+        assert_eq!(volume["allocation_display"], "3.00 GiB");
+    }
+
+    #[test]
+    fn payloads_without_byte_fields_still_deserialize() {
+        let pool: StoragePool = serde_json::from_value(json!({
+            "name": "default",
+            "pool_type": "dir",
+            "path": "/var/lib/libvirt/images",
+            "capacity": 1024,
+            "available": 512,
+            "used": 512,
+            "state": "active",
+            "autostart": true,
+            "volumes": [],
+        }))
+        .unwrap();
+
+        assert_eq!(pool.capacity, 1024);
+        assert_eq!(pool.capacity_bytes, 0);
+        assert!(pool.capacity_display.is_empty());
+    }
+}
@@ -1,34 +1,109 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use once_cell::sync::Lazy;
+use base64::Engine;
+use rand::Rng;
 use chrono::{Utc, TimeZone};
-use tracing::{info, warn, error, debug};
+use tracing::{info, warn, error, debug, instrument};
 use uuid::Uuid;
-use virt::{connect::Connect, domain::Domain, sys};
+use virt::{connect::Connect, domain::Domain, domain_snapshot::DomainSnapshot, error::ErrorNumber, sys};
 
 use crate::errors::{KvmError, Result};
+use crate::guest_agent::{self, GuestInfo};
+use crate::host_devices;
+use crate::keycodes;
+use crate::formatting::format_bytes;
+use crate::audit;
+use crate::monitoring;
+use crate::cloud_init;
+use crate::firmware::{self, OvmfFirmware};
+use crate::quotas::{self, QuotaRequest};
+use crate::recovery;
+use crate::screenshot;
+use crate::secrets;
+use crate::settings::Settings;
+use crate::network::NetworkManager;
+use crate::ova;
+use crate::storage::StorageManager;
+use crate::storage_layout;
+use crate::tools::{self, Tool};
 use crate::types::*;
-use crate::xml_parser::{XmlParser, VmXmlInfo};
+use crate::xml_parser::{XmlParser, VmXmlInfo, LINKED_CLONE_ELEMENT, METADATA_NAMESPACE, TAGS_ELEMENT};
+
+// Domains whose XML problems have already been logged this session
+static PARSE_WARNED: Lazy<std::sync::Mutex<HashSet<String>>> = Lazy::new(|| std::sync::Mutex::new(HashSet::new()));
+
+// How often the guest refreshes balloon statistics once we ask it to
+const BALLOON_STATS_PERIOD_SECS: i32 = 5;
+
+// Below this even minimal guests fail to boot
+const MIN_MEMORY_MB: u64 = 128;
+
+// Longest stop_vm waits for a guest to power off, whatever the caller asks for
+pub const MAX_SHUTDOWN_WAIT_SECS: u64 = 10 * 60;
+
+// Screenshots newer than this are served from the cache instead of asking QEMU again
+const SCREENSHOT_MIN_INTERVAL_SECS: i64 = 2;
+
+// Generated display passwords; VNC only looks at the first 8 characters
+const VNC_PASSWORD_LENGTH: usize = 8;
+const SPICE_PASSWORD_LENGTH: usize = 24;
+
+// The values libvirt accepts for <boot dev>
+const BOOT_DEVICES: [&str; 4] = ["hd", "cdrom", "network", "fd"];
+
+const SOUND_MODELS: [&str; 4] = ["ich9", "ich6", "ac97", "virtio"];
+// The qcow2 and OVA templates are desktop guests on SPICE
+const DEFAULT_SOUND_MODEL: &str = "ich9";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CloneMode {
+    Full,       // Copy every file-backed disk
+    ConfigOnly, // Define the clone without file-backed disks
+    Linked,     // qcow2 overlays backed by the source disks
+}
+
+/// Result of a cache refresh, collected before being applied
+pub struct CacheSnapshot {
+    pub vms: Vec<VirtualMachine>,
+    pub pools: Option<Vec<StoragePool>>, // None when pool enumeration failed
+}
 
 pub struct VmManager {
     connection: Connect,
+    uri: String,
     vm_cache: HashMap<String, VirtualMachine>,
+    pool_cache: Vec<StoragePool>,
+    status_revision: std::sync::Mutex<(u64, Option<StatusSummary>)>,
+    // Shared with the threads running each migration
+    migrations: Arc<std::sync::Mutex<HashMap<String, MigrationTask>>>,
+    screenshots: Arc<std::sync::Mutex<HashMap<String, screenshot::Capture>>>, // By VM, shared with screenshot tasks
 }
 
 impl VmManager {
     pub async fn new() -> Result<Self> {
-        info!("Initializing VM Manager with libvirt connection");
+        Self::with_uri(None).await
+    }
+
+    /// Connect to an explicit libvirt URI, e.g. `test:///default` for the
+    /// in-memory test driver. `None` uses libvirt's default (honouring
+    /// `LIBVIRT_DEFAULT_URI`).
+    pub async fn with_uri(uri: Option<&str>) -> Result<Self> {
+        info!("Initializing VM Manager with libvirt connection to {}", uri.unwrap_or("default URI"));
         
-        // Try to connect to libvirt
-        let connection = Connect::open(None)
-            .map_err(|e| {
-                error!("Failed to connect to libvirt: {}", e);
-                KvmError::LibvirtConnection(e)
-            })?;
+        let connection = Self::open_connection(uri)?;
+        let uri = connection.get_uri().unwrap_or_else(|_| uri.unwrap_or_default().to_string());
 
-        info!("Successfully connected to libvirt");
+        info!("Successfully connected to libvirt at {}", uri);
 
         let mut manager = Self {
             connection,
+            uri,
             vm_cache: HashMap::new(),
+            pool_cache: Vec::new(),
+            status_revision: std::sync::Mutex::new((0, None)),
+            migrations: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            screenshots: Arc::new(std::sync::Mutex::new(HashMap::new())),
         };
 
         // Initialize cache
@@ -37,8 +112,63 @@ impl VmManager {
         Ok(manager)
     }
 
+    fn open_connection(uri: Option<&str>) -> Result<Connect> {
+        let target = uri.unwrap_or("default URI");
+        Connect::open(uri).map_err(|e| {
+            error!("Failed to connect to libvirt at {}: {}", target, e);
+            match e.code() {
+                ErrorNumber::AuthFailed | ErrorNumber::AuthCancelled | ErrorNumber::AuthUnavailable
+                | ErrorNumber::AccessDenied => {
+                    KvmError::PermissionDenied(format!("Authentication to {} failed: {}", target, e))
+                }
+                ErrorNumber::NoConnect | ErrorNumber::RPC | ErrorNumber::SystemError => {
+                    KvmError::HostUnreachable(format!("{}: {}", target, e))
+                }
+                _ => KvmError::LibvirtConnection(e),
+            }
+        })
+    }
+
+    /// Switch to another hypervisor, e.g. `qemu+ssh://host/system`. The current
+    /// connection stays in use if the new one can't be opened.
+    pub async fn connect(&mut self, uri: &str) -> Result<()> {
+        info!("Connecting to {} (was {})", uri, self.uri);
+
+        let connection = Self::open_connection(Some(uri))?;
+        self.uri = connection.get_uri().unwrap_or_else(|_| uri.to_string());
+        self.connection = connection;
+        self.vm_cache.clear();
+        self.pool_cache.clear();
+
+        audit::record("libvirt_connected", &self.uri, "Switched libvirt connection");
+        self.refresh_vm_cache().await
+    }
+
+    pub fn get_connection_uri(&self) -> String {
+        self.uri.clone()
+    }
+
+    /// Whether libvirt still answers on the current connection
+    pub fn is_alive(&self) -> bool {
+        self.connection.is_alive().unwrap_or(false)
+    }
+
+    /// Reopen the current URI after the connection dropped
+    pub async fn reconnect(&mut self) -> Result<()> {
+        let uri = self.uri.clone();
+        self.connect(&uri).await
+    }
+
+    fn ensure_connected(&self) -> Result<()> {
+        if self.is_alive() {
+            return Ok(());
+        }
+        Err(KvmError::HostUnreachable(format!("Connection to {} was lost; reconnecting", self.uri)))
+    }
+
     pub async fn list_vms(&self) -> Result<Vec<VirtualMachine>> {
         debug!("Listing all virtual machines");
+        self.ensure_connected()?;
         
         let domain_flags = sys::VIR_CONNECT_LIST_DOMAINS_ACTIVE | 
                           sys::VIR_CONNECT_LIST_DOMAINS_INACTIVE;
@@ -74,17 +204,33 @@ impl VmManager {
         Ok(vms)
     }
 
-    pub async fn create_vm(&mut self, config: VmConfig) -> Result<String> {
+    #[instrument(skip_all, fields(operation = "create_vm", vm_name = %config.name))]
+    pub async fn create_vm(&mut self, mut config: VmConfig, recovery_options: &RecoveryOptions) -> Result<String> {
         info!("Creating new VM: {}", config.name);
 
         // Validate configuration
         self.validate_vm_config(&config)?;
+        quotas::check(&self.connection, &QuotaRequest {
+            vcpus: config.vcpus.unwrap_or_default(),
+            memory_mb: config.memory.unwrap_or_default(),
+            disk_gb: config.disk_size.unwrap_or_default(),
+            tags: Vec::new(),
+        })?;
+
+        // Make sure the referenced network and storage pool are usable
+        let network_name = config.network_config.network_name.clone()
+            .unwrap_or_else(|| recovery::DEFAULT_NETWORK.to_string());
+        let network_name = recovery::ensure_network(&self.connection, &network_name, recovery_options)?;
+        config.network_config.network_name = Some(network_name);
+        let pool_name = config.storage_config.pool_name.as_deref().unwrap_or(recovery::DEFAULT_POOL);
+        recovery::ensure_storage_pool(&self.connection, pool_name, recovery_options)?;
 
         // Generate VM UUID
         let vm_id = Uuid::new_v4().to_string();
 
         // Generate XML configuration
-        let xml_config = self.generate_vm_xml(&config, &vm_id)?;
+        let machine_type = config.machine_type.clone().unwrap_or_else(|| self.default_machine_type());
+        let xml_config = Self::generate_vm_xml(&config, &vm_id, &machine_type)?;
 
         // Define the domain first, then start it
         let domain = Domain::define_xml(&self.connection, &xml_config)
@@ -92,6 +238,12 @@ impl VmManager {
                 error!("Failed to define VM {}: {}", config.name, e);
                 KvmError::VmOperationFailed(format!("Failed to create VM: {}", e))
             })?;
+
+        if config.autostart {
+            if let Err(e) = domain.set_autostart(true) {
+                warn!("Failed to enable autostart for VM {}: {}", config.name, e);
+            }
+        }
         
         // Start the domain
         domain.create()
@@ -113,16 +265,61 @@ impl VmManager {
         Ok(vm_id)
     }
 
-    pub async fn start_vm(&self, vm_id: &str) -> Result<()> {
+    /// Virtual network operations on the same libvirt connection
+    pub fn network_manager(&self) -> NetworkManager {
+        NetworkManager::new(self.connection.clone())
+    }
+
+    /// Volume and pool operations on the same libvirt connection
+    pub fn storage_manager(&self) -> StorageManager {
+        StorageManager::new(self.connection.clone())
+    }
+
+    /// Render the domain XML `create_vm` would define, without touching libvirt
+    pub fn preview_vm_xml(&self, config: &VmConfig) -> Result<String> {
+        self.validate_vm_config(config)?;
+        let machine_type = config.machine_type.clone().unwrap_or_else(|| self.default_machine_type());
+        Self::generate_vm_xml(config, &Uuid::new_v4().to_string(), &machine_type)
+    }
+
+    #[instrument(skip(self, recovery_options), fields(operation = "start_vm"))]
+    pub async fn start_vm(&self, vm_id: &str, recovery_options: &RecoveryOptions) -> Result<()> {
         info!("Starting VM: {}", vm_id);
 
-        let domain = self.get_domain_by_id(vm_id)?;
+        let mut domain = self.get_domain_by_id(vm_id)?;
         
         // Check if VM is already running
         if domain.is_active().map_err(KvmError::LibvirtConnection)? {
             info!("VM {} is already running", vm_id);
             return Ok(());
         }
+
+        // Recover missing or inactive networks before libvirt refuses to start;
+        // the secure XML keeps any display password across the redefine
+        let xml = domain.get_xml_desc(sys::VIR_DOMAIN_XML_SECURE).map_err(KvmError::LibvirtConnection)?;
+        Self::check_disk_filesystems(vm_id, &xml)?;
+
+        let mut updated_xml = xml.clone();
+        if let Ok(xml_info) = XmlParser::parse_vm_from_xml(&xml) {
+            for interface in xml_info.network_interfaces.iter().filter(|i| i.type_ == "network") {
+                let network_name = recovery::ensure_network(&self.connection, &interface.source, recovery_options)?;
+                if network_name != interface.source {
+                    updated_xml = updated_xml.replace(
+                        &format!("<source network='{}'", interface.source),
+                        &format!("<source network='{}'", network_name),
+                    );
+                }
+            }
+        }
+
+        if updated_xml != xml {
+            domain = Domain::define_xml(&self.connection, &updated_xml)
+                .map_err(|e| {
+                    error!("Failed to redefine VM {} with fallback network: {}", vm_id, e);
+                    KvmError::VmOperationFailed(format!("Failed to switch VM network: {}", e))
+                })?;
+            audit::record("vm_network_switched", vm_id, "Redefined VM to use fallback network");
+        }
         
         domain.create()
             .map_err(|e| {
@@ -134,38 +331,319 @@ impl VmManager {
         Ok(())
     }
 
-    pub async fn stop_vm(&self, vm_id: &str) -> Result<()> {
+    /// Ask the guest to shut down via ACPI; never forces it off. Returns the
+    /// domain to pass to `wait_for_shutdown`, or `None` if it was already stopped.
+    #[instrument(skip(self), fields(operation = "stop_vm"))]
+    pub async fn stop_vm(&self, vm_id: &str) -> Result<Option<Domain>> {
         info!("Stopping VM: {}", vm_id);
 
         let domain = self.get_domain_by_id(vm_id)?;
-        
-        // Try graceful shutdown first
-        if let Err(_) = domain.shutdown() {
-            // If graceful shutdown fails, force shutdown
-            warn!("Graceful shutdown failed for VM {}, forcing shutdown", vm_id);
-            domain.destroy()
-                .map_err(|e| {
-                    error!("Failed to force stop VM {}: {}", vm_id, e);
-                    KvmError::VmOperationFailed(format!("Failed to stop VM: {}", e))
-                })?;
+        if !domain.is_active().map_err(KvmError::LibvirtConnection)? {
+            info!("VM {} is already stopped", vm_id);
+            return Ok(None);
+        }
+
+        domain.shutdown()
+            .map_err(|e| {
+                error!("Failed to request shutdown of VM {}: {}", vm_id, e);
+                KvmError::VmOperationFailed(format!("Failed to request shutdown: {}", e))
+            })?;
+
+        info!("Requested shutdown of VM: {}", vm_id);
+        Ok(Some(domain))
+    }
+
+    /// Wait up to `timeout_secs` (at most `MAX_SHUTDOWN_WAIT_SECS`) for a
+    /// domain to power off, failing with `ShutdownTimeout` if it hasn't. The
+    /// domain keeps its own reference to the connection, so callers poll
+    /// without holding the manager lock.
+    pub async fn wait_for_shutdown(domain: &Domain, vm_id: &str, timeout_secs: u64) -> Result<()> {
+        let timeout_secs = timeout_secs.min(MAX_SHUTDOWN_WAIT_SECS);
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(timeout_secs);
+        while domain.is_active().map_err(KvmError::LibvirtConnection)? {
+            if tokio::time::Instant::now() >= deadline {
+                warn!("VM {} still running {}s after shutdown request", vm_id, timeout_secs);
+                return Err(KvmError::ShutdownTimeout(ShutdownTimeout {
+                    vm_id: vm_id.to_string(),
+                    timeout_secs,
+                    message: format!(
+                        "The guest did not shut down within {} seconds; it may have ignored the ACPI request",
+                        timeout_secs
+                    ),
+                }));
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(500)).await;
         }
 
         info!("Successfully stopped VM: {}", vm_id);
         Ok(())
     }
 
+    /// Power the VM off immediately, like pulling the plug
+    #[instrument(skip(self), fields(operation = "force_stop_vm"))]
+    pub async fn force_stop_vm(&self, vm_id: &str) -> Result<()> {
+        info!("Force stopping VM: {}", vm_id);
+
+        let domain = self.get_domain_by_id(vm_id)?;
+        if !domain.is_active().map_err(KvmError::LibvirtConnection)? {
+            info!("VM {} is already stopped", vm_id);
+            return Ok(());
+        }
+
+        domain.destroy()
+            .map_err(|e| {
+                error!("Failed to force stop VM {}: {}", vm_id, e);
+                KvmError::VmOperationFailed(format!("Failed to force stop VM: {}", e))
+            })?;
+
+        info!("Successfully force stopped VM: {}", vm_id);
+        Ok(())
+    }
+
+    /// Reboot a running VM: an ACPI reboot request, or with `force` an immediate
+    /// hard reset. Returns the state libvirt reports afterwards.
+    #[instrument(skip(self), fields(operation = "reboot_vm"))]
+    pub async fn reboot_vm(&mut self, vm_id: &str, force: bool) -> Result<VmState> {
+        if force {
+            return self.reset_vm(vm_id).await;
+        }
+
+        info!("Rebooting VM: {}", vm_id);
+
+        let domain = self.get_domain_by_id(vm_id)?;
+        Self::require_running(&domain, "reboot")?;
+
+        domain.reboot(sys::VIR_DOMAIN_REBOOT_ACPI_POWER_BTN)
+            .map_err(|e| {
+                error!("Failed to reboot VM {}: {}", vm_id, e);
+                match e.code() {
+                    ErrorNumber::NoSupport | ErrorNumber::OperationUnsupported | ErrorNumber::AgentUnresponsive => {
+                        KvmError::VmOperationFailed(format!(
+                            "Failed to reboot VM: {}. A graceful reboot needs ACPI support in the guest \
+                             (or a running guest agent); use a hard reset instead",
+                            e
+                        ))
+                    }
+                    _ => KvmError::VmOperationFailed(format!("Failed to reboot VM: {}", e)),
+                }
+            })?;
+
+        info!("Successfully requested reboot of VM: {}", vm_id);
+        self.cache_current_state(vm_id, &domain)
+    }
+
+    /// Hard reset, like pressing the reset button; the guest gets no chance to shut down
+    #[instrument(skip(self), fields(operation = "reset_vm"))]
+    pub async fn reset_vm(&mut self, vm_id: &str) -> Result<VmState> {
+        info!("Resetting VM: {}", vm_id);
+
+        let domain = self.get_domain_by_id(vm_id)?;
+        Self::require_running(&domain, "reset")?;
+
+        domain.reset()
+            .map_err(|e| {
+                error!("Failed to reset VM {}: {}", vm_id, e);
+                KvmError::VmOperationFailed(format!("Failed to reset VM: {}", e))
+            })?;
+
+        info!("Successfully reset VM: {}", vm_id);
+        self.cache_current_state(vm_id, &domain)
+    }
+
+    fn require_running(domain: &Domain, operation: &str) -> Result<()> {
+        let (state, _) = domain.get_state().map_err(KvmError::LibvirtConnection)?;
+        if !matches!(state, sys::VIR_DOMAIN_RUNNING | sys::VIR_DOMAIN_BLOCKED) {
+            return Err(KvmError::VmOperationFailed(format!(
+                "Cannot {} VM: it is {}, not running", operation, Self::describe_domain_state(state)
+            )));
+        }
+        Ok(())
+    }
+
+    fn cache_current_state(&mut self, vm_id: &str, domain: &Domain) -> Result<VmState> {
+        let (state, _) = domain.get_state().map_err(KvmError::LibvirtConnection)?;
+        let state = Self::vm_state_from(state);
+        self.set_cached_state(vm_id, state.clone());
+
+        // Autoport displays only get a port once the VM runs, and lose it when it stops
+        if let (Some(vm), Ok(xml)) = (self.vm_cache.get_mut(vm_id), domain.get_xml_desc(0)) {
+            let graphics = XmlParser::parse_graphics_devices(&xml);
+            vm.vnc_port = graphics.iter().find(|g| g.protocol == "vnc").and_then(|g| g.port);
+            vm.spice_port = graphics.iter().find(|g| g.protocol == "spice").and_then(|g| g.port);
+        }
+        Ok(state)
+    }
+
+    /// Save the running VM's memory to disk and stop it (libvirt managed save)
+    #[instrument(skip(self), fields(operation = "save_vm"))]
+    pub async fn save_vm(&mut self, vm_id: &str) -> Result<()> {
+        info!("Saving state of VM: {}", vm_id);
+
+        let domain = self.get_domain_by_id(vm_id)?;
+        let (state, _) = domain.get_state().map_err(KvmError::LibvirtConnection)?;
+        if !matches!(state, sys::VIR_DOMAIN_RUNNING | sys::VIR_DOMAIN_BLOCKED | sys::VIR_DOMAIN_PAUSED) {
+            return Err(KvmError::VmOperationFailed(format!(
+                "Cannot save VM: it is {}", Self::describe_domain_state(state)
+            )));
+        }
+
+        domain.managed_save(0)
+            .map_err(|e| {
+                error!("Failed to save VM {}: {}", vm_id, e);
+                KvmError::VmOperationFailed(format!("Failed to save VM state: {}", e))
+            })?;
+
+        if let Some(vm) = self.vm_cache.get_mut(vm_id) {
+            vm.state = VmState::Stopped;
+            vm.has_saved_state = true;
+        }
+
+        info!("Successfully saved state of VM: {}", vm_id);
+        Ok(())
+    }
+
+    /// Start a VM from its managed save image
+    #[instrument(skip(self), fields(operation = "restore_saved_vm"))]
+    pub async fn restore_saved_vm(&mut self, vm_id: &str) -> Result<()> {
+        info!("Restoring saved state of VM: {}", vm_id);
+
+        let domain = self.get_domain_by_id(vm_id)?;
+        if !domain.has_managed_save(0).map_err(KvmError::LibvirtConnection)? {
+            return Err(KvmError::VmOperationFailed("VM has no saved state to restore".to_string()));
+        }
+
+        // Starting a domain with a managed save image resumes from it
+        domain.create()
+            .map_err(|e| {
+                error!("Failed to restore VM {}: {}", vm_id, e);
+                KvmError::VmOperationFailed(format!(
+                    "Failed to restore saved state: {}. If the saved image is stale or corrupt, \
+                     discard it and start the VM normally",
+                    e
+                ))
+            })?;
+
+        let state = self.cache_current_state(vm_id, &domain)?;
+        if let Some(vm) = self.vm_cache.get_mut(vm_id) {
+            vm.has_saved_state = false;
+        }
+
+        info!("Successfully restored VM {} ({:?})", vm_id, state);
+        Ok(())
+    }
+
+    pub async fn has_managed_save(&self, vm_id: &str) -> Result<bool> {
+        let domain = self.get_domain_by_id(vm_id)?;
+        domain.has_managed_save(0).map_err(KvmError::LibvirtConnection)
+    }
+
+    /// Throw away a saved state so the next start is a fresh boot
+    pub async fn discard_saved_state(&mut self, vm_id: &str) -> Result<()> {
+        info!("Discarding saved state of VM: {}", vm_id);
+
+        let domain = self.get_domain_by_id(vm_id)?;
+        domain.managed_save_remove(0)
+            .map_err(|e| {
+                error!("Failed to discard saved state of VM {}: {}", vm_id, e);
+                KvmError::VmOperationFailed(format!("Failed to discard saved state: {}", e))
+            })?;
+
+        if let Some(vm) = self.vm_cache.get_mut(vm_id) {
+            vm.has_saved_state = false;
+        }
+
+        audit::record("vm_saved_state_discarded", vm_id, "Removed managed save image");
+        Ok(())
+    }
+
+    #[instrument(skip(self), fields(operation = "pause_vm"))]
+    pub async fn pause_vm(&mut self, vm_id: &str) -> Result<()> {
+        info!("Pausing VM: {}", vm_id);
+
+        let domain = self.get_domain_by_id(vm_id)?;
+        Self::require_running(&domain, "pause")?;
+
+        domain.suspend()
+            .map_err(|e| {
+                error!("Failed to pause VM {}: {}", vm_id, e);
+                KvmError::VmOperationFailed(format!("Failed to pause VM: {}", e))
+            })?;
+
+        self.set_cached_state(vm_id, VmState::Paused);
+        info!("Successfully paused VM: {}", vm_id);
+        Ok(())
+    }
+
+    #[instrument(skip(self), fields(operation = "resume_vm"))]
+    pub async fn resume_vm(&mut self, vm_id: &str) -> Result<()> {
+        info!("Resuming VM: {}", vm_id);
+
+        let domain = self.get_domain_by_id(vm_id)?;
+        let (state, _) = domain.get_state().map_err(KvmError::LibvirtConnection)?;
+        if state != sys::VIR_DOMAIN_PAUSED {
+            return Err(KvmError::VmOperationFailed(format!(
+                "Cannot resume VM: it is {}, not paused", Self::describe_domain_state(state)
+            )));
+        }
+
+        domain.resume()
+            .map_err(|e| {
+                error!("Failed to resume VM {}: {}", vm_id, e);
+                KvmError::VmOperationFailed(format!("Failed to resume VM: {}", e))
+            })?;
+
+        self.set_cached_state(vm_id, VmState::Running);
+        info!("Successfully resumed VM: {}", vm_id);
+        Ok(())
+    }
+
+    /// Record a state change we caused ourselves, avoiding a full cache refresh
+    fn set_cached_state(&mut self, vm_id: &str, state: VmState) {
+        if let Some(vm) = self.vm_cache.get_mut(vm_id) {
+            vm.state = state;
+        }
+    }
+
+    fn vm_state_from(state: sys::virDomainState) -> VmState {
+        match state {
+            sys::VIR_DOMAIN_NOSTATE => VmState::Stopped,
+            sys::VIR_DOMAIN_RUNNING => VmState::Running,
+            sys::VIR_DOMAIN_BLOCKED => VmState::Running,
+            sys::VIR_DOMAIN_PAUSED => VmState::Paused,
+            sys::VIR_DOMAIN_SHUTDOWN => VmState::ShuttingDown,
+            sys::VIR_DOMAIN_SHUTOFF => VmState::Stopped,
+            sys::VIR_DOMAIN_CRASHED => VmState::Error,
+            sys::VIR_DOMAIN_PMSUSPENDED => VmState::Suspended,
+            _ => VmState::Error,
+        }
+    }
+
+    fn describe_domain_state(state: sys::virDomainState) -> &'static str {
+        match state {
+            sys::VIR_DOMAIN_RUNNING | sys::VIR_DOMAIN_BLOCKED => "running",
+            sys::VIR_DOMAIN_PAUSED => "paused",
+            sys::VIR_DOMAIN_SHUTDOWN => "shutting down",
+            sys::VIR_DOMAIN_SHUTOFF => "shut off",
+            sys::VIR_DOMAIN_CRASHED => "crashed",
+            sys::VIR_DOMAIN_PMSUSPENDED => "suspended by the guest",
+            _ => "in an unknown state",
+        }
+    }
+
+    #[instrument(skip(self), fields(operation = "delete_vm"))]
     pub async fn delete_vm(&mut self, vm_id: &str) -> Result<()> {
         info!("Deleting VM: {}", vm_id);
 
         let domain = self.get_domain_by_id(vm_id)?;
         
-        // Stop VM if running
+        // The VM is going away, so there is nothing to shut down gracefully for
         if domain.is_active().map_err(KvmError::LibvirtConnection)? {
-            self.stop_vm(vm_id).await?;
+            self.force_stop_vm(vm_id).await?;
         }
 
-        // Undefine the domain
-        domain.undefine()
+        // Undefine the domain, along with its UEFI variable store and any managed
+        // save image; libvirt refuses to undefine while a saved image exists
+        domain.undefine_flags(sys::VIR_DOMAIN_UNDEFINE_NVRAM | sys::VIR_DOMAIN_UNDEFINE_MANAGED_SAVE)
             .map_err(|e| {
                 error!("Failed to delete VM {}: {}", vm_id, e);
                 KvmError::VmOperationFailed(format!("Failed to delete VM: {}", e))
@@ -191,8 +669,8 @@ impl VmManager {
         // Get domain info
         let info = domain.get_info().map_err(KvmError::LibvirtConnection)?;
         
-        // Get enhanced CPU statistics
-        let cpu_usage = self.get_cpu_usage_percentage(&domain).await.unwrap_or(0.0);
+        // Percent of allocated vCPUs, as reported by the monitoring service
+        let cpu_usage = monitoring::sample_cpu_usage(&domain).await.unwrap_or(0.0);
 
         // Get enhanced memory stats
         let (memory_usage, memory_total) = self.get_memory_stats(&domain, &info).await;
@@ -208,112 +686,500 @@ impl VmManager {
 
         Ok(VmStats {
             cpu_usage,
-            memory_usage,
-            memory_total,
             disk_read,
             disk_write,
             network_rx,
             network_tx,
-            uptime,
             timestamp: Utc::now(),
-            guest_agent_connected: false,
-        })
+            cycle_id: None,
+            guest_agent_connected: self.guest_agent_responds(&domain).await,
+            ..Default::default()
+        }
+        .with_memory(memory_usage, memory_total)
+        .with_uptime(uptime))
     }
 
-    async fn get_cpu_usage_percentage(&self, domain: &Domain) -> Option<f64> {
-        // Get CPU stats from libvirt - this requires multiple samples for accuracy
-        if let Ok(info1) = domain.get_info() {
-            tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-            if let Ok(info2) = domain.get_info() {
-                // Calculate CPU usage based on CPU time changes
-                let time_diff = info2.cpu_time.saturating_sub(info1.cpu_time);
-                if time_diff > 0 {
-                    // Convert nanoseconds to percentage (rough approximation)
-                    let cpu_usage = (time_diff as f64 / 100_000_000.0).min(100.0);
-                    return Some(cpu_usage);
-                }
-            }
-        }
-        
-        // Fallback: try to read from /proc if available
-        if let Ok(name) = domain.get_name() {
-            if let Some(usage) = self.get_vm_cpu_from_proc(&name).await {
-                return Some(usage);
-            }
-        }
-        
-        None
+    /// UUIDs of the domains currently running, for the metrics collector
+    pub fn running_vm_ids(&self) -> Result<Vec<String>> {
+        let domains = self.connection
+            .list_all_domains(sys::VIR_CONNECT_LIST_DOMAINS_ACTIVE)
+            .map_err(KvmError::LibvirtConnection)?;
+        Ok(domains.iter().filter_map(|domain| domain.get_uuid_string().ok()).collect())
     }
 
-    async fn get_vm_cpu_from_proc(&self, vm_name: &str) -> Option<f64> {
-        // Try to get CPU usage from /proc filesystem
-        use std::process::Command;
-        
-        let output = Command::new("ps")
-            .args(["-eo", "comm,pcpu"])
-            .output()
-            .ok()?;
-        
-        if output.status.success() {
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            for line in stdout.lines() {
-                if line.contains(vm_name) || line.contains("qemu") {
-                    let parts: Vec<&str> = line.split_whitespace().collect();
-                    if parts.len() >= 2 {
-                        if let Ok(cpu_usage) = parts[1].parse::<f64>() {
-                            return Some(cpu_usage);
-                        }
-                    }
-                }
-            }
+    async fn guest_agent_responds(&self, domain: &Domain) -> bool {
+        match domain.get_name() {
+            Ok(name) => guest_agent::ping_cached(&self.uri, &name).await,
+            Err(_) => false,
         }
-        None
     }
 
-    async fn get_memory_stats(&self, domain: &Domain, info: &virt::domain::DomainInfo) -> (u64, u64) {
-        // Try to get detailed memory statistics
-        if let Ok(memory_stats) = domain.memory_stats(0) {
-            let mut actual_memory = info.memory / 1024; // Default fallback
-            let mut available_memory = 0;
-            let mut unused_memory = 0;
-            
-            for stat in memory_stats {
-                match stat.tag {
-                    sys::VIR_DOMAIN_MEMORY_STAT_ACTUAL_BALLOON => {
-                        actual_memory = stat.val / 1024; // Convert to MB
-                    },
-                    sys::VIR_DOMAIN_MEMORY_STAT_AVAILABLE => {
-                        available_memory = stat.val / 1024;
-                    },
-                    sys::VIR_DOMAIN_MEMORY_STAT_UNUSED => {
-                        unused_memory = stat.val / 1024;
-                    },
-                    _ => {}
-                }
-            }
-            
-            let used_memory = if available_memory > 0 {
-                actual_memory - available_memory
-            } else if unused_memory > 0 {
-                actual_memory - unused_memory
-            } else {
-                actual_memory
-            };
-            
-            return (used_memory, actual_memory);
+    /// Ask the guest agent for its version, bypassing the ping cache
+    pub async fn ping_guest_agent(&self, vm_id: &str) -> Result<guest_agent::AgentStatus> {
+        let domain = self.get_domain_by_id(vm_id)?;
+        Self::require_running(&domain, "ping the guest agent of")?;
+        let (uri, name) = (self.uri.clone(), domain.get_name().map_err(KvmError::LibvirtConnection)?);
+        tokio::task::spawn_blocking(move || guest_agent::status(&uri, &name))
+            .await
+            .map_err(|e| KvmError::VmOperationFailed(format!("Guest agent task failed: {}", e)))
+    }
+
+    /// Send a single command (e.g. `guest-get-osinfo`) to the VM's guest agent
+    /// and return the agent's reply
+    pub async fn query_guest_agent(&self, vm_id: &str, command: &str) -> Result<serde_json::Value> {
+        let domain = self.get_domain_by_id(vm_id)?;
+        Self::require_running(&domain, "query the guest agent of")?;
+        let name = domain.get_name().map_err(KvmError::LibvirtConnection)?;
+        guest_agent::execute_blocking(&self.uri, &name, command, None).await
+    }
+
+    /// The agent of a running VM, for calls made after the manager lock is
+    /// released. `operation` describes the attempt in the not-running error.
+    pub fn guest_agent_target(&self, vm_id: &str, operation: &str) -> Result<guest_agent::AgentTarget> {
+        let domain = self.get_domain_by_id(vm_id)?;
+        Self::require_running(&domain, operation)?;
+        Ok(guest_agent::AgentTarget {
+            vm_id: vm_id.to_string(),
+            uri: self.uri.clone(),
+            domain_name: domain.get_name().map_err(KvmError::LibvirtConnection)?,
+        })
+    }
+
+    /// Run a command inside the guest through the agent's guest-exec, waiting
+    /// at most `timeout_secs` (capped at `guest_agent::MAX_EXEC_TIMEOUT`) for
+    /// it to finish. Takes no lock, so other commands run while it waits.
+    pub async fn guest_exec(
+        target: &guest_agent::AgentTarget,
+        command: &str,
+        args: &[String],
+        capture_output: bool,
+        timeout_secs: u64,
+    ) -> Result<guest_agent::GuestExecResult> {
+        if command.trim().is_empty() {
+            return Err(KvmError::VmOperationFailed("No command to run".to_string()));
         }
-        
-        // Fallback to basic info
-        let total = info.memory / 1024;
-        (total, total)
+
+        info!("Running {} in VM {}", command, target.vm_id);
+        let timeout = std::time::Duration::from_secs(timeout_secs);
+        let result = guest_agent::exec(&target.uri, &target.domain_name, command, args, capture_output, timeout).await?;
+        audit::record("guest_exec", &target.vm_id, &format!("{} exited with {:?}", command, result.exit_code));
+        Ok(result)
     }
 
-    async fn get_disk_io_stats(&self, domain: &Domain) -> (u64, u64) {
-        // Try to get statistics from all disk devices
-        let mut total_read = 0;
-        let mut total_write = 0;
-        
-        // Common disk device names
+    /// Read a file inside the guest through the agent, at most `max_bytes`
+    /// (and never more than 1 MiB). Takes no lock, like `guest_exec`.
+    pub async fn guest_read_file(target: &guest_agent::AgentTarget, path: &str, max_bytes: Option<usize>) -> Result<guest_agent::GuestFileContents> {
+        debug!("Reading {} in VM {}", path, target.vm_id);
+        let (target, path) = (target.clone(), path.to_string());
+        let max_bytes = max_bytes.unwrap_or(guest_agent::MAX_FILE_BYTES);
+        tokio::task::spawn_blocking(move || guest_agent::read_file(&target.uri, &target.domain_name, &path, max_bytes))
+            .await
+            .map_err(|e| KvmError::VmOperationFailed(format!("Guest file read failed: {}", e)))?
+    }
+
+    /// Write a file inside the guest through the agent, replacing it unless
+    /// `append` is set. Returns the number of bytes written.
+    pub async fn guest_write_file(target: &guest_agent::AgentTarget, path: &str, contents_base64: &str, append: bool) -> Result<usize> {
+        let contents = base64::engine::general_purpose::STANDARD.decode(contents_base64.trim())
+            .map_err(|e| KvmError::VmOperationFailed(format!("File contents are not valid base64: {}", e)))?;
+
+        info!("Writing {} bytes to {} in VM {}", contents.len(), path, target.vm_id);
+        let (agent, guest_path) = (target.clone(), path.to_string());
+        let write = tokio::task::spawn_blocking(move || {
+            guest_agent::write_file(&agent.uri, &agent.domain_name, &guest_path, &contents, append)
+        });
+        let written = write.await
+            .map_err(|e| KvmError::VmOperationFailed(format!("Guest file write failed: {}", e)))??;
+        audit::record("guest_file_written", &target.vm_id, &format!("{} {} bytes to {}", if append { "Appended" } else { "Wrote" }, written, path));
+        Ok(written)
+    }
+
+    /// Filesystems and IP addresses as seen from inside the guest. A missing or
+    /// unresponsive agent is not an error; `agent_connected` is false instead.
+    pub async fn get_guest_info(&self, vm_id: &str) -> Result<GuestInfo> {
+        let domain = self.get_domain_by_id(vm_id)?;
+        if !domain.is_active().map_err(KvmError::LibvirtConnection)? {
+            return Ok(GuestInfo::default());
+        }
+
+        if self.query_guest_agent(vm_id, "guest-ping").await.is_err() {
+            return Ok(GuestInfo::default());
+        }
+
+        let filesystems = match self.query_guest_agent(vm_id, "guest-get-fsinfo").await {
+            Ok(reply) => guest_agent::parse_fsinfo(&reply),
+            Err(e) => {
+                debug!("No filesystem info from guest agent of VM {}: {}", vm_id, e);
+                Vec::new()
+            }
+        };
+        let interfaces = match self.query_guest_agent(vm_id, "guest-network-get-interfaces").await {
+            Ok(reply) => guest_agent::parse_interfaces(&reply),
+            Err(e) => {
+                debug!("No network info from guest agent of VM {}: {}", vm_id, e);
+                Vec::new()
+            }
+        };
+
+        Ok(GuestInfo {
+            agent_connected: true,
+            filesystems,
+            interfaces,
+        })
+    }
+
+    pub async fn get_active_graphics(&self, vm_id: &str) -> Result<Vec<GraphicsDevice>> {
+        debug!("Getting graphics devices for VM: {}", vm_id);
+
+        let domain = self.get_domain_by_id(vm_id)?;
+
+        // The live XML of a running domain carries the ports libvirt actually allocated
+        let xml = domain.get_xml_desc(0).map_err(KvmError::LibvirtConnection)?;
+        let devices = XmlParser::parse_graphics_devices(&xml);
+
+        debug!("Found {} graphics devices for VM {}", devices.len(), vm_id);
+        Ok(devices)
+    }
+
+    /// Connection details for the VM's primary display. Ports requested with
+    /// autoport are only assigned at start, so this reads the live XML.
+    pub async fn get_vm_display_info(&self, vm_id: &str) -> Result<DisplayInfo> {
+        self.display_info(vm_id, None)
+    }
+
+    /// Connection details for the VM's SPICE display, including the TLS port
+    pub async fn get_spice_connection(&self, vm_id: &str) -> Result<DisplayInfo> {
+        self.display_info(vm_id, Some("spice"))
+    }
+
+    fn display_info(&self, vm_id: &str, protocol: Option<&str>) -> Result<DisplayInfo> {
+        let domain = self.get_domain_by_id(vm_id)?;
+        Self::require_running(&domain, "connect to the display of")?;
+
+        // The secure XML is the only place that shows whether a password is set
+        let xml = domain.get_xml_desc(sys::VIR_DOMAIN_XML_SECURE).map_err(KvmError::LibvirtConnection)?;
+        let graphics = XmlParser::parse_graphics_devices(&xml)
+            .into_iter()
+            .find(|graphics| protocol.is_none_or(|protocol| graphics.protocol == protocol))
+            .ok_or_else(|| match protocol {
+                Some(protocol) => KvmError::VmOperationFailed(format!("VM has no {} display", protocol.to_uppercase())),
+                None => KvmError::VmOperationFailed("VM has no graphical display".to_string()),
+            })?;
+
+        let host = match graphics.listen.as_deref() {
+            None | Some("0.0.0.0") | Some("::") | Some("") => "127.0.0.1".to_string(),
+            Some(address) => address.to_string(),
+        };
+        let bracketed = if host.contains(':') { format!("[{}]", host) } else { host.clone() };
+        let uri = match (graphics.protocol.as_str(), graphics.port, graphics.tls_port) {
+            ("vnc", Some(port), _) => Some(format!("vnc://{}:{}", bracketed, port)),
+            ("spice", Some(port), Some(tls_port)) => Some(format!("spice://{}:{}?tls-port={}", bracketed, port, tls_port)),
+            ("spice", Some(port), None) => Some(format!("spice://{}:{}", bracketed, port)),
+            ("spice", None, Some(tls_port)) => Some(format!("spice://{}?tls-port={}", bracketed, tls_port)),
+            _ => None,
+        };
+
+        Ok(DisplayInfo {
+            vm_id: vm_id.to_string(),
+            graphics_type: graphics.protocol,
+            host,
+            listen: graphics.listen,
+            port: graphics.port,
+            tls_port: graphics.tls_port,
+            socket: graphics.socket,
+            password_set: graphics.password_set,
+            uri,
+        })
+    }
+
+    /// Set the password of the running VM's display (SPICE if it has one, else
+    /// VNC) without touching its saved configuration. With no password a
+    /// random one is generated; with `expire_seconds` it stops working after
+    /// that long. Returns the password, which is never logged.
+    pub async fn set_graphics_password(&self, vm_id: &str, password: Option<String>, expire_seconds: Option<u32>) -> Result<String> {
+        let domain = self.get_domain_by_id(vm_id)?;
+        let (state, _) = domain.get_state().map_err(KvmError::LibvirtConnection)?;
+        if !matches!(state, sys::VIR_DOMAIN_RUNNING | sys::VIR_DOMAIN_BLOCKED | sys::VIR_DOMAIN_PAUSED) {
+            return Err(KvmError::VmNotRunning(format!("{} is {}", vm_id, Self::describe_domain_state(state))));
+        }
+
+        let xml = domain.get_xml_desc(sys::VIR_DOMAIN_XML_SECURE).map_err(KvmError::LibvirtConnection)?;
+        let graphics = XmlParser::parse_graphics_devices(&xml);
+        let protocol = graphics.iter()
+            .map(|graphics| graphics.protocol.as_str())
+            .find(|protocol| *protocol == "spice")
+            .or_else(|| graphics.first().map(|graphics| graphics.protocol.as_str()))
+            .ok_or_else(|| KvmError::VmOperationFailed("VM has no graphical display".to_string()))?;
+
+        let password = match password {
+            Some(password) if password.is_empty() => {
+                return Err(KvmError::InvalidVmConfig("Display password cannot be empty".to_string()));
+            }
+            Some(password) => password,
+            None if protocol == "vnc" => Self::random_password(VNC_PASSWORD_LENGTH),
+            None => Self::random_password(SPICE_PASSWORD_LENGTH),
+        };
+        let valid_to = expire_seconds.map(|seconds| {
+            (Utc::now() + chrono::Duration::seconds(seconds as i64)).format("%Y-%m-%dT%H:%M:%S").to_string()
+        });
+
+        let graphics_xml = XmlParser::graphics_with_password(&xml, protocol, &password, valid_to.as_deref())
+            .ok_or_else(|| KvmError::XmlParsingError("Failed to read the VM's graphics element".to_string()))?;
+        domain.update_device_flags(&graphics_xml, sys::VIR_DOMAIN_AFFECT_LIVE)
+            .map_err(|e| {
+                error!("Failed to set display password of VM {}: {}", vm_id, e);
+                KvmError::VmOperationFailed(format!("Failed to set display password: {}", e))
+            })?;
+
+        info!("Set {} password of VM {}{}", protocol, vm_id,
+              valid_to.map(|valid_to| format!(", valid until {} UTC", valid_to)).unwrap_or_default());
+        Ok(password)
+    }
+
+    /// Where `take_vm_screenshot` captures from; fails unless the VM is running
+    pub fn screenshot_target(&self, vm_id: &str) -> Result<screenshot::ScreenshotTarget> {
+        let domain = self.get_domain_by_id(vm_id)?;
+        let (state, _) = domain.get_state().map_err(KvmError::LibvirtConnection)?;
+        if !matches!(state, sys::VIR_DOMAIN_RUNNING | sys::VIR_DOMAIN_BLOCKED | sys::VIR_DOMAIN_PAUSED) {
+            self.screenshots.lock().unwrap_or_else(|p| p.into_inner()).remove(vm_id);
+            return Err(KvmError::VmNotRunning(format!("{} is {}", vm_id, Self::describe_domain_state(state))));
+        }
+
+        Ok(screenshot::ScreenshotTarget {
+            vm_id: vm_id.to_string(),
+            uri: self.uri.clone(),
+            domain_name: domain.get_name().map_err(KvmError::LibvirtConnection)?,
+            domain,
+            captures: Arc::clone(&self.screenshots),
+        })
+    }
+
+    fn random_password(length: usize) -> String {
+        rand::rng().sample_iter(rand::distr::Alphanumeric).take(length).map(char::from).collect()
+    }
+
+    /// A PNG of the VM's screen, scaled to `max_width` for thumbnails. A
+    /// polling UI gets the previous capture back until it is a couple of
+    /// seconds old, so QEMU isn't asked for a frame on every call. Takes no
+    /// lock, like `guest_exec`.
+    pub async fn take_vm_screenshot(target: &screenshot::ScreenshotTarget, max_width: Option<u32>) -> Result<VmScreenshot> {
+        let now = Utc::now();
+        // The slot is claimed before capturing, so callers arriving meanwhile get
+        // the previous capture instead of asking QEMU for another frame
+        let (recent, previous) = {
+            let mut captures = target.captures.lock().unwrap_or_else(|p| p.into_inner());
+            match captures.get(&target.vm_id) {
+                Some(capture) if now - capture.taken_at < chrono::Duration::seconds(SCREENSHOT_MIN_INTERVAL_SECS) => {
+                    (Some(capture.clone()), None)
+                }
+                previous => {
+                    let previous = previous.cloned();
+                    let claim = screenshot::Capture {
+                        image: previous.as_ref().map(|capture| capture.image.clone()).unwrap_or_default(),
+                        taken_at: now,
+                    };
+                    captures.insert(target.vm_id.clone(), claim);
+                    (None, previous)
+                }
+            }
+        };
+
+        let capture = match recent {
+            // Claimed by a first capture that hasn't finished yet
+            Some(capture) if capture.image.is_empty() => {
+                return Err(KvmError::VmOperationFailed("A screenshot is already being taken; try again shortly".to_string()));
+            }
+            Some(capture) => capture,
+            None => {
+                let (domain, uri, name) = (target.domain.clone(), target.uri.clone(), target.domain_name.clone());
+                let captured = tokio::task::spawn_blocking(move || screenshot::capture(&domain, &uri, &name))
+                    .await
+                    .map_err(|e| KvmError::VmOperationFailed(format!("Screenshot task failed: {}", e)))
+                    .and_then(|captured| captured);
+
+                let mut captures = target.captures.lock().unwrap_or_else(|p| p.into_inner());
+                match captured {
+                    Ok(image) => {
+                        let capture = screenshot::Capture { image: Arc::new(image), taken_at: now };
+                        captures.insert(target.vm_id.clone(), capture.clone());
+                        capture
+                    }
+                    Err(e) => {
+                        // Give the slot back so the next request tries again straight away
+                        match previous {
+                            Some(previous) => { captures.insert(target.vm_id.clone(), previous); }
+                            None => { captures.remove(&target.vm_id); }
+                        }
+                        return Err(e);
+                    }
+                }
+            }
+        };
+
+        let (png, width, height) = screenshot::to_png(&capture.image, max_width)?;
+        Ok(VmScreenshot {
+            vm_id: target.vm_id.clone(),
+            png_base64: base64::engine::general_purpose::STANDARD.encode(png),
+            width,
+            height,
+            taken_at: capture.taken_at,
+        })
+    }
+
+    /// Press `keys` together in the guest, e.g. ["ctrl", "alt", "delete"] or
+    /// the name of a predefined combo such as "ctrl-alt-f2", holding them for
+    /// `hold_ms` (libvirt's default when None)
+    pub async fn send_keys(&self, vm_id: &str, keys: &[String], hold_ms: Option<u32>) -> Result<()> {
+        let mut keycodes = keycodes::resolve(keys)?;
+
+        let domain = self.get_domain_by_id(vm_id)?;
+        let (state, _) = domain.get_state().map_err(KvmError::LibvirtConnection)?;
+        if !matches!(state, sys::VIR_DOMAIN_RUNNING | sys::VIR_DOMAIN_BLOCKED) {
+            return Err(KvmError::VmNotRunning(format!("{} is {}", vm_id, Self::describe_domain_state(state))));
+        }
+
+        debug!("Sending keys {:?} to VM {}", keys, vm_id);
+        domain.send_key(
+            sys::VIR_KEYCODE_SET_LINUX,
+            hold_ms.unwrap_or(0),
+            keycodes.as_mut_ptr(),
+            keycodes.len() as i32,
+            0,
+        ).map_err(|e| {
+            error!("Failed to send keys to VM {}: {}", vm_id, e);
+            KvmError::VmOperationFailed(format!("Failed to send keys: {}", e))
+        })
+    }
+
+    pub async fn analyze_vm_storage_layout(&self, vm_id: &str) -> Result<StorageLayoutReport> {
+        info!("Analyzing storage layout for VM: {}", vm_id);
+
+        let domain = self.get_domain_by_id(vm_id)?;
+        let xml = domain.get_xml_desc(0).map_err(KvmError::LibvirtConnection)?;
+        let xml_info = XmlParser::parse_vm_from_xml(&xml)?;
+        let is_active = domain.is_active().map_err(KvmError::LibvirtConnection)?;
+
+        let host_os_devices = storage_layout::resolve_placement("/").physical_devices;
+
+        let mut disks = Vec::new();
+        for storage_device in &xml_info.storage_devices {
+            let placement = storage_device.path.as_deref()
+                .map(storage_layout::resolve_placement)
+                .unwrap_or_default();
+
+            // Block stats are only available while the domain is running
+            let (read_bytes, write_bytes) = if is_active {
+                domain.get_block_stats(&storage_device.device)
+                    .map(|stats| (stats.rd_bytes.max(0) as u64, stats.wr_bytes.max(0) as u64))
+                    .unwrap_or((0, 0))
+            } else {
+                (0, 0)
+            };
+
+            disks.push(DiskPlacement {
+                device: storage_device.device.clone(),
+                path: storage_device.path.clone(),
+                mount_point: placement.mount_point,
+                source_device: placement.source_device,
+                on_host_os_device: placement.physical_devices.iter().any(|d| host_os_devices.contains(d)),
+                physical_devices: placement.physical_devices,
+                read_bytes,
+                write_bytes,
+                busy: read_bytes > 0 || write_bytes > 0,
+            });
+        }
+
+        // Group disks by the physical device they land on
+        let mut by_device: HashMap<String, Vec<&DiskPlacement>> = HashMap::new();
+        for disk in &disks {
+            for physical in &disk.physical_devices {
+                by_device.entry(physical.clone()).or_default().push(disk);
+            }
+        }
+
+        let mut warnings = Vec::new();
+        let mut shared_devices = Vec::new();
+        for (physical_device, placed) in by_device {
+            if placed.len() < 2 {
+                continue;
+            }
+
+            let busy_disks: Vec<String> = placed.iter().filter(|d| d.busy).map(|d| d.device.clone()).collect();
+            if busy_disks.len() > 1 {
+                warnings.push(format!(
+                    "Busy disks {} share physical device {}",
+                    busy_disks.join(", "), physical_device
+                ));
+            }
+
+            shared_devices.push(SharedDevice {
+                physical_device,
+                disks: placed.iter().map(|d| d.device.clone()).collect(),
+                busy_disks,
+            });
+        }
+        shared_devices.sort_by(|a, b| a.physical_device.cmp(&b.physical_device));
+
+        for disk in disks.iter().filter(|d| d.on_host_os_device) {
+            warnings.push(format!("Disk {} is on the same physical device as the host OS", disk.device));
+        }
+
+        Ok(StorageLayoutReport {
+            vm_id: vm_id.to_string(),
+            host_os_devices,
+            disks,
+            shared_devices,
+            warnings,
+        })
+    }
+
+    async fn get_memory_stats(&self, domain: &Domain, info: &virt::domain::DomainInfo) -> (u64, u64) {
+        // Try to get detailed memory statistics
+        if let Ok(memory_stats) = domain.memory_stats(0) {
+            let mut actual_memory = info.memory / 1024; // Default fallback
+            let mut available_memory = 0;
+            let mut unused_memory = 0;
+            
+            for stat in memory_stats {
+                match stat.tag {
+                    sys::VIR_DOMAIN_MEMORY_STAT_ACTUAL_BALLOON => {
+                        actual_memory = stat.val / 1024; // Convert to MB
+                    },
+                    sys::VIR_DOMAIN_MEMORY_STAT_AVAILABLE => {
+                        available_memory = stat.val / 1024;
+                    },
+                    sys::VIR_DOMAIN_MEMORY_STAT_UNUSED => {
+                        unused_memory = stat.val / 1024;
+                    },
+                    _ => {}
+                }
+            }
+            
+            // After a live resize the balloon size changes at once, but the
+            // guest's figures lag until its next stats update; ignore any that
+            // still describe more memory than the balloon now holds
+            let used_memory = if available_memory > 0 && available_memory <= actual_memory {
+                actual_memory - available_memory
+            } else if unused_memory > 0 && unused_memory <= actual_memory {
+                actual_memory - unused_memory
+            } else {
+                actual_memory
+            };
+            
+            return (used_memory, actual_memory);
+        }
+        
+        // Fallback to basic info
+        let total = info.memory / 1024;
+        (total, total)
+    }
+
+    async fn get_disk_io_stats(&self, domain: &Domain) -> (u64, u64) {
+        // Try to get statistics from all disk devices
+        let mut total_read = 0;
+        let mut total_write = 0;
+        
+        // Common disk device names
         let disk_devices = ["vda", "vdb", "vdc", "vdd", "sda", "sdb", "hda", "hdb"];
         
         for device in &disk_devices {
@@ -396,10 +1262,13 @@ impl VmManager {
     
     async fn estimate_vm_uptime(&self, vm_name: &str) -> u64 {
         // Try to estimate uptime from process information
-        use std::process::Command;
+        let mut command = match tools::try_command(Tool::Ps) {
+            Some(command) => command,
+            None => return 0,
+        };
         
         // Look for qemu process for this VM
-        if let Ok(output) = Command::new("ps")
+        if let Ok(output) = command
             .args(["-eo", "comm,pid,etime"])
             .output() {
             
@@ -473,6 +1342,13 @@ impl VmManager {
             return Err(KvmError::VmOperationFailed(format!("Proxmox image not found: {}", proxmox_path)));
         }
 
+        quotas::check(&self.connection, &QuotaRequest {
+            vcpus,
+            memory_mb: memory_gb as u64 * 1024,
+            disk_gb: XmlParser::get_disk_size_from_file(&proxmox_path).unwrap_or(0.0).ceil() as u64,
+            tags: Vec::new(),
+        })?;
+
         let vm_id = Uuid::new_v4().to_string();
         let memory_mb = memory_gb * 1024;
 
@@ -485,7 +1361,7 @@ impl VmManager {
   <currentMemory unit='MiB'>{}</currentMemory>
   <vcpu placement='static'>{}</vcpu>
   <os>
-    <type arch='x86_64' machine='pc-q35-6.2'>hvm</type>
+    <type arch='x86_64' machine='{}'>hvm</type>
     <boot dev='hd'/>
   </os>
   <features>
@@ -565,7 +1441,7 @@ impl VmManager {
     </rng>
   </devices>
 </domain>
-"#, name, vm_id, memory_mb, memory_mb, vcpus, proxmox_path,
+"#, name, vm_id, memory_mb, memory_mb, vcpus, XmlParser::escape(&self.default_machine_type()), proxmox_path,
         rand::random::<u8>(), rand::random::<u8>(), rand::random::<u8>());
 
         // Define the domain
@@ -580,500 +1456,2907 @@ impl VmManager {
         Ok(vm_id)
     }
 
-    pub async fn get_host_info(&self) -> Result<HostInfo> {
-        debug!("Getting host information");
-
-        let node_info = self.connection.get_node_info()
-            .map_err(KvmError::LibvirtConnection)?;
+    /// Small header summary for frequent polling.
+    ///
+    /// Built only from cached state (the VM and pool caches and the system monitor
+    /// cache), so it makes no libvirt calls and may be up to one monitoring
+    /// interval stale. `revision` only changes when the content does.
+    pub fn get_status_summary(&self) -> StatusSummary {
+        let mut summary = StatusSummary::default();
 
-        let hostname = self.connection.get_hostname()
-            .map_err(KvmError::LibvirtConnection)?;
+        for vm in self.vm_cache.values() {
+            match vm.state {
+                VmState::Running | VmState::ShuttingDown => summary.running += 1,
+                VmState::Paused | VmState::Suspended => summary.paused += 1,
+                VmState::Stopped | VmState::Creating | VmState::Error => summary.stopped += 1,
+            }
+        }
 
-        let hypervisor = self.connection.get_type()
-            .map_err(KvmError::LibvirtConnection)?;
+        if let Some(stats) = crate::system_monitor::SystemMonitor::get_cached_stats() {
+            summary.host_cpu_percent = Some(stats.cpu_usage);
+            summary.host_memory_percent = Some(stats.memory_percentage);
+        }
 
-        let hypervisor_version = self.connection.get_lib_version()
-            .map_err(KvmError::LibvirtConnection)?;
+        let low_space_threshold = DiskSpacePolicy::default().min_free_bytes;
+        if let Some(pool) = self.pool_cache.iter().min_by_key(|pool| pool.available_bytes) {
+            summary.tightest_pool = Some(PoolSpace {
+                name: pool.name.clone(),
+                available_bytes: pool.available_bytes,
+                available_display: format_bytes(pool.available_bytes),
+            });
+        }
 
-        // Get domain counts
-        let active_vms = self.connection.num_of_domains()
-            .map_err(KvmError::LibvirtConnection)? as u32;
+        // Alerts: missing tools and pools running out of space
+        summary.active_alerts = tools::status().iter().filter(|tool| !tool.available).count() as u32
+            + self.pool_cache.iter().filter(|pool| pool.available_bytes < low_space_threshold).count() as u32;
 
-        let inactive_vms = self.connection.num_of_defined_domains()
-            .map_err(KvmError::LibvirtConnection)? as u32;
+        let mut revision = self.status_revision.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if revision.1.as_ref() != Some(&summary) {
+            revision.0 += 1;
+            revision.1 = Some(summary.clone());
+        }
+        summary.revision = revision.0;
+        summary
+    }
 
-        // Get memory info
-        let memory_total = node_info.memory / 1024; // Convert to MB from KB
-        
-        // Get actual free memory from system
-        let memory_free = self.get_host_free_memory().await.unwrap_or(0);
+    /// libvirt library and hypervisor versions, for diagnostics
+    pub fn version_info(&self) -> serde_json::Value {
+        let format_version = |version: u32| {
+            format!("{}.{}.{}", version / 1_000_000, (version / 1000) % 1000, version % 1000)
+        };
 
-        Ok(HostInfo {
-            hostname,
-            hypervisor,
-            hypervisor_version: hypervisor_version.to_string(),
-            cpu_model: node_info.model,
-            cpu_cores: node_info.cpus,
-            memory_total,
-            memory_free,
-            storage_pools: self.get_storage_pools().await?,
-            networks: self.get_networks().await?,
-            active_vms,
-            inactive_vms,
+        serde_json::json!({
+            "app": env!("CARGO_PKG_VERSION"),
+            "libvirt": self.connection.get_lib_version().ok().map(format_version),
+            "hypervisor": self.connection.get_hyp_version().ok().map(format_version),
+            "hypervisor_type": self.connection.get_type().ok(),
+            "uri": self.connection.get_uri().ok(),
         })
     }
 
-    pub async fn create_snapshot(&self, vm_id: &str, snapshot_name: &str) -> Result<()> {
-        info!("Creating snapshot {} for VM {}", snapshot_name, vm_id);
-        
-        let domain = self.get_domain_by_id(vm_id)?;
-        
-        // Generate snapshot XML (not used in virsh approach)
-        let _snapshot_xml = format!(
-            r#"<domainsnapshot>
-  <name>{}</name>
-  <description>Snapshot created by KVM Manager</description>
-  <creationTime>{}</creationTime>
-</domainsnapshot>"#,
-            snapshot_name,
-            chrono::Utc::now().timestamp()
-        );
-        
-        // Create the snapshot using virsh command as fallback
-        // This is needed because the virt crate might not have full snapshot support
-        let output = std::process::Command::new("virsh")
-            .args(["snapshot-create-as", &domain.get_name().unwrap_or_default(), snapshot_name, "--disk-only"])
-            .output()
-            .map_err(|e| KvmError::SnapshotOperationFailed(format!("Failed to execute virsh: {}", e)))?;
-        
-        if !output.status.success() {
-            let error = String::from_utf8_lossy(&output.stderr);
-            return Err(KvmError::SnapshotOperationFailed(format!("Snapshot creation failed: {}", error)));
-        }
-        
-        info!("Successfully created snapshot {} for VM {}", snapshot_name, vm_id);
-        Ok(())
+    pub async fn get_quota_usage(&self) -> Result<QuotaUsage> {
+        quotas::usage(&self.connection)
     }
 
-    pub async fn restore_snapshot(&self, vm_id: &str, snapshot_name: &str) -> Result<()> {
-        info!("Restoring snapshot {} for VM {}", snapshot_name, vm_id);
-        
-        let domain = self.get_domain_by_id(vm_id)?;
-        let vm_name = domain.get_name().unwrap_or_default();
-        
-        // Use virsh to restore snapshot
-        let output = std::process::Command::new("virsh")
-            .args(["snapshot-revert", &vm_name, snapshot_name])
-            .output()
-            .map_err(|e| KvmError::SnapshotOperationFailed(format!("Failed to execute virsh: {}", e)))?;
-        
-        if !output.status.success() {
-            let error = String::from_utf8_lossy(&output.stderr);
-            return Err(KvmError::SnapshotOperationFailed(format!("Snapshot restoration failed: {}", error)));
+    pub async fn set_nested_virtualization(&self, vm_id: &str, enabled: bool) -> Result<()> {
+        info!("Setting nested virtualization for VM {} to {}", vm_id, enabled);
+
+        let feature = match Self::host_nested_virtualization() {
+            Some(feature) => feature,
+            None if enabled => {
+                return Err(KvmError::VmOperationFailed(
+                    "Nested virtualization is disabled on this host; enable it with the kvm_intel or kvm_amd 'nested=1' module option".to_string()
+                ));
+            }
+            // Clear both so disabling works regardless of the host vendor
+            None => "vmx",
+        };
+
+        let domain = self.update_domain_xml(vm_id, |xml| {
+            if enabled {
+                XmlParser::set_cpu_feature(xml, feature, Some("require"))
+            } else {
+                let without_vmx = XmlParser::set_cpu_feature(xml, "vmx", None);
+                XmlParser::set_cpu_feature(&without_vmx, "svm", None)
+            }
+        })?;
+
+        if domain.is_active().unwrap_or(false) {
+            info!("VM {} is running; nested virtualization change applies on next boot", vm_id);
         }
-        
-        info!("Successfully restored snapshot {} for VM {}", snapshot_name, vm_id);
+
+        info!("Successfully updated nested virtualization for VM {}", vm_id);
         Ok(())
     }
-    
-    pub async fn list_snapshots(&self, vm_id: &str) -> Result<Vec<Snapshot>> {
-        info!("Listing snapshots for VM {}", vm_id);
-        
+
+    /// The VM's real configuration as libvirt stores it, in `VmConfig` shape
+    pub async fn get_effective_config(&self, vm_id: &str) -> Result<EffectiveVmConfig> {
+        debug!("Resolving effective configuration for VM: {}", vm_id);
+
         let domain = self.get_domain_by_id(vm_id)?;
-        let vm_name = domain.get_name().unwrap_or_default();
-        
-        // Use virsh to list snapshots
-        let output = std::process::Command::new("virsh")
-            .args(["snapshot-list", &vm_name, "--name"])
-            .output()
-            .map_err(|e| KvmError::SnapshotOperationFailed(format!("Failed to execute virsh: {}", e)))?;
-        
-        if !output.status.success() {
-            let error = String::from_utf8_lossy(&output.stderr);
-            return Err(KvmError::SnapshotOperationFailed(format!("Failed to list snapshots: {}", error)));
-        }
-        
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let mut snapshots = Vec::new();
-        
-        for line in stdout.lines() {
-            let snapshot_name = line.trim();
-            if !snapshot_name.is_empty() {
-                snapshots.push(Snapshot {
-                    name: snapshot_name.to_string(),
-                    description: Some("Snapshot created by KVM Manager".to_string()),
-                    created_at: Utc::now(), // We'd need to parse this from virsh output for accuracy
-                    state: "disk-snapshot".to_string(),
-                    parent: None,
-                });
+        let xml = domain.get_xml_desc(sys::VIR_DOMAIN_XML_INACTIVE)
+            .map_err(KvmError::LibvirtConnection)?;
+        let mut effective = XmlParser::parse_effective_config(&xml)?;
+        effective.config.autostart = domain.get_autostart().unwrap_or(false);
+
+        // Report the pool whose directory holds the primary disk
+        let disk_dir = effective.storage_devices.first()
+            .and_then(|disk| disk.path.as_deref())
+            .and_then(|path| std::path::Path::new(path).parent())
+            .map(|dir| dir.to_path_buf());
+        if let Some(disk_dir) = disk_dir {
+            if let Ok(pools) = self.get_storage_pools().await {
+                if let Some(pool) = pools.iter().find(|pool| std::path::Path::new(&pool.path) == disk_dir) {
+                    effective.config.storage_config.pool_name = Some(pool.name.clone());
+                }
             }
         }
-        
-        info!("Found {} snapshots for VM {}", snapshots.len(), vm_id);
-        Ok(snapshots)
+
+        Ok(effective)
     }
-    
-    pub async fn delete_snapshot(&self, vm_id: &str, snapshot_name: &str) -> Result<()> {
-        info!("Deleting snapshot {} for VM {}", snapshot_name, vm_id);
-        
+
+    /// The persistent domain XML, for hand editing. Display passwords are left
+    /// out; `update_vm_xml` keeps the existing ones.
+    pub async fn get_vm_xml(&self, vm_id: &str) -> Result<String> {
         let domain = self.get_domain_by_id(vm_id)?;
-        let vm_name = domain.get_name().unwrap_or_default();
-        
-        // Use virsh to delete snapshot
-        let output = std::process::Command::new("virsh")
-            .args(["snapshot-delete", &vm_name, snapshot_name])
-            .output()
-            .map_err(|e| KvmError::SnapshotOperationFailed(format!("Failed to execute virsh: {}", e)))?;
-        
-        if !output.status.success() {
-            let error = String::from_utf8_lossy(&output.stderr);
-            return Err(KvmError::SnapshotOperationFailed(format!("Snapshot deletion failed: {}", error)));
-        }
-        
-        info!("Successfully deleted snapshot {} for VM {}", snapshot_name, vm_id);
-        Ok(())
+        domain.get_xml_desc(sys::VIR_DOMAIN_XML_INACTIVE)
+            .map_err(KvmError::LibvirtConnection)
     }
 
-    pub async fn get_storage_pools(&self) -> Result<Vec<StoragePool>> {
-        debug!("Getting storage pools");
-
-        let pools = self.connection.list_all_storage_pools(0)
-            .map_err(KvmError::LibvirtConnection)?;
+    /// Redefine a VM from hand-edited XML. The XML must describe the same
+    /// domain (same UUID); a running VM keeps its current hardware and picks
+    /// the changes up on next boot.
+    pub async fn update_vm_xml(&mut self, vm_id: &str, new_xml: &str) -> Result<VirtualMachine> {
+        let domain = self.get_domain_by_id(vm_id)?;
+        let uuid = domain.get_uuid_string().map_err(KvmError::LibvirtConnection)?;
 
-        let mut storage_pools = Vec::new();
+        let edited = XmlParser::parse_vm_from_xml(new_xml)?;
+        if edited.uuid.is_empty() {
+            return Err(KvmError::InvalidVmConfig("The edited XML must keep the domain's <uuid>".to_string()));
+        }
+        if !edited.uuid.eq_ignore_ascii_case(&uuid) {
+            return Err(KvmError::InvalidVmConfig(format!(
+                "The edited XML describes domain {}, not {}", edited.uuid, uuid
+            )));
+        }
 
-        for pool in pools {
-            match self.pool_to_storage_pool(&pool).await {
-                Ok(storage_pool) => storage_pools.push(storage_pool),
-                Err(e) => {
-                    warn!("Failed to convert storage pool: {}", e);
-                    continue;
-                }
+        // The editor never saw the passwords, so put back any it didn't replace
+        let existing = domain.get_xml_desc(sys::VIR_DOMAIN_XML_INACTIVE | sys::VIR_DOMAIN_XML_SECURE)
+            .map_err(KvmError::LibvirtConnection)?;
+        let mut xml = new_xml.to_string();
+        let edited_passwords = XmlParser::graphics_passwords(&xml);
+        for (graphics_type, password) in XmlParser::graphics_passwords(&existing) {
+            if edited_passwords.iter().any(|(edited_type, _)| edited_type == &graphics_type) {
+                continue;
             }
+            let tag = regex::Regex::new(&format!(r#"<graphics\s+type=['"]{}['"]"#, regex::escape(&graphics_type)))
+                .map_err(|e| KvmError::XmlParsingError(e.to_string()))?;
+            xml = tag.replacen(&xml, 1, |found: &regex::Captures| {
+                format!("{} passwd='{}'", &found[0], XmlParser::escape(&password))
+            }).into_owned();
         }
 
-        Ok(storage_pools)
+        if domain.is_active().map_err(KvmError::LibvirtConnection)? {
+            warn!("VM {} is running; its edited XML applies on next boot", vm_id);
+        }
+
+        let domain = Domain::define_xml(&self.connection, &xml)
+            .map_err(|e| {
+                error!("Failed to redefine VM {} from edited XML: {}", vm_id, e);
+                KvmError::InvalidVmConfig(format!("libvirt rejected the XML: {}", e))
+            })?;
+        audit::record("vm_xml_edited", vm_id, "Redefined VM from edited XML");
+
+        self.refresh_vm_cache().await?;
+        self.domain_to_vm(&domain).await
     }
 
-    pub async fn get_networks(&self) -> Result<Vec<Network>> {
-        debug!("Getting networks");
+    /// Add the spicevmc agent channel (and a virtio-serial controller if needed)
+    /// that clipboard and file-drop sharing rely on.
+    ///
+    /// Changes the persistent config only, so a running VM picks it up on next
+    /// boot. Returns `false` when the channel was already there.
+    pub async fn enable_spice_agent(&mut self, vm_id: &str) -> Result<bool> {
+        info!("Enabling SPICE agent channel for VM {}", vm_id);
 
-        let networks = self.connection.list_all_networks(0)
+        let domain = self.get_domain_by_id(vm_id)?;
+        let xml = domain.get_xml_desc(sys::VIR_DOMAIN_XML_INACTIVE)
             .map_err(KvmError::LibvirtConnection)?;
 
-        let mut result_networks = Vec::new();
+        if XmlParser::spice_agent_state(&xml).is_some() {
+            return Ok(false);
+        }
+        if !XmlParser::parse_graphics_devices(&xml).iter().any(|g| g.protocol == "spice") {
+            return Err(KvmError::VmOperationFailed(
+                "The SPICE agent needs a SPICE display; switch the VM's graphics to SPICE first".to_string()
+            ));
+        }
 
-        for network in networks {
-            match self.network_to_network(&network).await {
-                Ok(net) => result_networks.push(net),
-                Err(e) => {
-                    warn!("Failed to convert network: {}", e);
-                    continue;
+        let attach = |device_xml: &str, what: &str| {
+            domain.attach_device_flags(device_xml, sys::VIR_DOMAIN_AFFECT_CONFIG)
+                .map_err(|e| {
+                    error!("Failed to add {} to VM {}: {}", what, vm_id, e);
+                    KvmError::VmOperationFailed(format!("Failed to add {}: {}", what, e))
+                })
+        };
+
+        if !XmlParser::has_virtio_serial_controller(&xml) {
+            attach("<controller type='virtio-serial' index='0'/>", "virtio-serial controller")?;
+        }
+        attach(
+            r#"<channel type='spicevmc'>
+  <target type='virtio' name='com.redhat.spice.0'/>
+</channel>"#,
+            "SPICE agent channel",
+        )?;
+
+        if domain.is_active().unwrap_or(false) {
+            info!("VM {} is running; the SPICE agent channel is available after next boot", vm_id);
+        }
+
+        self.refresh_vm_cache().await?;
+        Ok(true)
+    }
+
+    pub async fn set_vm_autostart(&mut self, vm_id: &str, enabled: bool) -> Result<()> {
+        info!("Setting autostart for VM {} to {}", vm_id, enabled);
+
+        let domain = self.get_domain_by_id(vm_id)?;
+        // libvirtd only starts domains it has a definition for
+        if !domain.is_persistent().map_err(KvmError::LibvirtConnection)? {
+            return Err(KvmError::VmOperationFailed("Transient VMs can't be started automatically".to_string()));
+        }
+        domain.set_autostart(enabled)
+            .map_err(|e| {
+                error!("Failed to set autostart for VM {}: {}", vm_id, e);
+                match e.code() {
+                    ErrorNumber::OperationDenied | ErrorNumber::AccessDenied | ErrorNumber::AuthFailed => {
+                        KvmError::PermissionDenied(format!("Cannot change autostart: {}", e))
+                    }
+                    _ => KvmError::VmOperationFailed(format!("Failed to set autostart: {}", e)),
                 }
+            })?;
+
+        if let Some(vm) = self.vm_cache.get_mut(vm_id) {
+            vm.autostart = enabled;
+        }
+        Ok(())
+    }
+
+    /// Inflate or deflate the balloon of a running VM. The target is bounded by
+    /// the domain's maximum memory, which libvirt only lets us raise offline.
+    pub async fn set_vm_memory_live(&mut self, vm_id: &str, memory_mb: u64) -> Result<()> {
+        self.set_vm_memory(vm_id, memory_mb, true).await.map(|_| ())
+    }
+
+    /// Change a VM's memory. Live changes move the balloon within the domain's
+    /// maximum and are mirrored to the config of persistent VMs; offline changes
+    /// only touch the config and raise the maximum when needed.
+    pub async fn set_vm_memory(&mut self, vm_id: &str, memory_mb: u64, live: bool) -> Result<ChangeScope> {
+        info!("Setting memory of VM {} to {} MB (live: {})", vm_id, memory_mb, live);
+
+        if memory_mb < MIN_MEMORY_MB {
+            return Err(KvmError::InvalidVmConfig(format!("Memory must be at least {} MB", MIN_MEMORY_MB)));
+        }
+
+        let domain = self.get_domain_by_id(vm_id)?;
+        let persistent = domain.is_persistent().map_err(KvmError::LibvirtConnection)?;
+        if live {
+            Self::require_running(&domain, "resize memory of")?;
+        } else if !persistent {
+            return Err(KvmError::VmOperationFailed("VM is transient and has no persistent configuration".to_string()));
+        }
+
+        let max_mb = if live {
+            domain.get_max_memory().map_err(KvmError::LibvirtConnection)? / 1024
+        } else {
+            let xml = domain.get_xml_desc(sys::VIR_DOMAIN_XML_INACTIVE).map_err(KvmError::LibvirtConnection)?;
+            XmlParser::parse_vm_from_xml(&xml)?.memory_mb
+        };
+        if live && memory_mb > max_mb {
+            return Err(KvmError::AboveMaxMemory { requested_mb: memory_mb, max_mb });
+        }
+
+        // Quota usage counts each VM's maximum memory, so only growth beyond it
+        // needs room; live changes never go past the maximum
+        let counted_mb = domain.get_info().map_err(KvmError::LibvirtConnection)?.max_mem / 1024;
+        let growth_mb = memory_mb.saturating_sub(counted_mb);
+        if !live && growth_mb > 0 {
+            let tags = self.vm_cache.get(vm_id).map(|vm| vm.tags.clone()).unwrap_or_default();
+            quotas::check(&self.connection, &QuotaRequest {
+                memory_mb: growth_mb,
+                tags,
+                ..Default::default()
+            })?;
+        }
+
+        let memory_error = |e: virt::error::Error| {
+            error!("Failed to set memory of VM {}: {}", vm_id, e);
+            match e.code() {
+                ErrorNumber::NoSupport | ErrorNumber::OperationUnsupported if live => KvmError::VmOperationFailed(
+                    "The VM has no memory balloon device, so its memory cannot be changed while running".to_string()
+                ),
+                _ => KvmError::VmOperationFailed(format!("Failed to set memory: {}", e)),
+            }
+        };
+
+        let applied = if live {
+            // Without a stats period the guest never refreshes its balloon figures,
+            // so get_vm_stats would keep reporting the old usage
+            if let Err(e) = domain.set_memory_stats_period(BALLOON_STATS_PERIOD_SECS, sys::VIR_DOMAIN_AFFECT_LIVE) {
+                debug!("Could not enable balloon stats for VM {}: {}", vm_id, e);
+            }
+            let flags = if persistent { sys::VIR_DOMAIN_AFFECT_LIVE | sys::VIR_DOMAIN_AFFECT_CONFIG } else { sys::VIR_DOMAIN_AFFECT_LIVE };
+            domain.set_memory_flags(memory_mb * 1024, flags).map_err(memory_error)?;
+            if persistent { ChangeScope::Both } else { ChangeScope::Live }
+        } else {
+            if memory_mb > max_mb {
+                domain.set_memory_flags(memory_mb * 1024, sys::VIR_DOMAIN_AFFECT_CONFIG | sys::VIR_DOMAIN_MEM_MAXIMUM)
+                    .map_err(memory_error)?;
+            }
+            domain.set_memory_flags(memory_mb * 1024, sys::VIR_DOMAIN_AFFECT_CONFIG).map_err(memory_error)?;
+            ChangeScope::Persistent
+        };
+
+        let running = domain.is_active().unwrap_or(false);
+        if let Some(vm) = self.vm_cache.get_mut(vm_id) {
+            if live || !running {
+                vm.memory = memory_mb;
             }
         }
 
-        Ok(result_networks)
+        info!("VM {} memory is now {} MB ({:?})", vm_id, memory_mb, applied);
+        Ok(applied)
     }
+    /// Change the vCPU count. Live changes hot-plug up to the domain's maximum
+    /// and are mirrored to the config of persistent VMs; offline changes only
+    /// touch the config and raise the maximum when needed.
+    pub async fn set_vm_vcpus(&mut self, vm_id: &str, count: u32, live: bool) -> Result<VcpuChange> {
+        info!("Setting vCPUs of VM {} to {} (live: {})", vm_id, count, live);
 
-    // Private helper methods
+        if count == 0 {
+            return Err(KvmError::InvalidVmConfig("A VM needs at least one vCPU".to_string()));
+        }
 
-    async fn refresh_vm_cache(&mut self) -> Result<()> {
-        debug!("Refreshing VM cache");
-        
-        let vms = self.list_vms().await?;
-        self.vm_cache.clear();
-        
-        for vm in vms {
-            self.vm_cache.insert(vm.id.clone(), vm);
+        let domain = self.get_domain_by_id(vm_id)?;
+        let persistent = domain.is_persistent().map_err(KvmError::LibvirtConnection)?;
+        let scope = if live { sys::VIR_DOMAIN_AFFECT_LIVE } else { sys::VIR_DOMAIN_AFFECT_CONFIG };
+
+        if live {
+            Self::require_running(&domain, "hot-plug vCPUs on")?;
+        } else if !persistent {
+            return Err(KvmError::VmOperationFailed("VM is transient and has no persistent configuration".to_string()));
         }
-        
+
+        let current = domain.get_vcpus_flags(scope).map_err(KvmError::LibvirtConnection)?;
+        let mut max_vcpus = domain.get_vcpus_flags(scope | sys::VIR_DOMAIN_VCPU_MAXIMUM)
+            .map_err(KvmError::LibvirtConnection)?;
+
+        if count > current {
+            let tags = self.vm_cache.get(vm_id).map(|vm| vm.tags.clone()).unwrap_or_default();
+            quotas::check(&self.connection, &QuotaRequest {
+                vcpus: count - current,
+                tags,
+                ..Default::default()
+            })?;
+        }
+
+        let vcpu_error = |e: virt::error::Error| {
+            error!("Failed to set vCPUs of VM {}: {}", vm_id, e);
+            match e.code() {
+                ErrorNumber::NoSupport | ErrorNumber::OperationUnsupported if live => KvmError::VmOperationFailed(format!(
+                    "The guest or its machine type does not support online vCPU hot-plug; change the count while the VM is shut off ({})", e
+                )),
+                ErrorNumber::NoSupport | ErrorNumber::OperationUnsupported => KvmError::VmOperationFailed(
+                    format!("This VM does not support changing vCPUs this way: {}", e)
+                ),
+                _ => KvmError::VmOperationFailed(format!("Failed to set vCPUs: {}", e)),
+            }
+        };
+
+        let applied = if live {
+            if count > max_vcpus {
+                return Err(KvmError::InvalidVmConfig(format!(
+                    "{} vCPUs is outside the hot-plug range of 1-{}; raise the maximum with an offline change first", count, max_vcpus
+                )));
+            }
+            let flags = if persistent { scope | sys::VIR_DOMAIN_AFFECT_CONFIG } else { scope };
+            domain.set_vcpus_flags(count, flags).map_err(vcpu_error)?;
+            if persistent { ChangeScope::Both } else { ChangeScope::Live }
+        } else {
+            if count > max_vcpus {
+                domain.set_vcpus_flags(count, scope | sys::VIR_DOMAIN_VCPU_MAXIMUM).map_err(vcpu_error)?;
+                max_vcpus = count;
+            }
+            domain.set_vcpus_flags(count, scope).map_err(vcpu_error)?;
+            ChangeScope::Persistent
+        };
+
+        // A stopped VM reports its config, so the cache follows either way
+        let running = domain.is_active().unwrap_or(false);
+        if let Some(vm) = self.vm_cache.get_mut(vm_id) {
+            if live || !running {
+                vm.vcpus = count;
+                vm.max_vcpus = max_vcpus;
+            }
+        }
+
+        info!("VM {} now has {} vCPUs ({:?})", vm_id, count, applied);
+        Ok(VcpuChange { vcpus: count, max_vcpus, applied })
+    }
+
+    /// Change the vCPU topology of a stopped VM; `None` returns to libvirt's default
+    pub async fn set_cpu_topology(&mut self, vm_id: &str, topology: Option<CpuTopology>) -> Result<()> {
+        info!("Setting CPU topology for VM {} to {:?}", vm_id, topology);
+
+        let domain = self.get_domain_by_id(vm_id)?;
+        if domain.is_active().map_err(KvmError::LibvirtConnection)? {
+            return Err(KvmError::VmOperationFailed("Stop the VM before changing its CPU topology".to_string()));
+        }
+
+        if let Some(topology) = &topology {
+            let vcpus = domain.get_info().map_err(KvmError::LibvirtConnection)?.nr_virt_cpu;
+            Self::validate_cpu_topology(topology, vcpus)?;
+        }
+
+        self.update_domain_xml(vm_id, |xml| XmlParser::set_cpu_topology(xml, topology.as_ref()))?;
+        self.refresh_vm_cache().await?;
         Ok(())
     }
 
-    fn get_domain_by_id(&self, vm_id: &str) -> Result<Domain> {
-        // Try to get by UUID string first (most reliable)
-        if let Ok(domain) = Domain::lookup_by_uuid_string(&self.connection, vm_id) {
-            return Ok(domain);
+    pub async fn get_cpu_pinning(&self, vm_id: &str) -> Result<Vec<VcpuPin>> {
+        let domain = self.get_domain_by_id(vm_id)?;
+        let xml = domain.get_xml_desc(sys::VIR_DOMAIN_XML_INACTIVE)
+            .map_err(KvmError::LibvirtConnection)?;
+        Ok(XmlParser::parse_vcpu_pins(&xml))
+    }
+
+    /// Pin vCPUs to host CPUs in the persistent config; a running VM picks the
+    /// pinning up on its next start. An empty list removes all pinning.
+    pub async fn set_cpu_pinning(&mut self, vm_id: &str, pins: Vec<VcpuPin>) -> Result<()> {
+        info!("Setting CPU pinning for VM {}: {:?}", vm_id, pins);
+
+        let domain = self.get_domain_by_id(vm_id)?;
+        let max_vcpus = domain.get_vcpus_flags(sys::VIR_DOMAIN_AFFECT_CONFIG | sys::VIR_DOMAIN_VCPU_MAXIMUM)
+            .map_err(KvmError::LibvirtConnection)?;
+        let host_cpus = self.connection.get_node_info().map_err(KvmError::LibvirtConnection)?.cpus;
+
+        let mut pins = pins;
+        for pin in &mut pins {
+            pin.host_cpus.sort_unstable();
+            pin.host_cpus.dedup();
         }
-        
-        // Try to get by name as fallback
-        if let Ok(domain) = Domain::lookup_by_name(&self.connection, vm_id) {
-            return Ok(domain);
+        pins.sort_by_key(|pin| pin.vcpu);
+
+        let mut seen = HashSet::new();
+        for pin in &pins {
+            if pin.vcpu >= max_vcpus {
+                return Err(KvmError::InvalidVmConfig(format!(
+                    "vCPU {} does not exist; the VM has {} vCPUs (0-{})", pin.vcpu, max_vcpus, max_vcpus - 1
+                )));
+            }
+            if !seen.insert(pin.vcpu) {
+                return Err(KvmError::InvalidVmConfig(format!("vCPU {} is pinned more than once", pin.vcpu)));
+            }
+            if pin.host_cpus.is_empty() {
+                return Err(KvmError::InvalidVmConfig(format!("vCPU {} is pinned to no host CPUs", pin.vcpu)));
+            }
+            if let Some(cpu) = pin.host_cpus.iter().find(|&&cpu| cpu >= host_cpus) {
+                return Err(KvmError::InvalidVmConfig(format!(
+                    "Host CPU {} does not exist; the host has {} CPUs (0-{})", cpu, host_cpus, host_cpus - 1
+                )));
+            }
         }
 
-        Err(KvmError::VmNotFound(vm_id.to_string()))
+        self.update_domain_xml(vm_id, |xml| XmlParser::set_vcpu_pins(xml, &pins))?;
+        Ok(())
     }
 
-    async fn domain_to_vm(&self, domain: &Domain) -> Result<VirtualMachine> {
-        let uuid = domain.get_uuid_string().map_err(|e| {
-            error!("Failed to get UUID for domain: {}", e);
-            KvmError::LibvirtConnection(e)
-        })?;
-        let name = domain.get_name().map_err(|e| {
-            error!("Failed to get name for domain: {}", e);
-            KvmError::LibvirtConnection(e)
+    pub async fn set_vm_description(&mut self, vm_id: &str, description: Option<String>) -> Result<()> {
+        info!("Setting description for VM {}", vm_id);
+
+        let description = description.filter(|d| !d.trim().is_empty());
+        let element = description.as_ref()
+            .map(|d| format!("<description>{}</description>", XmlParser::escape(d)));
+
+        self.update_domain_xml(vm_id, |xml| {
+            XmlParser::upsert_element(xml, "description", element.as_deref(), &["title", "uuid", "name"])
         })?;
-        let info = domain.get_info().map_err(|e| {
-            error!("Failed to get info for domain {}: {}", name, e);
-            KvmError::LibvirtConnection(e)
+
+        self.refresh_vm_cache().await?;
+        Ok(())
+    }
+
+    pub async fn set_vm_tags(&mut self, vm_id: &str, tags: Vec<String>) -> Result<()> {
+        info!("Setting tags for VM {}: {:?}", vm_id, tags);
+
+        let mut tags: Vec<String> = tags.into_iter()
+            .map(|tag| tag.trim().to_string())
+            .filter(|tag| !tag.is_empty())
+            .collect();
+        tags.sort();
+        tags.dedup();
+
+        let element = (!tags.is_empty()).then(|| XmlParser::tags_element(&tags));
+
+        self.update_domain_xml(vm_id, |xml| {
+            XmlParser::upsert_metadata_element(xml, TAGS_ELEMENT, element.as_deref())
         })?;
-        
-        debug!("Converting domain to VM: name={}, uuid={}, state={}", name, uuid, info.state);
-        
-        let state = match info.state {
-            sys::VIR_DOMAIN_NOSTATE => VmState::Stopped,
-            sys::VIR_DOMAIN_RUNNING => VmState::Running,
-            sys::VIR_DOMAIN_BLOCKED => VmState::Running,
-            sys::VIR_DOMAIN_PAUSED => VmState::Paused,
-            sys::VIR_DOMAIN_SHUTDOWN => VmState::ShuttingDown,
-            sys::VIR_DOMAIN_SHUTOFF => VmState::Stopped,
-            sys::VIR_DOMAIN_CRASHED => VmState::Error,
-            sys::VIR_DOMAIN_PMSUSPENDED => VmState::Suspended,
-            _ => VmState::Error,
-        };
 
-        // Use XML parser to extract comprehensive VM information
-        let xml_info = match domain.get_xml_desc(0) {
-            Ok(xml) => {
-                debug!("Got XML for VM {}: {} chars", name, xml.len());
-                match XmlParser::parse_vm_from_xml(&xml) {
-                    Ok(info) => info,
-                    Err(e) => {
-                        warn!("Failed to parse XML for VM {}: {}", name, e);
-                        VmXmlInfo {
-                            name: name.clone(),
-                            uuid: uuid.clone(),
-                            memory_mb: info.memory / 1024,
-                            vcpus: info.nr_virt_cpu,
-                            os_type: "linux".to_string(),
-                            os_variant: Some("generic".to_string()),
-                            disk_size_gb: 0.0,
-                            storage_devices: Vec::new(),
-                            network_interfaces: Vec::new(),
-                            vnc_port: None,
-                            spice_port: None,
-                            description: None,
-                        }
-                    }
+        self.refresh_vm_cache().await?;
+        Ok(())
+    }
+
+    /// Make sure every writable disk image sits on a filesystem with room to grow.
+    ///
+    /// A guest that hits ENOSPC is paused by QEMU mid-operation, which looks like a
+    /// hang. Depending on the configured policy the first problem is either
+    /// returned as `KvmError::FilesystemIssue` or only logged.
+    fn check_disk_filesystems(vm_id: &str, xml: &str) -> Result<()> {
+        let policy = Settings::load()
+            .map(|settings| settings.disk_space_policy)
+            .unwrap_or_default();
+
+        for path in XmlParser::writable_disk_files(xml) {
+            if let Some(issue) = storage_layout::check_filesystem(&path, policy.min_free_bytes) {
+                if policy.refuse {
+                    error!("Refusing to continue with VM {}: {}", vm_id, issue.message);
+                    return Err(KvmError::FilesystemIssue(issue));
                 }
-            },
-            Err(e) => {
-                warn!("Failed to get XML for VM {}: {}", name, e);
-                VmXmlInfo {
-                    name: name.clone(),
-                    uuid: uuid.clone(),
-                    memory_mb: info.memory / 1024,
-                    vcpus: info.nr_virt_cpu,
-                    os_type: "linux".to_string(),
-                    os_variant: Some("generic".to_string()),
-                    disk_size_gb: 0.0,
-                    storage_devices: Vec::new(),
-                    network_interfaces: Vec::new(),
-                    vnc_port: None,
-                    spice_port: None,
-                    description: None,
+                warn!("VM {}: {}", vm_id, issue.message);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Apply a targeted edit to the persistent domain XML and redefine it.
+    ///
+    /// The edit works on the existing document so elements this app does not
+    /// know about, including anything the user added by hand, are kept as-is.
+    fn update_domain_xml<F>(&self, vm_id: &str, edit: F) -> Result<Domain>
+    where
+        F: FnOnce(&str) -> String,
+    {
+        let domain = self.get_domain_by_id(vm_id)?;
+        // Without the secure flag libvirt omits display passwords, and the
+        // redefine would drop them
+        let xml = domain.get_xml_desc(sys::VIR_DOMAIN_XML_INACTIVE | sys::VIR_DOMAIN_XML_SECURE)
+            .map_err(KvmError::LibvirtConnection)?;
+
+        let updated_xml = edit(&xml);
+        if updated_xml == xml {
+            debug!("Domain XML for VM {} unchanged, skipping redefine", vm_id);
+            return Ok(domain);
+        }
+
+        Domain::define_xml(&self.connection, &updated_xml)
+            .map_err(|e| {
+                error!("Failed to redefine VM {}: {}", vm_id, e);
+                KvmError::VmOperationFailed(format!("Failed to update VM configuration: {}", e))
+            })
+    }
+
+    /// Return the CPU feature to expose (`vmx` or `svm`) if the host has nested KVM enabled
+    fn host_nested_virtualization() -> Option<&'static str> {
+        let modules = [("kvm_intel", "vmx"), ("kvm_amd", "svm")];
+
+        for (module, feature) in modules {
+            let path = format!("/sys/module/{}/parameters/nested", module);
+            if let Ok(value) = std::fs::read_to_string(&path) {
+                let value = value.trim();
+                if value == "Y" || value == "1" {
+                    return Some(feature);
                 }
             }
-        };
+        }
 
-        let vm = VirtualMachine {
-            id: uuid.clone(),
-            name: name.clone(),
-            state,
-            memory: info.memory / 1024, // Use libvirt info for memory (more reliable)
-            vcpus: info.nr_virt_cpu,    // Use libvirt info for vCPUs
-            disk_size: xml_info.disk_size_gb as u64,
-            os_type: xml_info.os_type.clone(),
-            os_variant: xml_info.os_variant.clone(),
-            created_at: self.extract_creation_time(&xml_info, &domain).await,
-            last_started: self.extract_last_started_time(&domain).await,
-            description: xml_info.description,
-            vnc_port: xml_info.vnc_port,
-            spice_port: xml_info.spice_port,
-            snapshots: self.load_vm_snapshots(&domain).await.unwrap_or_default(),
-            network_interfaces: xml_info.network_interfaces,
-            storage_devices: xml_info.storage_devices,
-        };
-        
-        debug!("Successfully converted domain to VM: name={}, os_type={}, disks={}", 
-               name, vm.os_type, vm.storage_devices.len());
-        Ok(vm)
+        None
     }
 
-    fn validate_vm_config(&self, config: &VmConfig) -> Result<()> {
-        if config.name.is_empty() {
-            return Err(KvmError::InvalidVmConfig("VM name cannot be empty".to_string()));
+    pub async fn attach_host_block_device(
+        &self,
+        vm_id: &str,
+        device_path: &str,
+        readonly: bool,
+        live: bool,
+        encryption: Option<&EncryptionSpec>,
+    ) -> Result<String> {
+        info!("Attaching host block device {} to VM {}", device_path, vm_id);
+
+        let device = host_devices::validate_for_passthrough(device_path)?;
+        let domain = self.get_domain_by_id(vm_id)?;
+        let xml = domain.get_xml_desc(0).map_err(KvmError::LibvirtConnection)?;
+        let xml_info = XmlParser::parse_vm_from_xml(&xml)?;
+
+        if xml_info.storage_devices.iter().any(|d| d.path.as_deref() == Some(device_path)) {
+            return Err(KvmError::VmOperationFailed(format!("{} is already attached to this VM", device_path)));
+        }
+
+        let target_dev = ('a'..='z')
+            .map(|letter| format!("vd{}", letter))
+            .find(|name| !xml_info.storage_devices.iter().any(|d| &d.device == name))
+            .ok_or_else(|| KvmError::VmOperationFailed("No free virtio disk target available".to_string()))?;
+
+        // The drive's serial gives the guest a stable /dev/disk/by-id name
+        let serial = device.serial.as_deref()
+            .or(Some(device.name.as_str()))
+            .map(|s| s.chars().filter(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_').collect::<String>())
+            .unwrap_or_default();
+
+        let disk_xml = format!(
+            r#"<disk type='block' device='disk'>
+  <driver name='qemu' type='raw' cache='none' io='native'/>
+  <source dev='{}'/>
+  <target dev='{}' bus='virtio' removable='on'/>
+  <serial>{}</serial>{}{}
+</disk>"#,
+            XmlParser::escape(device_path),
+            target_dev,
+            serial,
+            if readonly { "\n  <readonly/>" } else { "" },
+            encryption
+                .map(|spec| format!("\n  {}", secrets::encryption_xml(spec).replace('\n', "\n  ")))
+                .unwrap_or_default(),
+        );
+
+        let mut flags = sys::VIR_DOMAIN_AFFECT_CONFIG;
+        if live && domain.is_active().map_err(KvmError::LibvirtConnection)? {
+            flags |= sys::VIR_DOMAIN_AFFECT_LIVE;
+        }
+
+        domain.attach_device_flags(&disk_xml, flags)
+            .map_err(|e| {
+                error!("Failed to attach {} to VM {}: {}", device_path, vm_id, e);
+                KvmError::VmOperationFailed(format!("Failed to attach block device: {}", e))
+            })?;
+
+        info!("Attached {} to VM {} as {}", device_path, vm_id, target_dev);
+        Ok(target_dev)
+    }
+
+    pub async fn create_disk_secret(&self, usage_id: &str, passphrase: &str) -> Result<String> {
+        secrets::create_disk_secret(&self.connection, usage_id, passphrase)
+    }
+
+    pub async fn list_secrets(&self) -> Result<Vec<secrets::SecretInfo>> {
+        secrets::list_secrets(&self.connection)
+    }
+
+    pub async fn delete_secret(&self, uuid: &str) -> Result<()> {
+        secrets::delete_secret(&self.connection, uuid)
+    }
+
+    /// SR-IOV NICs with each virtual function marked with the VM it is assigned to
+    pub async fn list_sriov_vfs(&self) -> Result<Vec<host_devices::SriovNic>> {
+        let mut nics = host_devices::list_sriov_nics()?;
+        let assignments = self.hostdev_assignments()?;
+
+        for vf in nics.iter_mut().flat_map(|nic| nic.vfs.iter_mut()) {
+            vf.assigned_to = assignments.get(&vf.pci_address).cloned();
         }
 
-        if config.memory < 128 {
-            return Err(KvmError::InvalidVmConfig("Memory must be at least 128 MB".to_string()));
-        }
+        Ok(nics)
+    }
+
+    pub async fn attach_sriov_vf(&self, vm_id: &str, vf_pci_address: &str, live: bool) -> Result<()> {
+        info!("Attaching SR-IOV VF {} to VM {}", vf_pci_address, vm_id);
+
+        let vf_pci_address = vf_pci_address.to_lowercase();
+        let is_vf = host_devices::list_sriov_nics()?
+            .iter()
+            .flat_map(|nic| nic.vfs.iter())
+            .any(|vf| vf.pci_address == vf_pci_address);
+        if !is_vf {
+            return Err(KvmError::InvalidVmConfig(format!("{} is not an SR-IOV virtual function on this host", vf_pci_address)));
+        }
+
+        if let Some(owner) = self.hostdev_assignments()?.get(&vf_pci_address) {
+            return Err(KvmError::VmOperationFailed(format!("{} is already assigned to VM {}", vf_pci_address, owner)));
+        }
+
+        let (pci_domain, bus, slot, function) = host_devices::parse_pci_address(&vf_pci_address)?;
+        // managed='yes' lets libvirt rebind the VF to vfio-pci and back
+        let interface_xml = format!(
+            r#"<interface type='hostdev' managed='yes'>
+  <source>
+    <address type='pci' domain='0x{:04x}' bus='0x{:02x}' slot='0x{:02x}' function='0x{:x}'/>
+  </source>
+</interface>"#,
+            pci_domain, bus, slot, function
+        );
+
+        let domain = self.get_domain_by_id(vm_id)?;
+        let mut flags = sys::VIR_DOMAIN_AFFECT_CONFIG;
+        if live && domain.is_active().map_err(KvmError::LibvirtConnection)? {
+            flags |= sys::VIR_DOMAIN_AFFECT_LIVE;
+        }
+
+        domain.attach_device_flags(&interface_xml, flags)
+            .map_err(|e| {
+                error!("Failed to attach VF {} to VM {}: {}", vf_pci_address, vm_id, e);
+                KvmError::VmOperationFailed(format!("Failed to attach SR-IOV virtual function: {}", e))
+            })?;
+
+        info!("Attached SR-IOV VF {} to VM {}", vf_pci_address, vm_id);
+        Ok(())
+    }
+
+    /// Host PCI devices with each one marked with the VM it is assigned to
+    pub async fn list_host_pci_devices(&self) -> Result<Vec<host_devices::HostPciDevice>> {
+        let mut devices = host_devices::list_pci_devices()?;
+        let assignments = self.hostdev_assignments()?;
+
+        for device in devices.iter_mut() {
+            device.assigned_to = assignments.get(&device.address).cloned();
+        }
+
+        Ok(devices)
+    }
+
+    /// Pass a host PCI device through to the VM. Returns the device, whose
+    /// `group_warning` explains which other devices leave the host with it.
+    pub async fn attach_pci_device(&self, vm_id: &str, pci_address: &str, live: bool) -> Result<host_devices::HostPciDevice> {
+        info!("Attaching PCI device {} to VM {}", pci_address, vm_id);
+
+        if !host_devices::iommu_enabled() {
+            return Err(KvmError::VmOperationFailed(
+                "The IOMMU is disabled on this host, so PCI devices cannot be passed through; see the passthrough readiness check".to_string()
+            ));
+        }
+
+        let pci_address = pci_address.to_lowercase();
+        let (pci_domain, bus, slot, function) = host_devices::parse_pci_address(&pci_address)?;
+        let host_devices = self.list_host_pci_devices().await?;
+        let mut device = host_devices.iter()
+            .find(|device| device.address == pci_address)
+            .cloned()
+            .ok_or_else(|| KvmError::InvalidVmConfig(format!("No PCI device {} on this host", pci_address)))?;
+        if let Some(owner) = &device.assigned_to {
+            return Err(KvmError::VmOperationFailed(format!("{} is already assigned to VM {}", pci_address, owner)));
+        }
+
+        // A group can't be split between VMs
+        let domain = self.get_domain_by_id(vm_id)?;
+        let vm_name = domain.get_name().map_err(KvmError::LibvirtConnection)?;
+        let group_owner = host_devices.iter()
+            .filter(|other| device.group_members.contains(&other.address))
+            .find_map(|other| other.assigned_to.as_ref().filter(|owner| **owner != vm_name).map(|owner| (&other.address, owner)));
+        if let Some((member, owner)) = group_owner {
+            return Err(KvmError::VmOperationFailed(format!(
+                "{} shares IOMMU group {} with {}, which is assigned to VM {}; a group can only go to one VM",
+                pci_address, device.iommu_group.unwrap_or_default(), member, owner
+            )));
+        }
+
+        // GPUs fail in libvirt with little explanation, so say what to fix first
+        if host_devices::is_display_device(&device) {
+            let readiness = host_devices::check_gpu_passthrough_readiness(&pci_address)?;
+            let problems: Vec<String> = readiness.checks.iter()
+                .filter(|check| !check.passed)
+                .map(|check| match &check.remediation {
+                    Some(remediation) => format!("{} ({})", check.detail, remediation),
+                    None => check.detail.clone(),
+                })
+                .collect();
+            if !problems.is_empty() {
+                return Err(KvmError::VmOperationFailed(format!(
+                    "GPU {} is not ready for passthrough: {}", pci_address, problems.join("; ")
+                )));
+            }
+        }
+
+        // managed='yes' lets libvirt rebind the device to vfio-pci and back
+        let hostdev_xml = format!(
+            r#"<hostdev mode='subsystem' type='pci' managed='yes'>
+  <source>
+    <address domain='0x{:04x}' bus='0x{:02x}' slot='0x{:02x}' function='0x{:x}'/>
+  </source>
+</hostdev>"#,
+            pci_domain, bus, slot, function
+        );
+
+        let mut flags = sys::VIR_DOMAIN_AFFECT_CONFIG;
+        if live && domain.is_active().map_err(KvmError::LibvirtConnection)? {
+            flags |= sys::VIR_DOMAIN_AFFECT_LIVE;
+        }
+
+        domain.attach_device_flags(&hostdev_xml, flags)
+            .map_err(|e| {
+                error!("Failed to attach PCI device {} to VM {}: {}", pci_address, vm_id, e);
+                KvmError::VmOperationFailed(format!("Failed to attach PCI device: {}", e))
+            })?;
+
+        // QEMU only starts once the whole group is detached from the host, so
+        // point out the group members this VM doesn't have yet
+        let missing: Vec<&str> = device.group_members.iter()
+            .filter(|member| {
+                host_devices.iter()
+                    .find(|other| &other.address == *member)
+                    .is_some_and(|other| other.assigned_to.as_deref() != Some(vm_name.as_str()) && !other.class.contains("bridge"))
+            })
+            .map(String::as_str)
+            .collect();
+        if !missing.is_empty() {
+            warn!("VM {} has {} but not the rest of its IOMMU group: {}", vm_id, pci_address, missing.join(", "));
+            device.group_warning = Some(format!(
+                "IOMMU group {} also contains {}, which {} not passed through to this VM; attach {} too or the VM will fail to start",
+                device.iommu_group.unwrap_or_default(),
+                missing.join(", "),
+                if missing.len() == 1 { "is" } else { "are" },
+                if missing.len() == 1 { "it" } else { "them" },
+            ));
+        }
+        device.assigned_to = Some(vm_name);
+
+        info!("Attached PCI device {} to VM {}", pci_address, vm_id);
+        Ok(device)
+    }
+
+    /// Remove a passed-through PCI device; with managed passthrough libvirt
+    /// hands it back to its host driver
+    pub async fn detach_pci_device(&self, vm_id: &str, pci_address: &str, live: bool) -> Result<()> {
+        info!("Detaching PCI device {} from VM {}", pci_address, vm_id);
+
+        let pci_address = pci_address.to_lowercase();
+        let (pci_domain, bus, slot, function) = host_devices::parse_pci_address(&pci_address)?;
+
+        let domain = self.get_domain_by_id(vm_id)?;
+        let active = domain.is_active().map_err(KvmError::LibvirtConnection)?;
+        let xml_flags = if live && active { 0 } else { sys::VIR_DOMAIN_XML_INACTIVE };
+        let xml = domain.get_xml_desc(xml_flags).map_err(KvmError::LibvirtConnection)?;
+        if !XmlParser::hostdev_pci_addresses(&xml).contains(&pci_address) {
+            return Err(KvmError::VmOperationFailed(format!("{} is not passed through to VM {}", pci_address, vm_id)));
+        }
+
+        // libvirt matches hostdevs on their source address
+        let hostdev_xml = format!(
+            r#"<hostdev mode='subsystem' type='pci' managed='yes'>
+  <source>
+    <address domain='0x{:04x}' bus='0x{:02x}' slot='0x{:02x}' function='0x{:x}'/>
+  </source>
+</hostdev>"#,
+            pci_domain, bus, slot, function
+        );
+
+        let mut flags = sys::VIR_DOMAIN_AFFECT_CONFIG;
+        if live && active {
+            flags |= sys::VIR_DOMAIN_AFFECT_LIVE;
+        }
+
+        domain.detach_device_flags(&hostdev_xml, flags)
+            .map_err(|e| {
+                error!("Failed to detach PCI device {} from VM {}: {}", pci_address, vm_id, e);
+                KvmError::VmOperationFailed(format!("Failed to detach PCI device: {}", e))
+            })?;
+
+        info!("Detached PCI device {} from VM {}", pci_address, vm_id);
+        Ok(())
+    }
+
+    /// Host USB devices, with the VM each one is passed through to
+    pub async fn list_host_usb_devices(&self) -> Result<Vec<host_devices::HostUsbDevice>> {
+        let mut devices = host_devices::list_usb_devices()?;
+        let domains = self.connection
+            .list_all_domains(sys::VIR_CONNECT_LIST_DOMAINS_ACTIVE | sys::VIR_CONNECT_LIST_DOMAINS_INACTIVE)
+            .map_err(KvmError::LibvirtConnection)?;
+
+        for domain in domains {
+            let (Ok(name), Ok(xml)) = (domain.get_name(), domain.get_xml_desc(0)) else {
+                continue;
+            };
+            for (vendor, product, address) in XmlParser::hostdev_usb_devices(&xml) {
+                // Without a pinned address libvirt takes the only device with these IDs
+                let assigned = devices.iter_mut().filter(|device| {
+                    device.vendor_id == vendor
+                        && device.product_id == product
+                        && address.is_none_or(|(bus, number)| device.address == host_devices::UsbAddress { bus, device: number })
+                });
+                for device in assigned {
+                    device.assigned_to = Some(name.clone());
+                }
+            }
+        }
+
+        Ok(devices)
+    }
+
+    /// Pass a host USB device through to the VM by vendor and product ID. When
+    /// several plugged-in devices share those IDs, `address` picks one.
+    pub async fn attach_usb_device(
+        &self,
+        vm_id: &str,
+        vendor_id: &str,
+        product_id: &str,
+        address: Option<host_devices::UsbAddress>,
+        live: bool,
+    ) -> Result<host_devices::HostUsbDevice> {
+        info!("Attaching USB device {}:{} to VM {}", vendor_id, product_id, vm_id);
+
+        let (vendor_id, product_id) = (host_devices::parse_usb_id(vendor_id)?, host_devices::parse_usb_id(product_id)?);
+        let matching: Vec<host_devices::HostUsbDevice> = self.list_host_usb_devices().await?
+            .into_iter()
+            .filter(|device| device.vendor_id == vendor_id && device.product_id == product_id)
+            .filter(|device| address.is_none_or(|address| device.address == address))
+            .collect();
+        let device = match matching.as_slice() {
+            [] => return Err(KvmError::InvalidVmConfig(format!("No USB device {}:{} is plugged in", vendor_id, product_id))),
+            [device] => device.clone(),
+            devices => {
+                let addresses: Vec<String> = devices.iter()
+                    .map(|device| format!("bus {} device {}", device.address.bus, device.address.device))
+                    .collect();
+                return Err(KvmError::InvalidVmConfig(format!(
+                    "{} devices match {}:{} ({}); pick one by its bus and device number",
+                    devices.len(), vendor_id, product_id, addresses.join(", ")
+                )));
+            }
+        };
+        if let Some(owner) = &device.assigned_to {
+            return Err(KvmError::VmOperationFailed(format!("USB device {}:{} is already assigned to VM {}", vendor_id, product_id, owner)));
+        }
+
+        let domain = self.get_domain_by_id(vm_id)?;
+        let mut flags = sys::VIR_DOMAIN_AFFECT_CONFIG;
+        if live && domain.is_active().map_err(KvmError::LibvirtConnection)? {
+            flags |= sys::VIR_DOMAIN_AFFECT_LIVE;
+        }
+
+        domain.attach_device_flags(&Self::usb_hostdev_xml(&vendor_id, &product_id, address), flags)
+            .map_err(|e| {
+                error!("Failed to attach USB device {}:{} to VM {}: {}", vendor_id, product_id, vm_id, e);
+                KvmError::VmOperationFailed(format!("Failed to attach USB device: {}", e))
+            })?;
+
+        info!("Attached USB device {}:{} to VM {}", vendor_id, product_id, vm_id);
+        Ok(device)
+    }
+
+    pub async fn detach_usb_device(
+        &self,
+        vm_id: &str,
+        vendor_id: &str,
+        product_id: &str,
+        address: Option<host_devices::UsbAddress>,
+        live: bool,
+    ) -> Result<()> {
+        info!("Detaching USB device {}:{} from VM {}", vendor_id, product_id, vm_id);
+
+        let (vendor_id, product_id) = (host_devices::parse_usb_id(vendor_id)?, host_devices::parse_usb_id(product_id)?);
+        let domain = self.get_domain_by_id(vm_id)?;
+        let active = domain.is_active().map_err(KvmError::LibvirtConnection)?;
+        let xml_flags = if live && active { 0 } else { sys::VIR_DOMAIN_XML_INACTIVE };
+        let xml = domain.get_xml_desc(xml_flags).map_err(KvmError::LibvirtConnection)?;
+
+        // Detach exactly the element the VM has, pinned address included
+        let attached = XmlParser::hostdev_usb_devices(&xml)
+            .into_iter()
+            .find(|(vendor, product, pinned)| {
+                vendor == &vendor_id
+                    && product == &product_id
+                    && address.is_none_or(|address| *pinned == Some((address.bus, address.device)))
+            })
+            .ok_or_else(|| KvmError::VmOperationFailed(format!("USB device {}:{} is not passed through to VM {}", vendor_id, product_id, vm_id)))?;
+        let pinned = attached.2.map(|(bus, device)| host_devices::UsbAddress { bus, device });
+
+        let mut flags = sys::VIR_DOMAIN_AFFECT_CONFIG;
+        if live && active {
+            flags |= sys::VIR_DOMAIN_AFFECT_LIVE;
+        }
+
+        domain.detach_device_flags(&Self::usb_hostdev_xml(&vendor_id, &product_id, pinned), flags)
+            .map_err(|e| {
+                error!("Failed to detach USB device {}:{} from VM {}: {}", vendor_id, product_id, vm_id, e);
+                KvmError::VmOperationFailed(format!("Failed to detach USB device: {}", e))
+            })?;
+
+        info!("Detached USB device {}:{} from VM {}", vendor_id, product_id, vm_id);
+        Ok(())
+    }
+
+    fn usb_hostdev_xml(vendor_id: &str, product_id: &str, address: Option<host_devices::UsbAddress>) -> String {
+        let address = address
+            .map(|address| format!("\n    <address bus='{}' device='{}'/>", address.bus, address.device))
+            .unwrap_or_default();
+        format!(
+            r#"<hostdev mode='subsystem' type='usb' managed='yes'>
+  <source>
+    <vendor id='0x{}'/>
+    <product id='0x{}'/>{}
+  </source>
+</hostdev>"#,
+            vendor_id, product_id, address
+        )
+    }
+
+    /// Map of host PCI address to the name of the VM it is passed through to
+    fn hostdev_assignments(&self) -> Result<HashMap<String, String>> {
+        let domains = self.connection
+            .list_all_domains(sys::VIR_CONNECT_LIST_DOMAINS_ACTIVE | sys::VIR_CONNECT_LIST_DOMAINS_INACTIVE)
+            .map_err(KvmError::LibvirtConnection)?;
+
+        let mut assignments = HashMap::new();
+        for domain in domains {
+            let (Ok(name), Ok(xml)) = (domain.get_name(), domain.get_xml_desc(0)) else {
+                continue;
+            };
+            for address in XmlParser::hostdev_pci_addresses(&xml) {
+                assignments.insert(address, name.clone());
+            }
+        }
+
+        Ok(assignments)
+    }
+
+    /// Add a serial port that writes the guest console to `log_path` on the host.
+    ///
+    /// Serial ports cannot be hotplugged, so the port is added to the persistent
+    /// definition and starts capturing on the next boot. Returns the target port.
+    pub async fn add_log_console(&self, vm_id: &str, log_path: &str) -> Result<u32> {
+        info!("Adding log console for VM {} at {}", vm_id, log_path);
+
+        let path = std::path::Path::new(log_path);
+        if !path.is_absolute() {
+            return Err(KvmError::InvalidVmConfig(format!("Log path must be absolute: {}", log_path)));
+        }
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        // Create the file up front so permission problems surface here, not at boot
+        std::fs::OpenOptions::new().create(true).append(true).open(path)
+            .map_err(|e| KvmError::PermissionDenied(format!("Cannot write {}: {}", log_path, e)))?;
+
+        let domain = self.get_domain_by_id(vm_id)?;
+        let xml = domain.get_xml_desc(sys::VIR_DOMAIN_XML_INACTIVE)
+            .map_err(KvmError::LibvirtConnection)?;
+
+        if XmlParser::serial_log_files(&xml).iter().any(|(_, existing)| existing == log_path) {
+            return Err(KvmError::VmOperationFailed(format!("VM already logs its console to {}", log_path)));
+        }
+
+        let used_ports = XmlParser::serial_ports(&xml);
+        let port = (0..4)
+            .find(|port| !used_ports.contains(port))
+            .ok_or_else(|| KvmError::VmOperationFailed("No free serial port available".to_string()))?;
+
+        let serial_xml = format!(
+            r#"<serial type='file'>
+  <source path='{}' append='on'/>
+  <target port='{}'/>
+</serial>"#,
+            XmlParser::escape(log_path),
+            port
+        );
+
+        domain.attach_device_flags(&serial_xml, sys::VIR_DOMAIN_AFFECT_CONFIG)
+            .map_err(|e| {
+                error!("Failed to add log console to VM {}: {}", vm_id, e);
+                KvmError::VmOperationFailed(format!("Failed to add log console: {}", e))
+            })?;
+
+        if domain.is_active().unwrap_or(false) {
+            info!("VM {} is running; console logging starts on next boot", vm_id);
+        }
+
+        info!("Added log console on serial port {} for VM {}", port, vm_id);
+        Ok(port)
+    }
+
+    /// Last `lines` lines written to the VM's file-backed serial console
+    pub async fn get_log_console_tail(&self, vm_id: &str, lines: usize) -> Result<Vec<String>> {
+        let domain = self.get_domain_by_id(vm_id)?;
+        let xml = domain.get_xml_desc(0).map_err(KvmError::LibvirtConnection)?;
+
+        let (_, log_path) = XmlParser::serial_log_files(&xml)
+            .into_iter()
+            .max_by_key(|(port, _)| *port)
+            .ok_or_else(|| KvmError::VmOperationFailed("VM has no log console".to_string()))?;
+
+        let bytes = std::fs::read(&log_path)?;
+        let content = String::from_utf8_lossy(&bytes);
+        let all_lines: Vec<&str> = content.lines().collect();
+        let start = all_lines.len().saturating_sub(lines);
+
+        Ok(all_lines[start..].iter().map(|line| line.to_string()).collect())
+    }
+
+    /// Source paths of every disk currently attached to the VM
+    pub async fn get_vm_disk_paths(&self, vm_id: &str) -> Result<Vec<String>> {
+        let domain = self.get_domain_by_id(vm_id)?;
+        let xml = domain.get_xml_desc(0).map_err(KvmError::LibvirtConnection)?;
+        Ok(XmlParser::disk_source_paths(&xml))
+    }
+
+    pub async fn detach_host_block_device(&self, vm_id: &str, device_path: &str, live: bool) -> Result<()> {
+        info!("Detaching host block device {} from VM {}", device_path, vm_id);
+
+        let domain = self.get_domain_by_id(vm_id)?;
+        let xml = domain.get_xml_desc(0).map_err(KvmError::LibvirtConnection)?;
+
+        // Live XML adds attributes such as index='1', so match on the dev attribute only
+        let source = format!("dev='{}'", XmlParser::escape(device_path));
+        let disk_xml = XmlParser::find_elements(&xml, "disk")
+            .into_iter()
+            .find(|disk| disk.contains(&source))
+            .ok_or_else(|| KvmError::VmOperationFailed(format!("{} is not attached to this VM", device_path)))?;
+
+        let mut flags = sys::VIR_DOMAIN_AFFECT_CONFIG;
+        if live && domain.is_active().map_err(KvmError::LibvirtConnection)? {
+            flags |= sys::VIR_DOMAIN_AFFECT_LIVE;
+        }
+
+        domain.detach_device_flags(&disk_xml, flags)
+            .map_err(|e| {
+                error!("Failed to detach {} from VM {}: {}", device_path, vm_id, e);
+                KvmError::VmOperationFailed(format!("Failed to detach block device: {}", e))
+            })?;
+
+        info!("Detached {} from VM {}", device_path, vm_id);
+        Ok(())
+    }
+
+    /// Attach `disk` to a VM. `path` must point at an existing image or block
+    /// device. An empty `device` picks the next free target on the bus (vdb,
+    /// vdc, ...) and an empty `type_` has qemu-img detect the format. Returns
+    /// the target the disk was attached at.
+    pub async fn attach_disk(&mut self, vm_id: &str, disk: StorageDevice, live: bool) -> Result<String> {
+        info!("Attaching disk {} to VM {}", disk.path.as_deref().unwrap_or_default(), vm_id);
+
+        let mut disk = disk;
+        let path = disk.path.clone()
+            .filter(|path| !path.is_empty())
+            .ok_or_else(|| KvmError::InvalidVmConfig("Disk needs a source path".to_string()))?;
+        let path = path.as_str();
+        let metadata = std::fs::metadata(path)
+            .map_err(|e| KvmError::InvalidVmConfig(format!("Cannot use {} as a disk: {}", path, e)))?;
+
+        let domain = self.get_domain_by_id(vm_id)?;
+        let xml = domain.get_xml_desc(0).map_err(KvmError::LibvirtConnection)?;
+        let xml_info = XmlParser::parse_vm_from_xml(&xml)?;
+
+        if disk.type_.is_empty() {
+            disk.type_ = XmlParser::get_image_format_from_file(path).unwrap_or_else(|| "raw".to_string());
+        }
+        if disk.size_gb <= 0.0 {
+            disk.size_gb = XmlParser::get_disk_size_from_file(path).unwrap_or(0.0);
+        }
+        if disk.device.is_empty() {
+            let prefix = Self::disk_target_prefix(&disk.bus)?;
+            disk.device = ('a'..='z')
+                .map(|letter| format!("{}{}", prefix, letter))
+                .find(|name| !xml_info.storage_devices.iter().any(|d| &d.device == name))
+                .ok_or_else(|| KvmError::VmOperationFailed(format!("No free {} disk target available", disk.bus)))?;
+        }
+        Self::validate_disk_target(&disk)?;
+
+        if xml_info.storage_devices.iter().any(|d| d.device == disk.device) {
+            return Err(KvmError::VmOperationFailed(format!("Target {} is already in use on this VM", disk.device)));
+        }
+        if xml_info.storage_devices.iter().any(|d| d.path.as_deref() == Some(path)) {
+            return Err(KvmError::VmOperationFailed(format!("{} is already attached to this VM", path)));
+        }
+
+        let tags = self.vm_cache.get(vm_id).map(|vm| vm.tags.clone()).unwrap_or_default();
+        quotas::check(&self.connection, &QuotaRequest {
+            disk_gb: (xml_info.disk_size_gb + disk.size_gb).ceil() as u64,
+            tags,
+            ..Default::default()
+        })?;
+
+        use std::os::unix::fs::FileTypeExt;
+        let is_block = metadata.file_type().is_block_device();
+        let disk_xml = format!(
+            r#"<disk type='{}' device='disk'>
+  <driver name='qemu' type='{}'{}/>
+  <source {}='{}'/>
+  <target dev='{}' bus='{}'/>
+</disk>"#,
+            if is_block { "block" } else { "file" },
+            XmlParser::escape(&disk.type_),
+            disk.cache.as_deref()
+                .map(|cache| format!(" cache='{}'", XmlParser::escape(cache)))
+                .unwrap_or_default(),
+            if is_block { "dev" } else { "file" },
+            XmlParser::escape(path),
+            disk.device,
+            disk.bus,
+        );
+
+        let mut flags = sys::VIR_DOMAIN_AFFECT_CONFIG;
+        if live && domain.is_active().map_err(KvmError::LibvirtConnection)? {
+            flags |= sys::VIR_DOMAIN_AFFECT_LIVE;
+        }
+
+        domain.attach_device_flags(&disk_xml, flags)
+            .map_err(|e| {
+                error!("Failed to attach disk {} to VM {}: {}", path, vm_id, e);
+                KvmError::VmOperationFailed(format!("Failed to attach disk: {}", e))
+            })?;
+
+        info!("Attached {} to VM {} as {}", path, vm_id, disk.device);
+        self.refresh_vm_cache().await?;
+        Ok(disk.device)
+    }
+
+    fn disk_target_prefix(bus: &str) -> Result<&'static str> {
+        match bus {
+            "virtio" => Ok("vd"),
+            "sata" | "scsi" | "usb" => Ok("sd"),
+            "ide" => Ok("hd"),
+            other => Err(KvmError::InvalidVmConfig(format!("Unsupported disk bus: {}", other))),
+        }
+    }
+
+    fn validate_disk_target(disk: &StorageDevice) -> Result<()> {
+        let prefix = Self::disk_target_prefix(&disk.bus)?;
+        let suffix = disk.device.strip_prefix(prefix).unwrap_or_default();
+        if suffix.is_empty() || !suffix.chars().all(|c| c.is_ascii_lowercase()) {
+            return Err(KvmError::InvalidVmConfig(format!(
+                "Disk target {} does not match {} bus naming ({}a, {}b, ...)", disk.device, disk.bus, prefix, prefix
+            )));
+        }
+        if !matches!(disk.type_.as_str(), "qcow2" | "raw") {
+            return Err(KvmError::InvalidVmConfig(format!("Unsupported disk format: {}", disk.type_)));
+        }
+        Ok(())
+    }
+
+    /// Detach the disk at `target_dev`. The image itself is left in place.
+    pub async fn detach_disk(&self, vm_id: &str, target_dev: &str, live: bool) -> Result<()> {
+        info!("Detaching disk {} from VM {}", target_dev, vm_id);
+
+        let domain = self.get_domain_by_id(vm_id)?;
+        let active = domain.is_active().map_err(KvmError::LibvirtConnection)?;
+        let xml_flags = if live && active { 0 } else { sys::VIR_DOMAIN_XML_INACTIVE };
+        let xml = domain.get_xml_desc(xml_flags).map_err(KvmError::LibvirtConnection)?;
+
+        let disk_xml = XmlParser::find_device(&xml, "disk", "target", "dev", |dev| dev == target_dev)
+            .ok_or_else(|| KvmError::VmOperationFailed(format!("VM has no disk at {}", target_dev)))?;
+
+        let mut flags = sys::VIR_DOMAIN_AFFECT_CONFIG;
+        if live && active {
+            flags |= sys::VIR_DOMAIN_AFFECT_LIVE;
+        }
+
+        domain.detach_device_flags(&disk_xml, flags)
+            .map_err(|e| {
+                error!("Failed to detach disk {} from VM {}: {}", target_dev, vm_id, e);
+                KvmError::VmOperationFailed(format!("Failed to detach disk: {}", e))
+            })?;
+
+        info!("Detached disk {} from VM {}", target_dev, vm_id);
+        Ok(())
+    }
+
+    /// Insert `iso_path` into the VM's CD-ROM drive, or eject it with `None`.
+    /// A VM without a drive gets a SATA one; SATA can't be hot-plugged, so on a
+    /// running VM the new drive only appears after the next boot.
+    pub async fn change_cdrom_media(&self, vm_id: &str, iso_path: Option<String>) -> Result<ChangeScope> {
+        info!("Changing CD-ROM media of VM {} to {:?}", vm_id, iso_path);
+
+        if let Some(path) = &iso_path {
+            if !std::path::Path::new(path).is_file() {
+                return Err(KvmError::InvalidVmConfig(format!("ISO not found: {}", path)));
+            }
+        }
+
+        let domain = self.get_domain_by_id(vm_id)?;
+        let active = domain.is_active().map_err(KvmError::LibvirtConnection)?;
+        let persistent = domain.is_persistent().map_err(KvmError::LibvirtConnection)?;
+        let xml_flags = if active { 0 } else { sys::VIR_DOMAIN_XML_INACTIVE };
+        let xml = domain.get_xml_desc(xml_flags).map_err(KvmError::LibvirtConnection)?;
+        let disks = XmlParser::disk_elements(&xml);
+
+        let source = iso_path.as_deref()
+            .map(|path| format!("\n  <source file='{}'/>", XmlParser::escape(path)))
+            .unwrap_or_default();
+        let cdrom_xml = |target: &str, bus: &str| format!(
+            r#"<disk type='file' device='cdrom'>
+  <driver name='qemu' type='raw'/>{}
+  <target dev='{}' bus='{}'/>
+  <readonly/>
+</disk>"#,
+            source, target, bus
+        );
+
+        let media_error = |e: virt::error::Error| {
+            error!("Failed to change CD-ROM media of VM {}: {}", vm_id, e);
+            KvmError::VmOperationFailed(format!("Failed to change CD-ROM media: {}", e))
+        };
+
+        if let Some(cdrom) = disks.iter().find(|disk| disk.device == "cdrom") {
+            let bus = cdrom.bus.clone().unwrap_or_else(|| "sata".to_string());
+            let (flags, applied) = match (active, persistent) {
+                (true, true) => (sys::VIR_DOMAIN_AFFECT_LIVE | sys::VIR_DOMAIN_AFFECT_CONFIG, ChangeScope::Both),
+                (true, false) => (sys::VIR_DOMAIN_AFFECT_LIVE, ChangeScope::Live),
+                (false, _) => (sys::VIR_DOMAIN_AFFECT_CONFIG, ChangeScope::Persistent),
+            };
+            // libvirt matches the drive by target and swaps only its media
+            domain.update_device_flags(&cdrom_xml(&cdrom.target, &bus), flags).map_err(media_error)?;
+            info!("Changed CD-ROM media of VM {} at {}", vm_id, cdrom.target);
+            return Ok(applied);
+        }
+
+        if iso_path.is_none() {
+            return Err(KvmError::VmOperationFailed("VM has no CD-ROM drive to eject".to_string()));
+        }
+        if !persistent {
+            return Err(KvmError::VmOperationFailed(
+                "VM is transient and has no CD-ROM drive; SATA drives cannot be hot-plugged".to_string()
+            ));
+        }
+
+        let target = ('a'..='z')
+            .map(|letter| format!("sd{}", letter))
+            .find(|name| !disks.iter().any(|disk| &disk.target == name))
+            .ok_or_else(|| KvmError::VmOperationFailed("No free SATA target available".to_string()))?;
+        domain.attach_device_flags(&cdrom_xml(&target, "sata"), sys::VIR_DOMAIN_AFFECT_CONFIG).map_err(media_error)?;
+
+        if active {
+            info!("Added CD-ROM drive {} to VM {}; it appears after the next boot", target, vm_id);
+        } else {
+            info!("Added CD-ROM drive {} to VM {}", target, vm_id);
+        }
+        Ok(ChangeScope::Persistent)
+    }
+
+    /// Add a NIC described by `config`, on its bridge if one is set and else on
+    /// its libvirt network (`default` when neither is). A random 52:54:00 MAC
+    /// is assigned when none is given; returns the MAC the interface ended up with.
+    pub async fn attach_network_interface(&mut self, vm_id: &str, config: NetworkConfig, live: bool) -> Result<String> {
+        let (interface_type, source) = match config.bridge.as_deref().filter(|bridge| !bridge.is_empty()) {
+            Some(bridge) => ("bridge", bridge.to_string()),
+            None => ("network", config.network_name.clone().filter(|name| !name.is_empty()).unwrap_or_else(|| "default".to_string())),
+        };
+        info!("Attaching {} interface on {} to VM {}", interface_type, source, vm_id);
+
+        let mac_address = match config.mac_address.as_deref().filter(|mac| !mac.is_empty()) {
+            Some(mac) if XmlParser::is_valid_mac(mac) => mac.to_lowercase(),
+            Some(mac) => return Err(KvmError::InvalidVmConfig(format!("Invalid MAC address: {}", mac))),
+            None => XmlParser::random_mac(),
+        };
+
+        let domain = self.get_domain_by_id(vm_id)?;
+        let xml = domain.get_xml_desc(0).map_err(KvmError::LibvirtConnection)?;
+        let xml_info = XmlParser::parse_vm_from_xml(&xml)?;
+        let in_use = xml_info.network_interfaces.iter()
+            .any(|existing| existing.mac_address.as_deref().is_some_and(|mac| mac.eq_ignore_ascii_case(&mac_address)));
+        if in_use {
+            return Err(KvmError::VmOperationFailed(format!("VM already has an interface with MAC {}", mac_address)));
+        }
+
+        let interface_xml = format!(
+            r#"<interface type='{}'>
+  <mac address='{}'/>
+  <source {}='{}'/>
+  <model type='{}'/>
+</interface>"#,
+            interface_type,
+            mac_address,
+            interface_type,
+            XmlParser::escape(&source),
+            XmlParser::escape(config.model.as_deref().filter(|model| !model.is_empty()).unwrap_or("virtio")),
+        );
+
+        let mut flags = sys::VIR_DOMAIN_AFFECT_CONFIG;
+        if live && domain.is_active().map_err(KvmError::LibvirtConnection)? {
+            flags |= sys::VIR_DOMAIN_AFFECT_LIVE;
+        }
+
+        domain.attach_device_flags(&interface_xml, flags)
+            .map_err(|e| {
+                error!("Failed to attach interface to VM {}: {}", vm_id, e);
+                KvmError::VmOperationFailed(format!("Failed to attach network interface: {}", e))
+            })?;
+
+        info!("Attached interface {} to VM {}", mac_address, vm_id);
+        self.refresh_vm_cache().await?;
+        Ok(mac_address)
+    }
+
+    /// Unplug the NIC with `mac_address`. Target names like vnet3 change on every
+    /// start, so the MAC is the only stable way to pick an interface.
+    pub async fn detach_network_interface(&mut self, vm_id: &str, mac_address: &str, live: bool) -> Result<()> {
+        info!("Detaching interface {} from VM {}", mac_address, vm_id);
+
+        let domain = self.get_domain_by_id(vm_id)?;
+        let active = domain.is_active().map_err(KvmError::LibvirtConnection)?;
+        let xml_flags = if live && active { 0 } else { sys::VIR_DOMAIN_XML_INACTIVE };
+        let xml = domain.get_xml_desc(xml_flags).map_err(KvmError::LibvirtConnection)?;
+
+        let interface_xml = XmlParser::find_device(&xml, "interface", "mac", "address", |mac| mac.eq_ignore_ascii_case(mac_address))
+            .ok_or_else(|| KvmError::VmOperationFailed(format!("VM has no network interface with MAC {}", mac_address)))?;
+
+        let mut flags = sys::VIR_DOMAIN_AFFECT_CONFIG;
+        if live && active {
+            flags |= sys::VIR_DOMAIN_AFFECT_LIVE;
+        }
+
+        domain.detach_device_flags(&interface_xml, flags)
+            .map_err(|e| {
+                error!("Failed to detach interface {} from VM {}: {}", mac_address, vm_id, e);
+                KvmError::VmOperationFailed(format!("Failed to detach network interface: {}", e))
+            })?;
+
+        info!("Detached interface {} from VM {}", mac_address, vm_id);
+        self.refresh_vm_cache().await?;
+        Ok(())
+    }
+
+    /// Plug or unplug the virtual cable of the NIC with `mac_address` without
+    /// removing the device. Applies to the running guest and the saved
+    /// definition, whichever exist.
+    pub async fn set_interface_link_state(&mut self, vm_id: &str, mac_address: &str, up: bool) -> Result<ChangeScope> {
+        info!("Setting link of interface {} on VM {} {}", mac_address, vm_id, if up { "up" } else { "down" });
+
+        let domain = self.get_domain_by_id(vm_id)?;
+        let active = domain.is_active().map_err(KvmError::LibvirtConnection)?;
+        let persistent = domain.is_persistent().map_err(KvmError::LibvirtConnection)?;
+        let xml_flags = if active { 0 } else { sys::VIR_DOMAIN_XML_INACTIVE };
+        let xml = domain.get_xml_desc(xml_flags).map_err(KvmError::LibvirtConnection)?;
+
+        let interface_xml = XmlParser::find_device(&xml, "interface", "mac", "address", |mac| mac.eq_ignore_ascii_case(mac_address))
+            .ok_or_else(|| KvmError::VmOperationFailed(format!("VM has no network interface with MAC {}", mac_address)))?;
+        let link = format!("<link state='{}'/>", if up { "up" } else { "down" });
+        let interface_xml = XmlParser::upsert_element(&interface_xml, "link", Some(&link), &[]);
+
+        let (flags, applied) = match (active, persistent) {
+            (true, true) => (sys::VIR_DOMAIN_AFFECT_LIVE | sys::VIR_DOMAIN_AFFECT_CONFIG, ChangeScope::Both),
+            (true, false) => (sys::VIR_DOMAIN_AFFECT_LIVE, ChangeScope::Live),
+            (false, _) => (sys::VIR_DOMAIN_AFFECT_CONFIG, ChangeScope::Persistent),
+        };
+        domain.update_device_flags(&interface_xml, flags)
+            .map_err(|e| {
+                error!("Failed to set link state of interface {} on VM {}: {}", mac_address, vm_id, e);
+                KvmError::VmOperationFailed(format!("Failed to set link state: {}", e))
+            })?;
+
+        self.refresh_vm_cache().await?;
+        Ok(applied)
+    }
+
+    /// The sound model to emit, None for no sound; fails on models libvirt doesn't know
+    fn sound_model(model: Option<&str>) -> Result<Option<&str>> {
+        match model.filter(|model| !model.is_empty() && *model != "none") {
+            Some(model) if SOUND_MODELS.contains(&model) => Ok(Some(model)),
+            Some(model) => Err(KvmError::InvalidVmConfig(format!(
+                "Unknown sound model {}; use {} or none", model, SOUND_MODELS.join(", ")
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    /// Give a shut-off VM a sound device of `model`, replacing any it has;
+    /// "none" removes it
+    pub async fn attach_sound_device(&mut self, vm_id: &str, model: &str) -> Result<()> {
+        info!("Setting sound device of VM {} to {}", vm_id, model);
+
+        let domain = self.get_domain_by_id(vm_id)?;
+        if domain.is_active().map_err(KvmError::LibvirtConnection)? {
+            return Err(KvmError::VmOperationFailed("Sound devices can only be changed while the VM is shut off".to_string()));
+        }
+
+        let xml = domain.get_xml_desc(sys::VIR_DOMAIN_XML_INACTIVE | sys::VIR_DOMAIN_XML_SECURE)
+            .map_err(KvmError::LibvirtConnection)?;
+        let updated = match Self::sound_model(Some(model))? {
+            Some(model) => {
+                let updated = XmlParser::upsert_element(&xml, "sound", Some(&format!("<sound model='{}'/>", model)), &["video", "graphics"]);
+                let spice = XmlParser::parse_graphics_devices(&xml).iter().any(|graphics| graphics.protocol == "spice");
+                if spice && XmlParser::find_elements(&updated, "audio").is_empty() {
+                    XmlParser::upsert_element(&updated, "audio", Some("<audio id='1' type='spice'/>"), &["video", "graphics"])
+                } else {
+                    updated
+                }
+            }
+            None => XmlParser::upsert_element(&xml, "sound", None, &[]),
+        };
+
+        Domain::define_xml(&self.connection, &updated)
+            .map_err(|e| {
+                error!("Failed to update sound device of VM {}: {}", vm_id, e);
+                KvmError::VmOperationFailed(format!("Failed to update sound device: {}", e))
+            })?;
+
+        self.refresh_vm_cache().await
+    }
+
+    fn validate_shared_folder(folder: &SharedFolderConfig) -> Result<()> {
+        if !std::path::Path::new(&folder.host_path).is_dir() {
+            return Err(KvmError::InvalidVmConfig(format!("Shared folder {} is not a directory", folder.host_path)));
+        }
+        if folder.guest_tag.trim().is_empty() {
+            return Err(KvmError::InvalidVmConfig("Shared folder needs a guest tag".to_string()));
+        }
+        Ok(())
+    }
+
+    /// Share a host directory with the VM. virtiofs needs shared guest memory;
+    /// if the VM doesn't have it yet, it is added to the definition and the
+    /// folder appears after the next boot.
+    pub async fn attach_shared_folder(&mut self, vm_id: &str, folder: SharedFolderConfig, live: bool) -> Result<ChangeScope> {
+        info!("Sharing {} with VM {} as {}", folder.host_path, vm_id, folder.guest_tag);
+        Self::validate_shared_folder(&folder)?;
+
+        let domain = self.get_domain_by_id(vm_id)?;
+        let active = domain.is_active().map_err(KvmError::LibvirtConnection)?;
+        let xml = domain.get_xml_desc(sys::VIR_DOMAIN_XML_INACTIVE | sys::VIR_DOMAIN_XML_SECURE).map_err(KvmError::LibvirtConnection)?;
+        let in_use = XmlParser::parse_vm_from_xml(&xml)?.shared_folders.iter()
+            .any(|existing| existing.guest_tag == folder.guest_tag);
+        if in_use {
+            return Err(KvmError::VmOperationFailed(format!("VM already has a shared folder tagged {}", folder.guest_tag)));
+        }
+
+        let mut hot_plug = live && active;
+        if folder.driver == SharedFolderDriver::Virtiofs && !XmlParser::has_shared_memory(&xml) {
+            let backing = XmlParser::memory_backing_xml(XmlParser::parse_memory_backing(&xml).as_ref(), true);
+            let updated = XmlParser::upsert_element(&xml, "memoryBacking", backing.as_deref(), &["currentMemory", "memory"]);
+            Domain::define_xml(&self.connection, &updated)
+                .map_err(|e| KvmError::VmOperationFailed(format!("Failed to enable shared memory: {}", e)))?;
+            // The running guest's memory isn't shared, so it can't take the device now
+            hot_plug = false;
+        }
+        if folder.driver == SharedFolderDriver::NineP {
+            hot_plug = false; // QEMU can't hot-plug 9p
+        }
+
+        let flags = if hot_plug { sys::VIR_DOMAIN_AFFECT_CONFIG | sys::VIR_DOMAIN_AFFECT_LIVE } else { sys::VIR_DOMAIN_AFFECT_CONFIG };
+        domain.attach_device_flags(&XmlParser::shared_folder_xml(&folder), flags)
+            .map_err(|e| {
+                error!("Failed to share {} with VM {}: {}", folder.host_path, vm_id, e);
+                KvmError::VmOperationFailed(format!("Failed to attach shared folder: {}", e))
+            })?;
+
+        self.refresh_vm_cache().await?;
+        Ok(if hot_plug { ChangeScope::Both } else { ChangeScope::Persistent })
+    }
+
+    /// Remove the shared folder with `guest_tag`
+    pub async fn detach_shared_folder(&mut self, vm_id: &str, guest_tag: &str, live: bool) -> Result<ChangeScope> {
+        info!("Removing shared folder {} from VM {}", guest_tag, vm_id);
+
+        let domain = self.get_domain_by_id(vm_id)?;
+        let active = domain.is_active().map_err(KvmError::LibvirtConnection)?;
+        let xml = domain.get_xml_desc(sys::VIR_DOMAIN_XML_INACTIVE).map_err(KvmError::LibvirtConnection)?;
+        let filesystem_xml = XmlParser::find_device(&xml, "filesystem", "target", "dir", |dir| dir == guest_tag)
+            .ok_or_else(|| KvmError::VmOperationFailed(format!("VM has no shared folder tagged {}", guest_tag)))?;
+
+        let hot_unplug = live && active && filesystem_xml.contains("type='virtiofs'");
+        let flags = if hot_unplug { sys::VIR_DOMAIN_AFFECT_CONFIG | sys::VIR_DOMAIN_AFFECT_LIVE } else { sys::VIR_DOMAIN_AFFECT_CONFIG };
+        domain.detach_device_flags(&filesystem_xml, flags)
+            .map_err(|e| {
+                error!("Failed to remove shared folder {} from VM {}: {}", guest_tag, vm_id, e);
+                KvmError::VmOperationFailed(format!("Failed to detach shared folder: {}", e))
+            })?;
+
+        self.refresh_vm_cache().await?;
+        Ok(if hot_unplug { ChangeScope::Both } else { ChangeScope::Persistent })
+    }
+
+    pub async fn get_supported_machine_types(&self) -> Result<Vec<MachineType>> {
+        self.machine_types()
+    }
+
+    fn machine_types(&self) -> Result<Vec<MachineType>> {
+        let capabilities = self.connection.get_capabilities().map_err(KvmError::LibvirtConnection)?;
+        XmlParser::parse_machine_types(&capabilities, "x86_64")
+    }
+
+    /// Machine type for new VMs that don't ask for one. Falls back to the bare
+    /// `q35` alias, which QEMU resolves itself, if the host can't be queried.
+    fn default_machine_type(&self) -> String {
+        match self.machine_types() {
+            Ok(machines) => XmlParser::newest_q35_machine(&machines).unwrap_or_else(|| "q35".to_string()),
+            Err(e) => {
+                warn!("Could not read supported machine types, using q35: {}", e);
+                "q35".to_string()
+            }
+        }
+    }
+
+    /// CPU models the hypervisor can emulate on this host, for `CpuModel::Named`
+    pub async fn list_host_cpu_models(&self) -> Result<Vec<HostCpuModel>> {
+        let capabilities = self.connection
+            .get_domain_capabilities(None, Some("x86_64"), None, Some("kvm"), 0)
+            .map_err(KvmError::LibvirtConnection)?;
+        XmlParser::parse_host_cpu_models(&capabilities)
+    }
+
+    pub async fn get_host_info(&self) -> Result<HostInfo> {
+        debug!("Getting host information");
+        self.ensure_connected()?;
+
+        let node_info = self.connection.get_node_info()
+            .map_err(KvmError::LibvirtConnection)?;
+
+        let hostname = self.connection.get_hostname()
+            .map_err(KvmError::LibvirtConnection)?;
+
+        let hypervisor = self.connection.get_type()
+            .map_err(KvmError::LibvirtConnection)?;
+
+        let hypervisor_version = self.connection.get_lib_version()
+            .map_err(KvmError::LibvirtConnection)?;
+
+        // Get domain counts
+        let active_vms = self.connection.num_of_domains()
+            .map_err(KvmError::LibvirtConnection)? as u32;
+
+        let inactive_vms = self.connection.num_of_defined_domains()
+            .map_err(KvmError::LibvirtConnection)? as u32;
+
+        // Get memory info
+        let memory_total = node_info.memory / 1024; // Convert to MB from KB
+        
+        // Get actual free memory from system
+        let memory_free = self.get_host_free_memory().await.unwrap_or(0);
+
+        Ok(HostInfo {
+            hostname,
+            hypervisor,
+            hypervisor_version: hypervisor_version.to_string(),
+            cpu_model: node_info.model,
+            cpu_cores: node_info.cpus,
+            storage_pools: self.get_storage_pools().await?,
+            networks: self.get_networks().await?,
+            active_vms,
+            inactive_vms,
+            ..Default::default()
+        }
+        .with_memory(memory_total, memory_free))
+    }
+
+    #[instrument(skip(self), fields(operation = "create_snapshot"))]
+    pub async fn create_snapshot(&self, vm_id: &str, snapshot_name: &str, kind: Option<SnapshotKind>) -> Result<Snapshot> {
+        info!("Creating snapshot {} for VM {}", snapshot_name, vm_id);
+        
+        let domain = self.get_domain_by_id(vm_id)?;
+        
+        // Overlays are written next to the disk images and need headroom too
+        let xml = domain.get_xml_desc(0).map_err(KvmError::LibvirtConnection)?;
+        Self::check_disk_filesystems(vm_id, &xml)?;
+        let running = domain.is_active().map_err(KvmError::LibvirtConnection)?;
+        let kind = Self::resolve_snapshot_kind(&xml, kind, running)?;
+        
+        Self::check_snapshot_name(snapshot_name)?;
+        
+        // Without a memory element libvirt saves the memory of a running guest internally
+        let snapshot_xml = XmlParser::snapshot_xml(snapshot_name, "Snapshot created by KVM Manager");
+        let flags = match kind {
+            SnapshotKind::DiskOnly => sys::VIR_DOMAIN_SNAPSHOT_CREATE_DISK_ONLY,
+            SnapshotKind::Full => 0,
+        };
+        let snapshot = DomainSnapshot::create_xml(&domain, &snapshot_xml, flags)
+            .map_err(|e| KvmError::SnapshotOperationFailed(format!("Snapshot creation failed: {}", e)))?;
+        
+        info!("Successfully created snapshot {} for VM {}", snapshot_name, vm_id);
+        let snapshot_xml = snapshot.get_xml_desc(0).map_err(KvmError::LibvirtConnection)?;
+        XmlParser::parse_snapshot(&snapshot_xml)
+    }
+
+    /// Pick the snapshot kind every disk supports, preferring the requested one.
+    ///
+    /// A disk-only request falls back to a full internal snapshot when some disk
+    /// can't take an overlay (qcow2 on a block device); raw disks are refused with
+    /// the conversion command instead of virsh's error. A stopped guest has no
+    /// memory to save, so a full request for it becomes disk-only where possible.
+    fn resolve_snapshot_kind(xml: &str, requested: Option<SnapshotKind>, running: bool) -> Result<SnapshotKind> {
+        let xml_info = XmlParser::parse_vm_from_xml(xml)?;
+        let capabilities: Vec<DiskSnapshotCapability> = xml_info.storage_devices
+            .iter()
+            .map(DiskSnapshotCapability::for_device)
+            .collect();
+
+        if let Some(blocked) = capabilities.iter().find(|c| !c.disk_only && !c.full) {
+            return Err(KvmError::SnapshotOperationFailed(
+                blocked.reason.clone().unwrap_or_else(|| format!("{} cannot be snapshotted", blocked.device))
+            ));
+        }
+
+        let all_disk_only = capabilities.iter().all(|c| c.disk_only);
+        match requested {
+            Some(SnapshotKind::Full) if !running && all_disk_only => {
+                info!("VM is not running, taking a disk-only snapshot instead of a full one");
+                Ok(SnapshotKind::DiskOnly)
+            }
+            Some(SnapshotKind::Full) => Ok(SnapshotKind::Full),
+            Some(SnapshotKind::DiskOnly) | None if all_disk_only => Ok(SnapshotKind::DiskOnly),
+            Some(SnapshotKind::DiskOnly) | None => {
+                info!("Some disks cannot take overlays, falling back to a full snapshot");
+                Ok(SnapshotKind::Full)
+            }
+        }
+    }
+
+    /// Refuse snapshot names that are empty or can't be represented in the
+    /// snapshot XML. Names only travel through the libvirt API, never a command
+    /// line, so a leading '-' is fine.
+    fn check_snapshot_name(snapshot_name: &str) -> Result<()> {
+        if snapshot_name.trim().is_empty() {
+            return Err(KvmError::SnapshotOperationFailed("Snapshot name must not be empty".to_string()));
+        }
+        if snapshot_name.chars().any(char::is_control) {
+            return Err(KvmError::SnapshotOperationFailed("Snapshot name must not contain control characters".to_string()));
+        }
+        Ok(())
+    }
+
+    fn lookup_snapshot(domain: &Domain, snapshot_name: &str) -> Result<DomainSnapshot> {
+        Self::check_snapshot_name(snapshot_name)?;
+        DomainSnapshot::lookup_by_name(domain, snapshot_name, 0).map_err(|e| match e.code() {
+            ErrorNumber::NoDomainSnapshot => {
+                KvmError::SnapshotOperationFailed(format!("Snapshot {} not found", snapshot_name))
+            }
+            _ => KvmError::LibvirtConnection(e),
+        })
+    }
+
+    #[instrument(skip(self), fields(operation = "restore_snapshot"))]
+    pub async fn restore_snapshot(&self, vm_id: &str, snapshot_name: &str) -> Result<()> {
+        info!("Restoring snapshot {} for VM {}", snapshot_name, vm_id);
+        
+        let domain = self.get_domain_by_id(vm_id)?;
+        let snapshot = Self::lookup_snapshot(&domain, snapshot_name)?;
+        snapshot.revert(0)
+            .map_err(|e| KvmError::SnapshotOperationFailed(format!("Snapshot restoration failed: {}", e)))?;
+        
+        info!("Successfully restored snapshot {} for VM {}", snapshot_name, vm_id);
+        Ok(())
+    }
+    
+    pub async fn list_snapshots(&self, vm_id: &str) -> Result<Vec<Snapshot>> {
+        info!("Listing snapshots for VM {}", vm_id);
+        
+        let domain = self.get_domain_by_id(vm_id)?;
+        let snapshots = Self::domain_snapshots(&domain)
+            .map_err(|e| KvmError::SnapshotOperationFailed(format!("Failed to list snapshots: {}", e)))?;
+        
+        info!("Found {} snapshots for VM {}", snapshots.len(), vm_id);
+        Ok(snapshots)
+    }
+    
+    #[instrument(skip(self), fields(operation = "delete_snapshot"))]
+    pub async fn delete_snapshot(&self, vm_id: &str, snapshot_name: &str) -> Result<()> {
+        info!("Deleting snapshot {} for VM {}", snapshot_name, vm_id);
+        
+        let domain = self.get_domain_by_id(vm_id)?;
+        let snapshot = Self::lookup_snapshot(&domain, snapshot_name)?;
+        snapshot.delete(0)
+            .map_err(|e| KvmError::SnapshotOperationFailed(format!("Snapshot deletion failed: {}", e)))?;
+        
+        info!("Successfully deleted snapshot {} for VM {}", snapshot_name, vm_id);
+        Ok(())
+    }
+
+    pub async fn get_storage_pools(&self) -> Result<Vec<StoragePool>> {
+        debug!("Getting storage pools");
+
+        let pools = self.connection.list_all_storage_pools(0)
+            .map_err(KvmError::LibvirtConnection)?;
+
+        let mut storage_pools = Vec::new();
+
+        for pool in pools {
+            match self.pool_to_storage_pool(&pool).await {
+                Ok(storage_pool) => storage_pools.push(storage_pool),
+                Err(e) => {
+                    warn!("Failed to convert storage pool: {}", e);
+                    continue;
+                }
+            }
+        }
+
+        Ok(storage_pools)
+    }
+
+    pub async fn get_networks(&self) -> Result<Vec<Network>> {
+        debug!("Getting networks");
+
+        let networks = self.connection.list_all_networks(0)
+            .map_err(KvmError::LibvirtConnection)?;
+
+        let mut result_networks = Vec::new();
+
+        for network in networks {
+            match self.network_to_network(&network).await {
+                Ok(net) => result_networks.push(net),
+                Err(e) => {
+                    warn!("Failed to convert network: {}", e);
+                    continue;
+                }
+            }
+        }
+
+        Ok(result_networks)
+    }
+
+    // Private helper methods
+
+    async fn refresh_vm_cache(&mut self) -> Result<()> {
+        debug!("Refreshing VM cache");
+        
+        let snapshot = self.collect_cache_snapshot().await?;
+        self.apply_cache_snapshot(snapshot);
+        
+        Ok(())
+    }
+
+    /// Enumerate VMs and pools without touching the caches, so callers can do the
+    /// slow part under a read lock
+    pub async fn collect_cache_snapshot(&self) -> Result<CacheSnapshot> {
+        let vms = self.list_vms().await?;
+        let pools = match self.get_storage_pools().await {
+            Ok(pools) => Some(pools),
+            Err(e) => {
+                warn!("Failed to refresh storage pool cache: {}", e);
+                None
+            }
+        };
+        
+        Ok(CacheSnapshot { vms, pools })
+    }
+
+    /// Swap a collected snapshot into the caches in one step
+    pub fn apply_cache_snapshot(&mut self, snapshot: CacheSnapshot) {
+        self.vm_cache = snapshot.vms
+            .into_iter()
+            .map(|vm| (vm.id.clone(), vm))
+            .collect();
+        
+        if let Some(pools) = snapshot.pools {
+            self.pool_cache = pools;
+        }
+    }
+
+    fn get_domain_by_id(&self, vm_id: &str) -> Result<Domain> {
+        // Try to get by UUID string first (most reliable)
+        if let Ok(domain) = Domain::lookup_by_uuid_string(&self.connection, vm_id) {
+            return Ok(domain);
+        }
+        
+        // Try to get by name as fallback
+        if let Ok(domain) = Domain::lookup_by_name(&self.connection, vm_id) {
+            return Ok(domain);
+        }
+
+        Err(KvmError::VmNotFound(vm_id.to_string()))
+    }
+
+    async fn domain_to_vm(&self, domain: &Domain) -> Result<VirtualMachine> {
+        let uuid = domain.get_uuid_string().map_err(|e| {
+            error!("Failed to get UUID for domain: {}", e);
+            KvmError::LibvirtConnection(e)
+        })?;
+        let name = domain.get_name().map_err(|e| {
+            error!("Failed to get name for domain: {}", e);
+            KvmError::LibvirtConnection(e)
+        })?;
+        let info = domain.get_info().map_err(|e| {
+            error!("Failed to get info for domain {}: {}", name, e);
+            KvmError::LibvirtConnection(e)
+        })?;
+        
+        debug!("Converting domain to VM: name={}, uuid={}, state={}", name, uuid, info.state);
+        
+        let state = Self::vm_state_from(info.state);
+
+        // Use XML parser to extract comprehensive VM information
+        let xml_info = match domain.get_xml_desc(0) {
+            Ok(xml) => {
+                debug!("Got XML for VM {}: {} chars", name, xml.len());
+                Self::parse_domain_xml(&xml, &name, &uuid, info.memory / 1024, info.nr_virt_cpu)
+            },
+            Err(e) => VmXmlInfo::fallback(&name, &uuid, info.memory / 1024, info.nr_virt_cpu, format!("Failed to get XML: {}", e)),
+        };
+
+        if !xml_info.parse_errors.is_empty() && PARSE_WARNED.lock().map(|mut warned| warned.insert(uuid.clone())).unwrap_or(false) {
+            // Once per domain per session; the errors stay visible on the VM itself
+            warn!("Problems reading XML for VM {} ({}): {}", name, uuid, xml_info.parse_errors.join("; "));
+        }
+
+        let clipboard_issue = Self::clipboard_issue(&xml_info, &state);
+
+        let vm = VirtualMachine {
+            id: uuid.clone(),
+            name: name.clone(),
+            state,
+            memory: info.memory / 1024, // Use libvirt info for memory (more reliable)
+            vcpus: info.nr_virt_cpu,    // Use libvirt info for vCPUs
+            max_vcpus: xml_info.vcpus.max(info.nr_virt_cpu), // <vcpu> holds the maximum
+            disk_size: xml_info.disk_size_gb as u64,
+            os_type: xml_info.os_type.clone(),
+            os_variant: xml_info.os_variant.clone(),
+            created_at: self.extract_creation_time(&xml_info, &domain).await,
+            last_started: self.extract_last_started_time(&domain).await,
+            description: xml_info.description,
+            tags: xml_info.tags,
+            cpu_topology: xml_info.cpu_topology,
+            numa_nodes: xml_info.numa_nodes,
+            autostart: domain.get_autostart().unwrap_or(false),
+            has_saved_state: domain.has_managed_save(0).unwrap_or(false),
+            has_tpm: xml_info.tpm.is_some(),
+            shared_folders: xml_info.shared_folders,
+            sound_model: xml_info.sound_model,
+            clipboard_ready: clipboard_issue.is_none(),
+            clipboard_issue,
+            vnc_port: xml_info.vnc_port,
+            spice_port: xml_info.spice_port,
+            snapshots: self.load_vm_snapshots(&domain).await.unwrap_or_default(),
+            snapshot_capabilities: xml_info.storage_devices.iter().map(DiskSnapshotCapability::for_device).collect(),
+            network_interfaces: xml_info.network_interfaces,
+            storage_devices: xml_info.storage_devices,
+            parse_errors: xml_info.parse_errors,
+        };
+        
+        debug!("Successfully converted domain to VM: name={}, os_type={}, disks={}", 
+               name, vm.os_type, vm.storage_devices.len());
+        Ok(vm)
+    }
+
+    /// Parse a domain's XML, falling back to libvirt's own figures when it is
+    /// unusable. libvirt's identity always wins over whatever the XML yielded.
+    fn parse_domain_xml(xml: &str, name: &str, uuid: &str, memory_mb: u64, vcpus: u32) -> VmXmlInfo {
+        let mut xml_info = XmlParser::parse_vm_from_xml(xml).unwrap_or_else(|e| {
+            VmXmlInfo::fallback(name, uuid, memory_mb, vcpus, format!("Failed to parse XML: {}", e))
+        });
+        xml_info.name = name.to_string();
+        xml_info.uuid = uuid.to_string();
+        xml_info
+    }
+
+    fn clipboard_issue(xml_info: &VmXmlInfo, state: &VmState) -> Option<ClipboardIssue> {
+        if !xml_info.spice_graphics {
+            return Some(ClipboardIssue::NoSpiceDisplay);
+        }
+        match xml_info.spice_agent_state.as_deref() {
+            None => Some(ClipboardIssue::MissingChannel),
+            Some(_) if !matches!(state, VmState::Running) => Some(ClipboardIssue::NotRunning),
+            Some("connected") => None,
+            Some(_) => Some(ClipboardIssue::AgentNotResponding),
+        }
+    }
+
+    fn validate_cpu_topology(topology: &CpuTopology, vcpus: u32) -> Result<()> {
+        if topology.sockets == 0 || topology.cores == 0 || topology.threads == 0 {
+            return Err(KvmError::InvalidVmConfig("CPU topology values must be at least 1".to_string()));
+        }
+
+        if topology.vcpus() != vcpus {
+            return Err(KvmError::InvalidVmConfig(format!(
+                "CPU topology {} sockets x {} cores x {} threads gives {} vCPUs, but the VM has {}",
+                topology.sockets, topology.cores, topology.threads, topology.vcpus(), vcpus
+            )));
+        }
+
+        Ok(())
+    }
+
+    fn validate_numa_nodes(nodes: &[NumaNodeConfig], vcpus: u32, memory_mb: u64) -> Result<()> {
+        let mut assigned = HashSet::new();
+        for (id, node) in nodes.iter().enumerate() {
+            if node.cpus.is_empty() || node.memory_mb == 0 {
+                return Err(KvmError::InvalidVmConfig(format!("NUMA cell {} needs at least one vCPU and some memory", id)));
+            }
+            for &cpu in &node.cpus {
+                if cpu >= vcpus {
+                    return Err(KvmError::InvalidVmConfig(format!(
+                        "NUMA cell {} references vCPU {}, but the VM has {} vCPUs", id, cpu, vcpus
+                    )));
+                }
+                if !assigned.insert(cpu) {
+                    return Err(KvmError::InvalidVmConfig(format!("vCPU {} is in more than one NUMA cell", cpu)));
+                }
+            }
+        }
+
+        let cell_memory: u64 = nodes.iter().map(|node| node.memory_mb).sum();
+        if cell_memory != memory_mb {
+            return Err(KvmError::InvalidVmConfig(format!(
+                "NUMA cells add up to {} MB, but the VM has {} MB", cell_memory, memory_mb
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Names become part of disk image file names and virsh arguments, so they
+    /// must be a single path component that can't be read as an option
+    fn validate_vm_name(name: &str) -> Result<()> {
+        if name.is_empty() {
+            return Err(KvmError::InvalidVmConfig("VM name cannot be empty".to_string()));
+        }
+        let problem = tools::argument_problem(name).or_else(|| {
+            (name.contains('/') || name == "." || name == "..").then_some("must not be a path")
+        });
+        match problem {
+            Some(problem) => Err(KvmError::InvalidVmConfig(format!("VM name {:?} {}", name, problem))),
+            None => Ok(()),
+        }
+    }
+
+    fn validate_vm_config(&self, config: &VmConfig) -> Result<()> {
+        Self::validate_vm_name(&config.name)?;
+
+        // Unset values have no preset or default applied yet and fail below
+        let memory = config.memory.unwrap_or_default();
+        let vcpus = config.vcpus.unwrap_or_default();
+
+        if memory < MIN_MEMORY_MB {
+            return Err(KvmError::InvalidVmConfig(format!("Memory must be at least {} MB", MIN_MEMORY_MB)));
+        }
+
+        if vcpus == 0 {
+            return Err(KvmError::InvalidVmConfig("Must have at least 1 vCPU".to_string()));
+        }
+
+        if let Some(tpm) = &config.tpm {
+            if tpm.model == TpmModel::Crb && tpm.version != TpmVersion::V2_0 {
+                return Err(KvmError::InvalidVmConfig("The CRB TPM interface only supports TPM 2.0; use TIS for TPM 1.2".to_string()));
+            }
+            // libvirt starts swtpm itself, but only finds out it is missing at boot
+            tools::require(Tool::Swtpm)?;
+        }
+
+        if config.secure_boot {
+            if config.firmware != FirmwareType::Uefi {
+                return Err(KvmError::InvalidVmConfig("Secure Boot requires UEFI firmware".to_string()));
+            }
+            // SMM, which Secure Boot relies on, is only emulated on q35
+            if config.machine_type.as_deref().is_some_and(|machine| !machine.contains("q35")) {
+                return Err(KvmError::InvalidVmConfig("Secure Boot requires a q35 machine type".to_string()));
+            }
+        }
+
+        if let Some(watchdog) = &config.watchdog {
+            let machine_type = config.machine_type.clone().unwrap_or_else(|| self.default_machine_type());
+            match watchdog.model {
+                WatchdogModel::Itco if !machine_type.contains("q35") => {
+                    return Err(KvmError::InvalidVmConfig("The iTCO watchdog is only available on q35 machines".to_string()));
+                }
+                WatchdogModel::Ib700 if machine_type.contains("q35") => {
+                    return Err(KvmError::InvalidVmConfig("The ib700 watchdog needs an ISA bus, which q35 machines lack".to_string()));
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(topology) = &config.cpu_topology {
+            Self::validate_cpu_topology(topology, vcpus)?;
+        }
+
+        if !config.numa_nodes.is_empty() {
+            Self::validate_numa_nodes(&config.numa_nodes, vcpus, memory)?;
+        }
+
+        if let (Some(machine_type), Ok(machines)) = (&config.machine_type, self.machine_types()) {
+            if !machines.iter().any(|machine| &machine.name == machine_type) {
+                return Err(KvmError::InvalidVmConfig(format!(
+                    "Machine type {} is not supported by this host's QEMU", machine_type
+                )));
+            }
+        }
+
+        if let Some(backing) = config.memory_backing.filter(|backing| backing.hugepages) {
+            host_devices::check_hugepages(memory, backing.page_size_kib)?;
+        }
+
+        for folder in &config.shared_folders {
+            Self::validate_shared_folder(folder)?;
+        }
+
+        let display = &config.display_config;
+        if !display.autoport && display.port.is_none() {
+            return Err(KvmError::InvalidVmConfig("Set a display port or turn on autoport".to_string()));
+        }
+        if let Some(listen) = display.listen.as_deref().filter(|listen| !listen.is_empty()) {
+            if listen.parse::<std::net::IpAddr>().is_err() {
+                return Err(KvmError::InvalidVmConfig(format!("Invalid display listen address {}", listen)));
+            }
+        }
+
+        Self::sound_model(config.display_config.sound_model.as_deref())?;
+        let mut tags: Vec<&str> = config.shared_folders.iter().map(|folder| folder.guest_tag.as_str()).collect();
+        tags.sort_unstable();
+        if tags.windows(2).any(|pair| pair[0] == pair[1]) {
+            return Err(KvmError::InvalidVmConfig("Shared folder tags must be unique".to_string()));
+        }
+
+        if config.disk_size.unwrap_or_default() < 1 {
+            return Err(KvmError::InvalidVmConfig("Disk size must be at least 1 GB".to_string()));
+        }
+
+        // Direct kernel boot needs a kernel, and the referenced files must exist
+        if config.boot_config.kernel.is_none()
+            && (config.boot_config.initrd.is_some() || config.boot_config.cmdline.is_some())
+        {
+            return Err(KvmError::InvalidVmConfig("initrd and cmdline require a kernel to be set".to_string()));
+        }
+
+        for (label, path) in [
+            ("Kernel", &config.boot_config.kernel),
+            ("Initrd", &config.boot_config.initrd),
+            ("ISO", &config.boot_config.iso_path),
+        ] {
+            if let Some(path) = path {
+                if !std::path::Path::new(path).is_file() {
+                    return Err(KvmError::InvalidVmConfig(format!("{} image not found: {}", label, path)));
+                }
+            }
+        }
+
+        let boot_order = config.boot_config.boot_order.as_deref().unwrap_or_default();
+        if let Some(device) = boot_order.iter().find(|device| !BOOT_DEVICES.contains(&device.as_str())) {
+            return Err(KvmError::InvalidVmConfig(format!(
+                "Unknown boot device {}; use {}", device, BOOT_DEVICES.join(", ")
+            )));
+        }
+        if boot_order.iter().enumerate().any(|(i, device)| boot_order[..i].contains(device)) {
+            return Err(KvmError::InvalidVmConfig("Boot order lists a device more than once".to_string()));
+        }
+
+        Ok(())
+    }
+
+    /// Domain XML for a new VM; `machine_type` is the resolved `config.machine_type`
+    pub(crate) fn generate_vm_xml(config: &VmConfig, vm_id: &str, machine_type: &str) -> Result<String> {
+        // Firmware and direct kernel boot entries for <os>, emitted only when configured
+        let ovmf = match config.firmware {
+            FirmwareType::Uefi => Some(firmware::detect_ovmf(config.secure_boot)?),
+            FirmwareType::Bios => None,
+        };
+        let mut os_entries = ovmf.as_ref()
+            .map(|ovmf| firmware::os_firmware_xml(ovmf, &config.name))
+            .unwrap_or_default();
+        if let Some(kernel) = &config.boot_config.kernel {
+            os_entries.push_str(&format!("    <kernel>{}</kernel>\n", XmlParser::escape(kernel)));
+        }
+        if let Some(initrd) = &config.boot_config.initrd {
+            os_entries.push_str(&format!("    <initrd>{}</initrd>\n", XmlParser::escape(initrd)));
+        }
+        if let Some(cmdline) = &config.boot_config.cmdline {
+            os_entries.push_str(&format!("    <cmdline>{}</cmdline>\n", XmlParser::escape(cmdline)));
+        }
+
+        let boot_order = config.boot_config.boot_order.as_deref().unwrap_or_default();
+        let boot_entries: String = if boot_order.is_empty() {
+            "    <boot dev='hd'/>\n    <boot dev='cdrom'/>\n".to_string()
+        } else {
+            boot_order.iter()
+                .map(|device| format!("    <boot dev='{}'/>\n", XmlParser::escape(device)))
+                .collect()
+        };
+
+        let install_cdrom = config.boot_config.iso_path.as_ref()
+            .map(|iso| format!(
+                "    <disk type='file' device='cdrom'>\n      <driver name='qemu' type='raw'/>\n      <source file='{}'/>\n      <target dev='sda' bus='sata'/>\n      <readonly/>\n    </disk>\n",
+                XmlParser::escape(iso)
+            ))
+            .unwrap_or_default();
+
+        let cpu_xml = XmlParser::cpu_xml(&config.cpu_model, config.cpu_topology.as_ref(), &config.numa_nodes);
+
+        let needs_shared_memory = config.shared_folders.iter()
+            .any(|folder| folder.driver == SharedFolderDriver::Virtiofs);
+        let memory_backing = XmlParser::memory_backing_xml(config.memory_backing.as_ref(), needs_shared_memory)
+            .map(|xml| format!("  {}\n", xml))
+            .unwrap_or_default();
+
+        let sound = Self::sound_model(config.display_config.sound_model.as_deref())?
+            .map(|model| {
+                let xml = XmlParser::sound_xml(model, config.display_config.graphics_type.as_deref() == Some("spice"));
+                format!("    {}\n", xml.replace('\n', "\n    "))
+            })
+            .unwrap_or_default();
+
+        let graphics = format!("    {}\n", XmlParser::graphics_xml(&config.display_config).replace('\n', "\n    "));
+
+        let shared_folders: String = config.shared_folders.iter()
+            .map(|folder| format!("    {}\n", XmlParser::shared_folder_xml(folder).replace('\n', "\n    ")))
+            .collect();
+
+        let tpm = config.tpm.as_ref()
+            .map(|tpm| format!("    {}\n", XmlParser::tpm_xml(tpm).replace('\n', "\n    ")))
+            .unwrap_or_default();
+
+        let watchdog = config.watchdog.as_ref()
+            .map(|watchdog| format!("    {}\n", XmlParser::watchdog_xml(watchdog)))
+            .unwrap_or_default();
+
+        let memory = config.memory.unwrap_or_default();
+        let storage = &config.storage_config;
+        let format = storage.format.as_deref().unwrap_or_default();
+
+        let disk_encryption = config.storage_config.encryption.as_ref()
+            .map(|spec| format!("\n      {}", secrets::encryption_xml(spec).replace('\n', "\n      ")))
+            .unwrap_or_default();
+
+        let xml = format!(
+            r#"<domain type='kvm'>
+  <name>{}</name>
+  <uuid>{}</uuid>
+  <memory unit='MiB'>{}</memory>
+  <currentMemory unit='MiB'>{}</currentMemory>
+{}  <vcpu placement='static'>{}</vcpu>
+  <os>
+    <type arch='x86_64' machine='{}'>hvm</type>
+{}{}  </os>
+  <features>
+    <acpi/>
+    <apic/>
+    <vmport state='off'/>
+{}  </features>
+  {}
+  <clock offset='utc'>
+    <timer name='rtc' tickpolicy='catchup'/>
+    <timer name='pit' tickpolicy='delay'/>
+    <timer name='hpet' present='no'/>
+  </clock>
+  <on_poweroff>destroy</on_poweroff>
+  <on_reboot>restart</on_reboot>
+  <on_crash>destroy</on_crash>
+  <pm>
+    <suspend-to-mem enabled='no'/>
+    <suspend-to-disk enabled='no'/>
+  </pm>
+  <devices>
+    <emulator>/usr/bin/qemu-system-x86_64</emulator>
+    <disk type='file' device='disk'>
+      <driver name='qemu' type='{}' cache='{}'/>
+      <source file='/var/lib/libvirt/images/{}.{}'/>
+      <target dev='vda' bus='{}'/>{}
+      <address type='pci' domain='0x0000' bus='0x03' slot='0x00' function='0x0'/>
+    </disk>
+{}    <controller type='usb' index='0' model='qemu-xhci'>
+      <address type='pci' domain='0x0000' bus='0x02' slot='0x00' function='0x0'/>
+    </controller>
+    <interface type='network'>
+      <source network='{}'/>
+      <model type='{}'/>
+      <address type='pci' domain='0x0000' bus='0x01' slot='0x00' function='0x0'/>
+    </interface>
+{}    <video>
+      <model type='qxl' ram='65536' vram='65536' vgamem='16384' heads='1' primary='yes'/>
+      <address type='pci' domain='0x0000' bus='0x00' slot='0x02' function='0x0'/>
+    </video>
+{}{}{}{}    <memballoon model='virtio'>
+      <address type='pci' domain='0x0000' bus='0x04' slot='0x00' function='0x0'/>
+    </memballoon>
+  </devices>
+</domain>"#,
+            config.name,
+            vm_id,
+            memory,
+            memory,
+            memory_backing,
+            config.vcpus.unwrap_or_default(),
+            XmlParser::escape(machine_type),
+            os_entries,
+            boot_entries,
+            firmware::features_xml(ovmf.as_ref()),
+            cpu_xml,
+            format,
+            storage.cache.as_deref().unwrap_or_default(),
+            config.name,
+            format,
+            storage.bus.as_deref().unwrap_or_default(),
+            disk_encryption,
+            install_cdrom,
+            config.network_config.network_name.as_ref().unwrap_or(&"default".to_string()),
+            config.network_config.model.as_deref().unwrap_or_default(),
+            graphics,
+            sound,
+            shared_folders,
+            tpm,
+            watchdog,
+        );
+
+        Ok(xml)
+    }
+
+    async fn create_vm_storage(&self, _config: &VmConfig, vm_id: &str) -> Result<()> {
+        // This would create the disk image file
+        // For now, we'll assume it's handled by libvirt
+        info!("Storage creation for VM {} handled by libvirt", vm_id);
+        Ok(())
+    }
+
+    fn parse_os_info_from_xml(&self, xml: &str) -> Result<(String, Option<String>)> {
+        debug!("Parsing OS info from XML: {} chars", xml.len());
+        
+        // Try to parse OS info from XML, with fallback to default
+        if xml.contains("<os>") {
+            if xml.contains("linux") || xml.contains("debian") || xml.contains("ubuntu") || xml.contains("fedora") || xml.contains("centos") || xml.contains("rhel") {
+                debug!("Detected Linux OS type from XML");
+                return Ok(("linux".to_string(), Some("generic".to_string())));
+            } else if xml.contains("windows") || xml.contains("win") {
+                debug!("Detected Windows OS type from XML");
+                return Ok(("windows".to_string(), Some("win10".to_string())));
+            } else if xml.contains("freebsd") || xml.contains("openbsd") || xml.contains("netbsd") {
+                debug!("Detected BSD OS type from XML");
+                return Ok(("bsd".to_string(), Some("generic".to_string())));
+            }
+        }
+        
+        // Check for common OS indicators in the full XML
+        let xml_lower = xml.to_lowercase();
+        if xml_lower.contains("debian") || xml_lower.contains("ubuntu") || xml_lower.contains("fedora") || xml_lower.contains("centos") {
+            debug!("Detected Linux from XML content analysis");
+            return Ok(("linux".to_string(), Some("generic".to_string())));
+        } else if xml_lower.contains("windows") || xml_lower.contains("win10") || xml_lower.contains("win11") {
+            debug!("Detected Windows from XML content analysis");
+            return Ok(("windows".to_string(), Some("win10".to_string())));
+        }
+        
+        // Default fallback
+        debug!("Using default OS type (Linux)");
+        Ok(("linux".to_string(), Some("generic".to_string())))
+    }
+    
+    pub async fn import_vm_from_xml(&mut self, xml_path: &str) -> Result<String> {
+        info!("Importing VM from XML: {}", xml_path);
+        
+        let xml_content = std::fs::read_to_string(xml_path)
+            .map_err(|e| KvmError::VmOperationFailed(format!("Failed to read XML file: {}", e)))?;
+        
+        // Define the domain from XML
+        let domain = Domain::define_xml(&self.connection, &xml_content)
+            .map_err(|e| {
+                error!("Failed to define VM from XML {}: {}", xml_path, e);
+                KvmError::VmOperationFailed(format!("Failed to import VM: {}", e))
+            })?;
+        
+        let name = domain.get_name().map_err(KvmError::LibvirtConnection)?;
+        let uuid = domain.get_uuid_string().map_err(KvmError::LibvirtConnection)?;
+        
+        info!("Successfully imported VM {} with UUID {}", name, uuid);
+        
+        // Refresh cache
+        self.refresh_vm_cache().await?;
+        
+        Ok(uuid)
+    }
+    
+    /// Import an OVA appliance: unpack it, convert every disk the OVF references
+    /// to qcow2 in `target_pool`, and define a domain from the OVF hardware.
+    /// Returns the new VM's UUID.
+    pub async fn import_ova(&mut self, ova_path: &str, target_pool: &str) -> Result<String> {
+        info!("Importing OVA {} into pool {}", ova_path, target_pool);
+
+        if !std::path::Path::new(ova_path).is_file() {
+            return Err(KvmError::VmOperationFailed(format!("OVA file not found: {}", ova_path)));
+        }
+
+        let pool_name = recovery::ensure_storage_pool(&self.connection, target_pool, &RecoveryOptions::default())?;
+        let pool = virt::storage_pool::StoragePool::lookup_by_name(&self.connection, &pool_name)
+            .map_err(|e| KvmError::StorageOperationFailed(format!("Storage pool not found: {}", e)))?;
+        let pool_xml = pool.get_xml_desc(0).map_err(KvmError::LibvirtConnection)?;
+        let pool_info = XmlParser::parse_storage_pool_from_xml(&pool_xml)?;
+        let pool_dir = match (pool_info.pool_type.as_str(), &pool_info.path) {
+            ("dir" | "fs" | "netfs", Some(path)) => std::path::PathBuf::from(path),
+            _ => return Err(KvmError::StorageOperationFailed(format!(
+                "Pool {} is not file based; OVA disks need a dir, fs or netfs pool", pool_name
+            ))),
+        };
+
+        let work_dir = std::env::temp_dir().join(format!("kvm-manager-ova-{}", Uuid::new_v4()));
+        let result = self.import_ova_from(std::path::Path::new(ova_path), &work_dir, &pool_dir).await;
+        let _ = std::fs::remove_dir_all(&work_dir);
+
+        if let Err(e) = pool.refresh(0) {
+            warn!("Failed to refresh pool {} after OVA import: {}", pool_name, e);
+        }
+        let vm_uuid = result?;
+        self.refresh_vm_cache().await?;
+        Ok(vm_uuid)
+    }
+
+    async fn import_ova_from(&self, ova_path: &std::path::Path, work_dir: &std::path::Path, pool_dir: &std::path::Path) -> Result<String> {
+        let descriptor_path = ova::extract(ova_path, work_dir)?;
+        let descriptor = ova::parse_descriptor(&std::fs::read_to_string(&descriptor_path)?)?;
+        let name = descriptor.name.clone();
+        Self::validate_vm_name(&name)?;
+
+        if Domain::lookup_by_name(&self.connection, &name).is_ok() {
+            return Err(KvmError::VmOperationFailed(format!("A VM named {} already exists", name)));
+        }
+        if descriptor.memory_mb < MIN_MEMORY_MB {
+            return Err(KvmError::InvalidVmConfig(format!(
+                "OVF asks for {} MB of memory; at least {} MB is needed", descriptor.memory_mb, MIN_MEMORY_MB
+            )));
+        }
+        let disk_bytes: u64 = descriptor.disks.iter().filter_map(|disk| disk.capacity_bytes).sum();
+        quotas::check(&self.connection, &QuotaRequest {
+            vcpus: descriptor.vcpus,
+            memory_mb: descriptor.memory_mb,
+            disk_gb: disk_bytes.div_ceil(1024 * 1024 * 1024),
+            tags: Vec::new(),
+        })?;
+        if descriptor.disks.len() > 26 {
+            return Err(KvmError::InvalidVmConfig(format!("OVF has {} disks; at most 26 are supported", descriptor.disks.len())));
+        }
+        let ovmf = if descriptor.uefi { Some(firmware::detect_ovmf(false)?) } else { None };
+
+        let mut images: Vec<String> = Vec::new();
+        let converted = (|| {
+            for (index, disk) in descriptor.disks.iter().enumerate() {
+                let image = pool_dir.join(format!("{}-disk{}.qcow2", name, index));
+                if image.exists() {
+                    return Err(KvmError::StorageOperationFailed(format!("{} already exists", image.display())));
+                }
+                ova::convert_disk(&work_dir.join(&disk.file), &image)?;
+                images.push(image.to_string_lossy().into_owned());
+            }
+            Ok(())
+        })();
+        let remove_images = |images: &[String]| {
+            for image in images {
+                let _ = std::fs::remove_file(image);
+            }
+        };
+        if let Err(e) = converted {
+            remove_images(&images);
+            return Err(e);
+        }
+
+        let vm_uuid = Uuid::new_v4().to_string();
+        let mut xml = Self::generate_qcow2_vm_xml(
+            &name,
+            &vm_uuid,
+            &images[0],
+            descriptor.memory_mb,
+            descriptor.vcpus,
+            &self.default_machine_type(),
+            None,
+            None,
+            None,
+            ovmf.as_ref()
+        )?;
+
+        // The template has the first disk and NIC; add the rest before </devices>
+        let mut extra_devices = String::new();
+        for (index, image) in images.iter().enumerate().skip(1) {
+            extra_devices.push_str(&format!(
+                "    <disk type='file' device='disk'>\n      <driver name='qemu' type='qcow2'/>\n      <source file='{}'/>\n      <target dev='vd{}' bus='virtio'/>\n    </disk>\n",
+                XmlParser::escape(image),
+                (b'a' + index as u8) as char
+            ));
+        }
+        for _ in 1..descriptor.nics {
+            extra_devices.push_str(&format!(
+                "    <interface type='network'>\n      <mac address='{}'/>\n      <source network='default'/>\n      <model type='virtio'/>\n    </interface>\n",
+                XmlParser::random_mac()
+            ));
+        }
+        if let Some(position) = xml.rfind("</devices>") {
+            xml.insert_str(position, &extra_devices);
+        }
+
+        if let Err(e) = Domain::define_xml(&self.connection, &xml) {
+            error!("Failed to define VM {} from OVA: {}", name, e);
+            remove_images(&images);
+            return Err(KvmError::VmOperationFailed(format!("Failed to define imported VM: {}", e)));
+        }
+
+        info!("Imported OVA {} as VM {} ({}) with {} disks", ova_path.display(), name, vm_uuid, images.len());
+        Ok(vm_uuid)
+    }
+
+    /// Refresh the storage pool whose directory holds `path` so libvirt lists the
+    /// file as a volume. Returns the pool name, or None if no pool covers it.
+    pub async fn refresh_pool_containing(&self, path: &str) -> Result<Option<String>> {
+        let directory = match std::path::Path::new(path).parent() {
+            Some(directory) => directory,
+            None => return Ok(None),
+        };
+
+        let pools = self.connection.list_all_storage_pools(sys::VIR_CONNECT_LIST_STORAGE_POOLS_ACTIVE)
+            .map_err(KvmError::LibvirtConnection)?;
+
+        for pool in pools {
+            let pool_xml = pool.get_xml_desc(0).map_err(KvmError::LibvirtConnection)?;
+            let pool_info = XmlParser::parse_storage_pool_from_xml(&pool_xml)?;
+            if pool_info.path.as_deref().map(std::path::Path::new) != Some(directory) {
+                continue;
+            }
+
+            pool.refresh(0)
+                .map_err(|e| KvmError::StorageOperationFailed(format!("Failed to refresh pool {}: {}", pool_info.name, e)))?;
+            info!("Refreshed pool {} to register {}", pool_info.name, path);
+            return Ok(Some(pool_info.name));
+        }
+
+        Ok(None)
+    }
+
+    /// Import a domain XML found by the import watcher, refusing name or UUID clashes
+    pub async fn import_detected_xml(&mut self, xml_path: &str) -> Result<String> {
+        let xml_content = std::fs::read_to_string(xml_path)?;
+        let xml_info = XmlParser::parse_vm_from_xml(&xml_content)?;
+
+        if Domain::lookup_by_name(&self.connection, &xml_info.name).is_ok() {
+            return Err(KvmError::InvalidVmConfig(format!("A VM named '{}' already exists", xml_info.name)));
+        }
+        if Domain::lookup_by_uuid_string(&self.connection, &xml_info.uuid).is_ok() {
+            return Err(KvmError::InvalidVmConfig(format!("A VM with UUID {} already exists", xml_info.uuid)));
+        }
+
+        self.import_vm_from_xml(xml_path).await
+    }
+
+    /// Clone a VM under a new name with a new UUID and MAC addresses.
+    ///
+    /// With `clone_storage` every file-backed disk is copied next to its source;
+    /// without it the clone is defined without those disks. Disks that can't be
+    /// copied (block devices, network disks) are always left out and reported in
+    /// `warnings`. Copies made before a failure are deleted again.
+    ///
+    /// A running VM is only cloned with `force`; its disk copies are then merely
+    /// crash-consistent.
+    #[instrument(skip(self), fields(operation = "clone_vm"))]
+    pub async fn clone_vm(&mut self, vm_id: &str, new_name: &str, clone_storage: bool, force: bool) -> Result<CloneResult> {
+        let mode = if clone_storage { CloneMode::Full } else { CloneMode::ConfigOnly };
+        self.clone_domain(vm_id, new_name, mode, force).await
+    }
+
+    /// Clone a VM onto thin qcow2 overlays backed by the source's disks.
+    ///
+    /// The source must stay shut off and unchanged for as long as the clone
+    /// exists, so running sources and raw disks are refused outright.
+    #[instrument(skip(self), fields(operation = "clone_vm_linked"))]
+    pub async fn clone_vm_linked(&mut self, vm_id: &str, new_name: &str) -> Result<CloneResult> {
+        let domain = self.get_domain_by_id(vm_id)?;
+        if domain.is_active().map_err(KvmError::LibvirtConnection)? {
+            return Err(KvmError::VmOperationFailed(
+                "Shut the VM down before creating a linked clone; its disks become read-only backing images".to_string()
+            ));
+        }
+
+        let xml = domain.get_xml_desc(sys::VIR_DOMAIN_XML_INACTIVE)
+            .map_err(KvmError::LibvirtConnection)?;
+        let raw_disks: Vec<String> = XmlParser::disk_elements(&xml)
+            .into_iter()
+            .filter(|disk| disk.device == "disk" && disk.disk_type == "file" && disk.format.as_deref() != Some("qcow2"))
+            .map(|disk| disk.target)
+            .collect();
+        if !raw_disks.is_empty() {
+            return Err(KvmError::InvalidVmConfig(format!(
+                "Linked clones need qcow2 disks; {} {} not qcow2",
+                raw_disks.join(", "),
+                if raw_disks.len() == 1 { "is" } else { "are" }
+            )));
+        }
+
+        self.clone_domain(vm_id, new_name, CloneMode::Linked, false).await
+    }
+
+    async fn clone_domain(&mut self, vm_id: &str, new_name: &str, mode: CloneMode, force: bool) -> Result<CloneResult> {
+        info!("Cloning VM {} as {} ({:?})", vm_id, new_name, mode);
+
+        Self::validate_vm_name(new_name)?;
+        if new_name.trim().is_empty() {
+            return Err(KvmError::InvalidVmConfig("VM name cannot be empty".to_string()));
+        }
+        if Domain::lookup_by_name(&self.connection, new_name).is_ok() {
+            return Err(KvmError::InvalidVmConfig(format!("A VM named '{}' already exists", new_name)));
+        }
+
+        let domain = self.get_domain_by_id(vm_id)?;
+        if domain.is_active().map_err(KvmError::LibvirtConnection)? {
+            if !force {
+                return Err(KvmError::VmOperationFailed(
+                    "Shut the VM down before cloning it, or force the clone; a running guest's disks aren't consistent".to_string()
+                ));
+            }
+            warn!("Cloning running VM {}; disk copies are only crash-consistent", vm_id);
+        }
+
+        let source_xml = domain.get_xml_desc(sys::VIR_DOMAIN_XML_INACTIVE | sys::VIR_DOMAIN_XML_SECURE)
+            .map_err(KvmError::LibvirtConnection)?;
+        let source_info = XmlParser::parse_vm_from_xml(&source_xml)?;
+
+        quotas::check(&self.connection, &QuotaRequest {
+            vcpus: source_info.vcpus,
+            memory_mb: source_info.memory_mb,
+            disk_gb: if mode == CloneMode::ConfigOnly { 0 } else { source_info.disk_size_gb.ceil() as u64 },
+            tags: source_info.tags.clone(),
+        })?;
+
+        let new_uuid = Uuid::new_v4().to_string();
+        let mut xml = XmlParser::set_domain_name(&source_xml, new_name);
+        xml = XmlParser::set_domain_uuid(&xml, &new_uuid);
+        xml = XmlParser::regenerate_macs(&xml);
+        // The source's UEFI variable store must not be shared; libvirt creates a fresh one
+        xml = XmlParser::upsert_element(&xml, "nvram", None, &[]);
+        if mode == CloneMode::Linked {
+            let linked = format!(
+                "<{} xmlns:kvmmanager='{}' source='{}'/>",
+                LINKED_CLONE_ELEMENT, METADATA_NAMESPACE, source_info.uuid
+            );
+            xml = XmlParser::upsert_metadata_element(&xml, LINKED_CLONE_ELEMENT, Some(&linked));
+        }
+
+        let storage = self.storage_manager();
+        let mut cloned_disks: Vec<ClonedDisk> = Vec::new();
+        let mut warnings = Vec::new();
+
+        let copied = (|| -> Result<()> {
+            for disk in XmlParser::disk_elements(&source_xml) {
+                if disk.device == "cdrom" || disk.device == "floppy" {
+                    continue; // Install media can be shared
+                }
+
+                let source = match (&disk.file, disk.disk_type.as_str()) {
+                    (Some(file), "file") if mode != CloneMode::ConfigOnly => file.clone(),
+                    (Some(file), "file") => {
+                        warnings.push(format!("{} ({}) was not cloned; add storage to the clone manually", disk.target, file));
+                        xml = xml.replacen(&disk.xml, "", 1);
+                        continue;
+                    }
+                    _ => {
+                        warn!("Leaving {} disk {} out of clone {}", disk.disk_type, disk.target, new_name);
+                        warnings.push(format!("{} is a {} disk and cannot be copied; it was left out of the clone", disk.target, disk.disk_type));
+                        xml = xml.replacen(&disk.xml, "", 1);
+                        continue;
+                    }
+                };
+
+                let format = disk.format.clone().unwrap_or_else(|| "raw".to_string());
+                let path = if mode == CloneMode::Linked {
+                    storage.create_overlay(&source, &format!("{}-{}.qcow2", new_name, disk.target))?
+                } else {
+                    let extension = std::path::Path::new(&source)
+                        .extension()
+                        .map(|extension| extension.to_string_lossy().into_owned())
+                        .unwrap_or_else(|| format.clone());
+                    storage.copy_image(&source, &format!("{}-{}.{}", new_name, disk.target, extension), &format)?
+                };
+                cloned_disks.push(ClonedDisk { device: disk.target.clone(), source: source.clone(), path: path.clone() });
+
+                let new_disk = disk.xml.replacen(&XmlParser::escape(&source), &XmlParser::escape(&path), 1);
+                xml = xml.replacen(&disk.xml, &new_disk, 1);
+            }
+            Ok(())
+        })();
+
+        let defined = copied.and_then(|_| {
+            Domain::define_xml(&self.connection, &xml)
+                .map_err(|e| {
+                    error!("Failed to define clone {}: {}", new_name, e);
+                    KvmError::VmOperationFailed(format!("Failed to define clone: {}", e))
+                })
+        });
+
+        if let Err(e) = defined {
+            for disk in &cloned_disks {
+                if let Err(cleanup) = storage.remove_image(&disk.path) {
+                    error!("Failed to remove partial clone disk {}: {}", disk.path, cleanup);
+                }
+            }
+            return Err(e);
+        }
+
+        let detail = match mode {
+            CloneMode::Linked => format!("Linked clone {} ({})", new_name, new_uuid),
+            _ => format!("Cloned as {} ({})", new_name, new_uuid),
+        };
+        audit::record("vm_cloned", vm_id, &detail);
+        info!("Successfully cloned VM {} as {} with ID {}", vm_id, new_name, new_uuid);
+        self.refresh_vm_cache().await?;
+
+        Ok(CloneResult {
+            vm_id: new_uuid,
+            name: new_name.to_string(),
+            cloned_disks,
+            warnings,
+        })
+    }
+
+    /// Start migrating a VM to another libvirt host and return the task id to
+    /// poll with `get_migration_status`.
+    ///
+    /// Running VMs are migrated live (or paused for the copy when `live` is
+    /// false); stopped VMs only have their definition moved. Disks are not
+    /// copied, so the target must see the same storage at the same paths.
+    #[instrument(skip(self), fields(operation = "migrate_vm"))]
+    pub async fn migrate_vm(&mut self, vm_id: &str, target_uri: &str, live: bool) -> Result<String> {
+        info!("Migrating VM {} to {} (live: {})", vm_id, target_uri, live);
+
+        let domain = self.get_domain_by_id(vm_id)?;
+        let target = Connect::open(Some(target_uri))
+            .map_err(|e| KvmError::MigrationFailed(format!("Cannot connect to {}: {}", target_uri, e)))?;
+
+        let active = domain.is_active().map_err(KvmError::LibvirtConnection)?;
+        let mut flags = sys::VIR_MIGRATE_PERSIST_DEST | sys::VIR_MIGRATE_UNDEFINE_SOURCE;
+        if !active {
+            flags |= sys::VIR_MIGRATE_OFFLINE;
+        } else if live {
+            flags |= sys::VIR_MIGRATE_LIVE;
+        }
+
+        let task_id = Uuid::new_v4().to_string();
+        let task = MigrationTask {
+            id: task_id.clone(),
+            vm_id: vm_id.to_string(),
+            source_host: self.connection.get_hostname().unwrap_or_default(),
+            target_host: target_uri.to_string(),
+            state: MigrationState::Preparing,
+            progress: 0.0,
+            started_at: Utc::now(),
+            completed_at: None,
+            error_message: None,
+        };
+        if let Ok(mut migrations) = self.migrations.lock() {
+            migrations.insert(task_id.clone(), task);
+        }
+
+        let migrations = self.migrations.clone();
+        let id = task_id.clone();
+        let vm = vm_id.to_string();
+        tokio::task::spawn_blocking(move || {
+            let update = |change: &dyn Fn(&mut MigrationTask)| {
+                if let Ok(mut migrations) = migrations.lock() {
+                    if let Some(task) = migrations.get_mut(&id) {
+                        change(task);
+                    }
+                }
+            };
+            update(&|task| task.state = MigrationState::Migrating);
+
+            // Poll libvirt's job info for progress while the migration call blocks
+            let poller = active.then(|| {
+                let domain = domain.clone();
+                let migrations = migrations.clone();
+                let id = id.clone();
+                std::thread::spawn(move || loop {
+                    std::thread::sleep(std::time::Duration::from_secs(1));
+                    let still_running = migrations.lock()
+                        .map(|migrations| matches!(migrations.get(&id).map(|t| &t.state), Some(MigrationState::Migrating)))
+                        .unwrap_or(false);
+                    if !still_running {
+                        break;
+                    }
+                    if let Ok(job) = domain.get_job_info() {
+                        if job.data_total > 0 {
+                            let progress = job.data_processed as f64 / job.data_total as f64 * 100.0;
+                            if let Ok(mut migrations) = migrations.lock() {
+                                if let Some(task) = migrations.get_mut(&id) {
+                                    task.progress = progress.min(99.0);
+                                }
+                            }
+                        }
+                    }
+                })
+            });
+
+            let result = domain.migrate(&target, flags, None, None, 0);
+
+            match result {
+                Ok(_) => {
+                    info!("Migration of VM {} to {} completed", vm, target.get_uri().unwrap_or_default());
+                    update(&|task| {
+                        task.state = MigrationState::Completed;
+                        task.progress = 100.0;
+                        task.completed_at = Some(Utc::now());
+                    });
+                }
+                Err(e) => {
+                    let message = Self::migration_error_message(&e);
+                    error!("Migration of VM {} failed: {}", vm, message);
+                    update(&|task| {
+                        task.state = MigrationState::Failed;
+                        task.completed_at = Some(Utc::now());
+                        task.error_message = Some(message.clone());
+                    });
+                }
+            }
+
+            if let Some(poller) = poller {
+                let _ = poller.join();
+            }
+        });
 
-        if config.vcpus == 0 {
-            return Err(KvmError::InvalidVmConfig("Must have at least 1 vCPU".to_string()));
-        }
+        audit::record("vm_migration_started", vm_id, &format!("Migrating to {}", target_uri));
+        Ok(task_id)
+    }
 
-        if config.disk_size < 1 {
-            return Err(KvmError::InvalidVmConfig("Disk size must be at least 1 GB".to_string()));
+    /// libvirt's message, plus what usually causes it when the target can't see the disks
+    fn migration_error_message(error: &virt::error::Error) -> String {
+        let message = error.to_string();
+        let lower = message.to_lowercase();
+        if lower.contains("no such file") || lower.contains("cannot access storage") || lower.contains("unable to access") {
+            format!(
+                "{} (the target host cannot reach the VM's disks; mount the shared storage at the same path there)",
+                message
+            )
+        } else {
+            message
         }
+    }
 
-        Ok(())
+    pub fn get_migration_status(&self, task_id: &str) -> Result<MigrationTask> {
+        self.migrations.lock()
+            .ok()
+            .and_then(|migrations| migrations.get(task_id).cloned())
+            .ok_or_else(|| KvmError::MigrationFailed(format!("Unknown migration task: {}", task_id)))
     }
 
-    fn generate_vm_xml(&self, config: &VmConfig, vm_id: &str) -> Result<String> {
-        let xml = format!(
-            r#"<domain type='kvm'>
-  <name>{}</name>
-  <uuid>{}</uuid>
-  <memory unit='MiB'>{}</memory>
-  <currentMemory unit='MiB'>{}</currentMemory>
-  <vcpu placement='static'>{}</vcpu>
-  <os>
-    <type arch='x86_64' machine='pc-q35-6.2'>hvm</type>
-    <boot dev='hd'/>
-    <boot dev='cdrom'/>
-  </os>
-  <features>
-    <acpi/>
-    <apic/>
-    <vmport state='off'/>
-  </features>
-  <cpu mode='host-model' check='partial'/>
-  <clock offset='utc'>
-    <timer name='rtc' tickpolicy='catchup'/>
-    <timer name='pit' tickpolicy='delay'/>
-    <timer name='hpet' present='no'/>
-  </clock>
-  <on_poweroff>destroy</on_poweroff>
-  <on_reboot>restart</on_reboot>
-  <on_crash>destroy</on_crash>
-  <pm>
-    <suspend-to-mem enabled='no'/>
-    <suspend-to-disk enabled='no'/>
-  </pm>
-  <devices>
-    <emulator>/usr/bin/qemu-system-x86_64</emulator>
-    <disk type='file' device='disk'>
-      <driver name='qemu' type='{}' cache='{}'/>
-      <source file='/var/lib/libvirt/images/{}.{}'/>
-      <target dev='vda' bus='{}'/>
-      <address type='pci' domain='0x0000' bus='0x03' slot='0x00' function='0x0'/>
-    </disk>
-    <controller type='usb' index='0' model='qemu-xhci'>
-      <address type='pci' domain='0x0000' bus='0x02' slot='0x00' function='0x0'/>
-    </controller>
-    <interface type='network'>
-      <source network='{}'/>
-      <model type='{}'/>
-      <address type='pci' domain='0x0000' bus='0x01' slot='0x00' function='0x0'/>
-    </interface>
-    <graphics type='{}' port='-1' autoport='yes' listen='127.0.0.1'>
-      <listen type='address' address='127.0.0.1'/>
-    </graphics>
-    <video>
-      <model type='qxl' ram='65536' vram='65536' vgamem='16384' heads='1' primary='yes'/>
-      <address type='pci' domain='0x0000' bus='0x00' slot='0x02' function='0x0'/>
-    </video>
-    <memballoon model='virtio'>
-      <address type='pci' domain='0x0000' bus='0x04' slot='0x00' function='0x0'/>
-    </memballoon>
-  </devices>
-</domain>"#,
-            config.name,
-            vm_id,
-            config.memory,
-            config.memory,
-            config.vcpus,
-            config.storage_config.format,
-            config.storage_config.cache,
-            config.name,
-            config.storage_config.format,
-            config.storage_config.bus,
-            config.network_config.network_name.as_ref().unwrap_or(&"default".to_string()),
-            config.network_config.model,
-            config.display_config.graphics_type,
-        );
+    /// Import one domain XML as part of a batch, resolving name/UUID clashes per
+    /// `on_conflict`. Does not refresh the cache; the batch does that once at the end.
+    pub fn import_xml_file(&self, xml_path: &str, on_conflict: ImportConflictPolicy) -> ImportFileOutcome {
+        let failed = |reason: String| ImportFileOutcome::Failed { reason };
 
-        Ok(xml)
-    }
+        let mut xml = match std::fs::read_to_string(xml_path) {
+            Ok(xml) => xml,
+            Err(e) => return failed(format!("Failed to read file: {}", e)),
+        };
 
-    async fn create_vm_storage(&self, _config: &VmConfig, vm_id: &str) -> Result<()> {
-        // This would create the disk image file
-        // For now, we'll assume it's handled by libvirt
-        info!("Storage creation for VM {} handled by libvirt", vm_id);
-        Ok(())
-    }
+        match XmlParser::root_element(&xml).as_deref() {
+            Some("domain") => {}
+            Some(kind) => return ImportFileOutcome::NotDomain { kind: kind.to_string() },
+            None => return failed("Not a well-formed XML document".to_string()),
+        }
 
-    fn parse_os_info_from_xml(&self, xml: &str) -> Result<(String, Option<String>)> {
-        debug!("Parsing OS info from XML: {} chars", xml.len());
-        
-        // Try to parse OS info from XML, with fallback to default
-        if xml.contains("<os>") {
-            if xml.contains("linux") || xml.contains("debian") || xml.contains("ubuntu") || xml.contains("fedora") || xml.contains("centos") || xml.contains("rhel") {
-                debug!("Detected Linux OS type from XML");
-                return Ok(("linux".to_string(), Some("generic".to_string())));
-            } else if xml.contains("windows") || xml.contains("win") {
-                debug!("Detected Windows OS type from XML");
-                return Ok(("windows".to_string(), Some("win10".to_string())));
-            } else if xml.contains("freebsd") || xml.contains("openbsd") || xml.contains("netbsd") {
-                debug!("Detected BSD OS type from XML");
-                return Ok(("bsd".to_string(), Some("generic".to_string())));
-            }
+        let xml_info = match XmlParser::parse_vm_from_xml(&xml) {
+            Ok(xml_info) => xml_info,
+            Err(e) => return failed(e.to_string()),
+        };
+        if xml_info.name.is_empty() || !xml_info.parse_errors.is_empty() {
+            return failed(xml_info.parse_errors.join("; "));
         }
-        
-        // Check for common OS indicators in the full XML
-        let xml_lower = xml.to_lowercase();
-        if xml_lower.contains("debian") || xml_lower.contains("ubuntu") || xml_lower.contains("fedora") || xml_lower.contains("centos") {
-            debug!("Detected Linux from XML content analysis");
-            return Ok(("linux".to_string(), Some("generic".to_string())));
-        } else if xml_lower.contains("windows") || xml_lower.contains("win10") || xml_lower.contains("win11") {
-            debug!("Detected Windows from XML content analysis");
-            return Ok(("windows".to_string(), Some("win10".to_string())));
+
+        let by_name = Domain::lookup_by_name(&self.connection, &xml_info.name).ok();
+        let by_uuid = if xml_info.uuid.is_empty() {
+            None
+        } else {
+            Domain::lookup_by_uuid_string(&self.connection, &xml_info.uuid).ok()
+        };
+
+        if by_name.is_some() || by_uuid.is_some() {
+            match on_conflict {
+                ImportConflictPolicy::Skip => {
+                    let clash = if by_name.is_some() { format!("name '{}'", xml_info.name) } else { format!("UUID {}", xml_info.uuid) };
+                    return ImportFileOutcome::Skipped { reason: format!("A VM with {} already exists", clash) };
+                }
+                ImportConflictPolicy::Rename => {
+                    if by_name.is_some() {
+                        let new_name = (1..)
+                            .map(|n| if n == 1 { format!("{}-imported", xml_info.name) } else { format!("{}-imported-{}", xml_info.name, n) })
+                            .find(|candidate| Domain::lookup_by_name(&self.connection, candidate).is_err())
+                            .unwrap_or_default();
+                        xml = XmlParser::set_domain_name(&xml, &new_name);
+                    }
+                    if by_uuid.is_some() {
+                        xml = XmlParser::set_domain_uuid(&xml, &uuid::Uuid::new_v4().to_string());
+                    }
+                }
+                ImportConflictPolicy::Overwrite => {
+                    for existing in by_name.iter().chain(by_uuid.iter()) {
+                        if existing.is_active().unwrap_or(false) {
+                            let name = existing.get_name().unwrap_or_default();
+                            return ImportFileOutcome::Skipped { reason: format!("VM '{}' is running and cannot be overwritten", name) };
+                        }
+                    }
+                    // Same name and UUID redefines in place and keeps snapshots; anything
+                    // else has to be removed first or libvirt rejects the definition
+                    let same_domain = match (&by_name, &by_uuid) {
+                        (Some(named), Some(_)) => named.get_uuid_string().ok().as_deref() == Some(xml_info.uuid.as_str()),
+                        _ => false,
+                    };
+                    if !same_domain {
+                        for existing in by_name.iter().chain(by_uuid.iter()) {
+                            if let Err(e) = existing.undefine() {
+                                return failed(format!("Failed to remove existing definition: {}", e));
+                            }
+                        }
+                    }
+                }
+            }
         }
-        
-        // Default fallback
-        debug!("Using default OS type (Linux)");
-        Ok(("linux".to_string(), Some("generic".to_string())))
-    }
-    
-    pub async fn refresh_vm_list(&mut self) -> Result<Vec<VirtualMachine>> {
-        info!("Refreshing VM list");
-        self.refresh_vm_cache().await?;
-        self.list_vms().await
-    }
-    
-    pub async fn import_vm_from_xml(&mut self, xml_path: &str) -> Result<String> {
-        info!("Importing VM from XML: {}", xml_path);
-        
-        let xml_content = std::fs::read_to_string(xml_path)
-            .map_err(|e| KvmError::VmOperationFailed(format!("Failed to read XML file: {}", e)))?;
-        
-        // Define the domain from XML
-        let domain = Domain::define_xml(&self.connection, &xml_content)
-            .map_err(|e| {
+
+        match Domain::define_xml(&self.connection, &xml) {
+            Ok(domain) => {
+                let name = domain.get_name().unwrap_or_default();
+                let uuid = domain.get_uuid_string().unwrap_or_default();
+                info!("Imported VM {} ({}) from {}", name, uuid, xml_path);
+                ImportFileOutcome::Imported { uuid, name }
+            }
+            Err(e) => {
                 error!("Failed to define VM from XML {}: {}", xml_path, e);
-                KvmError::VmOperationFailed(format!("Failed to import VM: {}", e))
-            })?;
-        
-        let name = domain.get_name().map_err(KvmError::LibvirtConnection)?;
-        let uuid = domain.get_uuid_string().map_err(KvmError::LibvirtConnection)?;
-        
-        info!("Successfully imported VM {} with UUID {}", name, uuid);
-        
-        // Refresh cache
-        self.refresh_vm_cache().await?;
-        
-        Ok(uuid)
+                failed(format!("libvirt rejected the definition: {}", e))
+            }
+        }
     }
-    
+
     pub async fn create_vm_from_qcow2(
         &mut self, 
         qcow2_path: &str, 
         vm_name: &str, 
         memory_mb: u64, 
         vcpus: u32, 
-        passthrough_device: Option<&str>
+        passthrough_device: Option<&str>,
+        cloud_init: Option<&CloudInitConfig>,
+        cpu_model: Option<&CpuModelConfig>,
+        firmware: Option<FirmwareType>,
+        secure_boot: bool
     ) -> Result<String> {
         info!("Creating VM from qcow2: {} (name: {})", qcow2_path, vm_name);
         
@@ -1082,17 +4365,47 @@ impl VmManager {
             return Err(KvmError::VmOperationFailed(format!("qcow2 file not found: {}", qcow2_path)));
         }
         
+        quotas::check(&self.connection, &QuotaRequest {
+            vcpus,
+            memory_mb,
+            disk_gb: XmlParser::get_disk_size_from_file(qcow2_path).unwrap_or(0.0).ceil() as u64,
+            tags: Vec::new(),
+        })?;
+        
+        // Cloud images are mostly UEFI-only, so that is the default here
+        let ovmf = match firmware.unwrap_or(FirmwareType::Uefi) {
+            FirmwareType::Uefi => Some(firmware::detect_ovmf(secure_boot)?),
+            FirmwareType::Bios if secure_boot => {
+                return Err(KvmError::InvalidVmConfig("Secure Boot requires UEFI firmware".to_string()));
+            }
+            FirmwareType::Bios => None,
+        };
+        
         // Generate VM UUID
         let vm_uuid = uuid::Uuid::new_v4().to_string();
         
+        // Cloud images configure themselves from a NoCloud seed on first boot
+        let seed_iso = match cloud_init {
+            Some(config) => {
+                let iso_path = cloud_init::seed_iso_path(qcow2_path, vm_name);
+                cloud_init::build_seed_iso(config, &vm_uuid, &iso_path)?;
+                Some(iso_path.to_string_lossy().into_owned())
+            }
+            None => None,
+        };
+        
         // Generate XML configuration
-        let xml_config = self.generate_qcow2_vm_xml(
+        let xml_config = Self::generate_qcow2_vm_xml(
             vm_name, 
             &vm_uuid, 
             qcow2_path, 
             memory_mb, 
             vcpus, 
-            passthrough_device
+            &self.default_machine_type(),
+            passthrough_device,
+            seed_iso.as_deref(),
+            cpu_model,
+            ovmf.as_ref()
         )?;
         
         info!("Generated XML for VM {}", vm_name);
@@ -1101,6 +4414,9 @@ impl VmManager {
         let _domain = Domain::define_xml(&self.connection, &xml_config)
             .map_err(|e| {
                 error!("Failed to define VM {} from qcow2: {}", vm_name, e);
+                if let Some(iso) = &seed_iso {
+                    let _ = std::fs::remove_file(iso);
+                }
                 KvmError::VmOperationFailed(format!("Failed to create VM: {}", e))
             })?;
         
@@ -1112,17 +4428,24 @@ impl VmManager {
         Ok(vm_uuid)
     }
     
-    fn generate_qcow2_vm_xml(
-        &self,
+    pub(crate) fn generate_qcow2_vm_xml(
         vm_name: &str,
         vm_uuid: &str,
         qcow2_path: &str,
         memory_mb: u64,
         vcpus: u32,
-        passthrough_device: Option<&str>
+        machine_type: &str,
+        passthrough_device: Option<&str>,
+        seed_iso: Option<&str>,
+        cpu_model: Option<&CpuModelConfig>,
+        ovmf: Option<&OvmfFirmware>
     ) -> Result<String> {
         let memory_kb = memory_mb * 1024;
         
+        // Imported images default to passthrough; they usually stay on this host
+        let passthrough = CpuModelConfig { model: CpuModel::HostPassthrough, features: Vec::new() };
+        let cpu_xml = XmlParser::cpu_xml(cpu_model.unwrap_or(&passthrough), None, &[]);
+        
         let passthrough_disk = if let Some(device) = passthrough_device {
             format!(
                 r#"    <disk type='block' device='disk'>
@@ -1137,6 +4460,21 @@ impl VmManager {
             String::new()
         };
         
+        let seed_disk = match seed_iso {
+            Some(iso) => format!(
+                r#"
+    <!-- cloud-init seed -->
+    <disk type='file' device='cdrom'>
+      <driver name='qemu' type='raw'/>
+      <source file='{}'/>
+      <target dev='sda' bus='sata'/>
+      <readonly/>
+    </disk>"#,
+                XmlParser::escape(iso)
+            ),
+            None => String::new(),
+        };
+        
         let xml = format!(
             r#"<domain type='kvm'>
   <name>{}</name>
@@ -1150,18 +4488,16 @@ impl VmManager {
   <currentMemory unit='KiB'>{}</currentMemory>
   <vcpu placement='static'>{}</vcpu>
   <os>
-    <type arch='x86_64' machine='pc-q35-8.2'>hvm</type>
-    <loader readonly='yes' type='pflash'>/usr/share/edk2/x64/OVMF_CODE.4m.fd</loader>
-    <nvram>/var/lib/libvirt/qemu/nvram/{}_VARS.fd</nvram>
-    <boot dev='hd'/>
+    <type arch='x86_64' machine='{}'>hvm</type>
+{}    <boot dev='hd'/>
     <boot dev='cdrom'/>
   </os>
   <features>
     <acpi/>
     <apic/>
     <vmport state='off'/>
-  </features>
-  <cpu mode='host-passthrough' check='none' migratable='on'/>
+{}  </features>
+  {}
   <clock offset='utc'>
     <timer name='rtc' tickpolicy='catchup'/>
     <timer name='pit' tickpolicy='delay'/>
@@ -1184,7 +4520,7 @@ impl VmManager {
       <target dev='vda' bus='virtio'/>
       <address type='pci' domain='0x0000' bus='0x04' slot='0x00' function='0x0'/>
     </disk>
-{}
+{}{}
     
     <!-- Network interface -->
     <interface type='network'>
@@ -1211,6 +4547,9 @@ impl VmManager {
       <address type='pci' domain='0x0000' bus='0x00' slot='0x01' function='0x0'/>
     </video>
     
+    <!-- Sound -->
+    {}
+    
     <!-- USB Controller -->
     <controller type='usb' index='0' model='qemu-xhci' ports='15'>
       <address type='pci' domain='0x0000' bus='0x02' slot='0x00' function='0x0'/>
@@ -1271,12 +4610,17 @@ impl VmManager {
             memory_kb,
             memory_kb,
             vcpus,
-            vm_name,
+            XmlParser::escape(machine_type),
+            ovmf.map(|ovmf| firmware::os_firmware_xml(ovmf, vm_name)).unwrap_or_default(),
+            firmware::features_xml(ovmf),
+            cpu_xml,
             qcow2_path,
             passthrough_disk,
+            seed_disk,
             rand::random::<u8>(),
             rand::random::<u8>(),
-            rand::random::<u8>()
+            rand::random::<u8>(),
+            XmlParser::sound_xml(DEFAULT_SOUND_MODEL, true).replace('\n', "\n    ")
         );
         
         Ok(xml)
@@ -1310,10 +4654,10 @@ impl VmManager {
                         volume_list.push(StorageVolume {
                             name,
                             format,
-                            capacity: info.capacity,
-                            allocation: info.allocation,
                             path,
-                        });
+                            ..Default::default()
+                        }
+                        .with_sizes(info.capacity, info.allocation));
                     }
                 }
                 volume_list
@@ -1325,13 +4669,12 @@ impl VmManager {
             name,
             pool_type,
             path,
-            capacity: info.capacity,
-            available: info.available,
-            used: info.allocation,
             state: if info.state == sys::VIR_STORAGE_POOL_RUNNING { "active" } else { "inactive" }.to_string(),
             autostart: pool.get_autostart().map_err(KvmError::LibvirtConnection)?,
             volumes,
-        })
+            ..Default::default()
+        }
+        .with_sizes(info.capacity, info.available, info.allocation))
     }
 
     async fn network_to_network(&self, network: &virt::network::Network) -> Result<Network> {
@@ -1377,37 +4720,29 @@ impl VmManager {
     }
     
     async fn get_host_free_memory(&self) -> Option<u64> {
-        use std::process::Command;
-        
         // Try to get free memory from /proc/meminfo
-        if let Ok(output) = Command::new("cat")
-            .arg("/proc/meminfo")
-            .output() {
+        if let Ok(content) = std::fs::read_to_string("/proc/meminfo") {
+            let mut mem_available = None;
+            let mut mem_free = None;
             
-            if output.status.success() {
-                let content = String::from_utf8_lossy(&output.stdout);
-                let mut mem_available = None;
-                let mut mem_free = None;
-                
-                for line in content.lines() {
-                    if line.starts_with("MemAvailable:") {
-                        if let Some(value) = line.split_whitespace().nth(1) {
-                            if let Ok(kb) = value.parse::<u64>() {
-                                mem_available = Some(kb / 1024); // Convert to MB
-                            }
+            for line in content.lines() {
+                if line.starts_with("MemAvailable:") {
+                    if let Some(value) = line.split_whitespace().nth(1) {
+                        if let Ok(kb) = value.parse::<u64>() {
+                            mem_available = Some(kb / 1024); // Convert to MB
                         }
-                    } else if line.starts_with("MemFree:") {
-                        if let Some(value) = line.split_whitespace().nth(1) {
-                            if let Ok(kb) = value.parse::<u64>() {
-                                mem_free = Some(kb / 1024); // Convert to MB
-                            }
+                    }
+                } else if line.starts_with("MemFree:") {
+                    if let Some(value) = line.split_whitespace().nth(1) {
+                        if let Ok(kb) = value.parse::<u64>() {
+                            mem_free = Some(kb / 1024); // Convert to MB
                         }
                     }
                 }
-                
-                // Prefer MemAvailable over MemFree as it's more accurate
-                return mem_available.or(mem_free);
             }
+            
+            // Prefer MemAvailable over MemFree as it's more accurate
+            return mem_available.or(mem_free);
         }
         
         // Note: get_memory_stats method doesn't exist in virt crate - using fallback only
@@ -1416,35 +4751,35 @@ impl VmManager {
     }
     
     async fn load_vm_snapshots(&self, domain: &Domain) -> Result<Vec<Snapshot>> {
-        let vm_name = domain.get_name().map_err(KvmError::LibvirtConnection)?;
-        
-        // Use virsh to list snapshots (similar to list_snapshots but without extra logging)
-        let output = std::process::Command::new("virsh")
-            .args(["snapshot-list", &vm_name, "--name"])
-            .output()
-            .map_err(|e| KvmError::SnapshotOperationFailed(format!("Failed to execute virsh: {}", e)))?;
-        
-        if !output.status.success() {
-            // If no snapshots or command fails, return empty vec instead of error
-            return Ok(Vec::new());
-        }
-        
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let mut snapshots = Vec::new();
-        
-        for line in stdout.lines() {
-            let snapshot_name = line.trim();
-            if !snapshot_name.is_empty() {
-                snapshots.push(Snapshot {
-                    name: snapshot_name.to_string(),
-                    description: Some("Snapshot created by KVM Manager".to_string()),
-                    created_at: Utc::now(), // Real implementation would parse timestamp from virsh output
-                    state: "disk-snapshot".to_string(),
-                    parent: None,
-                });
-            }
-        }
-        
+        Self::domain_snapshots(domain)
+    }
+    
+    /// Every snapshot of the domain, oldest first, read from libvirt's own
+    /// snapshot descriptions. One that can't be parsed keeps its name with
+    /// placeholder metadata.
+    fn domain_snapshots(domain: &Domain) -> Result<Vec<Snapshot>> {
+        let mut snapshots: Vec<Snapshot> = domain.list_all_snapshots(0)
+            .map_err(KvmError::LibvirtConnection)?
+            .iter()
+            .filter_map(|snapshot| {
+                let name = snapshot.get_name().ok()?;
+                let parsed = snapshot.get_xml_desc(0)
+                    .map_err(KvmError::LibvirtConnection)
+                    .and_then(|xml| XmlParser::parse_snapshot(&xml));
+                Some(parsed.unwrap_or_else(|e| {
+                    warn!("Could not read snapshot {}: {}", name, e);
+                    Snapshot {
+                        name,
+                        description: None,
+                        created_at: Utc::now(),
+                        state: "disk-snapshot".to_string(),
+                        parent: None,
+                        includes_memory: false,
+                    }
+                }))
+            })
+            .collect();
+        snapshots.sort_by_key(|snapshot| snapshot.created_at);
         Ok(snapshots)
     }
     
@@ -1529,10 +4864,9 @@ impl VmManager {
     }
     
     async fn get_process_start_time(&self, vm_name: &str) -> Option<u64> {
-        use std::process::Command;
-        
         // Try to get process start time using ps command
-        if let Ok(output) = Command::new("ps")
+        let mut command = tools::try_command(Tool::Ps)?;
+        if let Ok(output) = command
             .args(["-eo", "comm,pid,etime"])
             .output() {
             
@@ -1573,3 +4907,211 @@ impl VmManager {
         "raw".to_string() // Default format
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_names_only_refuse_empty_and_control_characters() {
+        for name in ["before-upgrade", "-pre-release", "--", "nightly 2024/05/01", "Ünïcode ✓"] {
+            assert!(VmManager::check_snapshot_name(name).is_ok(), "{:?} should be accepted", name);
+        }
+        for name in ["", "   ", "bad\nname", "tab\there", "nul\0"] {
+            assert!(VmManager::check_snapshot_name(name).is_err(), "{:?} should be refused", name);
+        }
+    }
+
+    fn domain_with_disks(disks: &str) -> String {
+        format!("<domain type='kvm'><name>snap</name><uuid>6695eb01-f6a4-8304-79aa-97f2502e193f</uuid>\
+                 <memory unit='KiB'>524288</memory><vcpu>1</vcpu><os><type>hvm</type></os>\
+                 <devices>{}</devices></domain>", disks)
+    }
+
+    const QCOW2_FILE: &str = "<disk type='file' device='disk'><driver name='qemu' type='qcow2'/>\
+                              <source file='/var/lib/libvirt/images/a.qcow2'/><target dev='vda' bus='virtio'/></disk>";
+    const QCOW2_BLOCK: &str = "<disk type='block' device='disk'><driver name='qemu' type='qcow2'/>\
+                               <source dev='/dev/vg0/b'/><target dev='vdb' bus='virtio'/></disk>";
+    const RAW_FILE: &str = "<disk type='file' device='disk'><driver name='qemu' type='raw'/>\
+                            <source file='/var/lib/libvirt/images/c.img'/><target dev='vdc' bus='virtio'/></disk>";
+
+    #[test]
+    fn snapshot_kind_prefers_disk_only_when_every_disk_supports_it() {
+        let xml = domain_with_disks(QCOW2_FILE);
+        assert_eq!(VmManager::resolve_snapshot_kind(&xml, None, true).unwrap(), SnapshotKind::DiskOnly);
+        assert_eq!(VmManager::resolve_snapshot_kind(&xml, Some(SnapshotKind::Full), true).unwrap(), SnapshotKind::Full);
+    }
+
+    #[test]
+    fn full_snapshots_of_a_stopped_vm_are_disk_only() {
+        let xml = domain_with_disks(QCOW2_FILE);
+        assert_eq!(VmManager::resolve_snapshot_kind(&xml, Some(SnapshotKind::Full), false).unwrap(), SnapshotKind::DiskOnly);
+
+        // Block-backed qcow2 can't take an overlay, so it stays internal (and memoryless)
+        let xml = domain_with_disks(&format!("{}{}", QCOW2_FILE, QCOW2_BLOCK));
+        assert_eq!(VmManager::resolve_snapshot_kind(&xml, Some(SnapshotKind::Full), false).unwrap(), SnapshotKind::Full);
+    }
+
+    #[test]
+    fn snapshot_kind_falls_back_to_full_for_block_backed_qcow2() {
+        let xml = domain_with_disks(&format!("{}{}", QCOW2_FILE, QCOW2_BLOCK));
+        assert_eq!(
+            VmManager::resolve_snapshot_kind(&xml, Some(SnapshotKind::DiskOnly), true).unwrap(),
+            SnapshotKind::Full
+        );
+    }
+
+    #[test]
+    fn snapshot_kind_refuses_raw_disks_with_the_conversion_command() {
+        let xml = domain_with_disks(&format!("{}{}", QCOW2_FILE, RAW_FILE));
+        for (requested, running) in [(None, true), (Some(SnapshotKind::Full), true), (Some(SnapshotKind::Full), false)] {
+            match VmManager::resolve_snapshot_kind(&xml, requested, running) {
+                Err(KvmError::SnapshotOperationFailed(message)) => {
+                    assert!(message.contains("vdc") && message.contains("qemu-img convert"), "{}", message);
+                }
+                other => panic!("expected a refusal, got {:?}", other),
+            }
+        }
+    }
+
+    /// A config with explicit hardware and the given boot settings
+    fn config_with_boot(boot_config: serde_json::Value) -> VmConfig {
+        serde_json::from_value(serde_json::json!({
+            "name": "boot-test",
+            "memory": 1024,
+            "vcpus": 1,
+            "disk_size": 10,
+            "os_type": "linux",
+            "network_config": { "network_name": "default", "model": "virtio" },
+            "storage_config": { "pool_name": "default", "format": "qcow2", "bus": "virtio", "cache": "none" },
+            "display_config": { "graphics_type": "vnc", "listen": "127.0.0.1", "autoport": true },
+            "boot_config": boot_config,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn generates_a_direct_kernel_boot_domain() {
+        let config = config_with_boot(serde_json::json!({
+            "boot_order": ["hd"],
+            "kernel": "/var/lib/libvirt/boot/vmlinuz",
+            "initrd": "/var/lib/libvirt/boot/initrd.img",
+            "cmdline": "console=ttyS0 root=/dev/vda1 quiet & splash",
+        }));
+        let xml = VmManager::generate_vm_xml(&config, "3f0e1a52-8a64-4c1b-9d0e-2b8d3f1c7a10", "pc-q35-8.2").unwrap();
+
+        let os = &xml[xml.find("<os>").unwrap()..xml.find("</os>").unwrap()];
+        let kernel = os.find("<kernel>/var/lib/libvirt/boot/vmlinuz</kernel>").expect("kernel under <os>");
+        let initrd = os.find("<initrd>/var/lib/libvirt/boot/initrd.img</initrd>").expect("initrd under <os>");
+        let cmdline = os.find("<cmdline>console=ttyS0 root=/dev/vda1 quiet &amp; splash</cmdline>").expect("escaped cmdline under <os>");
+        assert!(kernel < initrd && initrd < cmdline);
+    }
+
+    #[test]
+    fn kernel_entries_are_left_out_without_a_kernel() {
+        let config = config_with_boot(serde_json::json!({ "boot_order": ["hd"] }));
+        let xml = VmManager::generate_vm_xml(&config, "3f0e1a52-8a64-4c1b-9d0e-2b8d3f1c7a10", "pc-q35-8.2").unwrap();
+
+        assert!(!xml.contains("<kernel>") && !xml.contains("<initrd>") && !xml.contains("<cmdline>"));
+    }
+
+    fn os_children(xml: &str) -> Vec<(String, Option<String>)> {
+        let document = roxmltree::Document::parse(xml).unwrap();
+        let os = document.root_element().children().find(|n| n.has_tag_name("os")).unwrap();
+        os.children()
+            .filter(roxmltree::Node::is_element)
+            .map(|n| (n.tag_name().name().to_string(), n.attribute("dev").or(n.text()).map(String::from)))
+            .collect()
+    }
+
+    #[test]
+    fn boot_order_and_install_iso_make_it_into_the_xml() {
+        let config = config_with_boot(serde_json::json!({
+            "boot_order": ["cdrom", "network", "hd"],
+            "iso_path": "/var/lib/libvirt/images/debian-12.iso",
+        }));
+        let xml = VmManager::generate_vm_xml(&config, "3f0e1a52-8a64-4c1b-9d0e-2b8d3f1c7a10", "pc-q35-8.2").unwrap();
+
+        let boot: Vec<_> = os_children(&xml).into_iter()
+            .filter(|(tag, _)| tag == "boot")
+            .filter_map(|(_, dev)| dev)
+            .collect();
+        assert_eq!(boot, ["cdrom", "network", "hd"]);
+
+        let document = roxmltree::Document::parse(&xml).unwrap();
+        let cdrom = document.descendants()
+            .find(|n| n.has_tag_name("disk") && n.attribute("device") == Some("cdrom"))
+            .expect("install CD-ROM");
+        let source = cdrom.children().find(|n| n.has_tag_name("source")).unwrap();
+        assert_eq!(source.attribute("file"), Some("/var/lib/libvirt/images/debian-12.iso"));
+        assert!(cdrom.children().any(|n| n.has_tag_name("readonly")));
+    }
+
+    #[test]
+    fn boot_order_defaults_to_disk_then_cdrom_without_an_iso() {
+        let config = config_with_boot(serde_json::json!({ "boot_order": [] }));
+        let xml = VmManager::generate_vm_xml(&config, "3f0e1a52-8a64-4c1b-9d0e-2b8d3f1c7a10", "pc-q35-8.2").unwrap();
+
+        let boot: Vec<_> = os_children(&xml).into_iter()
+            .filter(|(tag, _)| tag == "boot")
+            .filter_map(|(_, dev)| dev)
+            .collect();
+        assert_eq!(boot, ["hd", "cdrom"]);
+        assert!(!xml.contains("device='cdrom'"));
+    }
+
+    const LIBVIRT_NAME: &str = "legacy-web";
+    const LIBVIRT_UUID: &str = "8c1f4c2e-5b0a-4e8f-9a57-3d2f1b6e9c44";
+
+    // Hand-edited before being defined: a bare '&' in the description makes the
+    // whole document unparseable
+    const UNPARSEABLE_DOMAIN: &str = "<domain type='kvm'>
+  <name>legacy-web</name>
+  <uuid>8c1f4c2e-5b0a-4e8f-9a57-3d2f1b6e9c44</uuid>
+  <description>Web & mail</description>
+  <memory unit='KiB'>2097152</memory>
+  <vcpu placement='static'>2</vcpu>
+  <os><type arch='x86_64'>hvm</type></os>
+</domain>";
+
+    // Parses, but without an identity and with a vCPU count that is not a number
+    const PARTIAL_DOMAIN: &str = "<domain type='kvm'>
+  <memory unit='KiB'>2097152</memory>
+  <vcpu placement='static'>two</vcpu>
+  <os><type arch='x86_64'>hvm</type></os>
+  <devices>
+    <disk type='file' device='disk'>
+      <driver name='qemu' type='qcow2'/>
+      <source file='/var/lib/libvirt/images/legacy-web.qcow2'/>
+      <target dev='vda' bus='virtio'/>
+    </disk>
+  </devices>
+</domain>";
+
+    #[test]
+    fn unparseable_xml_falls_back_to_libvirts_identity_and_figures() {
+        let info = VmManager::parse_domain_xml(UNPARSEABLE_DOMAIN, LIBVIRT_NAME, LIBVIRT_UUID, 2048, 2);
+
+        assert_eq!(info.name, LIBVIRT_NAME);
+        assert_eq!(info.uuid, LIBVIRT_UUID);
+        assert_eq!((info.memory_mb, info.vcpus), (2048, 2));
+        assert_eq!(info.parse_errors.len(), 1);
+        assert!(info.parse_errors[0].starts_with("Failed to parse XML"), "{:?}", info.parse_errors);
+    }
+
+    #[test]
+    fn partly_readable_xml_keeps_libvirts_identity_and_reports_what_failed() {
+        let info = VmManager::parse_domain_xml(PARTIAL_DOMAIN, LIBVIRT_NAME, LIBVIRT_UUID, 2048, 2);
+
+        // Never a made-up name or a fresh random UUID
+        assert_eq!(info.name, LIBVIRT_NAME);
+        assert_eq!(info.uuid, LIBVIRT_UUID);
+        assert_eq!(info.memory_mb, 2048);
+        assert_eq!(info.storage_devices.len(), 1);
+
+        assert_eq!(info.parse_errors.len(), 3, "{:?}", info.parse_errors);
+        assert!(info.parse_errors.iter().any(|e| e == "Missing <name> element"));
+        assert!(info.parse_errors.iter().any(|e| e == "Missing <uuid> element"));
+        assert!(info.parse_errors.iter().any(|e| e.starts_with("Invalid <vcpu> value")));
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use futures::{SinkExt, StreamExt};
+use once_cell::sync::Lazy;
+use tauri::Manager;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::{http, Message};
+use tracing::{debug, info};
+
+use crate::errors::{KvmError, Result};
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+// With no viewer connected for this long the proxy shuts itself down
+const IDLE_TIMEOUT_SECS: i64 = 300;
+
+struct Proxy {
+    url: String,
+    target: (String, u16),
+    shutdown: watch::Sender<bool>,
+}
+
+// Running proxies by VM id
+static PROXIES: Lazy<std::sync::Mutex<HashMap<String, Proxy>>> = Lazy::new(|| std::sync::Mutex::new(HashMap::new()));
+
+fn proxies() -> std::sync::MutexGuard<'static, HashMap<String, Proxy>> {
+    PROXIES.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Serve a running VM's VNC display as a WebSocket on 127.0.0.1 for an
+/// embedded noVNC viewer and return its URL. The URL path is a random token,
+/// so other local processes can't guess it. The proxy stops with the VM, or
+/// once no viewer has been connected for a while.
+pub async fn start(app: tauri::AppHandle, vm_id: &str) -> Result<String> {
+    let manager = app.state::<crate::AppState>().inner().clone();
+    let display = manager.read().await.get_vm_display_info(vm_id).await?;
+    if display.graphics_type != "vnc" {
+        return Err(KvmError::VmOperationFailed(format!("VM's display is {}, not VNC", display.graphics_type)));
+    }
+    let port = display.port
+        .ok_or_else(|| KvmError::VmOperationFailed("VM's VNC display has no TCP port".to_string()))?;
+    let target = (display.host, port);
+
+    if let Some(proxy) = proxies().get(vm_id).filter(|proxy| proxy.target == target) {
+        return Ok(proxy.url.clone());
+    }
+    stop(vm_id);
+
+    let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+    let token = format!("{:032x}", rand::random::<u128>());
+    let url = format!("ws://127.0.0.1:{}/{}", listener.local_addr()?.port(), token);
+    let (shutdown, shutdown_signal) = watch::channel(false);
+    proxies().insert(vm_id.to_string(), Proxy { url: url.clone(), target: target.clone(), shutdown });
+
+    info!("Proxying VNC of VM {} ({}:{}) over WebSocket", vm_id, target.0, target.1);
+    tokio::spawn(serve(manager, vm_id.to_string(), url.clone(), listener, token, target, shutdown_signal));
+    Ok(url)
+}
+
+/// Shut down the VM's proxy and every viewer on it; false if none was running
+pub fn stop(vm_id: &str) -> bool {
+    match proxies().remove(vm_id) {
+        Some(proxy) => {
+            let _ = proxy.shutdown.send(true);
+            true
+        }
+        None => false,
+    }
+}
+
+async fn serve(
+    manager: crate::AppState,
+    vm_id: String,
+    url: String,
+    listener: TcpListener,
+    token: String,
+    target: (String, u16),
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let viewers = Arc::new(AtomicUsize::new(0));
+    let last_active = Arc::new(AtomicI64::new(chrono::Utc::now().timestamp()));
+    let mut check = tokio::time::interval(CHECK_INTERVAL);
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let Ok((stream, _)) = accepted else {
+                    continue;
+                };
+                viewers.fetch_add(1, Ordering::SeqCst);
+                let (viewers, last_active) = (viewers.clone(), last_active.clone());
+                let (token, target, shutdown) = (token.clone(), target.clone(), shutdown.clone());
+                tokio::spawn(async move {
+                    if let Err(e) = relay(stream, &token, &target, &last_active, shutdown).await {
+                        debug!("VNC viewer disconnected: {}", e);
+                    }
+                    last_active.store(chrono::Utc::now().timestamp(), Ordering::SeqCst);
+                    viewers.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+            _ = check.tick() => {
+                if manager.read().await.get_vm_display_info(&vm_id).await.is_err() {
+                    info!("VM {} stopped; closing its VNC proxy", vm_id);
+                    break;
+                }
+                let idle_secs = chrono::Utc::now().timestamp() - last_active.load(Ordering::SeqCst);
+                if viewers.load(Ordering::SeqCst) == 0 && idle_secs > IDLE_TIMEOUT_SECS {
+                    info!("Closing idle VNC proxy of VM {}", vm_id);
+                    break;
+                }
+            }
+            _ = shutdown.changed() => break,
+        }
+    }
+
+    // A newer proxy may have replaced this one in the meantime
+    let mut running = proxies();
+    if running.get(&vm_id).is_some_and(|proxy| proxy.url == url) {
+        if let Some(proxy) = running.remove(&vm_id) {
+            let _ = proxy.shutdown.send(true);
+        }
+    }
+}
+
+/// Pass one viewer's WebSocket frames to the VNC server and back
+async fn relay(
+    stream: TcpStream,
+    token: &str,
+    target: &(String, u16),
+    last_active: &AtomicI64,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let expected_path = format!("/{}", token);
+    let handshake = |request: &Request, mut response: Response| -> std::result::Result<Response, ErrorResponse> {
+        if request.uri().path() != expected_path {
+            let mut error = ErrorResponse::new(Some("Unknown proxy path".to_string()));
+            *error.status_mut() = http::StatusCode::NOT_FOUND;
+            return Err(error);
+        }
+        // noVNC asks for the "binary" subprotocol
+        let wants_binary = request.headers().get_all("Sec-WebSocket-Protocol").iter()
+            .filter_map(|value| value.to_str().ok())
+            .any(|value| value.split(',').any(|protocol| protocol.trim() == "binary"));
+        if wants_binary {
+            response.headers_mut().insert("Sec-WebSocket-Protocol", http::HeaderValue::from_static("binary"));
+        }
+        Ok(response)
+    };
+    let websocket_error = |e: tokio_tungstenite::tungstenite::Error| KvmError::VmOperationFailed(format!("WebSocket error: {}", e));
+
+    let websocket = tokio_tungstenite::accept_hdr_async(stream, handshake).await.map_err(websocket_error)?;
+    let vnc = TcpStream::connect((target.0.as_str(), target.1)).await?;
+    let (mut vnc_read, mut vnc_write) = vnc.into_split();
+    let (mut browser_write, mut browser_read) = websocket.split();
+    let touch = || last_active.store(chrono::Utc::now().timestamp(), Ordering::SeqCst);
+
+    let to_vnc = async {
+        while let Some(message) = browser_read.next().await {
+            match message.map_err(websocket_error)? {
+                Message::Binary(data) => {
+                    vnc_write.write_all(&data).await?;
+                    touch();
+                }
+                Message::Close(_) => break,
+                _ => {} // tungstenite answers pings itself
+            }
+        }
+        Ok::<(), KvmError>(())
+    };
+    let to_browser = async {
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            let read = vnc_read.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            browser_write.send(Message::Binary(buffer[..read].to_vec())).await.map_err(websocket_error)?;
+            touch();
+        }
+        let _ = browser_write.close().await;
+        Ok::<(), KvmError>(())
+    };
+
+    tokio::select! {
+        result = to_vnc => result,
+        result = to_browser => result,
+        _ = shutdown.changed() => Ok(()),
+    }
+}
@@ -0,0 +1,62 @@
+use std::process::Stdio;
+use std::time::Duration;
+use tauri::{Emitter, Manager};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tracing::{error, info, warn};
+
+use crate::tools::{self, Tool};
+
+// How long to wait before following events again after virsh exits
+const RESTART_DELAY: Duration = Duration::from_secs(10);
+
+/// Follow libvirt's watchdog events and re-emit each as a `vm-watchdog` event.
+/// The virt crate has no binding for domain event callbacks, so this reads
+/// `virsh event` instead.
+pub async fn watch(app: tauri::AppHandle) {
+    let manager = app.state::<crate::AppState>().inner().clone();
+
+    loop {
+        let uri = manager.read().await.get_connection_uri();
+        let mut command = match tools::command(Tool::Virsh) {
+            Ok(command) => command,
+            Err(e) => {
+                warn!("Not watching for watchdog events: {}", e);
+                return;
+            }
+        };
+        command
+            .args(["-c", &uri, "event", "--event", "watchdog", "--loop"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null());
+
+        match tokio::process::Command::from(command).kill_on_drop(true).spawn() {
+            Ok(mut child) => {
+                info!("Watching for watchdog events on {}", uri);
+                if let Some(stdout) = child.stdout.take() {
+                    let mut lines = BufReader::new(stdout).lines();
+                    while let Ok(Some(line)) = lines.next_line().await {
+                        let Some((vm_name, action)) = parse_event(&line) else {
+                            continue;
+                        };
+                        warn!("Watchdog fired for VM {}: {}", vm_name, action);
+                        let payload = serde_json::json!({ "vm_name": vm_name, "action": action });
+                        if let Err(e) = app.emit("vm-watchdog", payload) {
+                            error!("Failed to emit watchdog event: {}", e);
+                        }
+                    }
+                }
+                let _ = child.wait().await;
+            }
+            Err(e) => error!("Failed to run virsh event: {}", e),
+        }
+
+        tokio::time::sleep(RESTART_DELAY).await;
+    }
+}
+
+// event 'watchdog' for domain 'web01': reset
+fn parse_event(line: &str) -> Option<(String, String)> {
+    let rest = line.split("event 'watchdog' for domain '").nth(1)?;
+    let (vm_name, action) = rest.rsplit_once("': ")?;
+    Some((vm_name.to_string(), action.trim().to_string()))
+}
@@ -1,57 +1,104 @@
 use tracing::debug;
-use crate::errors::Result;
+use crate::errors::{KvmError, Result};
+use crate::tools::{self, Tool};
 use crate::types::*;
 
 pub struct XmlParser;
 
+/// Namespace for the app's own entries under a domain's `<metadata>`
+pub const METADATA_NAMESPACE: &str = "https://github.com/wlfogle/kvm-manager";
+pub const TAGS_ELEMENT: &str = "kvmmanager:tags";
+/// Marks a linked clone; `source` holds the UUID of the VM whose disks back it
+pub const LINKED_CLONE_ELEMENT: &str = "kvmmanager:linked-clone";
+const LIBOSINFO_NAMESPACE: &str = "http://libosinfo.org/xmlns/libvirt/domain/1.0";
+
 impl XmlParser {
     /// Parse VM properties from libvirt XML
     pub fn parse_vm_from_xml(xml: &str) -> Result<VmXmlInfo> {
         debug!("Parsing VM XML: {} chars", xml.len());
         
+        let document = Self::parse_document(xml, "domain")?;
+        let domain = document.root_element();
+        let devices = Self::child(domain, "devices");
+        
         let mut vm_info = VmXmlInfo::default();
         
-        // Parse basic info
-        vm_info.name = Self::extract_between_tags(xml, "name")
-            .unwrap_or_else(|| "unknown".to_string());
+        // Parse basic info. Missing identity is reported rather than invented so
+        // callers can fall back to what libvirt says.
+        match Self::child_text(domain, "name") {
+            Some(name) => vm_info.name = name,
+            None => vm_info.parse_errors.push("Missing <name> element".to_string()),
+        }
         
-        vm_info.uuid = Self::extract_between_tags(xml, "uuid")
-            .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+        match Self::child_text(domain, "uuid") {
+            Some(uuid) => vm_info.uuid = uuid,
+            None => vm_info.parse_errors.push("Missing <uuid> element".to_string()),
+        }
         
-        // Parse memory (in KiB, convert to MB)
-        if let Some(memory_str) = Self::extract_between_tags(xml, "memory") {
-            if let Ok(memory_kib) = memory_str.parse::<u64>() {
-                vm_info.memory_mb = memory_kib / 1024;
+        // Parse memory (in KiB unless another unit is given, convert to MB)
+        match Self::child(domain, "memory").map(|memory| (memory, Self::node_text(memory).parse::<u64>())) {
+            Some((node, Ok(memory))) => {
+                vm_info.memory_mb = match node.attribute("unit").unwrap_or("KiB") {
+                    "b" | "bytes" => memory / (1024 * 1024),
+                    "M" | "MiB" => memory,
+                    "G" | "GiB" => memory * 1024,
+                    _ => memory / 1024,
+                };
             }
+            Some((_, Err(e))) => vm_info.parse_errors.push(format!("Invalid <memory> value: {}", e)),
+            None => vm_info.parse_errors.push("Missing <memory> element".to_string()),
         }
         
         // Parse vCPUs
-        if let Some(vcpus_str) = Self::extract_between_tags(xml, "vcpu") {
-            if let Ok(vcpus) = vcpus_str.parse::<u32>() {
-                vm_info.vcpus = vcpus;
-            }
+        match Self::child_text(domain, "vcpu").map(|vcpus| vcpus.parse::<u32>()) {
+            Some(Ok(vcpus)) => vm_info.vcpus = vcpus,
+            Some(Err(e)) => vm_info.parse_errors.push(format!("Invalid <vcpu> value: {}", e)),
+            None => vm_info.parse_errors.push("Missing <vcpu> element".to_string()),
         }
         
+        vm_info.cpu_topology = Self::cpu_topology_of(domain);
+        vm_info.numa_nodes = Self::numa_nodes_of(domain);
+        
         // Parse OS info
-        let (os_type, os_variant) = Self::parse_os_info(xml);
+        let (os_type, os_variant) = Self::parse_os_info(domain, xml);
         vm_info.os_type = os_type;
         vm_info.os_variant = os_variant;
         
-        // Parse storage devices
-        vm_info.storage_devices = Self::parse_storage_devices(xml);
-        vm_info.disk_size_gb = vm_info.storage_devices.iter()
-            .map(|d| d.size_gb)
-            .sum();
-        
-        // Parse network interfaces
-        vm_info.network_interfaces = Self::parse_network_interfaces(xml);
-        
-        // Parse graphics ports
-        vm_info.vnc_port = Self::parse_vnc_port(xml);
-        vm_info.spice_port = Self::parse_spice_port(xml);
+        if let Some(devices) = devices {
+            vm_info.tpm = Self::tpm_of(devices);
+            vm_info.watchdog = Self::watchdog_of(devices);
+            vm_info.shared_folders = Self::children(devices, "filesystem")
+                .filter_map(Self::parse_shared_folder)
+                .collect();
+            vm_info.sound_model = Self::child(devices, "sound")
+                .and_then(|sound| sound.attribute("model"))
+                .map(str::to_string);
+            
+            // Parse storage devices
+            vm_info.storage_devices = Self::children(devices, "disk")
+                .filter_map(Self::parse_single_disk)
+                .collect();
+            vm_info.disk_size_gb = vm_info.storage_devices.iter()
+                .map(|d| d.size_gb)
+                .sum();
+            
+            // Parse network interfaces
+            vm_info.network_interfaces = Self::children(devices, "interface")
+                .filter_map(Self::parse_single_interface)
+                .collect();
+            
+            // Parse graphics ports
+            let graphics = Self::graphics_of(devices);
+            vm_info.vnc_port = graphics.iter().find(|g| g.protocol == "vnc").and_then(|g| g.port);
+            vm_info.spice_port = graphics.iter().find(|g| g.protocol == "spice").and_then(|g| g.port);
+            vm_info.spice_graphics = graphics.iter().any(|g| g.protocol == "spice");
+            vm_info.spice_agent_state = Self::spice_agent_state_of(devices);
+        }
         
-        // Parse description from metadata
-        vm_info.description = Self::extract_description(xml);
+        // Parse description and tags from metadata
+        vm_info.description = Self::child_text(domain, "description")
+            .or_else(|| Self::child_text(domain, "title"));
+        vm_info.tags = Self::tags_of(domain);
         
         debug!("Parsed VM info: name={}, memory={}MB, vcpus={}, disks={}", 
                vm_info.name, vm_info.memory_mb, vm_info.vcpus, vm_info.storage_devices.len());
@@ -59,6 +106,978 @@ impl XmlParser {
         Ok(vm_info)
     }
     
+    /// Parse a whole document, requiring `root` as its root element
+    fn parse_document<'input>(xml: &'input str, root: &str) -> Result<roxmltree::Document<'input>> {
+        let document = roxmltree::Document::parse(xml)
+            .map_err(|e| KvmError::XmlParsingError(e.to_string()))?;
+        
+        let found = document.root_element().tag_name().name();
+        if found != root {
+            return Err(KvmError::XmlParsingError(format!("Expected a <{}> document, found <{}>", root, found)));
+        }
+        Ok(document)
+    }
+    
+    /// Direct child elements named `name` (in no namespace)
+    fn children<'a, 'input: 'a>(
+        node: roxmltree::Node<'a, 'input>,
+        name: &'a str,
+    ) -> impl Iterator<Item = roxmltree::Node<'a, 'input>> + 'a {
+        node.children().filter(move |child| child.is_element() && child.has_tag_name(name))
+    }
+    
+    fn child<'a, 'input>(node: roxmltree::Node<'a, 'input>, name: &str) -> Option<roxmltree::Node<'a, 'input>> {
+        node.children().find(|child| child.is_element() && child.has_tag_name(name))
+    }
+    
+    fn child_attribute(node: roxmltree::Node, name: &str, attribute: &str) -> Option<String> {
+        Self::child(node, name)?.attribute(attribute).map(str::to_string)
+    }
+    
+    /// Trimmed text of a direct child, `None` when absent or empty
+    fn child_text(node: roxmltree::Node, name: &str) -> Option<String> {
+        Self::child(node, name)
+            .map(Self::node_text)
+            .filter(|text| !text.is_empty())
+    }
+    
+    /// All text inside an element, with entities and CDATA resolved
+    fn node_text(node: roxmltree::Node) -> String {
+        node.descendants()
+            .filter(|descendant| descendant.is_text())
+            .filter_map(|descendant| descendant.text())
+            .collect::<String>()
+            .trim()
+            .to_string()
+    }
+    
+    /// A `<domainsnapshot>` description for creating a snapshot
+    pub fn snapshot_xml(name: &str, description: &str) -> String {
+        format!(
+            "<domainsnapshot>\n  <name>{}</name>\n  <description>{}</description>\n</domainsnapshot>",
+            Self::escape(name),
+            Self::escape(description)
+        )
+    }
+    
+    /// Read a snapshot's `<domainsnapshot>` description as libvirt reports it
+    pub fn parse_snapshot(xml: &str) -> Result<Snapshot> {
+        let document = Self::parse_document(xml, "domainsnapshot")?;
+        let snapshot = document.root_element();
+        
+        let name = Self::child_text(snapshot, "name")
+            .ok_or_else(|| KvmError::XmlParsingError("Snapshot has no name".to_string()))?;
+        let created_at = Self::child_text(snapshot, "creationTime")
+            .and_then(|seconds| seconds.parse::<i64>().ok())
+            .and_then(|seconds| chrono::DateTime::from_timestamp(seconds, 0))
+            .ok_or_else(|| KvmError::XmlParsingError(format!("Snapshot {} has no creation time", name)))?;
+        // The guest's state when the snapshot was taken; "disk-snapshot" for external overlays
+        let state = Self::child_text(snapshot, "state").unwrap_or_else(|| "disk-snapshot".to_string());
+        let includes_memory = match Self::child_attribute(snapshot, "memory", "snapshot") {
+            Some(memory) => memory != "no",
+            None => matches!(state.as_str(), "running" | "paused"),
+        };
+        
+        Ok(Snapshot {
+            description: Self::child_text(snapshot, "description"),
+            created_at,
+            includes_memory,
+            state,
+            parent: Self::child(snapshot, "parent").and_then(|parent| Self::child_text(parent, "name")),
+            name,
+        })
+    }
+    
+    /// Name of the document's root element, `None` if it isn't well-formed XML
+    pub fn root_element(xml: &str) -> Option<String> {
+        let document = roxmltree::Document::parse(xml).ok()?;
+        Some(document.root_element().tag_name().name().to_string())
+    }
+    
+    /// Replace the domain's `<name>`
+    pub fn set_domain_name(xml: &str, name: &str) -> String {
+        Self::upsert_element(xml, "name", Some(&format!("<name>{}</name>", Self::escape(name))), &[])
+    }
+    
+    /// Replace the domain's `<uuid>`, adding one after `<name>` if absent
+    pub fn set_domain_uuid(xml: &str, uuid: &str) -> String {
+        Self::upsert_element(xml, "uuid", Some(&format!("<uuid>{}</uuid>", uuid)), &["name"])
+    }
+    
+    /// Escape a value for use in XML text or attribute content
+    pub fn escape(value: &str) -> String {
+        value
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('\'', "&apos;")
+            .replace('"', "&quot;")
+    }
+    
+    /// Set or clear a CPU feature policy in a domain XML.
+    ///
+    /// Any existing `<feature>` entry for `feature` is removed first; when `policy` is
+    /// given a new entry is added to `<cpu>`, creating the element if needed.
+    pub fn set_cpu_feature(xml: &str, feature: &str, policy: Option<&str>) -> String {
+        let updated = Self::remove_cpu_children(xml, |child| {
+            child.has_tag_name("feature") && child.attribute("name") == Some(feature)
+        });
+
+        match policy {
+            Some(policy) => Self::insert_cpu_child(&updated, &format!("<feature policy='{}' name='{}'/>", policy, feature)),
+            None => updated,
+        }
+    }
+    
+    /// Set or remove the `<topology>` of the domain's `<cpu>`
+    pub fn set_cpu_topology(xml: &str, topology: Option<&CpuTopology>) -> String {
+        let updated = Self::remove_cpu_children(xml, |child| child.has_tag_name("topology"));
+
+        match topology {
+            Some(topology) => Self::insert_cpu_child(&updated, &Self::cpu_topology_xml(topology)),
+            None => updated,
+        }
+    }
+    
+    pub fn cpu_topology_xml(topology: &CpuTopology) -> String {
+        format!(
+            "<topology sockets='{}' dies='1' cores='{}' threads='{}'/>",
+            topology.sockets, topology.cores, topology.threads
+        )
+    }
+    
+    /// Remove the children of the domain's `<cpu>` that `matches` selects,
+    /// along with the whitespace before each
+    fn remove_cpu_children<F>(xml: &str, matches: F) -> String
+    where
+        F: Fn(&roxmltree::Node) -> bool,
+    {
+        let document = match roxmltree::Document::parse(xml) {
+            Ok(document) => document,
+            Err(_) => return xml.to_string(),
+        };
+        let ranges: Vec<_> = document.root_element()
+            .children()
+            .find(|node| node.has_tag_name("cpu"))
+            .map(|cpu| cpu.children().filter(|child| child.is_element() && matches(child)).map(|child| child.range()).collect())
+            .unwrap_or_default();
+
+        // Back to front so earlier ranges stay valid
+        let mut updated = xml.to_string();
+        for range in ranges.into_iter().rev() {
+            let start = updated[..range.start].trim_end().len();
+            updated.replace_range(start..range.end, "");
+        }
+        updated
+    }
+
+    /// Add a child element to `<cpu>`, expanding a self-closing `<cpu/>` or
+    /// creating the element if the domain has none
+    fn insert_cpu_child(xml: &str, child_xml: &str) -> String {
+        let document = match roxmltree::Document::parse(xml) {
+            Ok(document) => document,
+            Err(_) => return xml.to_string(),
+        };
+        let root = document.root_element();
+        let mut updated = xml.to_string();
+
+        if let Some(cpu) = root.children().find(|node| node.has_tag_name("cpu")) {
+            let span = cpu.range();
+            let element = &xml[span.clone()];
+            if element.ends_with("/>") {
+                // Expand a self-closing <cpu .../> so it can hold the child
+                let attributes = element["<cpu".len()..element.len() - 2].trim_end();
+                updated.replace_range(span, &format!("<cpu{}>\n    {}\n  </cpu>", attributes, child_xml));
+            } else if let Some(close) = element.rfind("</") {
+                updated.insert_str(span.start + close, &format!("  {}\n  ", child_xml));
+            }
+        } else if let Some(devices) = root.children().find(|node| node.has_tag_name("devices")) {
+            updated.insert_str(
+                devices.range().start,
+                &format!("<cpu mode='host-model' check='partial'>\n    {}\n  </cpu>\n  ", child_xml),
+            );
+        }
+
+        updated
+    }
+    
+    /// Return the full text of every `<element ...>...</element>` (or self-closing) occurrence
+    pub fn find_elements(xml: &str, element: &str) -> Vec<String> {
+        let mut elements = Vec::new();
+        let end_tag = format!("</{}>", element);
+        
+        let pattern = format!(r#"<{}\s+[^>]*>"#, regex::escape(element));
+        if let Ok(regex) = regex::Regex::new(&pattern) {
+            for element_match in regex.find_iter(xml) {
+                let start = element_match.start();
+                
+                if element_match.as_str().ends_with("/>") {
+                    elements.push(element_match.as_str().to_string());
+                } else if let Some(end) = xml[start..].find(&end_tag) {
+                    elements.push(xml[start..start + end + end_tag.len()].to_string());
+                }
+            }
+        }
+        
+        elements
+    }
+    
+    /// Source file or device of every disk, without resolving sizes
+    pub fn disk_source_paths(xml: &str) -> Vec<String> {
+        Self::find_elements(xml, "disk")
+            .iter()
+            .filter_map(|disk| {
+                Self::extract_attribute_value(disk, "source", "file")
+                    .or_else(|| Self::extract_attribute_value(disk, "source", "dev"))
+            })
+            .collect()
+    }
+    
+    /// Normalize a domain definition into the high-level creation shape plus the
+    /// hardware details that were filled in by defaults
+    pub fn parse_effective_config(xml: &str) -> Result<EffectiveVmConfig> {
+        let vm_info = Self::parse_vm_from_xml(xml)?;
+        let os_section = Self::find_element_span(xml, "os")
+            .map(|span| xml[span].to_string())
+            .unwrap_or_default();
+        
+        let primary_disk = vm_info.storage_devices.first();
+        let primary_interface = vm_info.network_interfaces.first();
+        let graphics = Self::parse_graphics_devices(xml).into_iter().next();
+        
+        let disk_encryption = Self::find_elements(xml, "disk")
+            .into_iter()
+            .find(|disk| disk.contains("device='disk'"))
+            .and_then(|disk| {
+                Some(EncryptionSpec {
+                    format: Self::extract_attribute_value(&disk, "encryption", "format")?,
+                    secret_uuid: Self::extract_attribute_value(&disk, "secret", "uuid")?,
+                })
+            });
+        
+        let boot_order = regex::Regex::new(r#"<boot\s+dev=['"]([^'"]+)['"]"#)
+            .ok()
+            .map(|regex| regex.captures_iter(&os_section).map(|c| c[1].to_string()).collect());
+        
+        let iso_path = Self::find_elements(xml, "disk")
+            .into_iter()
+            .find(|disk| disk.contains("device='cdrom'"))
+            .and_then(|disk| Self::extract_attribute_value(&disk, "source", "file"));
+        
+        let config = VmConfig {
+            name: vm_info.name,
+            memory: Some(vm_info.memory_mb),
+            vcpus: Some(vm_info.vcpus),
+            disk_size: Some(vm_info.disk_size_gb.round() as u64),
+            os_type: Some(vm_info.os_type),
+            os_variant: vm_info.os_variant,
+            description: vm_info.description,
+            autostart: false, // Not part of the XML; filled in by the caller
+            cpu_topology: vm_info.cpu_topology,
+            numa_nodes: vm_info.numa_nodes,
+            tpm: vm_info.tpm,
+            watchdog: vm_info.watchdog,
+            shared_folders: vm_info.shared_folders,
+            machine_type: Self::extract_attribute_value(&os_section, "type", "machine"),
+            firmware: if Self::element_text(&os_section, "loader").is_some()
+                || Self::extract_attribute_value(&os_section, "os", "firmware").as_deref() == Some("efi")
+            {
+                FirmwareType::Uefi
+            } else {
+                FirmwareType::Bios
+            },
+            secure_boot: Self::extract_attribute_value(&os_section, "loader", "secure").as_deref() == Some("yes"),
+            cpu_model: Self::parse_document(xml, "domain")
+                .ok()
+                .and_then(|document| Self::cpu_model_of(document.root_element()))
+                .unwrap_or_default(),
+            memory_backing: Self::parse_memory_backing(xml),
+            network_config: NetworkConfig {
+                bridge: primary_interface.filter(|i| i.type_ == "bridge").map(|i| i.source.clone()),
+                network_name: primary_interface.filter(|i| i.type_ == "network").map(|i| i.source.clone()),
+                mac_address: primary_interface.and_then(|i| i.mac_address.clone()),
+                model: primary_interface.map(|i| i.model.clone()),
+            },
+            storage_config: StorageConfig {
+                pool_name: None, // Resolved against the host's pools by the caller
+                format: primary_disk.map(|d| d.type_.clone()),
+                bus: primary_disk.map(|d| d.bus.clone()),
+                cache: Some(primary_disk.and_then(|d| d.cache.clone()).unwrap_or_else(|| "default".to_string())),
+                encryption: disk_encryption,
+            },
+            display_config: DisplayConfig {
+                graphics_type: graphics.as_ref().map(|g| g.protocol.clone()),
+                listen: graphics.as_ref().and_then(|g| g.listen.clone()),
+                password: None, // Never echoed back
+                autoport: graphics.as_ref().map(|g| g.autoport).unwrap_or(false),
+                port: graphics.as_ref().filter(|g| !g.autoport).and_then(|g| g.port),
+                sound_model: vm_info.sound_model,
+            },
+            boot_config: BootConfig {
+                boot_order,
+                iso_path,
+                kernel: Self::extract_between_tags(&os_section, "kernel"),
+                initrd: Self::extract_between_tags(&os_section, "initrd"),
+                cmdline: Self::extract_between_tags(&os_section, "cmdline"),
+            },
+        };
+        
+        let controllers = Self::find_elements(xml, "controller")
+            .iter()
+            .filter_map(|controller| {
+                let controller_type = Self::extract_attribute_value(controller, "controller", "type")?;
+                Some(match Self::extract_attribute_value(controller, "controller", "model") {
+                    Some(model) => format!("{}:{}", controller_type, model),
+                    None => controller_type,
+                })
+            })
+            .collect();
+        
+        Ok(EffectiveVmConfig {
+            config,
+            arch: Self::extract_attribute_value(&os_section, "type", "arch"),
+            emulator: Self::extract_between_tags(xml, "emulator"),
+            cpu_mode: Self::extract_attribute_value(xml, "cpu", "mode"),
+            loader: Self::element_text(&os_section, "loader")
+                .or_else(|| Self::extract_attribute_value(&os_section, "os", "firmware")),
+            video_model: Self::extract_attribute_value(
+                &Self::extract_between_tags(xml, "video").unwrap_or_default(), "model", "type"
+            ),
+            controllers,
+            storage_devices: vm_info.storage_devices,
+            network_interfaces: vm_info.network_interfaces,
+        })
+    }
+    
+    /// Host PCI addresses (`0000:03:10.2`) passed through as `<hostdev>` or
+    /// `<interface type='hostdev'>`
+    pub fn hostdev_pci_addresses(xml: &str) -> Vec<String> {
+        let Ok(document) = Self::parse_document(xml, "domain") else {
+            return Vec::new();
+        };
+        let Some(devices) = Self::child(document.root_element(), "devices") else {
+            return Vec::new();
+        };
+        
+        devices
+            .children()
+            .filter(|device| {
+                device.has_tag_name("hostdev")
+                    || (device.has_tag_name("interface") && device.attribute("type") == Some("hostdev"))
+            })
+            .filter_map(|device| {
+                // The guest-side <address> is a sibling of <source>, so only look inside it
+                let address = Self::child(Self::child(device, "source")?, "address")?;
+                let field = |name: &str| {
+                    address.attribute(name)
+                        .and_then(|value| u32::from_str_radix(value.trim_start_matches("0x"), 16).ok())
+                };
+                Some(format!(
+                    "{:04x}:{:02x}:{:02x}.{:x}",
+                    field("domain")?, field("bus")?, field("slot")?, field("function")?
+                ))
+            })
+            .collect()
+    }
+    
+    /// USB devices passed through with `<hostdev type='usb'>`: vendor and
+    /// product ID (four hex digits), plus the host address when one is pinned
+    pub fn hostdev_usb_devices(xml: &str) -> Vec<(String, String, Option<(u32, u32)>)> {
+        let Ok(document) = Self::parse_document(xml, "domain") else {
+            return Vec::new();
+        };
+        let Some(devices) = Self::child(document.root_element(), "devices") else {
+            return Vec::new();
+        };
+        let hex_id = |value: String| u16::from_str_radix(value.trim_start_matches("0x"), 16).ok().map(|id| format!("{:04x}", id));
+
+        Self::children(devices, "hostdev")
+            .filter(|hostdev| hostdev.attribute("type") == Some("usb"))
+            .filter_map(|hostdev| {
+                let source = Self::child(hostdev, "source")?;
+                let vendor = Self::child_attribute(source, "vendor", "id").and_then(hex_id)?;
+                let product = Self::child_attribute(source, "product", "id").and_then(hex_id)?;
+                let number = |name: &str| Self::child_attribute(source, "address", name).and_then(|value| value.parse().ok());
+                let address = number("bus").zip(number("device"));
+                Some((vendor, product, address))
+            })
+            .collect()
+    }
+    
+    /// Host files that `<serial type='file'>` ports log to, with their target port
+    pub fn serial_log_files(xml: &str) -> Vec<(u32, String)> {
+        Self::find_elements(xml, "serial")
+            .iter()
+            .filter(|serial| serial.starts_with("<serial type='file'"))
+            .filter_map(|serial| {
+                let port = Self::extract_attribute_value(serial, "target", "port")
+                    .and_then(|port| port.parse().ok())
+                    .unwrap_or(0);
+                Some((port, Self::extract_attribute_value(serial, "source", "path")?))
+            })
+            .collect()
+    }
+    
+    /// Target ports already taken by serial devices
+    pub fn serial_ports(xml: &str) -> Vec<u32> {
+        Self::find_elements(xml, "serial")
+            .iter()
+            .filter_map(|serial| Self::extract_attribute_value(serial, "target", "port")?.parse().ok())
+            .collect()
+    }
+    
+    /// Connection state of the spicevmc agent channel, or `None` without one.
+    ///
+    /// libvirt only reports `state` in live XML, so a defined-but-stopped VM
+    /// yields an empty string.
+    pub fn spice_agent_state(xml: &str) -> Option<String> {
+        let document = Self::parse_document(xml, "domain").ok()?;
+        Self::spice_agent_state_of(Self::child(document.root_element(), "devices")?)
+    }
+    
+    fn spice_agent_state_of(devices: roxmltree::Node) -> Option<String> {
+        Self::children(devices, "channel")
+            .find(|channel| channel.attribute("type") == Some("spicevmc"))
+            .map(|channel| Self::child_attribute(channel, "target", "state").unwrap_or_default())
+    }
+    
+    pub fn has_virtio_serial_controller(xml: &str) -> bool {
+        Self::find_elements(xml, "controller")
+            .iter()
+            .any(|controller| Self::extract_attribute_value(controller, "controller", "type").as_deref() == Some("virtio-serial"))
+    }
+    
+    fn cpu_topology_of(domain: roxmltree::Node) -> Option<CpuTopology> {
+        let topology = Self::child(Self::child(domain, "cpu")?, "topology")?;
+        let field = |name: &str| topology.attribute(name)?.parse::<u32>().ok();
+        Some(CpuTopology {
+            sockets: field("sockets")?,
+            cores: field("cores")?,
+            threads: field("threads")?,
+        })
+    }
+    
+    /// `<memoryBacking>` for hugepages and/or the shared memory virtiofs needs;
+    /// None when neither applies
+    pub fn memory_backing_xml(backing: Option<&MemoryBackingConfig>, shared: bool) -> Option<String> {
+        let hugepages = backing.filter(|backing| backing.hugepages).map(|backing| match backing.page_size_kib {
+            Some(size) => format!("\n    <hugepages>\n      <page size='{}' unit='KiB'/>\n    </hugepages>", size),
+            None => "\n    <hugepages/>".to_string(),
+        });
+        if hugepages.is_none() && !shared {
+            return None;
+        }
+
+        let mut xml = format!("<memoryBacking>{}", hugepages.clone().unwrap_or_default());
+        if shared {
+            // Hugepages are already shareable; plain memory has to come from memfd
+            if hugepages.is_none() {
+                xml.push_str("\n    <source type='memfd'/>");
+            }
+            xml.push_str("\n    <access mode='shared'/>");
+        }
+        xml.push_str("\n  </memoryBacking>");
+        Some(xml)
+    }
+
+    pub fn has_shared_memory(xml: &str) -> bool {
+        Self::parse_document(xml, "domain")
+            .ok()
+            .and_then(|document| {
+                let backing = Self::child(document.root_element(), "memoryBacking")?;
+                Some(Self::child_attribute(backing, "access", "mode").as_deref() == Some("shared"))
+            })
+            .unwrap_or(false)
+    }
+
+    pub fn shared_folder_xml(folder: &SharedFolderConfig) -> String {
+        // virtiofs only supports passthrough; mapped keeps 9p files owned by the qemu user
+        let (access_mode, driver) = match folder.driver {
+            SharedFolderDriver::Virtiofs => ("passthrough", "\n  <driver type='virtiofs'/>"),
+            SharedFolderDriver::NineP => ("mapped", ""),
+        };
+        format!(
+            "<filesystem type='mount' accessmode='{}'>{}\n  <source dir='{}'/>\n  <target dir='{}'/>{}\n</filesystem>",
+            access_mode,
+            driver,
+            Self::escape(&folder.host_path),
+            Self::escape(&folder.guest_tag),
+            if folder.readonly { "\n  <readonly/>" } else { "" },
+        )
+    }
+
+    fn parse_shared_folder(filesystem: roxmltree::Node) -> Option<SharedFolderConfig> {
+        if filesystem.attribute("type").unwrap_or("mount") != "mount" {
+            return None;
+        }
+        Some(SharedFolderConfig {
+            host_path: Self::child_attribute(filesystem, "source", "dir")?,
+            guest_tag: Self::child_attribute(filesystem, "target", "dir")?,
+            driver: match Self::child_attribute(filesystem, "driver", "type").as_deref() {
+                Some("virtiofs") => SharedFolderDriver::Virtiofs,
+                _ => SharedFolderDriver::NineP,
+            },
+            readonly: Self::child(filesystem, "readonly").is_some(),
+        })
+    }
+
+    pub fn parse_memory_backing(xml: &str) -> Option<MemoryBackingConfig> {
+        let document = Self::parse_document(xml, "domain").ok()?;
+        let hugepages = Self::child(Self::child(document.root_element(), "memoryBacking")?, "hugepages")?;
+        let page_size_kib = Self::child(hugepages, "page").and_then(|page| {
+            let size = page.attribute("size")?.parse::<u64>().ok()?;
+            Some(match page.attribute("unit").unwrap_or("KiB") {
+                "M" | "MiB" => size * 1024,
+                "G" | "GiB" => size * 1024 * 1024,
+                _ => size,
+            })
+        });
+        Some(MemoryBackingConfig { hugepages: true, page_size_kib })
+    }
+
+    /// The `<vcpupin>` entries of `<cputune>`, ordered by vCPU
+    pub fn parse_vcpu_pins(xml: &str) -> Vec<VcpuPin> {
+        let document = match Self::parse_document(xml, "domain") {
+            Ok(document) => document,
+            Err(_) => return Vec::new(),
+        };
+        let cputune = match Self::child(document.root_element(), "cputune") {
+            Some(cputune) => cputune,
+            None => return Vec::new(),
+        };
+
+        let mut pins: Vec<VcpuPin> = Self::children(cputune, "vcpupin")
+            .filter_map(|pin| Some(VcpuPin {
+                vcpu: pin.attribute("vcpu")?.parse().ok()?,
+                host_cpus: Self::parse_cpuset(pin.attribute("cpuset")?),
+            }))
+            .collect();
+        pins.sort_by_key(|pin| pin.vcpu);
+        pins
+    }
+
+    /// Expand a libvirt cpuset such as `0-3,^2,8` into a sorted list
+    pub fn parse_cpuset(cpuset: &str) -> Vec<u32> {
+        let mut included = std::collections::BTreeSet::new();
+        let mut excluded = std::collections::BTreeSet::new();
+
+        for part in cpuset.split(',').map(str::trim).filter(|part| !part.is_empty()) {
+            let (set, range) = match part.strip_prefix('^') {
+                Some(range) => (&mut excluded, range),
+                None => (&mut included, part),
+            };
+            match range.split_once('-') {
+                Some((start, end)) => {
+                    if let (Ok(start), Ok(end)) = (start.trim().parse::<u32>(), end.trim().parse::<u32>()) {
+                        set.extend(start..=end);
+                    }
+                }
+                None => {
+                    if let Ok(cpu) = range.parse::<u32>() {
+                        set.insert(cpu);
+                    }
+                }
+            }
+        }
+
+        included.difference(&excluded).copied().collect()
+    }
+
+    /// Collapse sorted CPU numbers back into cpuset ranges, e.g. `0-3,8`
+    pub fn format_cpuset(cpus: &[u32]) -> String {
+        let mut ranges: Vec<(u32, u32)> = Vec::new();
+        for &cpu in cpus {
+            match ranges.last_mut() {
+                Some((_, end)) if cpu == *end + 1 => *end = cpu,
+                Some((_, end)) if cpu == *end => {}
+                _ => ranges.push((cpu, cpu)),
+            }
+        }
+        ranges.iter()
+            .map(|(start, end)| if start == end { start.to_string() } else { format!("{}-{}", start, end) })
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    /// Replace the `<vcpupin>` entries of `<cputune>`, keeping its other tuning
+    /// (emulatorpin, shares, ...) intact. An empty `pins` removes the pinning.
+    pub fn set_vcpu_pins(xml: &str, pins: &[VcpuPin]) -> String {
+        let pin_lines: String = pins.iter()
+            .map(|pin| format!(
+                "\n    <vcpupin vcpu='{}' cpuset='{}'/>",
+                pin.vcpu,
+                Self::format_cpuset(&pin.host_cpus)
+            ))
+            .collect();
+
+        let existing = match Self::find_element_span(xml, "cputune") {
+            Some(span) => xml[span].to_string(),
+            None => {
+                if pins.is_empty() {
+                    return xml.to_string();
+                }
+                let cputune = format!("<cputune>{}\n  </cputune>", pin_lines);
+                return Self::upsert_element(xml, "cputune", Some(&cputune), &["iothreads", "vcpu", "currentMemory", "memory"]);
+            }
+        };
+
+        let without_pins = match regex::Regex::new(r"\s*<vcpupin\s[^>]*/>") {
+            Ok(regex) => regex.replace_all(&existing, "").to_string(),
+            Err(_) => return xml.to_string(),
+        };
+        let body = match (without_pins.find('>'), without_pins.rfind("</cputune>")) {
+            (Some(open_end), Some(close)) if open_end < close => without_pins[open_end + 1..close].trim().to_string(),
+            _ => String::new(), // Self-closing <cputune/>
+        };
+
+        let cputune = if body.is_empty() && pins.is_empty() {
+            None
+        } else if body.is_empty() {
+            Some(format!("<cputune>{}\n  </cputune>", pin_lines))
+        } else {
+            Some(format!("<cputune>{}\n    {}\n  </cputune>", pin_lines, body))
+        };
+        Self::upsert_element(xml, "cputune", cputune.as_deref(), &[])
+    }
+
+    /// The complete `<cpu>` element for a new domain
+    pub fn cpu_xml(config: &CpuModelConfig, topology: Option<&CpuTopology>, numa_nodes: &[NumaNodeConfig]) -> String {
+        let (open_tag, mut children) = match &config.model {
+            CpuModel::HostPassthrough => ("<cpu mode='host-passthrough' check='none' migratable='on'", Vec::new()),
+            CpuModel::HostModel => ("<cpu mode='host-model' check='partial'", Vec::new()),
+            CpuModel::Named(model) => (
+                "<cpu mode='custom' match='exact' check='partial'",
+                vec![format!("<model fallback='forbid'>{}</model>", Self::escape(model))],
+            ),
+        };
+
+        if let Some(topology) = topology {
+            children.push(Self::cpu_topology_xml(topology));
+        }
+        for feature in &config.features {
+            children.push(format!(
+                "<feature policy='{}' name='{}'/>",
+                if feature.enabled { "require" } else { "disable" },
+                Self::escape(&feature.name)
+            ));
+        }
+        if !numa_nodes.is_empty() {
+            children.push(Self::numa_xml(numa_nodes));
+        }
+
+        if children.is_empty() {
+            format!("{}/>", open_tag)
+        } else {
+            format!("{}>\n    {}\n  </cpu>", open_tag, children.join("\n    "))
+        }
+    }
+
+    fn cpu_model_of(domain: roxmltree::Node) -> Option<CpuModelConfig> {
+        let cpu = Self::child(domain, "cpu")?;
+        let model = match cpu.attribute("mode").unwrap_or("custom") {
+            "host-passthrough" | "maximum" => CpuModel::HostPassthrough,
+            "host-model" => CpuModel::HostModel,
+            _ => CpuModel::Named(Self::child_text(cpu, "model")?),
+        };
+        let features = Self::children(cpu, "feature")
+            .filter_map(|feature| Some(CpuFeatureFlag {
+                name: feature.attribute("name")?.to_string(),
+                enabled: !matches!(feature.attribute("policy"), Some("disable") | Some("forbid")),
+            }))
+            .collect();
+        Some(CpuModelConfig { model, features })
+    }
+
+    /// Machine types for hardware-virtualized guests of `arch` in the host
+    /// capabilities XML, including those only offered with KVM
+    pub fn parse_machine_types(capabilities_xml: &str, arch: &str) -> Result<Vec<MachineType>> {
+        let document = Self::parse_document(capabilities_xml, "capabilities")?;
+        let mut machines: Vec<MachineType> = Vec::new();
+
+        let arches = Self::children(document.root_element(), "guest")
+            .filter(|guest| Self::child_text(*guest, "os_type").as_deref() == Some("hvm"))
+            .filter_map(|guest| Self::child(guest, "arch"))
+            .filter(|guest_arch| guest_arch.attribute("name") == Some(arch));
+
+        for guest_arch in arches {
+            let lists = std::iter::once(guest_arch).chain(Self::children(guest_arch, "domain"));
+            for machine in lists.flat_map(|list| Self::children(list, "machine")) {
+                let name = Self::node_text(machine);
+                if name.is_empty() || machines.iter().any(|known| known.name == name) {
+                    continue;
+                }
+                machines.push(MachineType {
+                    name,
+                    canonical: machine.attribute("canonical").map(str::to_string),
+                    max_cpus: machine.attribute("maxCpus").and_then(|max| max.parse().ok()),
+                });
+            }
+        }
+
+        Ok(machines)
+    }
+
+    /// The newest q35 machine: what the `q35` alias resolves to, else the
+    /// highest versioned `pc-q35-X.Y`
+    pub fn newest_q35_machine(machines: &[MachineType]) -> Option<String> {
+        if let Some(alias) = machines.iter().find(|machine| machine.name == "q35") {
+            return Some(alias.canonical.clone().unwrap_or_else(|| alias.name.clone()));
+        }
+
+        let version = |name: &str| -> Option<Vec<u32>> {
+            name.strip_prefix("pc-q35-")?
+                .split('.')
+                .map(|part| part.parse().ok())
+                .collect()
+        };
+        machines.iter()
+            .filter_map(|machine| Some((version(&machine.name)?, &machine.name)))
+            .max()
+            .map(|(_, name)| name.clone())
+    }
+
+    /// Named models the hypervisor offers, from the `custom` mode of the
+    /// domain capabilities XML
+    pub fn parse_host_cpu_models(capabilities_xml: &str) -> Result<Vec<HostCpuModel>> {
+        let document = Self::parse_document(capabilities_xml, "domainCapabilities")?;
+        let custom_mode = Self::child(document.root_element(), "cpu")
+            .and_then(|cpu| Self::children(cpu, "mode").find(|mode| mode.attribute("name") == Some("custom")));
+
+        Ok(custom_mode
+            .map(|mode| {
+                Self::children(mode, "model")
+                    .filter_map(|model| Some(HostCpuModel {
+                        name: Some(Self::node_text(model)).filter(|name| !name.is_empty())?,
+                        vendor: model.attribute("vendor").filter(|vendor| *vendor != "unknown").map(str::to_string),
+                        usable: model.attribute("usable") == Some("yes"),
+                    }))
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    /// `<numa>` for the given cells, to be placed inside `<cpu>`
+    pub fn numa_xml(nodes: &[NumaNodeConfig]) -> String {
+        let cells: String = nodes.iter()
+            .enumerate()
+            .map(|(id, node)| format!(
+                "\n      <cell id='{}' cpus='{}' memory='{}' unit='MiB'/>",
+                id,
+                Self::format_cpuset(&node.cpus),
+                node.memory_mb
+            ))
+            .collect();
+        format!("<numa>{}\n    </numa>", cells)
+    }
+
+    fn numa_nodes_of(domain: roxmltree::Node) -> Vec<NumaNodeConfig> {
+        let numa = match Self::child(domain, "cpu").and_then(|cpu| Self::child(cpu, "numa")) {
+            Some(numa) => numa,
+            None => return Vec::new(),
+        };
+
+        Self::children(numa, "cell")
+            .filter_map(|cell| {
+                let memory = cell.attribute("memory")?.parse::<u64>().ok()?;
+                Some(NumaNodeConfig {
+                    cpus: Self::parse_cpuset(cell.attribute("cpus").unwrap_or_default()),
+                    memory_mb: match cell.attribute("unit").unwrap_or("KiB") {
+                        "b" | "bytes" => memory / (1024 * 1024),
+                        "M" | "MiB" => memory,
+                        "G" | "GiB" => memory * 1024,
+                        _ => memory / 1024,
+                    },
+                })
+            })
+            .collect()
+    }
+
+    /// Every `<disk>` with the source details needed to copy or re-point it.
+    /// `xml` is the element exactly as it appears in the document.
+    pub fn disk_elements(xml: &str) -> Vec<DiskElement> {
+        Self::find_elements(xml, "disk")
+            .into_iter()
+            .filter_map(|element| {
+                let document = roxmltree::Document::parse(&element).ok()?;
+                let disk = document.root_element();
+                Some(DiskElement {
+                    device: disk.attribute("device").unwrap_or("disk").to_string(),
+                    disk_type: disk.attribute("type").unwrap_or("file").to_string(),
+                    target: Self::child_attribute(disk, "target", "dev").unwrap_or_default(),
+                    bus: Self::child_attribute(disk, "target", "bus"),
+                    format: Self::child_attribute(disk, "driver", "type"),
+                    file: Self::child_attribute(disk, "source", "file"),
+                    xml: element.clone(),
+                })
+            })
+            .collect()
+    }
+    
+    /// A random address in QEMU's locally administered 52:54:00 range
+    pub fn random_mac() -> String {
+        format!(
+            "52:54:00:{:02x}:{:02x}:{:02x}",
+            rand::random::<u8>(), rand::random::<u8>(), rand::random::<u8>()
+        )
+    }
+    
+    pub fn is_valid_mac(mac: &str) -> bool {
+        let octets: Vec<&str> = mac.split(':').collect();
+        octets.len() == 6 && octets.iter().all(|octet| octet.len() == 2 && u8::from_str_radix(octet, 16).is_ok())
+    }
+    
+    /// Give every `<mac address=...>` a fresh random address in QEMU's 52:54:00 range
+    pub fn regenerate_macs(xml: &str) -> String {
+        match regex::Regex::new(r#"<mac\s+address=['"][^'"]*['"]\s*/>"#) {
+            Ok(regex) => regex.replace_all(xml, |_: &regex::Captures| {
+                format!("<mac address='{}'/>", Self::random_mac())
+            }).to_string(),
+            Err(_) => xml.to_string(),
+        }
+    }
+    
+    /// Image files the guest writes to (CD-ROMs and read-only disks excluded)
+    pub fn writable_disk_files(xml: &str) -> Vec<String> {
+        let Ok(document) = Self::parse_document(xml, "domain") else {
+            return Vec::new();
+        };
+        let Some(devices) = Self::child(document.root_element(), "devices") else {
+            return Vec::new();
+        };
+        Self::children(devices, "disk")
+            .filter(|disk| disk.attribute("device") != Some("cdrom") && Self::child(*disk, "readonly").is_none())
+            .filter_map(|disk| Self::child_attribute(disk, "source", "file"))
+            .collect()
+    }
+    
+    /// Source text of the first device named `element` whose `child` has an
+    /// `attribute` accepted by `matches`, as libvirt expects it for detaching
+    /// or updating that device
+    pub fn find_device(xml: &str, element: &str, child: &str, attribute: &str, matches: impl Fn(&str) -> bool) -> Option<String> {
+        let document = Self::parse_document(xml, "domain").ok()?;
+        let devices = Self::child(document.root_element(), "devices")?;
+        let device = Self::children(devices, element)
+            .find(|device| Self::child(*device, child).and_then(|child| child.attribute(attribute)).is_some_and(&matches))?;
+        Some(xml[device.range()].to_string())
+    }
+    
+    /// Replace, insert or remove a single element in place, leaving the rest of the
+    /// document (including anything the user added by hand) untouched.
+    ///
+    /// `element_xml` is the complete replacement element; `None` removes it. New
+    /// elements are inserted after the first closing tag found in `after`.
+    pub fn upsert_element(xml: &str, element: &str, element_xml: Option<&str>, after: &[&str]) -> String {
+        let document = match roxmltree::Document::parse(xml) {
+            Ok(document) => document,
+            Err(e) => {
+                debug!("Leaving unparseable XML unchanged: {}", e);
+                return xml.to_string();
+            }
+        };
+        let mut updated = xml.to_string();
+        
+        if let Some(existing) = Self::find_element(&document, element).map(|node| node.range()) {
+            match element_xml {
+                Some(element_xml) => updated.replace_range(existing, element_xml),
+                None => {
+                    // Drop the whole line when the element sat on its own
+                    let line_start = updated[..existing.start].trim_end_matches([' ', '\t']).len();
+                    let own_line = updated[..line_start].ends_with('\n') && updated[existing.end..].starts_with('\n');
+                    let range = if own_line { line_start..existing.end + 1 } else { existing };
+                    updated.replace_range(range, "");
+                }
+            }
+            return updated;
+        }
+        
+        let element_xml = match element_xml {
+            Some(element_xml) => element_xml,
+            None => return updated,
+        };
+        
+        for anchor in after {
+            if let Some(anchor) = Self::find_element(&document, anchor) {
+                updated.insert_str(anchor.range().end, &format!("\n  {}", element_xml));
+                return updated;
+            }
+        }
+        
+        // Fall back to the start of the root element, just after its start tag
+        if let Some(first_child) = document.root_element().first_child() {
+            updated.insert_str(first_child.range().start, &format!("\n  {}", element_xml));
+        }
+        updated
+    }
+    
+    /// Replace or insert an element inside `<metadata>`, creating the section if needed
+    pub fn upsert_metadata_element(xml: &str, element: &str, element_xml: Option<&str>) -> String {
+        if Self::find_element_span(xml, "metadata").is_none() {
+            return match element_xml {
+                Some(element_xml) => Self::upsert_element(
+                    xml,
+                    "metadata",
+                    Some(&format!("<metadata>\n    {}\n  </metadata>", element_xml)),
+                    &["description", "title", "uuid", "name"],
+                ),
+                None => xml.to_string(),
+            };
+        }
+        
+        if Self::find_element_span(xml, element).is_some() {
+            return Self::upsert_element(xml, element, element_xml, &[]);
+        }
+        
+        let metadata = match Self::find_element_span(xml, "metadata") {
+            Some(span) => span,
+            None => return xml.to_string(),
+        };
+        let mut updated = xml.to_string();
+        match element_xml {
+            Some(element_xml) if xml[metadata.clone()].ends_with("/>") => {
+                updated.replace_range(metadata, &format!("<metadata>\n    {}\n  </metadata>", element_xml));
+            }
+            Some(element_xml) => {
+                if let Some(close) = xml[metadata.clone()].rfind("</") {
+                    updated.insert_str(metadata.start + close, &format!("  {}\n  ", element_xml));
+                }
+            }
+            None => {}
+        }
+        updated
+    }
+    
+    /// Text content of the first `<element>`, whether or not it has attributes
+    fn element_text(xml: &str, element: &str) -> Option<String> {
+        let element_xml = &xml[Self::find_element_span(xml, element)?];
+        let content_start = element_xml.find('>')? + 1;
+        let content_end = element_xml.rfind("</")?;
+        let text = element_xml.get(content_start..content_end)?.trim();
+        (!text.is_empty()).then(|| text.to_string())
+    }
+    
+    /// Byte range of the first `<element>` (with its content, or self-closing)
+    fn find_element_span(xml: &str, element: &str) -> Option<std::ops::Range<usize>> {
+        let document = roxmltree::Document::parse(xml).ok()?;
+        Self::find_element(&document, element).map(|node| node.range())
+    }
+    
+    /// First element, in document order, named `element` as written in domain
+    /// XML: `prefix:name` for the app's metadata entries, otherwise a plain name
+    /// outside any namespace, so same-named elements in other tools' metadata
+    /// are never picked
+    fn find_element<'a, 'input>(document: &'a roxmltree::Document<'input>, element: &str) -> Option<roxmltree::Node<'a, 'input>> {
+        let (prefix, name) = match element.split_once(':') {
+            Some((prefix, name)) => (Some(prefix), name),
+            None => (None, element),
+        };
+        document.descendants().find(|node| {
+            node.is_element() && node.tag_name().name() == name && match prefix {
+                Some(prefix) => node.lookup_namespace_uri(Some(prefix)).is_some_and(|uri| node.tag_name().namespace() == Some(uri)),
+                None => node.tag_name().namespace().is_none(),
+            }
+        })
+    }
+    
     fn extract_between_tags(xml: &str, tag: &str) -> Option<String> {
         let start_tag = format!("<{}>", tag);
         let end_tag = format!("</{}>", tag);
@@ -72,21 +1091,22 @@ impl XmlParser {
         None
     }
     
-    fn parse_os_info(xml: &str) -> (String, Option<String>) {
+    fn parse_os_info(domain: roxmltree::Node, xml: &str) -> (String, Option<String>) {
         // Look for libosinfo metadata first
-        if xml.contains("libosinfo:os") {
-            if let Some(os_id) = Self::extract_attribute_value(xml, "libosinfo:os", "id") {
-                if os_id.contains("debian") {
-                    return ("linux".to_string(), Some("debian".to_string()));
-                } else if os_id.contains("ubuntu") {
-                    return ("linux".to_string(), Some("ubuntu".to_string()));
-                } else if os_id.contains("fedora") {
-                    return ("linux".to_string(), Some("fedora".to_string()));
-                } else if os_id.contains("rhel") || os_id.contains("centos") {
-                    return ("linux".to_string(), Some("rhel".to_string()));
-                } else if os_id.contains("windows") {
-                    return ("windows".to_string(), Some("win10".to_string()));
-                }
+        let os_id = domain.descendants()
+            .find(|node| node.tag_name().namespace() == Some(LIBOSINFO_NAMESPACE) && node.tag_name().name() == "os")
+            .and_then(|node| node.attribute("id"));
+        if let Some(os_id) = os_id {
+            if os_id.contains("debian") {
+                return ("linux".to_string(), Some("debian".to_string()));
+            } else if os_id.contains("ubuntu") {
+                return ("linux".to_string(), Some("ubuntu".to_string()));
+            } else if os_id.contains("fedora") {
+                return ("linux".to_string(), Some("fedora".to_string()));
+            } else if os_id.contains("rhel") || os_id.contains("centos") {
+                return ("linux".to_string(), Some("rhel".to_string()));
+            } else if os_id.contains("windows") || os_id.contains("microsoft") {
+                return ("windows".to_string(), Some("win10".to_string()));
             }
         }
         
@@ -101,53 +1121,27 @@ impl XmlParser {
         }
     }
     
-    fn parse_storage_devices(xml: &str) -> Vec<StorageDevice> {
-        let mut devices = Vec::new();
-        
-        // Find all disk elements
-        let disk_pattern = r#"<disk\s+[^>]*>"#;
-        if let Ok(regex) = regex::Regex::new(disk_pattern) {
-            for disk_match in regex.find_iter(xml) {
-                let disk_start = disk_match.start();
-                
-                // Find the closing </disk> tag
-                if let Some(disk_end) = xml[disk_start..].find("</disk>") {
-                    let disk_xml = &xml[disk_start..disk_start + disk_end + 7];
-                    
-                    if let Some(device) = Self::parse_single_disk(disk_xml) {
-                        devices.push(device);
-                    }
-                }
-            }
-        }
-        
-        devices
-    }
-    
-    fn parse_single_disk(disk_xml: &str) -> Option<StorageDevice> {
-        let device_type = Self::extract_attribute_value(disk_xml, "disk", "device")?;
-        
-        if device_type != "disk" {
+    fn parse_single_disk(disk: roxmltree::Node) -> Option<StorageDevice> {
+        // libvirt defaults `device` to disk
+        if disk.attribute("device").unwrap_or("disk") != "disk" {
             return None; // Skip CD-ROM, floppy, etc.
         }
         
-        let driver_type = Self::extract_attribute_value(disk_xml, "driver", "type")
+        let driver_type = Self::child_attribute(disk, "driver", "type")
             .unwrap_or_else(|| "raw".to_string());
         
-        let target_dev = Self::extract_attribute_value(disk_xml, "target", "dev")
+        let target_dev = Self::child_attribute(disk, "target", "dev")
             .unwrap_or_else(|| "vda".to_string());
         
-        let target_bus = Self::extract_attribute_value(disk_xml, "target", "bus")
+        let target_bus = Self::child_attribute(disk, "target", "bus")
             .unwrap_or_else(|| "virtio".to_string());
         
         // Handle both file-based and block device sources
-        let source_file = Self::extract_attribute_value(disk_xml, "source", "file");
-        let source_dev = Self::extract_attribute_value(disk_xml, "source", "dev");
-        let source_path = source_file.or(source_dev);
+        let source_path = Self::child_attribute(disk, "source", "file")
+            .or_else(|| Self::child_attribute(disk, "source", "dev"));
         
         // Determine disk type based on XML type attribute
-        let disk_type = Self::extract_attribute_value(disk_xml, "disk", "type")
-            .unwrap_or_else(|| "file".to_string());
+        let disk_type = disk.attribute("type").unwrap_or("file");
         
         // Try to get disk size from file/device if available
         let size_gb = if let Some(path) = &source_path {
@@ -168,14 +1162,13 @@ impl XmlParser {
             size_gb,
             path: source_path,
             bus: target_bus,
-            cache: Self::extract_attribute_value(disk_xml, "driver", "cache"),
+            cache: Self::child_attribute(disk, "driver", "cache"),
         })
     }
     
-    fn get_disk_size_from_file(file_path: &str) -> Option<f64> {
-        use std::process::Command;
-        
-        let output = Command::new("qemu-img")
+    pub fn get_disk_size_from_file(file_path: &str) -> Option<f64> {
+        // Without qemu-img the size is simply left unresolved
+        let output = tools::try_command(Tool::QemuImg)?
             .args(["info", "--output=json", file_path])
             .output()
             .ok()?;
@@ -189,19 +1182,31 @@ impl XmlParser {
         }
     }
     
-    fn get_block_device_size(device_path: &str) -> Option<f64> {
-        use std::process::Command;
-        
-        // Try blockdev --getsize64 first (most reliable for block devices)
-        let output = Command::new("blockdev")
-            .args(["--getsize64", device_path])
+    /// Image format (qcow2, raw, ...) as qemu-img detects it
+    pub fn get_image_format_from_file(file_path: &str) -> Option<String> {
+        let output = tools::try_command(Tool::QemuImg)?
+            .args(["info", "--output=json", file_path])
             .output()
             .ok()?;
         
         if output.status.success() {
-            let size_str = String::from_utf8_lossy(&output.stdout);
-            if let Ok(size_bytes) = size_str.trim().parse::<u64>() {
-                return Some(size_bytes as f64 / 1024.0 / 1024.0 / 1024.0);
+            let info_json: serde_json::Value = serde_json::from_slice(&output.stdout).ok()?;
+            info_json["format"].as_str().map(str::to_string)
+        } else {
+            None
+        }
+    }
+    
+    fn get_block_device_size(device_path: &str) -> Option<f64> {
+        // Try blockdev --getsize64 first (most reliable for block devices)
+        if let Some(mut command) = tools::try_command(Tool::Blockdev) {
+            if let Ok(output) = command.args(["--getsize64", device_path]).output() {
+                if output.status.success() {
+                    let size_str = String::from_utf8_lossy(&output.stdout);
+                    if let Ok(size_bytes) = size_str.trim().parse::<u64>() {
+                        return Some(size_bytes as f64 / 1024.0 / 1024.0 / 1024.0);
+                    }
+                }
             }
         }
         
@@ -220,36 +1225,13 @@ impl XmlParser {
         None
     }
     
-    fn parse_network_interfaces(xml: &str) -> Vec<NetworkInterface> {
-        let mut interfaces = Vec::new();
-        
-        // Find all interface elements
-        let interface_pattern = r#"<interface\s+[^>]*>"#;
-        if let Ok(regex) = regex::Regex::new(interface_pattern) {
-            for interface_match in regex.find_iter(xml) {
-                let interface_start = interface_match.start();
-                
-                // Find the closing </interface> tag
-                if let Some(interface_end) = xml[interface_start..].find("</interface>") {
-                    let interface_xml = &xml[interface_start..interface_start + interface_end + 12];
-                    
-                    if let Some(interface) = Self::parse_single_interface(interface_xml) {
-                        interfaces.push(interface);
-                    }
-                }
-            }
-        }
+    fn parse_single_interface(interface: roxmltree::Node) -> Option<NetworkInterface> {
+        let interface_type = interface.attribute("type")?.to_string();
         
-        interfaces
-    }
-    
-    fn parse_single_interface(interface_xml: &str) -> Option<NetworkInterface> {
-        let interface_type = Self::extract_attribute_value(interface_xml, "interface", "type")?;
-        
-        let mac_address = Self::extract_attribute_value(interface_xml, "mac", "address");
-        let network_source = Self::extract_attribute_value(interface_xml, "source", "network");
-        let bridge_source = Self::extract_attribute_value(interface_xml, "source", "bridge");
-        let model_type = Self::extract_attribute_value(interface_xml, "model", "type")
+        let mac_address = Self::child_attribute(interface, "mac", "address");
+        let network_source = Self::child_attribute(interface, "source", "network");
+        let bridge_source = Self::child_attribute(interface, "source", "bridge");
+        let model_type = Self::child_attribute(interface, "model", "type")
             .unwrap_or_else(|| "rtl8139".to_string());
         
         Some(NetworkInterface {
@@ -257,82 +1239,269 @@ impl XmlParser {
             mac_address,
             source: network_source.or(bridge_source).unwrap_or_else(|| "default".to_string()),
             model: model_type,
-            connected: true, // Assume connected if defined
+            connected: Self::child_attribute(interface, "link", "state").as_deref() != Some("down"),
         })
     }
     
-    fn parse_vnc_port(xml: &str) -> Option<u16> {
-        if xml.contains("type='vnc'") {
-            Self::extract_attribute_value(xml, "graphics", "port")
-                .and_then(|port_str| {
-                    if port_str == "-1" {
-                        None // Auto-allocated port
-                    } else {
-                        port_str.parse().ok()
-                    }
-                })
-        } else {
-            None
+    /// `<graphics>` for a display config; an empty listen address means
+    /// localhost only. The password ends up in the domain XML, where libvirt
+    /// only returns it to callers asking for VIR_DOMAIN_XML_SECURE.
+    pub fn graphics_xml(display: &DisplayConfig) -> String {
+        let listen = display.listen.as_deref().filter(|listen| !listen.is_empty()).unwrap_or("127.0.0.1");
+        let port = match (display.autoport, display.port) {
+            (false, Some(port)) => port.to_string(),
+            _ => "-1".to_string(),
+        };
+        let passwd = display.password.as_ref()
+            .map(|password| format!(" passwd='{}'", Self::escape(password)))
+            .unwrap_or_default();
+        format!(
+            "<graphics type='{}' port='{}' autoport='{}' listen='{}'{}>\n  <listen type='address' address='{}'/>\n</graphics>",
+            Self::escape(display.graphics_type.as_deref().unwrap_or_default()),
+            port,
+            if display.autoport { "yes" } else { "no" },
+            Self::escape(listen),
+            passwd,
+            Self::escape(listen),
+        )
+    }
+    
+    /// `<sound>`, plus the `<audio>` backend that plays it through the SPICE
+    /// client; VNC guests keep libvirt's default backend
+    pub fn sound_xml(model: &str, spice: bool) -> String {
+        let mut xml = format!("<sound model='{}'/>", Self::escape(model));
+        if spice {
+            xml.push_str("\n<audio id='1' type='spice'/>");
         }
+        xml
     }
     
-    fn parse_spice_port(xml: &str) -> Option<u16> {
-        if xml.contains("type='spice'") {
-            Self::extract_attribute_value(xml, "graphics", "port")
-                .and_then(|port_str| {
-                    if port_str == "-1" {
-                        None // Auto-allocated port
-                    } else {
-                        port_str.parse().ok()
-                    }
-                })
-        } else {
-            None
+    pub fn watchdog_xml(watchdog: &WatchdogConfig) -> String {
+        format!(
+            "<watchdog model='{}' action='{}'/>",
+            match watchdog.model {
+                WatchdogModel::I6300esb => "i6300esb",
+                WatchdogModel::Ib700 => "ib700",
+                WatchdogModel::Itco => "itco",
+            },
+            match watchdog.action {
+                WatchdogAction::Reset => "reset",
+                WatchdogAction::Poweroff => "poweroff",
+                WatchdogAction::Pause => "pause",
+                WatchdogAction::None => "none",
+            },
+        )
+    }
+    
+    fn watchdog_of(devices: roxmltree::Node) -> Option<WatchdogConfig> {
+        let watchdog = Self::child(devices, "watchdog")?;
+        Some(WatchdogConfig {
+            model: match watchdog.attribute("model") {
+                Some("ib700") => WatchdogModel::Ib700,
+                Some("itco") => WatchdogModel::Itco,
+                _ => WatchdogModel::I6300esb,
+            },
+            action: match watchdog.attribute("action") {
+                Some("poweroff") => WatchdogAction::Poweroff,
+                Some("pause") => WatchdogAction::Pause,
+                Some("none") => WatchdogAction::None,
+                _ => WatchdogAction::Reset, // libvirt's default
+            },
+        })
+    }
+    
+    pub fn tpm_xml(tpm: &TpmConfig) -> String {
+        format!(
+            "<tpm model='{}'>\n  <backend type='emulator' version='{}'/>\n</tpm>",
+            match tpm.model {
+                TpmModel::Tis => "tpm-tis",
+                TpmModel::Crb => "tpm-crb",
+            },
+            match tpm.version {
+                TpmVersion::V1_2 => "1.2",
+                TpmVersion::V2_0 => "2.0",
+            },
+        )
+    }
+    
+    fn tpm_of(devices: roxmltree::Node) -> Option<TpmConfig> {
+        let tpm = Self::child(devices, "tpm")?;
+        Some(TpmConfig {
+            model: match tpm.attribute("model") {
+                Some("tpm-crb") => TpmModel::Crb,
+                _ => TpmModel::Tis, // libvirt's default when the model is omitted
+            },
+            version: match Self::child_attribute(tpm, "backend", "version").as_deref() {
+                Some("1.2") => TpmVersion::V1_2,
+                _ => TpmVersion::V2_0,
+            },
+        })
+    }
+    
+    /// Display passwords by graphics type; only present in XML read with
+    /// VIR_DOMAIN_XML_SECURE
+    pub fn graphics_passwords(xml: &str) -> Vec<(String, String)> {
+        let Ok(document) = Self::parse_document(xml, "domain") else {
+            return Vec::new();
+        };
+        let Some(devices) = Self::child(document.root_element(), "devices") else {
+            return Vec::new();
+        };
+        Self::children(devices, "graphics")
+            .filter_map(|graphics| Some((graphics.attribute("type")?.to_string(), graphics.attribute("passwd")?.to_string())))
+            .collect()
+    }
+    
+    /// The domain's `<graphics type=protocol>` element with its password
+    /// replaced, ready for update_device_flags; `valid_to` is a UTC time like
+    /// 2024-05-01T12:00:00 after which the password stops working
+    pub fn graphics_with_password(xml: &str, protocol: &str, password: &str, valid_to: Option<&str>) -> Option<String> {
+        let document = Self::parse_document(xml, "domain").ok()?;
+        let devices = Self::child(document.root_element(), "devices")?;
+        let graphics = Self::children(devices, "graphics").find(|graphics| graphics.attribute("type") == Some(protocol))?;
+        
+        let mut attributes = format!(" passwd='{}'", Self::escape(password));
+        if let Some(valid_to) = valid_to {
+            attributes.push_str(&format!(" passwdValidTo='{}'", Self::escape(valid_to)));
+        }
+        for attribute in graphics.attributes().filter(|attribute| !matches!(attribute.name(), "passwd" | "passwdValidTo")) {
+            attributes.push_str(&format!(" {}='{}'", attribute.name(), Self::escape(attribute.value())));
+        }
+        // Children (listen addresses, channels, ...) are kept as written
+        let content = match graphics.first_child() {
+            Some(first) => format!(">{}", &xml[first.range().start..graphics.range().end]),
+            None => "/>".to_string(),
+        };
+        Some(format!("<graphics{}{}", attributes, content))
+    }
+    
+    /// Parse every `<graphics>` element of a domain XML
+    pub fn parse_graphics_devices(xml: &str) -> Vec<GraphicsDevice> {
+        let document = match Self::parse_document(xml, "domain") {
+            Ok(document) => document,
+            Err(_) => return Vec::new(),
+        };
+        Self::child(document.root_element(), "devices")
+            .map(Self::graphics_of)
+            .unwrap_or_default()
+    }
+    
+    fn graphics_of(devices: roxmltree::Node) -> Vec<GraphicsDevice> {
+        Self::children(devices, "graphics")
+            .filter_map(Self::parse_single_graphics)
+            .collect()
+    }
+    
+    fn parse_single_graphics(graphics: roxmltree::Node) -> Option<GraphicsDevice> {
+        let protocol = graphics.attribute("type")?.to_string();
+        
+        let parse_port = |port_str: &str| -> Option<u16> {
+            if port_str == "-1" {
+                None // Auto-allocated port not yet assigned
+            } else {
+                port_str.parse().ok()
+            }
+        };
+        
+        let port = graphics.attribute("port").and_then(parse_port);
+        let tls_port = graphics.attribute("tlsPort").and_then(parse_port);
+        
+        let socket = graphics.attribute("socket").map(str::to_string)
+            .or_else(|| Self::child_attribute(graphics, "listen", "socket"));
+        
+        let listen = graphics.attribute("listen").map(str::to_string)
+            .or_else(|| Self::child_attribute(graphics, "listen", "address"));
+        
+        let autoport = graphics.attribute("autoport") == Some("yes");
+        
+        Some(GraphicsDevice {
+            protocol,
+            port,
+            tls_port,
+            socket,
+            listen,
+            autoport,
+            password_set: graphics.attribute("passwd").is_some(),
+        })
+    }
+    
+    pub fn parse_tags(xml: &str) -> Vec<String> {
+        match Self::parse_document(xml, "domain") {
+            Ok(document) => Self::tags_of(document.root_element()),
+            Err(_) => Vec::new(),
         }
     }
     
-    fn extract_description(xml: &str) -> Option<String> {
-        // Look for description in metadata
-        Self::extract_between_tags(xml, "description")
-            .or_else(|| Self::extract_between_tags(xml, "title"))
+    fn tags_of(domain: roxmltree::Node) -> Vec<String> {
+        let in_namespace = |node: &roxmltree::Node, name: &str| {
+            node.tag_name().namespace() == Some(METADATA_NAMESPACE) && node.tag_name().name() == name
+        };
+        
+        Self::child(domain, "metadata")
+            .and_then(|metadata| metadata.children().find(|node| in_namespace(node, "tags")))
+            .map(|tags| {
+                tags.children()
+                    .filter(|node| in_namespace(node, "tag"))
+                    .map(Self::node_text)
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+    
+    /// Render the `<metadata>` element holding the VM's tags
+    pub fn tags_element(tags: &[String]) -> String {
+        let entries: String = tags.iter()
+            .map(|tag| format!("\n      <kvmmanager:tag>{}</kvmmanager:tag>", Self::escape(tag)))
+            .collect();
+        format!("<{} xmlns:kvmmanager='{}'>{}\n    </{}>", TAGS_ELEMENT, METADATA_NAMESPACE, entries, TAGS_ELEMENT)
     }
     
     /// Parse network configuration from libvirt XML
     pub fn parse_network_from_xml(xml: &str) -> Result<NetworkXmlInfo> {
         debug!("Parsing network XML: {} chars", xml.len());
         
-        let mut network_info = NetworkXmlInfo::default();
+        let document = Self::parse_document(xml, "network")?;
+        let network = document.root_element();
         
         // Parse basic info
-        network_info.name = Self::extract_between_tags(xml, "name")
-            .unwrap_or_else(|| "unknown".to_string());
-        
-        network_info.uuid = Self::extract_between_tags(xml, "uuid")
-            .unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+        let mut network_info = NetworkXmlInfo {
+            name: Self::child_text(network, "name")
+                .unwrap_or_else(|| "unknown".to_string()),
+            uuid: Self::child_text(network, "uuid")
+                .unwrap_or_else(|| uuid::Uuid::new_v4().to_string()),
+            ..Default::default()
+        };
         
-        // Parse forward mode
-        network_info.forward_mode = Self::extract_attribute_value(xml, "forward", "mode")
+        // Parse forward mode; a network without <forward> is isolated, but the UI
+        // has always shown those as NAT
+        network_info.forward_mode = Self::child_attribute(network, "forward", "mode")
             .unwrap_or_else(|| "nat".to_string());
         
         // Parse bridge name
-        network_info.bridge_name = Self::extract_attribute_value(xml, "bridge", "name");
-        
-        // Parse IP configuration
-        if let Some(ip_address) = Self::extract_attribute_value(xml, "ip", "address") {
-            let netmask = Self::extract_attribute_value(xml, "ip", "netmask")
-                .unwrap_or_else(|| "255.255.255.0".to_string());
-            network_info.ip_range = Some(format!("{}/{}", ip_address, Self::netmask_to_cidr(&netmask)));
-        }
+        network_info.bridge_name = Self::child_attribute(network, "bridge", "name");
         
-        // Parse DHCP configuration
-        network_info.dhcp_enabled = xml.contains("<dhcp>");
-        if network_info.dhcp_enabled {
-            network_info.dhcp_start = Self::extract_attribute_value(xml, "range", "start");
-            network_info.dhcp_end = Self::extract_attribute_value(xml, "range", "end");
+        // Parse IP configuration, preferring the IPv4 range
+        let ip = Self::children(network, "ip")
+            .find(|ip| ip.attribute("family").unwrap_or("ipv4") == "ipv4")
+            .or_else(|| Self::child(network, "ip"));
+        if let Some(ip) = ip {
+            if let Some(ip_address) = ip.attribute("address") {
+                let prefix = ip.attribute("prefix")
+                    .and_then(|prefix| prefix.parse::<u8>().ok())
+                    .unwrap_or_else(|| Self::netmask_to_cidr(ip.attribute("netmask").unwrap_or("255.255.255.0")));
+                network_info.ip_range = Some(format!("{}/{}", ip_address, prefix));
+            }
+            
+            // Parse DHCP configuration
+            if let Some(dhcp) = Self::child(ip, "dhcp") {
+                network_info.dhcp_enabled = true;
+                network_info.dhcp_start = Self::child_attribute(dhcp, "range", "start");
+                network_info.dhcp_end = Self::child_attribute(dhcp, "range", "end");
+            }
         }
         
         // Parse domain name
-        network_info.domain = Self::extract_attribute_value(xml, "domain", "name");
+        network_info.domain = Self::child_attribute(network, "domain", "name");
         
         debug!("Parsed network info: name={}, mode={}, dhcp={}", 
                network_info.name, network_info.forward_mode, network_info.dhcp_enabled);
@@ -344,34 +1513,35 @@ impl XmlParser {
     pub fn parse_storage_pool_from_xml(xml: &str) -> Result<StoragePoolXmlInfo> {
         debug!("Parsing storage pool XML: {} chars", xml.len());
         
-        let mut pool_info = StoragePoolXmlInfo::default();
+        let document = Self::parse_document(xml, "pool")?;
+        let pool = document.root_element();
         
         // Parse basic info
-        pool_info.name = Self::extract_between_tags(xml, "name")
-            .unwrap_or_else(|| "unknown".to_string());
-        
-        // Parse pool type from root element
-        if let Some(start) = xml.find("<pool type='") {
-            let start_pos = start + 12; // Length of "<pool type='"
-            if let Some(end) = xml[start_pos..].find("'") {
-                pool_info.pool_type = xml[start_pos..start_pos + end].to_string();
-            }
-        }
+        let mut pool_info = StoragePoolXmlInfo {
+            name: Self::child_text(pool, "name")
+                .unwrap_or_else(|| "unknown".to_string()),
+            pool_type: pool.attribute("type").unwrap_or_default().to_string(),
+            ..Default::default()
+        };
         
         // Parse target path
-        if let Some(path_section) = Self::extract_section(xml, "target") {
-            pool_info.path = Self::extract_between_tags(&path_section, "path");
-        }
+        pool_info.path = Self::child(pool, "target")
+            .and_then(|target| Self::child_text(target, "path"));
         
         // Parse source information for different pool types
-        if let Some(source_section) = Self::extract_section(xml, "source") {
+        if let Some(source) = Self::child(pool, "source") {
             match pool_info.pool_type.as_str() {
                 "logical" => {
-                    pool_info.source_name = Self::extract_between_tags(&source_section, "name");
+                    pool_info.source_name = Self::child_text(source, "name");
+                    pool_info.source_device = Self::child_attribute(source, "device", "path");
+                }
+                "netfs" => {
+                    pool_info.source_host = Self::child_attribute(source, "host", "name");
+                    pool_info.source_path = Self::child_attribute(source, "dir", "path");
                 }
                 "iscsi" => {
-                    pool_info.source_host = Self::extract_attribute_value(&source_section, "host", "name");
-                    pool_info.source_device = Self::extract_attribute_value(&source_section, "device", "path");
+                    pool_info.source_host = Self::child_attribute(source, "host", "name");
+                    pool_info.source_device = Self::child_attribute(source, "device", "path");
                 }
                 _ => {}
             }
@@ -383,19 +1553,6 @@ impl XmlParser {
         Ok(pool_info)
     }
     
-    fn extract_section(xml: &str, section_name: &str) -> Option<String> {
-        let start_tag = format!("<{}>", section_name);
-        let end_tag = format!("</{}>", section_name);
-        
-        if let Some(start_pos) = xml.find(&start_tag) {
-            if let Some(end_pos) = xml[start_pos..].find(&end_tag) {
-                let section_end = start_pos + end_pos + end_tag.len();
-                return Some(xml[start_pos..section_end].to_string());
-            }
-        }
-        None
-    }
-    
     fn netmask_to_cidr(netmask: &str) -> u8 {
         match netmask {
             "255.255.255.255" => 32,
@@ -428,7 +1585,8 @@ impl XmlParser {
     }
     
     fn extract_attribute_value(xml: &str, element: &str, attribute: &str) -> Option<String> {
-        let pattern = format!(r#"<{}\s+[^>]*{}=['""]([^'"]*)['""]"#, element, attribute);
+        // Require whitespace before the attribute so `port` does not match `autoport`
+        let pattern = format!(r#"<{}\s+(?:[^>]*\s)?{}=['""]([^'"]*)['""]"#, element, attribute);
         if let Ok(regex) = regex::Regex::new(&pattern) {
             if let Some(captures) = regex.captures(xml) {
                 return captures.get(1).map(|m| m.as_str().to_string());
@@ -436,7 +1594,7 @@ impl XmlParser {
         }
         
         // Try alternative pattern with different quote order
-        let pattern2 = format!(r#"<{}\s+[^>]*{}=([^>\s]*)"#, element, attribute);
+        let pattern2 = format!(r#"<{}\s+(?:[^>]*\s)?{}=([^>\s]*)"#, element, attribute);
         if let Ok(regex) = regex::Regex::new(&pattern2) {
             if let Some(captures) = regex.captures(xml) {
                 return captures.get(1).map(|m| m.as_str().trim_matches('"').trim_matches('\'').to_string());
@@ -460,7 +1618,44 @@ pub struct VmXmlInfo {
     pub network_interfaces: Vec<NetworkInterface>,
     pub vnc_port: Option<u16>,
     pub spice_port: Option<u16>,
+    pub spice_graphics: bool,
+    pub spice_agent_state: Option<String>, // connected/disconnected, empty when not live
     pub description: Option<String>,
+    pub tags: Vec<String>,
+    pub cpu_topology: Option<CpuTopology>,
+    pub numa_nodes: Vec<NumaNodeConfig>,
+    pub tpm: Option<TpmConfig>,
+    pub shared_folders: Vec<SharedFolderConfig>,
+    pub sound_model: Option<String>,
+    pub watchdog: Option<WatchdogConfig>,
+    pub parse_errors: Vec<String>, // Parts of the XML that could not be read
+}
+
+impl VmXmlInfo {
+    /// Minimal info built from libvirt's own view when the XML is unusable
+    pub fn fallback(name: &str, uuid: &str, memory_mb: u64, vcpus: u32, error: String) -> Self {
+        Self {
+            name: name.to_string(),
+            uuid: uuid.to_string(),
+            memory_mb,
+            vcpus,
+            os_type: "linux".to_string(),
+            os_variant: Some("generic".to_string()),
+            parse_errors: vec![error],
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DiskElement {
+    pub xml: String,
+    pub device: String,    // disk, cdrom, floppy, lun
+    pub disk_type: String, // file, block, network, volume
+    pub target: String,
+    pub bus: Option<String>,
+    pub format: Option<String>,
+    pub file: Option<String>,
 }
 
 #[derive(Debug, Default, Clone)]
@@ -483,5 +1678,576 @@ pub struct StoragePoolXmlInfo {
     pub path: Option<String>,
     pub source_name: Option<String>,
     pub source_host: Option<String>,
+    pub source_path: Option<String>,
     pub source_device: Option<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm_manager::VmManager;
+
+    const DOMAIN_WITH_METADATA: &str = "<domain type='kvm'>
+  <name>meta</name>
+  <uuid>5b3c2f4e-8f8b-4a4c-9d55-0c7a1c7f6a10</uuid>
+  <metadata>
+    <app:info xmlns:app='urn:example:app'>
+      <app:description>Managed by app</app:description>
+    </app:info>
+    <note xmlns='urn:example:note'><description>Hand-written note</description></note>
+  </metadata>
+  <description>Old description</description>
+  <memory unit='KiB'>524288</memory>
+  <vcpu>2</vcpu>
+  <os><type>hvm</type></os>
+  <cpu mode='host-passthrough'>
+    <feature policy='require' name='vmx'/>
+  </cpu>
+  <devices/>
+</domain>";
+
+    fn metadata_of(xml: &str) -> &str {
+        &xml[XmlParser::find_element_span(xml, "metadata").unwrap()]
+    }
+
+    #[test]
+    fn description_update_leaves_custom_metadata_alone() {
+        let updated = XmlParser::upsert_element(
+            DOMAIN_WITH_METADATA,
+            "description",
+            Some("<description>New description</description>"),
+            &["title", "uuid", "name"],
+        );
+
+        assert_eq!(metadata_of(&updated), metadata_of(DOMAIN_WITH_METADATA));
+        assert!(updated.contains("<description>New description</description>"));
+        assert!(!updated.contains("Old description"));
+        assert_eq!(XmlParser::parse_vm_from_xml(&updated).unwrap().description.as_deref(), Some("New description"));
+    }
+
+    #[test]
+    fn description_is_inserted_after_its_anchor_and_removed_cleanly() {
+        let removed = XmlParser::upsert_element(DOMAIN_WITH_METADATA, "description", None, &[]);
+        assert!(!removed.contains("Old description"));
+        assert!(removed.contains("<description>Hand-written note</description>"));
+        assert!(removed.contains("</metadata>\n  <memory"));
+
+        let inserted = XmlParser::upsert_element(&removed, "description", Some("<description>Back</description>"), &["title", "uuid", "name"]);
+        assert!(inserted.contains("</uuid>\n  <description>Back</description>"));
+        assert!(roxmltree::Document::parse(&inserted).is_ok());
+    }
+
+    #[test]
+    fn metadata_entries_are_matched_by_namespace() {
+        let tags = XmlParser::tags_element(&["web".to_string()]);
+        let tagged = XmlParser::upsert_metadata_element(DOMAIN_WITH_METADATA, TAGS_ELEMENT, Some(&tags));
+        assert!(tagged.contains("<app:description>Managed by app</app:description>"));
+        assert_eq!(XmlParser::parse_vm_from_xml(&tagged).unwrap().tags, ["web"]);
+
+        let retagged = XmlParser::upsert_metadata_element(&tagged, TAGS_ELEMENT, Some(&XmlParser::tags_element(&["db".to_string()])));
+        assert_eq!(XmlParser::parse_vm_from_xml(&retagged).unwrap().tags, ["db"]);
+
+        let untagged = XmlParser::upsert_metadata_element(&retagged, TAGS_ELEMENT, None);
+        assert_eq!(metadata_of(&untagged), metadata_of(DOMAIN_WITH_METADATA));
+    }
+
+    #[test]
+    fn cpu_features_are_replaced_inside_cpu_only() {
+        let without_vmx = XmlParser::set_cpu_feature(DOMAIN_WITH_METADATA, "vmx", None);
+        assert!(!without_vmx.contains("name='vmx'"));
+
+        let with_svm = XmlParser::set_cpu_feature(&without_vmx, "svm", Some("require"));
+        let cpu = &with_svm[XmlParser::find_element_span(&with_svm, "cpu").unwrap()];
+        assert_eq!(cpu.matches("<feature").count(), 1);
+        assert!(cpu.contains("<feature policy='require' name='svm'/>"));
+        assert!(roxmltree::Document::parse(&with_svm).is_ok());
+    }
+
+    #[test]
+    fn cpu_element_is_expanded_or_created_for_a_feature() {
+        let self_closing = DOMAIN_WITH_METADATA.replace(
+            "<cpu mode='host-passthrough'>\n    <feature policy='require' name='vmx'/>\n  </cpu>",
+            "<cpu mode='host-passthrough'/>",
+        );
+        let expanded = XmlParser::set_cpu_feature(&self_closing, "vmx", Some("disable"));
+        assert!(expanded.contains("<cpu mode='host-passthrough'>\n    <feature policy='disable' name='vmx'/>\n  </cpu>"));
+
+        let without_cpu = XmlParser::upsert_element(&self_closing, "cpu", None, &[]);
+        let created = XmlParser::set_cpu_feature(&without_cpu, "vmx", Some("require"));
+        assert!(created.contains("<cpu mode='host-model' check='partial'>\n    <feature policy='require' name='vmx'/>\n  </cpu>\n  <devices/>"));
+    }
+
+    fn generated_config() -> VmConfig {
+        serde_json::from_value(serde_json::json!({
+            "name": "generated",
+            "memory": 4096,
+            "vcpus": 4,
+            "disk_size": 40,
+            "os_type": "linux",
+            "cpu_topology": { "sockets": 1, "cores": 2, "threads": 2 },
+            "tpm": { "model": "crb", "version": "2.0" },
+            "network_config": { "network_name": "default", "model": "virtio" },
+            "storage_config": { "pool_name": "default", "format": "qcow2", "bus": "virtio", "cache": "none" },
+            "display_config": { "graphics_type": "spice", "listen": "127.0.0.1", "autoport": true, "sound_model": "ich9" },
+            "boot_config": { "boot_order": ["cdrom", "hd"], "iso_path": "/var/lib/libvirt/images/fedora.iso" },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn parses_a_generated_domain() {
+        let xml = VmManager::generate_vm_xml(&generated_config(), "0c6c8a1e-3b57-4d8e-9a53-51f3f4a0e0d1", "pc-q35-8.2").unwrap();
+        let info = XmlParser::parse_vm_from_xml(&xml).unwrap();
+
+        assert!(info.parse_errors.is_empty(), "{:?}", info.parse_errors);
+        assert_eq!(info.name, "generated");
+        assert_eq!(info.uuid, "0c6c8a1e-3b57-4d8e-9a53-51f3f4a0e0d1");
+        assert_eq!(info.memory_mb, 4096);
+        assert_eq!(info.vcpus, 4);
+        assert_eq!(info.cpu_topology.map(|t| (t.sockets, t.cores, t.threads)), Some((1, 2, 2)));
+
+        // The install ISO is a CD-ROM, not a disk
+        assert_eq!(info.storage_devices.len(), 1);
+        assert_eq!(info.storage_devices[0].device, "vda");
+        assert_eq!(info.storage_devices[0].type_, "qcow2");
+        assert_eq!(info.storage_devices[0].path.as_deref(), Some("/var/lib/libvirt/images/generated.qcow2"));
+
+        assert_eq!(info.network_interfaces.len(), 1);
+        assert_eq!(info.network_interfaces[0].source, "default");
+        assert_eq!(info.network_interfaces[0].model, "virtio");
+
+        assert_eq!(XmlParser::parse_graphics_devices(&xml).len(), 1);
+        assert!(info.spice_graphics);
+        assert_eq!(info.sound_model.as_deref(), Some("ich9"));
+        assert!(info.tpm.is_some_and(|tpm| tpm.model == TpmModel::Crb));
+        assert!(info.watchdog.is_none());
+        assert!(info.shared_folders.is_empty());
+    }
+
+    #[test]
+    fn parses_a_generated_qcow2_import() {
+        let xml = VmManager::generate_qcow2_vm_xml(
+            "imported",
+            "9b1e1f7c-7f41-4a8e-bf0e-3f2d6b1c2a90",
+            "/var/lib/libvirt/images/imported.qcow2",
+            2048,
+            2,
+            "pc-q35-8.2",
+            Some("/dev/disk/by-id/ata-EXAMPLE"),
+            Some("/var/lib/libvirt/images/imported-seed.iso"),
+            None,
+            None,
+        )
+        .unwrap();
+        let info = XmlParser::parse_vm_from_xml(&xml).unwrap();
+
+        assert!(info.parse_errors.is_empty(), "{:?}", info.parse_errors);
+        assert_eq!(info.name, "imported");
+        assert_eq!(info.memory_mb, 2048);
+        assert_eq!(info.vcpus, 2);
+        assert_eq!(info.os_variant.as_deref(), Some("debian"));
+
+        // Main image plus the passed-through disk; the cloud-init seed is a CD-ROM
+        let disks: Vec<_> = info.storage_devices.iter().map(|d| (d.device.as_str(), d.type_.as_str())).collect();
+        assert_eq!(disks, [("vda", "qcow2"), ("vdb", "raw")]);
+        assert_eq!(info.network_interfaces.len(), 1);
+        assert!(info.network_interfaces[0].mac_address.as_deref().is_some_and(|mac| mac.starts_with("52:54:00:")));
+        assert_eq!(XmlParser::parse_graphics_devices(&xml).len(), 1);
+        assert!(info.spice_graphics);
+    }
+
+    /// `virsh dumpxml` of a running Linux server with several disks and NICs
+    const LINUX_DUMPXML: &str = r#"<domain type='kvm' id='3'>
+  <name>web01</name>
+  <uuid>3f1c8d2a-95b4-4c2e-8f61-7a0d3e5b9c12</uuid>
+  <metadata>
+    <libosinfo:libosinfo xmlns:libosinfo="http://libosinfo.org/xmlns/libvirt/domain/1.0">
+      <libosinfo:os id="http://fedoraproject.org/fedora/39"/>
+    </libosinfo:libosinfo>
+  </metadata>
+  <memory unit='KiB'>8388608</memory>
+  <currentMemory unit='KiB'>8388608</currentMemory>
+  <vcpu placement='static'>4</vcpu>
+  <resource>
+    <partition>/machine</partition>
+  </resource>
+  <os>
+    <type arch='x86_64' machine='pc-q35-8.1'>hvm</type>
+    <boot dev='hd'/>
+  </os>
+  <features>
+    <acpi/>
+    <apic/>
+  </features>
+  <cpu mode='host-passthrough' check='none' migratable='on'>
+    <topology sockets='1' dies='1' cores='4' threads='1'/>
+  </cpu>
+  <clock offset='utc'/>
+  <devices>
+    <emulator>/usr/bin/qemu-system-x86_64</emulator>
+    <disk type='file' device='disk'>
+      <driver name='qemu' type='qcow2' discard='unmap'/>
+      <source file='/var/lib/libvirt/images/web01.qcow2' index='3'/>
+      <backingStore/>
+      <target dev='vda' bus='virtio'/>
+      <alias name='virtio-disk0'/>
+      <address type='pci' domain='0x0000' bus='0x04' slot='0x00' function='0x0'/>
+    </disk>
+    <disk type='block' device='disk'>
+      <driver name='qemu' type='raw' cache='none' io='native'/>
+      <source dev='/dev/vg_data/web01-data' index='2'/>
+      <backingStore/>
+      <target dev='vdb' bus='virtio'/>
+      <alias name='virtio-disk1'/>
+      <address type='pci' domain='0x0000' bus='0x05' slot='0x00' function='0x0'/>
+    </disk>
+    <disk type='file' device='cdrom'>
+      <driver name='qemu' type='raw'/>
+      <target dev='sda' bus='sata'/>
+      <readonly/>
+      <alias name='sata0-0-0'/>
+      <address type='drive' controller='0' bus='0' target='0' unit='0'/>
+    </disk>
+    <controller type='usb' index='0' model='qemu-xhci' ports='15'>
+      <alias name='usb'/>
+    </controller>
+    <controller type='sata' index='0'>
+      <alias name='ide'/>
+    </controller>
+    <controller type='virtio-serial' index='0'>
+      <alias name='virtio-serial0'/>
+    </controller>
+    <interface type='network'>
+      <mac address='52:54:00:6b:3c:58'/>
+      <source network='default' portid='5a0f0b8e-0d0e-4c4d-9b8a-1c2d3e4f5a6b' bridge='virbr0'/>
+      <target dev='vnet2'/>
+      <model type='virtio'/>
+      <alias name='net0'/>
+      <address type='pci' domain='0x0000' bus='0x01' slot='0x00' function='0x0'/>
+    </interface>
+    <interface type='bridge'>
+      <mac address='52:54:00:a1:07:e4'/>
+      <source bridge='br0'/>
+      <target dev='vnet3'/>
+      <model type='virtio'/>
+      <link state='down'/>
+      <alias name='net1'/>
+      <address type='pci' domain='0x0000' bus='0x07' slot='0x00' function='0x0'/>
+    </interface>
+    <serial type='pty'>
+      <source path='/dev/pts/4'/>
+      <target type='isa-serial' port='0'/>
+      <alias name='serial0'/>
+    </serial>
+    <channel type='unix'>
+      <source mode='bind' path='/run/libvirt/qemu/channel/3-web01/org.qemu.guest_agent.0'/>
+      <target type='virtio' name='org.qemu.guest_agent.0' state='connected'/>
+      <alias name='channel0'/>
+    </channel>
+    <graphics type='vnc' port='5900' autoport='yes' listen='127.0.0.1'>
+      <listen type='address' address='127.0.0.1'/>
+    </graphics>
+    <video>
+      <model type='virtio' heads='1' primary='yes'/>
+      <alias name='video0'/>
+    </video>
+    <watchdog model='i6300esb' action='reset'>
+      <alias name='watchdog0'/>
+    </watchdog>
+    <memballoon model='virtio'>
+      <alias name='balloon0'/>
+    </memballoon>
+  </devices>
+  <seclabel type='dynamic' model='selinux' relabel='yes'>
+    <label>system_u:system_r:svirt_t:s0:c123,c456</label>
+  </seclabel>
+</domain>"#;
+
+    /// `virsh dumpxml` of a shut-off Windows 11 guest with install media attached
+    const WINDOWS_DUMPXML: &str = r#"<domain type='kvm'>
+  <name>win11</name>
+  <uuid>a7d4e2b9-1c3f-4e5a-8b6d-0f9e8d7c6b5a</uuid>
+  <description>Gaming VM</description>
+  <metadata>
+    <libosinfo:libosinfo xmlns:libosinfo="http://libosinfo.org/xmlns/libvirt/domain/1.0">
+      <libosinfo:os id="http://microsoft.com/win/11"/>
+    </libosinfo:libosinfo>
+  </metadata>
+  <memory unit='KiB'>16777216</memory>
+  <currentMemory unit='KiB'>16777216</currentMemory>
+  <vcpu placement='static'>8</vcpu>
+  <os firmware='efi'>
+    <type arch='x86_64' machine='pc-q35-8.1'>hvm</type>
+    <loader readonly='yes' secure='yes' type='pflash'>/usr/share/edk2/ovmf/OVMF_CODE.secboot.fd</loader>
+    <nvram template='/usr/share/edk2/ovmf/OVMF_VARS.secboot.fd'>/var/lib/libvirt/qemu/nvram/win11_VARS.fd</nvram>
+  </os>
+  <features>
+    <acpi/>
+    <apic/>
+    <hyperv mode='custom'>
+      <relaxed state='on'/>
+      <vapic state='on'/>
+      <spinlocks state='on' retries='8191'/>
+    </hyperv>
+    <smm state='on'/>
+  </features>
+  <cpu mode='host-passthrough' check='none' migratable='on'>
+    <topology sockets='1' dies='1' cores='4' threads='2'/>
+  </cpu>
+  <devices>
+    <emulator>/usr/bin/qemu-system-x86_64</emulator>
+    <disk type='file' device='disk'>
+      <driver name='qemu' type='qcow2' cache='writeback'/>
+      <source file='/var/lib/libvirt/images/win11.qcow2'/>
+      <target dev='sda' bus='sata'/>
+      <boot order='1'/>
+      <address type='drive' controller='0' bus='0' target='0' unit='0'/>
+    </disk>
+    <disk type='file' device='disk'>
+      <driver name='qemu' type='qcow2'/>
+      <source file='/var/lib/libvirt/images/win11-games.qcow2'/>
+      <target dev='vda' bus='virtio'/>
+      <address type='pci' domain='0x0000' bus='0x06' slot='0x00' function='0x0'/>
+    </disk>
+    <disk type='file' device='cdrom'>
+      <driver name='qemu' type='raw'/>
+      <source file='/var/lib/libvirt/images/Win11_23H2_English_x64.iso'/>
+      <target dev='sdb' bus='sata'/>
+      <readonly/>
+      <boot order='2'/>
+      <address type='drive' controller='0' bus='0' target='0' unit='1'/>
+    </disk>
+    <disk type='file' device='cdrom'>
+      <driver name='qemu' type='raw'/>
+      <source file='/var/lib/libvirt/images/virtio-win.iso'/>
+      <target dev='sdc' bus='sata'/>
+      <readonly/>
+      <address type='drive' controller='0' bus='0' target='0' unit='2'/>
+    </disk>
+    <controller type='sata' index='0'/>
+    <interface type='network'>
+      <mac address='52:54:00:1d:9e:20'/>
+      <source network='default'/>
+      <model type='e1000e'/>
+      <address type='pci' domain='0x0000' bus='0x01' slot='0x00' function='0x0'/>
+    </interface>
+    <interface type='network'>
+      <mac address='52:54:00:1d:9e:21'/>
+      <source network='isolated'/>
+      <model type='virtio'/>
+      <address type='pci' domain='0x0000' bus='0x02' slot='0x00' function='0x0'/>
+    </interface>
+    <interface type='direct'>
+      <mac address='52:54:00:1d:9e:22'/>
+      <source dev='enp5s0' mode='bridge'/>
+      <model type='virtio'/>
+      <address type='pci' domain='0x0000' bus='0x03' slot='0x00' function='0x0'/>
+    </interface>
+    <graphics type='spice' autoport='yes'>
+      <listen type='address'/>
+      <image compression='off'/>
+    </graphics>
+    <graphics type='vnc' port='5905' autoport='no' listen='0.0.0.0'>
+      <listen type='address' address='0.0.0.0'/>
+    </graphics>
+    <sound model='ich9'/>
+    <audio id='1' type='spice'/>
+    <video>
+      <model type='qxl' ram='65536' vram='65536' vgamem='16384' heads='1' primary='yes'/>
+    </video>
+    <tpm model='tpm-crb'>
+      <backend type='emulator' version='2.0'/>
+    </tpm>
+    <memballoon model='virtio'/>
+  </devices>
+</domain>"#;
+
+    #[test]
+    fn parses_a_linux_dumpxml_with_several_disks_and_nics() {
+        let info = XmlParser::parse_vm_from_xml(LINUX_DUMPXML).unwrap();
+
+        assert!(info.parse_errors.is_empty(), "{:?}", info.parse_errors);
+        assert_eq!(info.name, "web01");
+        assert_eq!(info.memory_mb, 8192);
+        assert_eq!(info.vcpus, 4);
+        assert_eq!(info.os_variant.as_deref(), Some("fedora"));
+
+        let disks: Vec<_> = info.storage_devices.iter()
+            .map(|d| (d.device.as_str(), d.type_.as_str(), d.path.as_deref()))
+            .collect();
+        assert_eq!(disks, [
+            ("vda", "qcow2", Some("/var/lib/libvirt/images/web01.qcow2")),
+            ("vdb", "raw", Some("/dev/vg_data/web01-data")),
+        ]);
+
+        let nics: Vec<_> = info.network_interfaces.iter()
+            .map(|n| (n.type_.as_str(), n.source.as_str(), n.connected))
+            .collect();
+        assert_eq!(nics, [("network", "default", true), ("bridge", "br0", false)]);
+
+        assert_eq!(XmlParser::parse_graphics_devices(LINUX_DUMPXML).len(), 1);
+        assert_eq!(info.vnc_port, Some(5900));
+        assert!(!info.spice_graphics);
+        assert!(info.watchdog.is_some());
+        assert!(info.tpm.is_none());
+        assert!(info.sound_model.is_none());
+    }
+
+    #[test]
+    fn parses_a_windows_dumpxml_with_several_disks_and_nics() {
+        let info = XmlParser::parse_vm_from_xml(WINDOWS_DUMPXML).unwrap();
+
+        assert!(info.parse_errors.is_empty(), "{:?}", info.parse_errors);
+        assert_eq!(info.name, "win11");
+        assert_eq!(info.memory_mb, 16384);
+        assert_eq!(info.vcpus, 8);
+        assert_eq!(info.os_type, "windows");
+        assert_eq!(info.description.as_deref(), Some("Gaming VM"));
+        assert_eq!(info.cpu_topology.map(|t| (t.sockets, t.cores, t.threads)), Some((1, 4, 2)));
+
+        // Two disks; both install ISOs are CD-ROMs
+        let disks: Vec<_> = info.storage_devices.iter().map(|d| (d.device.as_str(), d.bus.as_str())).collect();
+        assert_eq!(disks, [("sda", "sata"), ("vda", "virtio")]);
+
+        let nics: Vec<_> = info.network_interfaces.iter()
+            .map(|n| (n.type_.as_str(), n.model.as_str(), n.mac_address.as_deref()))
+            .collect();
+        assert_eq!(nics, [
+            ("network", "e1000e", Some("52:54:00:1d:9e:20")),
+            ("network", "virtio", Some("52:54:00:1d:9e:21")),
+            ("direct", "virtio", Some("52:54:00:1d:9e:22")),
+        ]);
+
+        assert_eq!(XmlParser::parse_graphics_devices(WINDOWS_DUMPXML).len(), 2);
+        assert!(info.spice_graphics);
+        assert_eq!(info.spice_port, None); // Autoport, not allocated while shut off
+        assert_eq!(info.vnc_port, Some(5905));
+        assert_eq!(info.sound_model.as_deref(), Some("ich9"));
+        assert_eq!(info.tpm, Some(TpmConfig { model: TpmModel::Crb, version: TpmVersion::V2_0 }));
+        assert!(info.watchdog.is_none());
+    }
+
+    // Live XML of a running domain: libvirt has filled in the autoport ports
+    const RUNNING_VNC_AND_SPICE: &str = "<domain type='kvm' id='7'>
+  <name>desktop</name>
+  <devices>
+    <graphics type='vnc' port='5900' autoport='yes' listen='127.0.0.1' passwd='secret'>
+      <listen type='address' address='127.0.0.1'/>
+    </graphics>
+    <graphics type='spice' port='5901' tlsPort='5902' autoport='yes'>
+      <listen type='address' address='0.0.0.0'/>
+    </graphics>
+    <graphics type='vnc' autoport='no'>
+      <listen type='socket' socket='/run/libvirt/qemu/desktop.vnc'/>
+    </graphics>
+  </devices>
+</domain>";
+
+    #[test]
+    fn parses_vnc_and_spice_graphics_of_a_running_domain() {
+        let devices = XmlParser::parse_graphics_devices(RUNNING_VNC_AND_SPICE);
+        let protocols: Vec<_> = devices.iter().map(|g| g.protocol.as_str()).collect();
+        assert_eq!(protocols, ["vnc", "spice", "vnc"]);
+
+        let vnc = &devices[0];
+        assert_eq!(vnc.port, Some(5900));
+        assert_eq!(vnc.tls_port, None);
+        assert_eq!(vnc.listen.as_deref(), Some("127.0.0.1"));
+        assert!(vnc.autoport);
+        assert!(vnc.password_set);
+
+        // The listen address comes from the <listen> child when the attribute is absent
+        let spice = &devices[1];
+        assert_eq!(spice.port, Some(5901));
+        assert_eq!(spice.tls_port, Some(5902));
+        assert_eq!(spice.listen.as_deref(), Some("0.0.0.0"));
+        assert!(!spice.password_set);
+
+        let socket = &devices[2];
+        assert_eq!(socket.port, None);
+        assert_eq!(socket.socket.as_deref(), Some("/run/libvirt/qemu/desktop.vnc"));
+        assert!(!socket.autoport);
+    }
+
+    #[test]
+    fn unallocated_ports_are_reported_as_none() {
+        let xml = "<domain><devices><graphics type='spice' port='-1' tlsPort='-1' autoport='yes'/></devices></domain>";
+        let devices = XmlParser::parse_graphics_devices(xml);
+
+        assert_eq!(devices.len(), 1);
+        assert_eq!((devices[0].port, devices[0].tls_port), (None, None));
+    }
+
+    #[test]
+    fn password_replaces_the_old_one_and_keeps_the_rest_of_the_element() {
+        let graphics = XmlParser::graphics_with_password(RUNNING_VNC_AND_SPICE, "vnc", "n&w", Some("2024-05-01T12:00:00")).unwrap();
+
+        let document = roxmltree::Document::parse(&graphics).unwrap();
+        let element = document.root_element();
+        assert_eq!(element.attribute("passwd"), Some("n&w"));
+        assert_eq!(element.attribute("passwdValidTo"), Some("2024-05-01T12:00:00"));
+        assert_eq!(element.attribute("port"), Some("5900"));
+        assert_eq!(element.attributes().filter(|a| a.name() == "passwd").count(), 1);
+        assert_eq!(XmlParser::child_attribute(element, "listen", "address").as_deref(), Some("127.0.0.1"));
+
+        let spice = XmlParser::graphics_with_password(RUNNING_VNC_AND_SPICE, "spice", "pw", None).unwrap();
+        assert!(spice.starts_with("<graphics passwd='pw' type='spice'"));
+        assert!(XmlParser::graphics_with_password(RUNNING_VNC_AND_SPICE, "rdp", "pw", None).is_none());
+    }
+
+    const PASSTHROUGH_AND_SHARED: &str = "<domain type='kvm'>
+  <name>passthrough</name>
+  <devices>
+    <disk type='file' device='disk'>
+      <source file='/var/lib/libvirt/images/root.qcow2'/>
+      <target dev='vda' bus='virtio'/>
+    </disk>
+    <disk type='file' device='disk'>
+      <source file='/var/lib/libvirt/images/golden.qcow2'/>
+      <target dev='vdb' bus='virtio'/>
+      <readonly/>
+    </disk>
+    <disk type='file' device='cdrom'>
+      <source file='/isos/install.iso'/>
+      <target dev='sda' bus='sata'/>
+    </disk>
+    <interface type=\"hostdev\" managed=\"yes\">
+      <mac address=\"52:54:00:AA:BB:CC\"/>
+      <source><address type='pci' domain='0x0000' bus='0x03' slot='0x10' function='0x2'/></source>
+    </interface>
+    <hostdev mode='subsystem' type='pci' managed='yes'>
+      <source><address domain='0x0000' bus='0x01' slot='0x00' function='0x0'/></source>
+      <address type='pci' domain='0x0000' bus='0x07' slot='0x00' function='0x0'/>
+    </hostdev>
+    <hostdev mode='subsystem' type='usb'>
+      <source><vendor id='0x046d'/><product id='0xc52b'/></source>
+    </hostdev>
+    <filesystem type='mount' accessmode='passthrough'>
+      <source dir='/srv/share'/>
+      <target dir='share'/>
+    </filesystem>
+  </devices>
+</domain>";
+
+    #[test]
+    fn hostdev_addresses_come_from_the_host_side_source() {
+        assert_eq!(XmlParser::hostdev_pci_addresses(PASSTHROUGH_AND_SHARED), ["0000:03:10.2", "0000:01:00.0"]);
+    }
+
+    #[test]
+    fn writable_disks_leave_out_cdroms_and_read_only_images() {
+        assert_eq!(XmlParser::writable_disk_files(PASSTHROUGH_AND_SHARED), ["/var/lib/libvirt/images/root.qcow2"]);
+    }
+
+    #[test]
+    fn devices_are_found_by_a_child_attribute() {
+        let disk = XmlParser::find_device(PASSTHROUGH_AND_SHARED, "disk", "target", "dev", |dev| dev == "vdb").unwrap();
+        assert!(disk.starts_with("<disk") && disk.ends_with("</disk>") && disk.contains("golden.qcow2"));
+
+        let nic = XmlParser::find_device(PASSTHROUGH_AND_SHARED, "interface", "mac", "address", |mac| mac.eq_ignore_ascii_case("52:54:00:aa:bb:cc"));
+        assert!(nic.is_some_and(|nic| nic.contains("bus='0x03'")));
+
+        assert!(XmlParser::find_device(PASSTHROUGH_AND_SHARED, "filesystem", "target", "dir", |dir| dir == "share").is_some());
+        assert!(XmlParser::find_device(PASSTHROUGH_AND_SHARED, "filesystem", "target", "dir", |dir| dir == "sha").is_none());
+    }
+}
@@ -0,0 +1,221 @@
+//! Integration tests against libvirt's in-memory test driver.
+//!
+//! `test:///default` starts every connection with the stock `test` domain,
+//! `default` network and `default-pool`, and keeps no state between
+//! connections, so each test opens its own manager and uses its own names.
+//!
+//! Needs the libvirt client library; run with
+//! `cargo test --features libvirt-tests --test libvirt_test_driver`.
+
+use kvm_manager_lib::settings::apply_hardware_defaults;
+use kvm_manager_lib::types::{RecoveryOptions, SnapshotKind, VmConfig, VmState};
+use kvm_manager_lib::vm_manager::VmManager;
+use kvm_manager_lib::xml_parser::XmlParser;
+use regex::Regex;
+use serde_json::json;
+
+const TEST_URI: &str = "test:///default";
+
+async fn manager() -> VmManager {
+    VmManager::with_uri(Some(TEST_URI)).await.expect("test driver connection")
+}
+
+/// Generate domain XML the way `create_vm` would, with the built-in hardware
+/// defaults filled in, then adjust it for the test driver: it only accepts
+/// `type='test'` and knows neither the host's machine types nor its emulator.
+fn test_domain_xml(manager: &VmManager, name: &str) -> String {
+    let config: VmConfig = serde_json::from_value(json!({
+        "name": name,
+        "memory": 512,
+        "vcpus": 1,
+        "disk_size": 1,
+        "os_type": "linux",
+    }))
+    .unwrap();
+    let config = apply_hardware_defaults(config, None);
+
+    let xml = manager.preview_vm_xml(&config).expect("generated XML");
+    let xml = xml.replacen("<domain type='kvm'>", "<domain type='test'>", 1);
+    let xml = Regex::new(r" machine='[^']*'").unwrap().replace(&xml, "").into_owned();
+    Regex::new(r"\s*<emulator>[^<]*</emulator>").unwrap().replace(&xml, "").into_owned()
+}
+
+/// Define a generated domain through the XML import path and return its UUID
+async fn define_test_domain(manager: &mut VmManager, name: &str) -> String {
+    let xml = test_domain_xml(manager, name);
+    define_domain_xml(manager, &xml).await
+}
+
+async fn define_domain_xml(manager: &mut VmManager, xml: &str) -> String {
+    let file = tempfile::Builder::new().suffix(".xml").tempfile().unwrap();
+    std::fs::write(file.path(), xml).unwrap();
+    manager.import_vm_from_xml(file.path().to_str().unwrap()).await.expect("define from generated XML")
+}
+
+async fn state_of(manager: &VmManager, id: &str) -> Option<VmState> {
+    manager.list_vms().await.unwrap().into_iter().find(|vm| vm.id == id).map(|vm| vm.state)
+}
+
+async fn is_running(manager: &VmManager, id: &str) -> bool {
+    matches!(state_of(manager, id).await, Some(VmState::Running))
+}
+
+async fn is_stopped(manager: &VmManager, id: &str) -> bool {
+    matches!(state_of(manager, id).await, Some(VmState::Stopped))
+}
+
+#[tokio::test]
+async fn lists_the_stock_test_domain() {
+    let manager = manager().await;
+
+    let vms = manager.list_vms().await.unwrap();
+    let test = vms.iter().find(|vm| vm.name == "test").expect("stock test domain");
+    assert!(matches!(test.state, VmState::Running));
+    assert!(test.memory > 0);
+    assert!(test.vcpus > 0);
+}
+
+#[tokio::test]
+async fn defines_a_domain_from_generated_xml() {
+    let mut manager = manager().await;
+
+    let id = define_test_domain(&mut manager, "kvmm-define").await;
+
+    let vms = manager.list_vms().await.unwrap();
+    let vm = vms.iter().find(|vm| vm.id == id).expect("defined domain is listed");
+    assert_eq!(vm.name, "kvmm-define");
+    assert!(matches!(vm.state, VmState::Stopped));
+    assert_eq!(vm.memory, 512);
+    assert_eq!(vm.vcpus, 1);
+}
+
+#[tokio::test]
+async fn starts_stops_and_deletes_a_domain() {
+    let mut manager = manager().await;
+    let id = define_test_domain(&mut manager, "kvmm-lifecycle").await;
+
+    manager.start_vm(&id, &RecoveryOptions::default()).await.unwrap();
+    assert!(is_running(&manager, &id).await);
+
+    // The test driver powers off as soon as shutdown is requested
+    let domain = manager.stop_vm(&id).await.unwrap().expect("domain was running");
+    VmManager::wait_for_shutdown(&domain, &id, 5).await.unwrap();
+    assert!(is_stopped(&manager, &id).await);
+
+    manager.start_vm(&id, &RecoveryOptions::default()).await.unwrap();
+    manager.force_stop_vm(&id).await.unwrap();
+    assert!(is_stopped(&manager, &id).await);
+
+    manager.delete_vm(&id).await.unwrap();
+    assert!(state_of(&manager, &id).await.is_none());
+}
+
+#[tokio::test]
+async fn deleting_a_running_domain_powers_it_off_first() {
+    let mut manager = manager().await;
+    let id = define_test_domain(&mut manager, "kvmm-delete-running").await;
+    manager.start_vm(&id, &RecoveryOptions::default()).await.unwrap();
+
+    manager.delete_vm(&id).await.unwrap();
+    assert!(state_of(&manager, &id).await.is_none());
+}
+
+#[tokio::test]
+async fn snapshots_round_trip_including_names_with_a_leading_dash() {
+    let mut manager = manager().await;
+    let id = define_test_domain(&mut manager, "kvmm-snapshots").await;
+    // Only a running guest has memory for a full snapshot
+    manager.start_vm(&id, &RecoveryOptions::default()).await.unwrap();
+
+    for name in ["before-upgrade", "-pre-release"] {
+        let snapshot = manager.create_snapshot(&id, name, Some(SnapshotKind::Full)).await.unwrap();
+        assert_eq!(snapshot.name, name);
+        assert!(snapshot.includes_memory);
+    }
+    let mut names: Vec<String> = manager.list_snapshots(&id).await.unwrap().into_iter().map(|s| s.name).collect();
+    names.sort();
+    assert_eq!(names, ["-pre-release", "before-upgrade"]);
+
+    manager.restore_snapshot(&id, "-pre-release").await.unwrap();
+    manager.delete_snapshot(&id, "-pre-release").await.unwrap();
+    let names: Vec<String> = manager.list_snapshots(&id).await.unwrap().into_iter().map(|s| s.name).collect();
+    assert_eq!(names, ["before-upgrade"]);
+
+    assert!(manager.create_snapshot(&id, "bad\nname", Some(SnapshotKind::Full)).await.is_err());
+    assert!(manager.delete_snapshot(&id, "missing").await.is_err());
+}
+
+#[tokio::test]
+async fn reports_vnc_and_spice_graphics_of_a_running_domain() {
+    let mut manager = manager().await;
+    let xml = test_domain_xml(&manager, "kvmm-graphics").replacen(
+        "</devices>",
+        "<graphics type='spice' port='5930' tlsPort='5931' autoport='no' listen='127.0.0.1'/></devices>",
+        1,
+    );
+    let id = define_domain_xml(&mut manager, &xml).await;
+    manager.start_vm(&id, &RecoveryOptions::default()).await.unwrap();
+
+    let graphics = manager.get_active_graphics(&id).await.unwrap();
+    let mut protocols: Vec<&str> = graphics.iter().map(|g| g.protocol.as_str()).collect();
+    protocols.sort();
+    assert_eq!(protocols, ["spice", "vnc"]);
+
+    let spice = graphics.iter().find(|g| g.protocol == "spice").unwrap();
+    assert_eq!((spice.port, spice.tls_port), (Some(5930), Some(5931)));
+    assert_eq!(spice.listen.as_deref(), Some("127.0.0.1"));
+}
+
+#[tokio::test]
+async fn refuses_clone_names_that_are_paths() {
+    let mut manager = manager().await;
+    let id = define_test_domain(&mut manager, "kvmm-clone-source").await;
+
+    for name in ["../x", "a/b", ".."] {
+        assert!(manager.clone_vm(&id, name, true, false).await.is_err(), "{:?} should be refused", name);
+        assert!(manager.clone_vm_linked(&id, name).await.is_err(), "{:?} should be refused", name);
+    }
+    let names: Vec<String> = manager.list_vms().await.unwrap().into_iter().map(|vm| vm.name).collect();
+    assert!(!names.iter().any(|name| name.contains('/') || name == ".."), "{:?}", names);
+}
+
+#[tokio::test]
+async fn lists_the_stock_storage_pool() {
+    let manager = manager().await;
+
+    let pools = manager.get_storage_pools().await.unwrap();
+    let pool = pools.iter().find(|pool| pool.name == "default-pool").expect("stock default-pool");
+    assert_eq!(pool.pool_type, "dir");
+    assert!(pool.capacity_bytes > 0);
+    assert!(pool.available_bytes <= pool.capacity_bytes);
+}
+
+#[tokio::test]
+async fn parses_the_stock_test_domain() {
+    let manager = manager().await;
+    let test = manager.list_vms().await.unwrap().into_iter().find(|vm| vm.name == "test").unwrap();
+
+    let xml = manager.get_vm_xml(&test.id).await.unwrap();
+    let info = XmlParser::parse_vm_from_xml(&xml).unwrap();
+    assert_eq!(info.name, "test");
+    assert_eq!(info.uuid, test.id);
+    assert!(info.memory_mb > 0);
+    assert!(info.vcpus > 0);
+    assert!(info.parse_errors.is_empty(), "{:?}", info.parse_errors);
+}
+
+#[tokio::test]
+async fn parses_a_generated_domain_after_libvirt_normalises_it() {
+    let mut manager = manager().await;
+    let id = define_test_domain(&mut manager, "kvmm-parse").await;
+
+    let xml = manager.get_vm_xml(&id).await.unwrap();
+    let info = XmlParser::parse_vm_from_xml(&xml).unwrap();
+    assert_eq!(info.name, "kvmm-parse");
+    assert_eq!(info.uuid, id);
+    assert_eq!(info.memory_mb, 512);
+    assert_eq!(info.vcpus, 1);
+    assert_eq!(info.storage_devices.len(), 1);
+    assert_eq!(info.network_interfaces.len(), 1);
+    assert!(info.parse_errors.is_empty(), "{:?}", info.parse_errors);
+}
@@ -47,9 +47,15 @@ interface ImportVmDialogProps {
 interface QcowInfo {
   path: string;
   filename: string;
+  /** Size on disk in GiB. @deprecated Use `size_bytes`; removed in the next release. */
   size_gb: number;
   format: string;
+  /** Virtual size in GiB. @deprecated Use `virtual_size_bytes`; removed in the next release. */
   virtual_size_gb: number;
+  size_bytes: number;
+  virtual_size_bytes: number;
+  size_display: string;
+  virtual_size_display: string;
   cluster_size?: number;
   backing_file?: string;
 }
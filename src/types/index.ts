@@ -6,17 +6,119 @@ export interface VirtualMachine {
   state: VmState;
   memory: number;        // Memory in MB
   vcpus: number;
+  max_vcpus: number;     // Hot-plug ceiling; vcpus can grow to this while running
   disk_size: number;     // Disk size in GB
   os_type: string;
   os_variant?: string;
   created_at: string;
   last_started?: string;
   description?: string;
+  tags: string[];
   vnc_port?: number;
   spice_port?: number;
   snapshots: Snapshot[];
   network_interfaces: NetworkInterface[];
   storage_devices: StorageDevice[];
+  snapshot_capabilities: DiskSnapshotCapability[];
+  cpu_topology?: CpuTopology;
+  numa_nodes: NumaNodeConfig[];
+  autostart: boolean;        // Started by libvirtd when the host boots
+  has_saved_state: boolean;  // Managed save image present; show Restore instead of Start
+  has_tpm: boolean;
+  shared_folders: SharedFolderConfig[];
+  sound_model?: string;
+  clipboard_ready: boolean;
+  clipboard_issue?: ClipboardIssue;  // Why clipboard sharing isn't available
+  parse_errors: string[];  // Why some details fell back to defaults
+}
+
+// missing_channel is fixed by enable_spice_agent; agent_not_responding needs spice-vdagent in the guest
+export type ClipboardIssue =
+  | 'no_spice_display'
+  | 'missing_channel'
+  | 'not_running'
+  | 'agent_not_responding';
+
+// sockets * cores * threads must equal the vCPU count
+export interface CpuTopology {
+  sockets: number;
+  cores: number;
+  threads: number;
+}
+
+// Which CPU the guest sees. Named models keep a VM migratable between hosts
+// with different CPUs; passthrough gives the best performance.
+export type CpuModel = 'host_passthrough' | 'host_model' | { named: string };
+
+export interface CpuModelConfig {
+  model: CpuModel;
+  features: CpuFeatureFlag[];
+}
+
+export interface CpuFeatureFlag {
+  name: string;  // e.g. vmx, avx512f
+  enabled: boolean;
+}
+
+// A named CPU model from the host's domain capabilities
+export interface HostCpuModel {
+  name: string;
+  vendor?: string;
+  usable: boolean;  // The host CPU can run it without missing features
+}
+
+export type FirmwareType = 'bios' | 'uefi';
+
+// Windows 11 needs TPM 2.0; CRB is TPM 2.0 only
+export interface WatchdogConfig {
+  model?: 'i6300esb' | 'ib700' | 'itco';           // Defaults to i6300esb
+  action?: 'reset' | 'poweroff' | 'pause' | 'none'; // Defaults to reset
+}
+
+export interface TpmConfig {
+  model?: 'tis' | 'crb';       // Defaults to crb
+  version?: '1.2' | '2.0';     // Defaults to 2.0
+}
+
+export interface MachineType {
+  name: string;        // q35, pc-q35-9.0, ...
+  canonical?: string;  // What an alias such as q35 currently resolves to
+  max_cpus?: number;
+}
+
+// One guest NUMA cell; cells must split the VM's vCPUs and memory between them
+export interface NumaNodeConfig {
+  cpus: number[];  // vCPU indices
+  memory_mb: number;
+}
+
+// Back guest memory with host hugepages (<memoryBacking>)
+export interface SharedFolderConfig {
+  host_path: string;
+  guest_tag: string;                // Mount tag inside the guest
+  driver?: 'virtiofs' | '9p';       // Defaults to virtiofs
+  readonly?: boolean;
+}
+
+export interface MemoryBackingConfig {
+  hugepages: boolean;
+  page_size_kib?: number;  // 2048 or 1048576; omit for the host default
+}
+
+// Host CPUs a single vCPU may run on (<vcpupin>)
+export interface VcpuPin {
+  vcpu: number;
+  host_cpus: number[];
+}
+
+export type SnapshotKind = 'disk_only' | 'full';
+
+export interface DiskSnapshotCapability {
+  device: string;
+  format: string;
+  disk_only: boolean;
+  full: boolean;
+  reason?: string;  // Why a kind is unavailable, for the tooltip
 }
 
 export type VmState = 
@@ -28,59 +130,247 @@ export type VmState =
   | 'Creating'
   | 'Error';
 
+// Omitted fields take the hardware preset's value, then the built-in default;
+// any value given wins, even an empty or zero one
 export interface VmConfig {
   name: string;
-  memory: number;
-  vcpus: number;
-  disk_size: number;
-  os_type: string;
+  memory?: number;     // MB
+  vcpus?: number;
+  disk_size?: number;  // GB
+  os_type?: string;
   os_variant?: string;
   description?: string;
+  autostart?: boolean;         // Start with libvirtd when the host boots
+  cpu_topology?: CpuTopology;  // Defaults to one socket per vCPU
+  numa_nodes?: NumaNodeConfig[];  // Guest NUMA cells; empty for a single node
+  cpu_model?: CpuModelConfig;     // Defaults to host-model
+  machine_type?: string;         // Defaults to the host's newest q35
+  firmware?: FirmwareType;       // Defaults to bios
+  secure_boot?: boolean;         // UEFI only; also turns on SMM
+  tpm?: TpmConfig;               // Emulated by swtpm
+  watchdog?: WatchdogConfig;
+  memory_backing?: MemoryBackingConfig;
+  shared_folders?: SharedFolderConfig[];
   network_config: NetworkConfig;
   storage_config: StorageConfig;
   display_config: DisplayConfig;
   boot_config: BootConfig;
 }
 
+// VmConfig fields flattened alongside the details filled in by defaults
+export interface EffectiveVmConfig extends VmConfig {
+  arch?: string;
+  emulator?: string;
+  cpu_mode?: string;
+  loader?: string;           // UEFI code image, absent for BIOS
+  video_model?: string;
+  controllers: string[];     // type:model, e.g. usb:qemu-xhci
+  storage_devices: StorageDevice[];
+  network_interfaces: NetworkInterface[];
+}
+
 export interface NetworkConfig {
   bridge?: string;
   network_name?: string;
   mac_address?: string;
-  model: string; // e1000, virtio, etc.
+  model?: string; // e1000, virtio, etc.
 }
 
 export interface StorageConfig {
-  pool_name: string;
-  format: string; // qcow2, raw, etc.
-  bus: string;    // virtio, sata, ide, etc.
-  cache: string;  // none, writeback, writethrough, etc.
+  pool_name?: string;
+  format?: string; // qcow2, raw, etc.
+  bus?: string;    // virtio, sata, ide, etc.
+  cache?: string;  // none, writeback, writethrough, etc.
+  encryption?: EncryptionSpec;
+}
+
+export interface EncryptionSpec {
+  secret_uuid: string;
+  format: string; // luks
+}
+
+export interface SecretInfo {
+  uuid: string;
+  usage_type: string;  // volume, ceph, iscsi, tls, vtpm
+  usage_id?: string;   // Volume path for disk secrets
+  description?: string;
 }
 
 export interface DisplayConfig {
-  graphics_type: string; // vnc, spice
-  listen: string;
+  graphics_type?: string; // vnc, spice
+  listen?: string;        // Empty means localhost only
   password?: string;
   autoport: boolean;
+  port?: number;       // Required when autoport is false
+  sound_model?: 'ich9' | 'ac97' | 'virtio' | 'none';  // No audio when omitted
+}
+
+export interface HardwarePreset {
+  name: string;
+  description?: string;
+  memory?: number;
+  vcpus?: number;
+  disk_size?: number;
+  os_type?: string;
+  network_name?: string;
+  network_model?: string;
+  storage_pool?: string;
+  storage_format?: string;
+  storage_bus?: string;
+  storage_cache?: string;
+  graphics_type?: string;
+  graphics_listen?: string;
+  boot_order?: string[];
+}
+
+export interface GraphicsDevice {
+  protocol: string;     // vnc, spice
+  port?: number;        // Resolved port, absent while unallocated
+  tls_port?: number;    // SPICE only
+  socket?: string;      // UNIX socket path when listening on a socket
+  listen?: string;      // Listen address
+  autoport: boolean;
+  password_set: boolean;  // Only reported when read from the secure XML
+}
+
+// Everything a viewer needs to connect to a running VM's display
+export interface DisplayInfo {
+  vm_id: string;
+  graphics_type: string;  // vnc, spice
+  host: string;           // Address to connect to; wildcard listens map to 127.0.0.1
+  listen?: string;        // Address the display is bound to
+  port?: number;          // Absent when only TLS or a socket is offered
+  tls_port?: number;      // SPICE only
+  socket?: string;
+  password_set: boolean;
+  uri?: string;           // vnc://host:port or spice://host:port
+}
+
+export interface VmScreenshot {
+  vm_id: string;
+  png_base64: string;
+  width: number;
+  height: number;
+  taken_at: string;
 }
 
 export interface BootConfig {
-  boot_order: string[]; // cdrom, hd, network
+  boot_order?: string[]; // cdrom, hd, network
   iso_path?: string;
   kernel?: string;
   initrd?: string;
   cmdline?: string;
 }
 
+export interface ToolStatus {
+  name: string;
+  package: string;
+  path?: string;
+  available: boolean;
+}
+
+export interface PreflightReport {
+  tools: ToolStatus[];
+}
+
+export interface RecoveryOptions {
+  define_missing: boolean;  // Define the stock default network/pool if missing
+  use_fallback: boolean;    // Substitute another active network if missing
+}
+
+// Serialized as `{ "needs_action": NeedsAction }` in the error string of create_vm/start_vm
+export interface NeedsAction {
+  action: string;           // define_default_network, use_fallback_network, define_default_pool
+  resource: string;
+  message: string;
+  fallback?: string;
+}
+
+// Serialized as `{ "filesystem_issue": FilesystemIssue }` in the error string of start_vm/create_snapshot
+export interface FilesystemIssue {
+  path: string;             // Disk image path
+  mount_point?: string;
+  free_bytes: number;
+  read_only: boolean;
+  message: string;
+}
+
+// Serialized as `{ "shutdown_timeout": ShutdownTimeout }` in the error string of stop_vm;
+// offer force_stop_vm when this comes back
+export interface ShutdownTimeout {
+  vm_id: string;
+  timeout_secs: number;
+  message: string;
+}
+
 export interface VmStats {
-  cpu_usage: number;        // Percentage
-  memory_usage: number;     // Used memory in MB
-  memory_total: number;     // Total memory in MB
+  cpu_usage: number;        // Percent of allocated vCPUs, 0-100
+  /** Used memory in MB. @deprecated Use `memory_usage_bytes`; removed in the next release. */
+  memory_usage: number;
+  /** Total memory in MB. @deprecated Use `memory_total_bytes`; removed in the next release. */
+  memory_total: number;
+  memory_usage_bytes: number;
+  memory_total_bytes: number;
+  memory_usage_display: string;
   disk_read: number;        // Bytes read per second
   disk_write: number;       // Bytes written per second
   network_rx: number;       // Bytes received per second
   network_tx: number;       // Bytes transmitted per second
   uptime: number;           // Uptime in seconds
-  guest_agent_connected: boolean;
+  uptime_display: string;
+  timestamp: string;        // When these stats were collected
+  cycle_id?: number;        // Collection cycle shared with the host metrics, if any
+  guest_agent_connected: boolean;  // The agent answered a guest-ping
+}
+
+// Payload of the vm-stats-update event, sent for VMs passed to subscribe_vm_stats
+export interface VmStatsEvent extends VmStats {
+  vm_id: string;
+}
+
+// Result of guest_exec; errors starting "Guest agent not connected" or
+// "Command not found in guest" tell those cases apart
+export interface GuestExecResult {
+  pid: number;
+  exit_code?: number;  // Absent when a signal killed the process
+  signal?: number;
+  stdout: string;      // Empty unless capture_output was set
+  stderr: string;
+  truncated: boolean;  // The agent caps captured output
+}
+
+// Result of guest_read_file, which reads at most 1 MiB
+export interface GuestFileContents {
+  path: string;
+  contents_base64: string;
+  bytes: number;
+  truncated: boolean;  // The file goes on past max_bytes
+}
+
+// What the QEMU guest agent reports from inside a running VM
+export interface GuestInfo {
+  agent_connected: boolean;
+  filesystems: GuestFilesystem[];
+  interfaces: GuestInterface[];
+}
+
+export interface GuestFilesystem {
+  mountpoint: string;
+  fs_type: string;
+  total_bytes?: number;  // Older agents don't report usage
+  used_bytes?: number;
+}
+
+export interface GuestInterface {
+  name: string;
+  mac_address?: string;
+  ip_addresses: string[];  // CIDR notation, e.g. 192.168.122.10/24
+}
+
+export interface MetricPoint {
+  timestamp: string;
+  cycle_id: number;         // Align series from the same collection cycle on this
+  value: number;
 }
 
 export interface SystemStats {
@@ -92,6 +382,7 @@ export interface SystemStats {
   network_tx: number;       // System network bytes transmitted per second
   load_average: [number, number, number]; // 1m, 5m, 15m load averages
   uptime: number;           // System uptime in seconds
+  cycle_id?: number;        // Collection cycle shared with the per-VM stats
 }
 
 export interface HostInfo {
@@ -100,8 +391,14 @@ export interface HostInfo {
   hypervisor_version: string;
   cpu_model: string;
   cpu_cores: number;
-  memory_total: number;     // Total host memory in MB
-  memory_free: number;      // Free host memory in MB
+  /** Total host memory in MB. @deprecated Use `memory_total_bytes`; removed in the next release. */
+  memory_total: number;
+  /** Free host memory in MB. @deprecated Use `memory_free_bytes`; removed in the next release. */
+  memory_free: number;
+  memory_total_bytes: number;
+  memory_free_bytes: number;
+  memory_total_display: string;
+  memory_free_display: string;
   storage_pools: StoragePool[];
   networks: Network[];
   active_vms: number;
@@ -112,8 +409,9 @@ export interface Snapshot {
   name: string;
   description?: string;
   created_at: string;
-  state: string;
+  state: string;             // running or paused (with memory), shutoff, disk-snapshot
   parent?: string;
+  includes_memory: boolean;  // Reverting resumes the guest where it was
 }
 
 export interface NetworkInterface {
@@ -133,26 +431,71 @@ export interface StorageDevice {
   cache: string;        // cache mode
 }
 
+export type ConnectionStatus = 'connected' | 'reconnecting' | 'disconnected';
+
 export interface StoragePool {
   name: string;
   pool_type: string;   // dir, disk, netfs, etc.
   path: string;
-  capacity: number;    // Total capacity in bytes
-  available: number;   // Available space in bytes
-  used: number;        // Used space in bytes
+  /** Total capacity in bytes. @deprecated Use `capacity_bytes`; removed in the next release. */
+  capacity: number;
+  /** Available space in bytes. @deprecated Use `available_bytes`; removed in the next release. */
+  available: number;
+  /** Used space in bytes. @deprecated Use `used_bytes`; removed in the next release. */
+  used: number;
+  capacity_bytes: number;
+  available_bytes: number;
+  used_bytes: number;
+  capacity_display: string;
+  available_display: string;
+  used_display: string;
   state: string;       // active, inactive
   autostart: boolean;
   volumes: StorageVolume[];
 }
 
+export interface StoragePoolCreateConfig {
+  name: string;
+  pool_type: 'dir' | 'netfs' | 'logical';
+  target_path?: string;    // Required for dir and netfs
+  source_host?: string;    // netfs server
+  source_path?: string;    // netfs export
+  source_name?: string;    // logical volume group, defaults to the pool name
+  source_device?: string;  // logical physical volume to build on
+  build?: boolean;
+  autostart?: boolean;
+}
+
 export interface StorageVolume {
   name: string;
   format: string;
+  /** @deprecated Use `capacity_bytes`; removed in the next release. */
   capacity: number;
+  /** @deprecated Use `allocation_bytes`; removed in the next release. */
   allocation: number;
+  capacity_bytes: number;
+  allocation_bytes: number;
+  capacity_display: string;
+  allocation_display: string;
   path: string;
 }
 
+export interface VolumeConfig {
+  name: string;
+  format: string;       // qcow2, raw
+  capacity: number;     // Bytes
+  allocation?: number;  // Bytes preallocated, defaults to sparse
+  encryption?: EncryptionSpec;
+}
+
+export interface VolumeInfo {
+  name: string;
+  path: string;
+  format: string;
+  capacity: number;     // Bytes
+  allocation: number;   // Bytes
+}
+
 export interface Network {
   name: string;
   uuid: string;
@@ -165,6 +508,45 @@ export interface Network {
   connected_vms: string[];
 }
 
+export interface NetworkCreateConfig {
+  name: string;
+  forward_mode: string;       // nat, route, bridge, none
+  bridge_name?: string;       // Picked by libvirt when absent
+  ip_range?: string;          // Gateway address, optionally with /prefix
+  dhcp_enabled: boolean;
+  dhcp_range_start?: string;
+  dhcp_range_end?: string;
+  auto_start: boolean;        // Start the network right after defining it
+}
+
+export interface ClonedDisk {
+  device: string;  // Target, e.g. vda
+  source: string;  // Image the copy was made from
+  path: string;    // New image used by the clone
+}
+
+export interface CloneResult {
+  vm_id: string;
+  name: string;
+  cloned_disks: ClonedDisk[];
+  warnings: string[];  // Disks left out of the clone and why
+}
+
+export type MigrationState = 'Preparing' | 'Migrating' | 'Completed' | 'Failed' | 'Cancelled';
+
+// Poll get_migration_status with the id returned by migrate_vm
+export interface MigrationTask {
+  id: string;
+  vm_id: string;
+  source_host: string;
+  target_host: string;       // Target libvirt URI
+  state: MigrationState;
+  progress: number;          // Percentage
+  started_at: string;
+  completed_at?: string;
+  error_message?: string;
+}
+
 // UI-specific types
 export interface DashboardMetrics {
   total_vms: number;
@@ -184,3 +566,123 @@ export interface ToastMessage {
   message: string;
   duration?: number;
 }
+
+export interface ResourceQuota {
+  max_total_vcpus?: number;
+  max_total_memory_mb?: number;
+  max_disk_per_vm_gb?: number;
+}
+
+export interface QuotaSettings {
+  global: ResourceQuota;
+  per_tag: Record<string, ResourceQuota>;
+}
+
+export interface ResourceUsage {
+  vm_count: number;
+  vcpus: number;
+  memory_mb: number;
+}
+
+export interface QuotaUsage {
+  global: ResourceUsage;
+  per_tag: Record<string, ResourceUsage>;
+  limits: QuotaSettings;
+}
+
+// Serialized as `{ "quota_exceeded": QuotaViolation }` in the error string of create commands
+export interface QuotaViolation {
+  scope: string;     // global or tag:<name>
+  resource: string;  // vcpus, memory_mb, disk_gb
+  current: number;
+  requested: number;
+  limit: number;
+  message: string;
+}
+
+// Cached header summary; may be up to one monitoring interval stale
+export interface StatusSummary {
+  revision: number;  // Skip re-rendering while unchanged
+  running: number;
+  paused: number;
+  stopped: number;
+  host_cpu_percent?: number;
+  host_memory_percent?: number;
+  tightest_pool?: PoolSpace;
+  active_alerts: number;
+}
+
+export interface PoolSpace {
+  name: string;
+  available_bytes: number;
+  available_display: string;
+}
+
+export interface RefreshOutcome {
+  vms: VirtualMachine[];
+  performed: boolean;  // false when the call joined a refresh already in flight
+}
+
+export interface LogEntry {
+  timestamp: string;
+  level: string;
+  target: string;
+  message: string;
+  vm_id?: string;
+  operation?: string;
+}
+
+export type ImportConflictPolicy = 'skip' | 'rename' | 'overwrite';
+
+export type ImportFileOutcome =
+  | { status: 'imported'; uuid: string; name: string }
+  | { status: 'skipped'; reason: string }
+  | { status: 'not_domain'; kind: string }  // Root element, e.g. network or pool
+  | { status: 'failed'; reason: string };
+
+export interface ImportFileNote {
+  file: string;
+  detail: string;  // Reason, or the root element for non-domain files
+}
+
+export interface ImportedVm {
+  file: string;
+  uuid: string;
+  name: string;
+}
+
+// Payload of the `batch-import-finished` event
+export interface BatchImportReport {
+  task_id: string;
+  directory: string;
+  imported: ImportedVm[];
+  skipped: ImportFileNote[];
+  failed: ImportFileNote[];
+  not_domain: ImportFileNote[];
+}
+
+// Payload of the `batch-import-progress` event
+export interface BatchImportProgress {
+  task_id: string;
+  file: string;
+  index: number;  // 1-based
+  total: number;
+  outcome: ImportFileOutcome;
+}
+
+// Rendered into a NoCloud seed ISO attached to VMs created from cloud images
+export interface CloudInitConfig {
+  hostname: string;
+  username: string;
+  ssh_authorized_key?: string;
+  network_config?: string;  // Network config v2 YAML, written verbatim
+}
+
+// Where a configuration change took effect
+export type ChangeScope = 'live' | 'persistent' | 'both';
+
+export interface VcpuChange {
+  vcpus: number;
+  max_vcpus: number;
+  applied: ChangeScope;
+}